@@ -0,0 +1,16 @@
+use dakv_cuckoo::comparative::{run, to_json, Workload};
+use std::env;
+
+fn main() {
+    let num_keys = env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1_000_000);
+    let results = run(&Workload {
+        num_keys,
+        key_len: 16,
+        seed: 42,
+    })
+    .expect("filters could not be allocated");
+    println!("{}", to_json(&results));
+}
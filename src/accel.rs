@@ -0,0 +1,278 @@
+use crate::bucket::BUCKET_SIZE;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::{env, fmt};
+
+/// Bytes compared per key: both of its candidate buckets.
+pub(crate) const LANE: usize = 2 * BUCKET_SIZE;
+
+/// Keys compared per kernel call, filling one 256-bit register.
+pub(crate) const GROUP: usize = 4;
+
+/// Environment variable overriding the detected path: `scalar`, `avx2`,
+/// `neon` or `auto`.
+pub const ACCELERATION_ENV: &str = "DAKV_CUCKOO_ACCELERATION";
+
+// `ACTIVE` before the path is chosen
+const UNSET: u8 = 0;
+
+static ACTIVE: AtomicU8 = AtomicU8::new(UNSET);
+
+/// An implementation of the bucket comparisons of `contains_batch`.
+///
+/// The SIMD paths are only compiled with `unsafe-optimizations`, and only
+/// used on CPUs that report the instructions at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Acceleration {
+    /// Portable code, compared one slot at a time.
+    Scalar = 1,
+    /// x86_64 AVX2, comparing the buckets of four keys per instruction.
+    Avx2 = 2,
+    /// aarch64 NEON, comparing the buckets of two keys per instruction.
+    Neon = 3,
+}
+
+impl Acceleration {
+    /// Returns `true` if this build contains the path and the CPU it runs
+    /// on supports it.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::Acceleration;
+    /// assert!(Acceleration::Scalar.is_supported());
+    /// ```
+    pub fn is_supported(self) -> bool {
+        match self {
+            Acceleration::Scalar => true,
+            Acceleration::Avx2 => {
+                #[cfg(all(feature = "unsafe-optimizations", target_arch = "x86_64"))]
+                {
+                    is_x86_feature_detected!("avx2")
+                }
+                #[cfg(not(all(feature = "unsafe-optimizations", target_arch = "x86_64")))]
+                {
+                    false
+                }
+            }
+            Acceleration::Neon => {
+                #[cfg(all(feature = "unsafe-optimizations", target_arch = "aarch64"))]
+                {
+                    std::arch::is_aarch64_feature_detected!("neon")
+                }
+                #[cfg(not(all(feature = "unsafe-optimizations", target_arch = "aarch64")))]
+                {
+                    false
+                }
+            }
+        }
+    }
+
+    /// Returns the fastest supported path.
+    pub fn detect() -> Self {
+        [Acceleration::Avx2, Acceleration::Neon]
+            .iter()
+            .copied()
+            .find(|path| path.is_supported())
+            .unwrap_or(Acceleration::Scalar)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Acceleration::Scalar => "scalar",
+            Acceleration::Avx2 => "avx2",
+            Acceleration::Neon => "neon",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        [Acceleration::Scalar, Acceleration::Avx2, Acceleration::Neon]
+            .iter()
+            .copied()
+            .find(|path| path.name().eq_ignore_ascii_case(name.trim()))
+    }
+
+    fn from_u8(v: u8) -> Self {
+        match v {
+            2 => Acceleration::Avx2,
+            3 => Acceleration::Neon,
+            _ => Acceleration::Scalar,
+        }
+    }
+}
+
+impl fmt::Display for Acceleration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Returns the path `contains_batch` uses in this process.
+///
+/// Chosen on first use: the path named by `ACCELERATION_ENV` if it is
+/// supported, otherwise the fastest supported one. An unsupported or unknown
+/// name is ignored rather than trusted, as running instructions the CPU lacks
+/// would crash the process. Log it at startup to confirm a host runs the
+/// expected path.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::active_acceleration;
+/// println!("cuckoo lookups use {}", active_acceleration());
+/// ```
+pub fn active_acceleration() -> Acceleration {
+    match ACTIVE.load(Ordering::Relaxed) {
+        UNSET => {
+            let path = env::var(ACCELERATION_ENV)
+                .ok()
+                .and_then(|name| Acceleration::from_name(&name))
+                .filter(|path| path.is_supported())
+                .unwrap_or_else(Acceleration::detect);
+            // A concurrent first call may have chosen already; both agree
+            // unless `force_acceleration` raced, in which case it wins.
+            let relaxed = Ordering::Relaxed;
+            match ACTIVE.compare_exchange(UNSET, path as u8, relaxed, relaxed) {
+                Ok(_) => path,
+                Err(v) => Acceleration::from_u8(v),
+            }
+        }
+        v => Acceleration::from_u8(v),
+    }
+}
+
+/// Selects `path` for the rest of the process, overriding detection and
+/// `ACCELERATION_ENV`, e.g. from a config file or to compare paths.
+///
+/// Returns `false` and keeps the current path if `path` is not supported.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{active_acceleration, force_acceleration, Acceleration};
+/// assert!(force_acceleration(Acceleration::Scalar));
+/// assert_eq!(active_acceleration(), Acceleration::Scalar);
+/// ```
+pub fn force_acceleration(path: Acceleration) -> bool {
+    if !path.is_supported() {
+        return false;
+    }
+    ACTIVE.store(path as u8, Ordering::Relaxed);
+    true
+}
+
+/// Returns, for every lane of `slots`, whether it holds the fingerprint of
+/// the same index in `fps`, on the active path.
+#[cfg_attr(feature = "unsafe-optimizations", allow(unsafe_code))]
+pub(crate) fn match_lanes(slots: &[[u8; LANE]; GROUP], fps: &[u8; GROUP]) -> [bool; GROUP] {
+    #[cfg(all(feature = "unsafe-optimizations", target_arch = "x86_64"))]
+    if active_acceleration() == Acceleration::Avx2 {
+        // SAFETY: `active_acceleration` only returns paths the CPU reported
+        // support for.
+        return unsafe { match_lanes_avx2(slots, fps) };
+    }
+    #[cfg(all(feature = "unsafe-optimizations", target_arch = "aarch64"))]
+    if active_acceleration() == Acceleration::Neon {
+        // SAFETY: as above.
+        return unsafe { match_lanes_neon(slots, fps) };
+    }
+    match_lanes_scalar(slots, fps)
+}
+
+fn match_lanes_scalar(slots: &[[u8; LANE]; GROUP], fps: &[u8; GROUP]) -> [bool; GROUP] {
+    let mut out = [false; GROUP];
+    for ((hit, lane), &fp) in out.iter_mut().zip(slots).zip(fps) {
+        *hit = lane.contains(&fp);
+    }
+    out
+}
+
+/// Repeats every fingerprint across its lane.
+#[cfg(all(
+    feature = "unsafe-optimizations",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn broadcast(fps: &[u8; GROUP]) -> [[u8; LANE]; GROUP] {
+    let mut out = [[0; LANE]; GROUP];
+    for (lane, &fp) in out.iter_mut().zip(fps) {
+        *lane = [fp; LANE];
+    }
+    out
+}
+
+#[cfg(all(feature = "unsafe-optimizations", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+#[allow(unsafe_code)]
+unsafe fn match_lanes_avx2(slots: &[[u8; LANE]; GROUP], fps: &[u8; GROUP]) -> [bool; GROUP] {
+    use std::arch::x86_64::{
+        __m256i, _mm256_cmpeq_epi8, _mm256_loadu_si256, _mm256_movemask_epi8,
+    };
+    let fps = broadcast(fps);
+    // SAFETY: both arrays are exactly 32 bytes, and unaligned loads have no
+    // alignment requirement.
+    let slots = _mm256_loadu_si256(slots.as_ptr() as *const __m256i);
+    let fps = _mm256_loadu_si256(fps.as_ptr() as *const __m256i);
+    let mask = _mm256_movemask_epi8(_mm256_cmpeq_epi8(slots, fps)) as u32;
+    let mut out = [false; GROUP];
+    for (k, hit) in out.iter_mut().enumerate() {
+        *hit = (mask >> (LANE * k)) & 0xff != 0;
+    }
+    out
+}
+
+#[cfg(all(feature = "unsafe-optimizations", target_arch = "aarch64"))]
+#[target_feature(enable = "neon")]
+#[allow(unsafe_code)]
+unsafe fn match_lanes_neon(slots: &[[u8; LANE]; GROUP], fps: &[u8; GROUP]) -> [bool; GROUP] {
+    use std::arch::aarch64::{vceqq_u8, vgetq_lane_u64, vld1q_u8, vreinterpretq_u64_u8};
+    let fps = broadcast(fps);
+    let mut out = [false; GROUP];
+    for (half, hits) in out.chunks_exact_mut(2).enumerate() {
+        // SAFETY: each half of both arrays is 16 contiguous bytes, and the
+        // loads have no alignment requirement.
+        let a = vld1q_u8(slots.as_ptr().cast::<u8>().add(2 * LANE * half));
+        let b = vld1q_u8(fps.as_ptr().cast::<u8>().add(2 * LANE * half));
+        let eq = vreinterpretq_u64_u8(vceqq_u8(a, b));
+        hits[0] = vgetq_lane_u64::<0>(eq) != 0;
+        hits[1] = vgetq_lane_u64::<1>(eq) != 0;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::accel::{match_lanes, match_lanes_scalar, GROUP, LANE};
+    use crate::{active_acceleration, Acceleration, CuckooFilter};
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_kernels_agree() {
+        assert!(active_acceleration().is_supported());
+        let mut rng = StdRng::seed_from_u64(9);
+        for _ in 0..10_000 {
+            let mut slots = [[0; LANE]; GROUP];
+            for lane in &mut slots {
+                for slot in lane.iter_mut() {
+                    *slot = rng.gen_range(0, 8);
+                }
+            }
+            let mut fps = [0; GROUP];
+            for fp in &mut fps {
+                *fp = rng.gen_range(1, 8);
+            }
+            assert_eq!(match_lanes(&slots, &fps), match_lanes_scalar(&slots, &fps));
+        }
+        assert_eq!(Acceleration::from_name(" AVX2"), Some(Acceleration::Avx2));
+        assert_eq!(Acceleration::from_name("auto"), None);
+    }
+
+    #[test]
+    fn test_contains_batch() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(4096, 2);
+        for i in 0..3000u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let keys = (0..10_001u16).map(u16::to_le_bytes).collect::<Vec<_>>();
+        let expected = keys.iter().map(|k| cf.contains(k)).collect::<Vec<_>>();
+        assert_eq!(cf.contains_batch(&keys), expected);
+        assert!(cf.contains_batch(&[] as &[&[u8]]).is_empty());
+    }
+}
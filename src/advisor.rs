@@ -0,0 +1,145 @@
+use crate::bucket::BUCKET_SIZE;
+use crate::cuckoo_filter::max_load;
+use crate::CuckooFilter;
+use std::time::{Duration, Instant};
+
+/// Load factor at which inserts into a four-slot cuckoo filter start failing.
+pub(crate) const FULL_LOAD_FACTOR: f64 = max_load(BUCKET_SIZE);
+
+/// Projects when a filter will saturate from its observed growth.
+///
+/// Feed it the filter periodically with `observe`; it keeps an exponentially
+/// decayed estimate of the net insert rate, so bursts fade out after a few
+/// `half_life`s and the projection follows the current workload.
+#[derive(Debug, Clone)]
+pub struct CapacityAdvisor {
+    half_life: Duration,
+    last: Option<(Instant, usize)>,
+    capacity: usize,
+    rate: Option<f64>,
+}
+
+impl CapacityAdvisor {
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CapacityAdvisor;
+    /// use std::time::Duration;
+    /// let advisor = CapacityAdvisor::new(Duration::from_secs(600));
+    /// ```
+    pub fn new(half_life: Duration) -> Self {
+        CapacityAdvisor {
+            half_life: half_life.max(Duration::from_nanos(1)),
+            last: None,
+            capacity: 0,
+            rate: None,
+        }
+    }
+
+    /// Records the current size of `filter`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CapacityAdvisor, CuckooFilter};
+    /// use std::time::Duration;
+    /// let mut advisor = CapacityAdvisor::new(Duration::from_secs(600));
+    /// let cf = CuckooFilter::new(1000);
+    /// advisor.observe(&cf);
+    /// ```
+    pub fn observe(&mut self, filter: &CuckooFilter) {
+        self.observe_at(filter.size(), filter.capacity(), Instant::now());
+    }
+
+    /// Returns the estimated net inserts per second, once two observations
+    /// have been made.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CapacityAdvisor;
+    /// use std::time::Duration;
+    /// let advisor = CapacityAdvisor::new(Duration::from_secs(600));
+    /// assert_eq!(advisor.insert_rate(), None);
+    /// ```
+    pub fn insert_rate(&self) -> Option<f64> {
+        self.rate
+    }
+
+    /// Returns how long after the last observation the filter is expected to
+    /// be full, or `None` while it is not growing or so slowly that the
+    /// projection does not fit in a `Duration`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CapacityAdvisor;
+    /// use std::time::Duration;
+    /// let advisor = CapacityAdvisor::new(Duration::from_secs(600));
+    /// assert_eq!(advisor.projected_full_at(), None);
+    /// ```
+    pub fn projected_full_at(&self) -> Option<Duration> {
+        let rate = self.rate.filter(|r| *r > 0.0)?;
+        let (_, size) = self.last?;
+        let full = self.capacity as f64 * FULL_LOAD_FACTOR;
+        let remaining = (full - size as f64).max(0.0);
+        Duration::try_from_secs_f64(remaining / rate).ok()
+    }
+
+    fn observe_at(&mut self, size: usize, capacity: usize, now: Instant) {
+        self.capacity = capacity;
+        if let Some((then, before)) = self.last {
+            let dt = now.saturating_duration_since(then);
+            if dt.is_zero() {
+                self.last = Some((then, size));
+                return;
+            }
+            let sample = (size as f64 - before as f64) / dt.as_secs_f64();
+            let alpha = 1.0 - 0.5f64.powf(dt.as_secs_f64() / self.half_life.as_secs_f64());
+            self.rate = Some(match self.rate {
+                Some(rate) => rate + alpha * (sample - rate),
+                None => sample,
+            });
+        }
+        self.last = Some((now, size));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CapacityAdvisor;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_projection() {
+        let mut advisor = CapacityAdvisor::new(Duration::from_secs(60));
+        let t0 = Instant::now();
+        advisor.observe_at(0, 2000, t0);
+        assert_eq!(advisor.projected_full_at(), None);
+        advisor.observe_at(100, 2000, t0 + Duration::from_secs(10));
+        assert_eq!(advisor.insert_rate(), Some(10.0));
+        // 1920 usable slots minus 100 used, at 10 per second.
+        let full_at = advisor.projected_full_at().unwrap().as_secs_f64();
+        assert!((full_at - 182.0).abs() < 1e-6);
+
+        // Growth stopping pulls the estimate towards zero but keeps it positive.
+        advisor.observe_at(100, 2000, t0 + Duration::from_secs(70));
+        let rate = advisor.insert_rate().unwrap();
+        assert_eq!(rate, 5.0);
+    }
+
+    #[test]
+    fn test_shrinking() {
+        let mut advisor = CapacityAdvisor::new(Duration::from_secs(60));
+        let t0 = Instant::now();
+        advisor.observe_at(500, 2000, t0);
+        advisor.observe_at(400, 2000, t0 + Duration::from_secs(10));
+        assert_eq!(advisor.projected_full_at(), None);
+    }
+
+    #[test]
+    fn test_projection_out_of_range() {
+        let mut advisor = CapacityAdvisor::new(Duration::from_secs(60));
+        let t0 = Instant::now();
+        advisor.observe_at(0, usize::MAX, t0);
+        advisor.observe_at(1, usize::MAX, t0 + Duration::from_secs(1 << 32));
+        assert!(advisor.insert_rate().unwrap() > 0.0);
+        assert_eq!(advisor.projected_full_at(), None);
+    }
+}
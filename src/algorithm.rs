@@ -0,0 +1,352 @@
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+
+// Hashed under a secret key to identify it in serialized filters.
+#[cfg(feature = "siphash")]
+const KEY_CHECK_INPUT: &[u8] = b"dakv_cuckoo key check";
+
+// SeaHash's default keys; seeding XORs into the first.
+const SEAHASH_KEYS: [u64; 4] = [
+    0x16f11fe89b0d677c,
+    0xb480a793d8e6c86c,
+    0x6fe2e5aaf078ebc9,
+    0x14f994a4c5259381,
+];
+
+/// A hash function a filter can be switched to at run time.
+///
+/// SeaHash is always available; the others are compiled in by the feature
+/// of the same name. Filters written with `to_bytes_with_hasher` record the
+/// algorithm, so one binary loads filters built with any of the compiled-in
+/// ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+    /// The reference hash; with seed 0 it places keys like a default filter
+    /// without the `fast` feature.
+    SeaHash,
+    /// XXH3, the fastest on long keys.
+    #[cfg(feature = "xxh3")]
+    Xxh3,
+    /// SipHash-1-3 keyed by the seed. For keys chosen by an attacker, use
+    /// `RuntimeHasher::keyed` and a secret 128-bit key instead.
+    #[cfg(feature = "siphash")]
+    SipHash13,
+    /// HighwayHash, keyed by the seed.
+    #[cfg(feature = "highway")]
+    HighwayHash,
+    /// wyhash, the fastest on short keys.
+    #[cfg(feature = "wyhash")]
+    WyHash,
+}
+
+impl HashAlgorithm {
+    /// Returns the id recorded in serialized filters.
+    pub fn id(self) -> u8 {
+        match self {
+            HashAlgorithm::SeaHash => 0,
+            #[cfg(feature = "xxh3")]
+            HashAlgorithm::Xxh3 => 1,
+            #[cfg(feature = "siphash")]
+            HashAlgorithm::SipHash13 => 2,
+            #[cfg(feature = "highway")]
+            HashAlgorithm::HighwayHash => 3,
+            #[cfg(feature = "wyhash")]
+            HashAlgorithm::WyHash => 4,
+        }
+    }
+
+    /// Returns the algorithm with `id`, or `None` if it is unknown or not
+    /// compiled in.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(HashAlgorithm::SeaHash),
+            #[cfg(feature = "xxh3")]
+            1 => Some(HashAlgorithm::Xxh3),
+            #[cfg(feature = "siphash")]
+            2 => Some(HashAlgorithm::SipHash13),
+            #[cfg(feature = "highway")]
+            3 => Some(HashAlgorithm::HighwayHash),
+            #[cfg(feature = "wyhash")]
+            4 => Some(HashAlgorithm::WyHash),
+            _ => None,
+        }
+    }
+
+    fn hash(self, data: &[u8], seed: u64) -> u64 {
+        match self {
+            HashAlgorithm::SeaHash => {
+                let [a, b, c, d] = SEAHASH_KEYS;
+                seahash::hash_seeded(data, a ^ seed, b, c, d)
+            }
+            #[cfg(feature = "xxh3")]
+            HashAlgorithm::Xxh3 => xxhash_rust::xxh3::xxh3_64_with_seed(data, seed),
+            #[cfg(feature = "siphash")]
+            HashAlgorithm::SipHash13 => siphash13(data, seed, !seed),
+            #[cfg(feature = "highway")]
+            HashAlgorithm::HighwayHash => {
+                use highway::{HighwayHash, HighwayHasher, Key};
+                HighwayHasher::new(Key([seed, !seed, seed.rotate_left(32), 0])).hash64(data)
+            }
+            #[cfg(feature = "wyhash")]
+            HashAlgorithm::WyHash => wyhash::wyhash(data, seed),
+        }
+    }
+}
+
+/// A `BuildHasher` hashing keys with a `HashAlgorithm` chosen at run time.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{CuckooFilter, HashAlgorithm, RuntimeHasher};
+///
+/// let mut cf = CuckooFilter::with_hasher(1000, RuntimeHasher::new(HashAlgorithm::SeaHash, 7));
+/// cf.add(b"test").unwrap();
+/// let restored = CuckooFilter::from_bytes_with_hasher(&cf.to_bytes_with_hasher()).unwrap();
+/// assert_eq!(restored.hasher().seed(), 7);
+/// assert!(restored.contains(b"test"));
+/// ```
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RuntimeHasher {
+    algorithm: HashAlgorithm,
+    seed: u64,
+    #[cfg(feature = "siphash")]
+    key: Option<(u64, u64)>,
+}
+
+impl RuntimeHasher {
+    pub fn new(algorithm: HashAlgorithm, seed: u64) -> Self {
+        RuntimeHasher {
+            algorithm,
+            seed,
+            #[cfg(feature = "siphash")]
+            key: None,
+        }
+    }
+
+    /// Hashes keys with SipHash-1-3 under a 128-bit secret `key`.
+    ///
+    /// With a public hash function, anyone can search offline for keys
+    /// sharing a fingerprint and bucket pair; a few of them fill the pair and
+    /// every further one fails with `NotEnoughSpace`, whatever the load. An
+    /// untrusted client that does not know `key` cannot predict where its
+    /// keys land, so it cannot precompute such a set.
+    ///
+    /// Keying does not hide which keys collide from a client that can watch
+    /// `add` fail or probe `contains` for false positives over many
+    /// requests, and it protects nothing once the key leaks: keep it out of
+    /// logs and source control. Serialized filters record that they are
+    /// keyed and a check value of the key, never the key itself; load them
+    /// with `CuckooFilter::from_bytes_with_key`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, RuntimeHasher};
+    ///
+    /// let key = *b"0123456789abcdef";
+    /// let mut cf = CuckooFilter::with_hasher(1000, RuntimeHasher::keyed(key));
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.hasher().is_keyed());
+    /// let restored = CuckooFilter::from_bytes_with_key(&cf.to_bytes_with_hasher(), key).unwrap();
+    /// assert!(restored.contains(b"test"));
+    /// ```
+    #[cfg(feature = "siphash")]
+    pub fn keyed(key: [u8; 16]) -> Self {
+        let key = u128::from_le_bytes(key);
+        RuntimeHasher {
+            algorithm: HashAlgorithm::SipHash13,
+            seed: 0,
+            key: Some((key as u64, (key >> 64) as u64)),
+        }
+    }
+
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    /// Returns the seed; keyed hashers have none, and return 0.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Returns whether keys are hashed under a secret key.
+    pub fn is_keyed(&self) -> bool {
+        #[cfg(feature = "siphash")]
+        {
+            self.key.is_some()
+        }
+        #[cfg(not(feature = "siphash"))]
+        {
+            false
+        }
+    }
+
+    /// Returns a value identifying the secret key without revealing it, or
+    /// `None` if the hasher is not keyed.
+    pub(crate) fn key_check(&self) -> Option<u64> {
+        #[cfg(feature = "siphash")]
+        {
+            self.key.map(|(k0, k1)| siphash13(KEY_CHECK_INPUT, k0, k1))
+        }
+        #[cfg(not(feature = "siphash"))]
+        {
+            None
+        }
+    }
+
+    fn hash(&self, data: &[u8]) -> u64 {
+        #[cfg(feature = "siphash")]
+        {
+            if let Some((k0, k1)) = self.key {
+                return siphash13(data, k0, k1);
+            }
+        }
+        self.algorithm.hash(data, self.seed)
+    }
+}
+
+// Leaves the secret key out of logs.
+impl fmt::Debug for RuntimeHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuntimeHasher")
+            .field("algorithm", &self.algorithm)
+            .field("seed", &self.seed)
+            .field("keyed", &self.is_keyed())
+            .finish()
+    }
+}
+
+#[cfg(feature = "siphash")]
+fn siphash13(data: &[u8], k0: u64, k1: u64) -> u64 {
+    let mut hasher = siphasher::sip::SipHasher13::new_with_keys(k0, k1);
+    hasher.write(data);
+    hasher.finish()
+}
+
+impl BuildHasher for RuntimeHasher {
+    type Hasher = RuntimeHashState;
+
+    fn build_hasher(&self) -> RuntimeHashState {
+        RuntimeHashState {
+            hasher: *self,
+            hash: 0,
+        }
+    }
+}
+
+/// The `Hasher` of `RuntimeHasher`. Like `KeyHasher`, a single `write`
+/// hashes its bytes in one pass; further writes are mixed in.
+#[derive(Debug, Clone, Copy)]
+pub struct RuntimeHashState {
+    hasher: RuntimeHasher,
+    hash: u64,
+}
+
+impl Hasher for RuntimeHashState {
+    fn write(&mut self, bytes: &[u8]) {
+        let hash = self.hasher.hash(bytes);
+        self.hash = self.hash.rotate_left(5) ^ hash;
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::{FormatError, FLAGS_OFFSET};
+    use crate::{CuckooFilter, HashAlgorithm, RuntimeHasher};
+
+    fn algorithms() -> Vec<HashAlgorithm> {
+        (0..=u8::MAX).filter_map(HashAlgorithm::from_id).collect()
+    }
+
+    #[test]
+    fn test_algorithm_round_trip() {
+        for algorithm in algorithms() {
+            assert_eq!(HashAlgorithm::from_id(algorithm.id()), Some(algorithm));
+            let mut cf = CuckooFilter::with_hasher(1000, RuntimeHasher::new(algorithm, 99));
+            for i in 0..900u32 {
+                cf.add(&i.to_le_bytes()).unwrap();
+            }
+            let bytes = cf.to_bytes_with_hasher();
+            assert!(CuckooFilter::from_bytes(&bytes).is_err());
+            let restored = CuckooFilter::from_bytes_with_hasher(&bytes).unwrap();
+            assert_eq!(restored.hasher(), cf.hasher());
+            assert!((0..900u32).all(|i| restored.contains(&i.to_le_bytes())));
+        }
+
+        let cf = CuckooFilter::with_hasher(1000, RuntimeHasher::new(HashAlgorithm::SeaHash, 0));
+        let mut bytes = cf.to_bytes_with_hasher();
+        let last = bytes.len() - 9;
+        bytes[last] = 200;
+        assert_eq!(
+            CuckooFilter::from_bytes_with_hasher(&bytes).err(),
+            Some(FormatError::Invalid {
+                field: "hash_algorithm",
+                value: 200
+            })
+        );
+        bytes[FLAGS_OFFSET] &= !crate::format::FLAG_HASH_ALGORITHM;
+        assert!(CuckooFilter::from_bytes_with_hasher(&bytes).is_err());
+    }
+
+    #[cfg(any(not(feature = "fast"), feature = "portable-strict"))]
+    #[test]
+    fn test_unseeded_seahash_is_the_reference_hash() {
+        use crate::util::hash_key;
+        use std::hash::{BuildHasher, Hasher};
+
+        let mut hasher = RuntimeHasher::new(HashAlgorithm::SeaHash, 0).build_hasher();
+        hasher.write(b"test");
+        assert_eq!(hasher.finish(), hash_key(b"test"));
+    }
+
+    #[cfg(feature = "siphash")]
+    #[test]
+    fn test_keyed_hashing() {
+        use std::hash::{BuildHasher, Hasher};
+
+        let key = [7; 16];
+        let mut other = key;
+        other[15] = 8;
+        let hash = |hasher: RuntimeHasher| {
+            let mut state = hasher.build_hasher();
+            state.write(b"test");
+            state.finish()
+        };
+        assert_ne!(
+            hash(RuntimeHasher::keyed(key)),
+            hash(RuntimeHasher::keyed(other))
+        );
+        assert!(!format!("{:?}", RuntimeHasher::keyed(key)).contains("7"));
+
+        let mut cf = CuckooFilter::with_key(1000, key);
+        for i in 0..900u32 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let bytes = cf.to_bytes_with_hasher();
+        assert!(!bytes.windows(8).any(|w| w == [7; 8]));
+        let restored = CuckooFilter::from_bytes_with_key(&bytes, key).unwrap();
+        assert!(restored.hasher().is_keyed());
+        assert!((0..900u32).all(|i| restored.contains(&i.to_le_bytes())));
+        assert!(matches!(
+            CuckooFilter::from_bytes_with_key(&bytes, other),
+            Err(FormatError::Mismatch {
+                field: "hash_key",
+                ..
+            })
+        ));
+        assert!(CuckooFilter::from_bytes_with_hasher(&bytes).is_err());
+
+        let hasher = RuntimeHasher::new(HashAlgorithm::SipHash13, 1);
+        let unkeyed = CuckooFilter::with_hasher(1000, hasher);
+        assert!(matches!(
+            CuckooFilter::from_bytes_with_key(&unkeyed.to_bytes_with_hasher(), key),
+            Err(FormatError::Mismatch {
+                field: "hash_algorithm",
+                ..
+            })
+        ));
+    }
+}
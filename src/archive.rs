@@ -0,0 +1,186 @@
+use crate::bucket::{Bucket, BUCKET_SIZE};
+use crate::format::FormatError;
+use crate::util::get_indices_and_fingerprint;
+use crate::CuckooFilter;
+
+/// The archived form of a filter, written by `CuckooFilter::to_archive`.
+///
+/// Like the `serde` representation it leaves out the eviction RNG.
+#[derive(rkyv::Archive, rkyv::Serialize)]
+pub(crate) struct FilterImage {
+    pub(crate) pow: u8,
+    pub(crate) size: u64,
+    pub(crate) max_duplicates: Option<u64>,
+    pub(crate) displaced: bool,
+    pub(crate) buckets: Vec<[u8; BUCKET_SIZE]>,
+}
+
+/// A read-only filter answering queries straight from archived bytes.
+///
+/// Opening one validates the archive structure and the parameters but never
+/// copies the bucket array or counts its entries. The bytes are typically a memory-mapped file written from
+/// `CuckooFilter::to_archive`, and must be aligned as rkyv requires.
+#[derive(Clone, Copy)]
+pub struct ArchivedCuckooFilter<'a> {
+    image: &'a ArchivedFilterImage,
+}
+
+impl<'a> ArchivedCuckooFilter<'a> {
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ArchivedCuckooFilter, CuckooFilter};
+    ///
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let bytes = cf.to_archive().unwrap();
+    /// let archived = ArchivedCuckooFilter::access(&bytes).unwrap();
+    /// assert!(archived.contains(b"test"));
+    /// assert!(ArchivedCuckooFilter::access(b"nope").is_err());
+    /// ```
+    pub fn access(bytes: &'a [u8]) -> Result<Self, FormatError> {
+        let image =
+            rkyv::access::<ArchivedFilterImage, rkyv::rancor::Error>(bytes).map_err(|_| {
+                FormatError::Invalid {
+                    field: "archive",
+                    value: bytes.len() as u64,
+                }
+            })?;
+        if image.pow >= 64 {
+            return Err(FormatError::Invalid {
+                field: "pow",
+                value: u64::from(image.pow),
+            });
+        }
+        let reachable = 1u64 << image.pow;
+        if (image.buckets.len() as u64) < reachable {
+            return Err(FormatError::Mismatch {
+                field: "num_buckets",
+                expected: reachable,
+                found: image.buckets.len() as u64,
+            });
+        }
+        Ok(ArchivedCuckooFilter { image })
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ArchivedCuckooFilter, CuckooFilter};
+    ///
+    /// let bytes = CuckooFilter::tiny().to_archive().unwrap();
+    /// let archived = ArchivedCuckooFilter::access(&bytes).unwrap();
+    /// assert!(!archived.contains(b"test"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let finger = get_indices_and_fingerprint(item, usize::from(self.image.pow));
+        self.bucket_contains(finger.i1, finger.fp)
+            || (self.image.displaced && self.bucket_contains(finger.i2, finger.fp))
+    }
+
+    /// Returns the number of stored items, as recorded in the archive.
+    pub fn size(&self) -> usize {
+        self.image.size.to_native() as usize
+    }
+
+    /// Returns the total number of fingerprint slots.
+    pub fn capacity(&self) -> usize {
+        self.image.buckets.len() * BUCKET_SIZE
+    }
+
+    /// Copies the archive into a mutable `CuckooFilter`.
+    ///
+    /// Unlike `access`, this reads every bucket and rejects an archive whose
+    /// recorded size does not match its occupied slots.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ArchivedCuckooFilter, CuckooFilter};
+    ///
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let bytes = cf.to_archive().unwrap();
+    /// let mut loaded = ArchivedCuckooFilter::access(&bytes).unwrap().to_filter().unwrap();
+    /// assert!(loaded.delete(b"test"));
+    /// ```
+    pub fn to_filter(&self) -> Result<CuckooFilter, FormatError> {
+        let buckets = self
+            .image
+            .buckets
+            .iter()
+            .map(|data| Bucket::from_data(*data))
+            .collect::<Vec<_>>();
+        let occupied = buckets.iter().map(Bucket::len).sum::<usize>() as u64;
+        let size = self.image.size.to_native();
+        if occupied != size {
+            return Err(FormatError::Mismatch {
+                field: "size",
+                expected: size,
+                found: occupied,
+            });
+        }
+        let max_duplicates = self
+            .image
+            .max_duplicates
+            .as_ref()
+            .map(|max| max.to_native() as usize);
+        Ok(CuckooFilter::from_image_parts(
+            buckets,
+            occupied as usize,
+            usize::from(self.image.pow),
+            max_duplicates,
+            self.image.displaced,
+        ))
+    }
+
+    fn bucket_contains(&self, i: u64, fp: u8) -> bool {
+        self.image
+            .buckets
+            .get(i as usize)
+            .is_some_and(|slots| slots.contains(&fp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::FormatError;
+    use crate::{ArchivedCuckooFilter, CuckooError, CuckooFilter};
+
+    #[test]
+    fn test_archive_round_trip() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(64, 3);
+        for i in 0..200u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        cf.set_max_duplicates(Some(3));
+        let bytes = cf.to_archive().unwrap();
+        let archived = ArchivedCuckooFilter::access(&bytes).unwrap();
+        assert_eq!(archived.size(), 200);
+        assert_eq!(archived.capacity(), cf.capacity());
+        assert!((0..200u16).all(|i| archived.contains(&i.to_le_bytes())));
+
+        let mut back = archived.to_filter().unwrap();
+        assert_eq!(
+            back.fingerprints().collect::<Vec<_>>(),
+            cf.fingerprints().collect::<Vec<_>>()
+        );
+        assert!(back.check_invariants().is_ok());
+        back.add(b"dup").unwrap();
+        back.add(b"dup").unwrap();
+        back.add(b"dup").unwrap();
+        assert!(matches!(
+            back.add(b"dup"),
+            Err(CuckooError::TooManyDuplicates(_))
+        ));
+    }
+
+    #[test]
+    fn test_archive_rejects_garbage() {
+        assert!(ArchivedCuckooFilter::access(&[]).is_err());
+        assert!(matches!(
+            ArchivedCuckooFilter::access(&[0xff; 64]),
+            Err(FormatError::Invalid {
+                field: "archive",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,89 @@
+//! Standard base64 (RFC 4648) with padding, for embedding filters in text.
+use crate::format::FormatError;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// Chunks hold at most three bytes, and alphabet indices are masked to six
+// bits.
+#[allow(clippy::indexing_slicing)]
+pub fn encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let mut group = [0; 3];
+        group[..chunk.len()].copy_from_slice(chunk);
+        let n = u32::from(group[0]) << 16 | u32::from(group[1]) << 8 | u32::from(group[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(char::from(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize]));
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Decodes `text`, ignoring ASCII whitespace so wrapped lines paste as is.
+pub fn decode(text: &str) -> Result<Vec<u8>, FormatError> {
+    let invalid = |offset: usize| FormatError::Invalid {
+        field: "base64",
+        value: offset as u64,
+    };
+    let mut out = Vec::with_capacity(text.len() / 4 * 3);
+    let (mut n, mut bits, mut padding) = (0u32, 0, 0);
+    for (offset, c) in text.bytes().enumerate() {
+        if c.is_ascii_whitespace() {
+            continue;
+        }
+        if c == b'=' {
+            padding += 1;
+            continue;
+        }
+        // Data after padding.
+        if padding > 0 {
+            return Err(invalid(offset));
+        }
+        let value = ALPHABET
+            .iter()
+            .position(|&a| a == c)
+            .ok_or_else(|| invalid(offset))?;
+        n = n << 6 | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((n >> bits) as u8);
+        }
+    }
+    // A complete input pads up to a multiple of four characters and leaves
+    // only zero bits over.
+    let chars = out.len() * 8 / 6 + usize::from(bits > 0);
+    if bits >= 6 || n & ((1 << bits) - 1) != 0 || (chars + padding) % 4 != 0 || padding > 2 {
+        return Err(invalid(text.len()));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::base64::{decode, encode};
+
+    #[test]
+    fn test_base64_round_trip() {
+        for (raw, text) in [
+            (&b""[..], ""),
+            (&b"f"[..], "Zg=="),
+            (&b"fo"[..], "Zm8="),
+            (&b"foo"[..], "Zm9v"),
+            (&b"foob"[..], "Zm9vYg=="),
+            (&[0xfb, 0xff][..], "+/8="),
+        ] {
+            assert_eq!(encode(raw), text);
+            assert_eq!(decode(text).unwrap(), raw);
+        }
+        assert_eq!(decode("Zm9v\n  Yg==").unwrap(), b"foob");
+        assert!(decode("Zm9").is_err());
+        assert!(decode("Zh==").is_err());
+        assert!(decode("Zg==Zg==").is_err());
+        assert!(decode("Z!==").is_err());
+    }
+}
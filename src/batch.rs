@@ -0,0 +1,212 @@
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+use crate::redact::display_key;
+use crate::{CResult, CuckooError, CuckooFilter};
+use std::fmt;
+
+const BATCH_MAGIC: &[u8; 4] = b"DKFB";
+const OP_ADD: u8 = 1;
+#[cfg(not(feature = "no-delete"))]
+const OP_DELETE: u8 = 2;
+
+/// The part of a key-value store's write batch the filter needs.
+///
+/// LevelDB-style stores call this `PutLogData`: the blob is written to the
+/// write-ahead log together with the rest of the batch, but never reaches the
+/// memtable. On recovery, every such blob is handed to `FilterBatch::decode`
+/// and re-applied, so the filter always matches the keys the store committed.
+pub trait WriteBatch {
+    fn put_log_data(&mut self, blob: &[u8]);
+}
+
+/// A filter mutation shadowing a key-value mutation.
+///
+/// `Debug` prints keys through the installed `KeyRedactor`.
+#[derive(Clone, PartialEq, Eq)]
+pub enum FilterOp {
+    Add(Vec<u8>),
+    #[cfg(not(feature = "no-delete"))]
+    Delete(Vec<u8>),
+}
+
+impl fmt::Debug for FilterOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterOp::Add(key) => f.debug_tuple("Add").field(&display_key(key)).finish(),
+            #[cfg(not(feature = "no-delete"))]
+            FilterOp::Delete(key) => f.debug_tuple("Delete").field(&display_key(key)).finish(),
+        }
+    }
+}
+
+/// Filter mutations collected alongside a `WriteBatch`.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{CuckooFilter, FilterBatch, WriteBatch};
+///
+/// #[derive(Default)]
+/// struct KvBatch {
+///     log_data: Vec<Vec<u8>>,
+/// }
+///
+/// impl WriteBatch for KvBatch {
+///     fn put_log_data(&mut self, blob: &[u8]) {
+///         self.log_data.push(blob.to_vec());
+///     }
+/// }
+///
+/// let mut cf = CuckooFilter::new(100);
+/// let mut kv = KvBatch::default();
+/// let mut batch = FilterBatch::new();
+/// batch.add(b"user:1");
+/// batch.write_to(&mut kv);
+/// // ... commit `kv` to the store, then:
+/// batch.apply(&mut cf).unwrap();
+/// assert!(cf.contains(b"user:1"));
+///
+/// // During recovery, replay what the log holds.
+/// let mut recovered = CuckooFilter::new(100);
+/// for blob in &kv.log_data {
+///     FilterBatch::decode(blob).unwrap().apply(&mut recovered).unwrap();
+/// }
+/// assert!(recovered.contains(b"user:1"));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterBatch {
+    ops: Vec<FilterOp>,
+}
+
+impl FilterBatch {
+    pub fn new() -> Self {
+        FilterBatch::default()
+    }
+
+    pub fn add(&mut self, item: &[u8]) {
+        self.ops.push(FilterOp::Add(item.to_vec()));
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) {
+        self.ops.push(FilterOp::Delete(item.to_vec()));
+    }
+
+    pub fn ops(&self) -> &[FilterOp] {
+        &self.ops
+    }
+
+    /// Enqueues the encoded batch into `batch`.
+    pub fn write_to<W: WriteBatch>(&self, batch: &mut W) {
+        batch.put_log_data(&self.encode());
+    }
+
+    /// Applies every mutation to `filter`, or none of them if an add fails.
+    pub fn apply(&self, filter: &mut CuckooFilter) -> CResult<CuckooError> {
+        filter.apply_ops(&self.ops)
+    }
+
+    /// Encodes the batch as `"DKFB"`, a u32 LE op count, and per op a kind
+    /// byte, a u32 LE key length and the key.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = BATCH_MAGIC.to_vec();
+        out.extend_from_slice(&(self.ops.len() as u32).to_le_bytes());
+        for op in &self.ops {
+            let (kind, item) = match op {
+                FilterOp::Add(item) => (OP_ADD, item),
+                #[cfg(not(feature = "no-delete"))]
+                FilterOp::Delete(item) => (OP_DELETE, item),
+            };
+            out.push(kind);
+            out.extend_from_slice(&(item.len() as u32).to_le_bytes());
+            out.extend_from_slice(item);
+        }
+        out
+    }
+
+    /// Parses a blob produced by `encode`, returning `None` if it is not one.
+    pub fn decode(blob: &[u8]) -> Option<Self> {
+        let rest = blob.strip_prefix(&BATCH_MAGIC[..])?;
+        let (count, mut rest) = read_u32(rest)?;
+        let mut ops = Vec::new();
+        for _ in 0..count {
+            let (&kind, tail) = rest.split_first()?;
+            let (len, tail) = read_u32(tail)?;
+            if tail.len() < len as usize {
+                return None;
+            }
+            let (item, tail) = tail.split_at(len as usize);
+            ops.push(match kind {
+                OP_ADD => FilterOp::Add(item.to_vec()),
+                #[cfg(not(feature = "no-delete"))]
+                OP_DELETE => FilterOp::Delete(item.to_vec()),
+                _ => return None,
+            });
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+        Some(FilterBatch { ops })
+    }
+}
+
+fn read_u32(data: &[u8]) -> Option<(u32, &[u8])> {
+    if data.len() < 4 {
+        return None;
+    }
+    let (head, tail) = data.split_at(4);
+    let mut word = [0; 4];
+    word.copy_from_slice(head);
+    Some((u32::from_le_bytes(word), tail))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, FilterBatch};
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let mut batch = FilterBatch::new();
+        batch.add(b"");
+        batch.add(b"abc");
+        #[cfg(not(feature = "no-delete"))]
+        batch.delete(b"abc");
+        let blob = batch.encode();
+        assert_eq!(FilterBatch::decode(&blob), Some(batch));
+        assert_eq!(FilterBatch::decode(&blob[..blob.len() - 1]), None);
+        assert_eq!(FilterBatch::decode(b"DKFB"), None);
+    }
+
+    #[test]
+    fn test_corrupted_blob_never_panics() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut batch = FilterBatch::new();
+        for i in 0..8u8 {
+            batch.add(&[i; 5]);
+        }
+        let blob = batch.encode();
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..5000 {
+            let mut input = blob.clone();
+            let at = rng.gen_range(0, input.len());
+            input[at] = rng.gen();
+            input.truncate(rng.gen_range(0, input.len() + 1));
+            FilterBatch::decode(&input);
+        }
+    }
+
+    #[test]
+    fn test_apply_is_atomic() {
+        let mut cf = CuckooFilter::new(1);
+        cf.add(b"kept").unwrap();
+        let before = cf.fingerprints().collect::<Vec<_>>();
+        let mut batch = FilterBatch::new();
+        for i in 0..20u8 {
+            batch.add(&[i]);
+        }
+        assert!(batch.apply(&mut cf).is_err());
+        assert_eq!(cf.fingerprints().collect::<Vec<_>>(), before);
+        assert_eq!(cf.size(), 1);
+    }
+}
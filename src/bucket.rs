@@ -1,53 +1,189 @@
+// Slot indices come from `0..BUCKET_SIZE` or from the occupancy bits, so
+// indexing the fixed-size slot array cannot go out of bounds.
+#![allow(clippy::indexing_slicing)]
+
 use std::ops;
 
 pub const BUCKET_SIZE: usize = 4;
 
+// Occupancy bits of a bucket whose slots are all taken.
+const FULL: u8 = (1 << BUCKET_SIZE) - 1;
+
+/// A fixed group of fingerprint slots.
+///
+/// Which slots are taken is tracked in a bitmap, so finding a free slot is a
+/// bit operation rather than a scan. Empty slots still read as `0`, which is
+/// how the serialized layout marks them.
 #[derive(Default, Copy, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "BucketData", into = "BucketData")
+)]
 pub struct Bucket {
     data: [u8; BUCKET_SIZE],
+    // Bit `i` is set if slot `i` holds a fingerprint.
+    occupied: u8,
+    // Bumped on every modification, see `CuckooFilter::read_stamp`.
+    #[cfg(feature = "bucket-versions")]
+    version: u32,
+}
+
+// What a serialized bucket holds; the bitmap is derived from it.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BucketData {
+    data: [u8; BUCKET_SIZE],
+}
+
+#[cfg(feature = "serde")]
+impl From<BucketData> for Bucket {
+    fn from(b: BucketData) -> Self {
+        Bucket::from_data(b.data)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<Bucket> for BucketData {
+    fn from(b: Bucket) -> Self {
+        BucketData { data: b.data }
+    }
 }
 
 impl Bucket {
     pub fn new() -> Self {
         Bucket {
             data: [0; BUCKET_SIZE],
+            occupied: 0,
+            #[cfg(feature = "bucket-versions")]
+            version: 0,
+        }
+    }
+
+    /// Builds a bucket from raw slots, treating `0` as empty.
+    pub fn from_data(data: [u8; BUCKET_SIZE]) -> Self {
+        let occupied = data
+            .iter()
+            .enumerate()
+            .filter(|(_, &fp)| fp != 0)
+            .fold(0, |bits, (i, _)| bits | 1 << i);
+        Bucket {
+            data,
+            occupied,
+            #[cfg(feature = "bucket-versions")]
+            version: 0,
+        }
+    }
+
+    pub fn data(&self) -> [u8; BUCKET_SIZE] {
+        self.data
+    }
+
+    #[cfg(feature = "bucket-versions")]
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn touch(&mut self) {
+        #[cfg(feature = "bucket-versions")]
+        {
+            self.version = self.version.wrapping_add(1);
         }
     }
 
     pub fn insert(&mut self, finger: u8) -> bool {
-        for fp in self.data.iter_mut() {
-            if *fp == 0 {
-                *fp = finger;
-                return true;
-            }
+        let free = !self.occupied & FULL;
+        if free == 0 {
+            return false;
         }
-        false
+        let slot = free.trailing_zeros() as usize;
+        self.data[slot] = finger;
+        self.occupied |= 1 << slot;
+        self.touch();
+        true
     }
 
+    #[cfg(not(feature = "no-delete"))]
     pub fn delete(&mut self, finger: u8) -> bool {
-        for fp in self.data.iter_mut() {
-            if *fp == finger {
-                *fp = 0;
-                return true;
+        match self.get_fingerprint_index(finger) {
+            Some(slot) => {
+                self.take(slot);
+                true
             }
+            None => false,
         }
-        false
     }
 
     pub fn get_fingerprint_index(self, finger: u8) -> Option<usize> {
-        for (i, fp) in self.data.iter().enumerate() {
-            if *fp == finger {
-                return Some(i);
-            }
+        (0..BUCKET_SIZE).find(|&i| self.is_occupied(i) && self.data[i] == finger)
+    }
+
+    /// Like `get_fingerprint_index` for a bucket kept in `sort` order, which
+    /// stops at the first larger fingerprint.
+    pub fn get_fingerprint_index_sorted(self, finger: u8) -> Option<usize> {
+        self.data[..self.len()]
+            .iter()
+            .take_while(|&&fp| fp <= finger)
+            .position(|&fp| fp == finger)
+    }
+
+    pub fn is_occupied(&self, slot: usize) -> bool {
+        self.occupied & 1 << slot != 0
+    }
+
+    #[cfg(any(test, feature = "segmented"))]
+    pub fn is_full(&self) -> bool {
+        self.occupied == FULL
+    }
+
+    pub fn len(&self) -> usize {
+        self.occupied.count_ones() as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.occupied == 0
+    }
+
+    /// Puts `finger` into the taken `slot`, returning the fingerprint it
+    /// displaced.
+    pub fn replace(&mut self, slot: usize, finger: u8) -> u8 {
+        debug_assert!(self.is_occupied(slot));
+        self.touch();
+        std::mem::replace(&mut self.data[slot], finger)
+    }
+
+    /// Empties `slot`, returning what it held.
+    #[cfg(any(test, not(feature = "no-delete")))]
+    pub fn take(&mut self, slot: usize) -> u8 {
+        self.occupied &= !(1 << slot);
+        self.touch();
+        std::mem::replace(&mut self.data[slot], 0)
+    }
+
+    /// Iterates over the stored fingerprints only.
+    pub fn fingerprints(&self) -> impl Iterator<Item = u8> + '_ {
+        (0..BUCKET_SIZE)
+            .filter(move |&i| self.is_occupied(i))
+            .map(move |i| self.data[i])
+    }
+
+    /// Moves the stored fingerprints to the leading slots, in ascending order.
+    pub fn sort(&mut self) {
+        let len = self.len();
+        let mut sorted = [0; BUCKET_SIZE];
+        for (slot, fp) in sorted.iter_mut().zip(self.fingerprints()) {
+            *slot = fp;
         }
-        None
+        sorted[..len].sort_unstable();
+        self.data = sorted;
+        self.occupied = FULL >> (BUCKET_SIZE - len);
     }
 
     #[allow(dead_code)]
     pub fn reset(&mut self) {
-        for fp in self.data.iter_mut() {
-            *fp = 0;
-        }
+        self.data = [0; BUCKET_SIZE];
+        self.occupied = 0;
+        self.touch();
     }
 }
 
@@ -59,8 +195,35 @@ impl ops::Index<usize> for Bucket {
     }
 }
 
-impl ops::IndexMut<usize> for Bucket {
-    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
-        &mut self.data[index]
+#[cfg(test)]
+mod tests {
+    use crate::bucket::Bucket;
+
+    #[test]
+    fn test_occupancy() {
+        let mut b = Bucket::from_data([7, 0, 9, 0]);
+        assert_eq!(b.len(), 2);
+        assert!(b.is_occupied(0) && !b.is_occupied(1));
+        assert!(b.insert(3));
+        assert_eq!(b.data(), [7, 3, 9, 0]);
+        assert!(b.insert(4));
+        assert!(b.is_full());
+        assert!(!b.insert(5));
+        assert_eq!(b.take(1), 3);
+        assert_eq!(b.fingerprints().collect::<Vec<_>>(), [7, 9, 4]);
+        assert_eq!(b.get_fingerprint_index(0), None);
+    }
+
+    #[test]
+    fn test_sort() {
+        let mut b = Bucket::from_data([0, 9, 0, 4]);
+        b.sort();
+        assert_eq!(b.data(), [4, 9, 0, 0]);
+        assert_eq!(b.len(), 2);
+        assert_eq!(b.get_fingerprint_index_sorted(9), Some(1));
+        assert_eq!(b.get_fingerprint_index_sorted(5), None);
+        assert!(b.insert(7));
+        b.sort();
+        assert_eq!(b.data(), [4, 7, 9, 0]);
     }
 }
@@ -0,0 +1,344 @@
+use crate::bucket::BUCKET_SIZE;
+use crate::format::FINGERPRINT_BITS;
+use crate::{AnyCuckooFilter, ConfigError, CuckooConfig, CuckooFilter, EvictionPolicy};
+
+/// The function keys are hashed with, fixed when the crate is compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashProfile {
+    /// SeaHash, bit-exact on every platform and release.
+    Reference,
+    /// The wyhash-style mixer of the `fast` feature.
+    Fast,
+}
+
+impl HashProfile {
+    /// Returns the profile this build hashes keys with.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::HashProfile;
+    /// # #[cfg(not(feature = "fast"))]
+    /// assert_eq!(HashProfile::active(), HashProfile::Reference);
+    /// ```
+    pub const fn active() -> Self {
+        if cfg!(all(feature = "fast", not(feature = "portable-strict"))) {
+            HashProfile::Fast
+        } else {
+            HashProfile::Reference
+        }
+    }
+
+    /// Returns the id serialized filters record the profile under.
+    pub const fn id(self) -> u8 {
+        match self {
+            HashProfile::Reference => 0,
+            HashProfile::Fast => 1,
+        }
+    }
+}
+
+/// Builds a `CuckooFilter` from named settings, checking them all at once.
+///
+/// The bucket size, fingerprint width and hash profile of a `CuckooFilter`
+/// are fixed when the crate is compiled; they can be set so a deployment
+/// states what it relies on, and `build` rejects other values instead of
+/// silently producing a filter with different properties. `build_any` also
+/// builds buckets of 2 or 8 slots and 16- or 32-bit fingerprints, as a
+/// `WideCuckooFilter`.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::CuckooFilterBuilder;
+///
+/// let cf = CuckooFilterBuilder::new()
+///     .capacity(100_000)
+///     .bucket_size(4)
+///     .fingerprint_bits(8)
+///     .seed(42)
+///     .max_kicks(100)
+///     .build()
+///     .unwrap();
+/// assert_eq!(cf.max_kicks(), 100);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CuckooFilterBuilder {
+    config: CuckooConfig,
+    bucket_size: usize,
+    fingerprint_bits: u32,
+    hasher: HashProfile,
+}
+
+impl CuckooFilterBuilder {
+    /// Starts from the settings of `CuckooFilter::default`.
+    pub fn new() -> Self {
+        Self::from_config(CuckooConfig::default())
+    }
+
+    /// Starts from the settings of `config`.
+    pub fn from_config(config: CuckooConfig) -> Self {
+        CuckooFilterBuilder {
+            config,
+            bucket_size: BUCKET_SIZE,
+            fingerprint_bits: u32::from(FINGERPRINT_BITS),
+            hasher: HashProfile::active(),
+        }
+    }
+
+    /// Sets the number of keys the filter is sized for.
+    pub fn capacity(mut self, max_num_keys: u64) -> Self {
+        self.config.max_num_keys = max_num_keys;
+        self
+    }
+
+    /// Sets the number of slots per bucket: 4 for `build`, or 2, 4 or 8 for
+    /// `build_any`.
+    pub fn bucket_size(mut self, bucket_size: usize) -> Self {
+        self.bucket_size = bucket_size;
+        self
+    }
+
+    /// Sets the width of one fingerprint: 8 bits for `build`, or 8, 12, 16
+    /// or 32 for `build_any`.
+    pub fn fingerprint_bits(mut self, bits: u32) -> Self {
+        self.fingerprint_bits = bits;
+        self
+    }
+
+    /// Sets the hash profile; only `HashProfile::active` can be built.
+    pub fn hasher(mut self, hasher: HashProfile) -> Self {
+        self.hasher = hasher;
+        self
+    }
+
+    /// Seeds the eviction RNG, so the same operations produce the same
+    /// layout.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config = self.config.seed(seed);
+        self
+    }
+
+    /// See `CuckooConfig::max_kicks`.
+    pub fn max_kicks(mut self, max_kicks: usize) -> Self {
+        self.config = self.config.max_kicks(max_kicks);
+        self
+    }
+
+    /// See `EvictionPolicy`.
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.config = self.config.eviction_policy(policy);
+        self
+    }
+
+    /// See `CuckooFilter::set_max_duplicates`.
+    pub fn max_duplicates(mut self, max_duplicates: usize) -> Self {
+        self.config = self.config.max_duplicates(max_duplicates);
+        self
+    }
+
+    /// See `CuckooConfig::sorted_buckets`.
+    pub fn sorted_buckets(mut self, sorted: bool) -> Self {
+        self.config = self.config.sorted_buckets(sorted);
+        self
+    }
+
+    /// Returns the settings a filter would be built from.
+    pub fn config(&self) -> &CuckooConfig {
+        &self.config
+    }
+
+    /// Checks every setting and builds the filter.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ConfigError, CuckooFilterBuilder};
+    /// assert!(matches!(
+    ///     CuckooFilterBuilder::new().fingerprint_bits(16).build(),
+    ///     Err(ConfigError::Invalid { field: "fingerprint_bits", .. })
+    /// ));
+    /// ```
+    pub fn build(&self) -> Result<CuckooFilter, ConfigError> {
+        if self.bucket_size != BUCKET_SIZE {
+            return Err(ConfigError::Invalid {
+                field: "bucket_size",
+                reason: "a CuckooFilter has 4 slots per bucket; see build_any",
+            });
+        }
+        if self.fingerprint_bits != u32::from(FINGERPRINT_BITS) {
+            return Err(ConfigError::Invalid {
+                field: "fingerprint_bits",
+                reason: "a CuckooFilter stores 8-bit fingerprints; see build_any",
+            });
+        }
+        self.check_hasher()?;
+        self.config.validate()?;
+        Ok(CuckooFilter::from_config(&self.config))
+    }
+
+    /// Checks every setting and builds a filter of any bucket size and
+    /// fingerprint width `WideCuckooFilter` supports. The built-in layout
+    /// gives the same filter as `build`; the others keep the seed and kick
+    /// limit, but have no duplicate limit, sorted buckets or eviction policy
+    /// to set.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ConfigError, CuckooFilterBuilder};
+    ///
+    /// let builder = CuckooFilterBuilder::new().capacity(1000).bucket_size(2);
+    /// let cf = builder.clone().fingerprint_bits(16).build_any().unwrap();
+    /// assert_eq!((cf.bucket_size(), cf.fingerprint_bits()), (2, 16));
+    /// assert!(matches!(
+    ///     builder.max_duplicates(1).build_any(),
+    ///     Err(ConfigError::Invalid { field: "max_duplicates", .. })
+    /// ));
+    /// ```
+    pub fn build_any(&self) -> Result<AnyCuckooFilter, ConfigError> {
+        if self.bucket_size == BUCKET_SIZE && self.fingerprint_bits == u32::from(FINGERPRINT_BITS)
+        {
+            return self.build().map(AnyCuckooFilter::from);
+        }
+        self.check_hasher()?;
+        self.config.validate()?;
+        let unsupported = |field| ConfigError::Invalid {
+            field,
+            reason: "not supported by WideCuckooFilter",
+        };
+        if self.config.max_duplicates.is_some() {
+            return Err(unsupported("max_duplicates"));
+        }
+        if self.config.sorted_buckets {
+            return Err(unsupported("sorted_buckets"));
+        }
+        if self.config.eviction_policy != EvictionPolicy::Random {
+            return Err(unsupported("eviction_policy"));
+        }
+        AnyCuckooFilter::wide(&self.config, self.bucket_size, self.fingerprint_bits).ok_or(
+            if [2, 4, 8].contains(&self.bucket_size) {
+                ConfigError::Invalid {
+                    field: "fingerprint_bits",
+                    reason: "must be 8, 12, 16 or 32",
+                }
+            } else {
+                ConfigError::Invalid {
+                    field: "bucket_size",
+                    reason: "must be 2, 4 or 8",
+                }
+            },
+        )
+    }
+
+    fn check_hasher(&self) -> Result<(), ConfigError> {
+        if self.hasher != HashProfile::active() {
+            return Err(ConfigError::Invalid {
+                field: "hasher",
+                reason: "the hash profile is chosen by the `fast` and `portable-strict` features",
+            });
+        }
+        Ok(())
+    }
+}
+
+impl Default for CuckooFilterBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ConfigError, CuckooFilterBuilder, HashProfile};
+
+    #[test]
+    fn test_builder() {
+        let mut cf = CuckooFilterBuilder::new()
+            .capacity(1000)
+            .seed(7)
+            .max_kicks(0)
+            .max_duplicates(1)
+            .build()
+            .unwrap();
+        assert_eq!((cf.capacity(), cf.max_kicks()), (2048, 0));
+        cf.add(b"test").unwrap();
+        assert!(cf.add(b"test").is_err());
+
+        let builder = CuckooFilterBuilder::new();
+        assert!(matches!(
+            builder.clone().capacity(0).build(),
+            Err(ConfigError::Invalid {
+                field: "max_num_keys",
+                ..
+            })
+        ));
+        assert!(matches!(
+            builder.clone().bucket_size(8).build(),
+            Err(ConfigError::Invalid {
+                field: "bucket_size",
+                ..
+            })
+        ));
+        // Other bucket sizes are routed to a WideCuckooFilter.
+        for &bucket_size in &[2, 4, 8] {
+            let mut cf = builder
+                .clone()
+                .bucket_size(bucket_size)
+                .build_any()
+                .unwrap();
+            assert_eq!(cf.bucket_size(), bucket_size);
+            assert_eq!(cf.as_standard().is_some(), bucket_size == 4);
+            cf.add(b"test").unwrap();
+            assert!(cf.contains(b"test"));
+        }
+        let cf = builder
+            .clone()
+            .bucket_size(2)
+            .fingerprint_bits(12)
+            .build_any()
+            .unwrap();
+        assert_eq!((cf.bucket_size(), cf.fingerprint_bits()), (2, 12));
+        assert!(matches!(
+            builder.clone().bucket_size(3).build_any(),
+            Err(ConfigError::Invalid {
+                field: "bucket_size",
+                ..
+            })
+        ));
+        assert!(matches!(
+            builder
+                .clone()
+                .bucket_size(2)
+                .fingerprint_bits(24)
+                .build_any(),
+            Err(ConfigError::Invalid {
+                field: "fingerprint_bits",
+                ..
+            })
+        ));
+        let other = match HashProfile::active() {
+            HashProfile::Reference => HashProfile::Fast,
+            HashProfile::Fast => HashProfile::Reference,
+        };
+        assert!(matches!(
+            builder.hasher(other).build(),
+            Err(ConfigError::Invalid {
+                field: "hasher",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_max_kicks_bounds_insertion() {
+        // Without kicks, the first key whose two buckets are full is rejected.
+        let fill = |max_kicks| {
+            let builder = CuckooFilterBuilder::new().capacity(1000).seed(1);
+            let mut cf = builder.max_kicks(max_kicks).build().unwrap();
+            let mut n = 0u32;
+            while cf.add(&n.to_le_bytes()).is_ok() {
+                n += 1;
+            }
+            assert!((0..n).all(|key| cf.contains(&key.to_le_bytes())));
+            n
+        };
+        assert!(fill(0) < fill(500));
+    }
+}
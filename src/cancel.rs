@@ -0,0 +1,106 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A flag shared between a long-running operation and whoever may want to
+/// abandon it, such as a shutdown handler.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::CancelToken;
+/// let token = CancelToken::new();
+/// let handle = token.clone();
+/// handle.cancel();
+/// assert!(token.is_cancelled());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks every operation watching this token, or a clone of it, to stop.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// When a heavy operation should give up: at a deadline, once a
+/// `CancelToken` is cancelled, or whichever comes first.
+///
+/// Operations taking one check it between units of work, roughly every
+/// `YIELD_EVERY` entries, and stop with `CuckooError::Cancelled`. The
+/// default never stops.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{CancelToken, Cancellation};
+/// use std::time::Duration;
+///
+/// let token = CancelToken::new();
+/// let cancel = Cancellation::new().timeout(Duration::from_secs(30)).token(token.clone());
+/// assert!(!cancel.is_stopped());
+/// token.cancel();
+/// assert!(cancel.is_stopped());
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation {
+    deadline: Option<Instant>,
+    token: Option<CancelToken>,
+}
+
+impl Cancellation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the operation at `deadline`.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Stops the operation `timeout` from now.
+    pub fn timeout(self, timeout: Duration) -> Self {
+        self.deadline(Instant::now() + timeout)
+    }
+
+    /// Stops the operation once `token` is cancelled.
+    pub fn token(mut self, token: CancelToken) -> Self {
+        self.token = Some(token);
+        self
+    }
+
+    /// Returns `true` if the operation should stop now.
+    pub fn is_stopped(&self) -> bool {
+        self.token.as_ref().is_some_and(CancelToken::is_cancelled)
+            || self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CancelToken, Cancellation};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_cancellation() {
+        assert!(!Cancellation::new().is_stopped());
+        assert!(Cancellation::new().deadline(Instant::now()).is_stopped());
+        assert!(!Cancellation::new()
+            .timeout(Duration::from_secs(3600))
+            .is_stopped());
+
+        let token = CancelToken::new();
+        let cancel = Cancellation::new().token(token.clone());
+        assert!(!cancel.is_stopped());
+        token.clone().cancel();
+        assert!(cancel.is_stopped());
+    }
+}
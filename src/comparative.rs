@@ -0,0 +1,225 @@
+//! Side-by-side benchmarks against other filter crates.
+//!
+//! Every filter runs behind the same `Adapter` interface on the same keys,
+//! sized for the same number of items and, where configurable, a comparable
+//! false-positive rate, so the numbers measure the filters and not the
+//! harness.
+use crate::{CuckooError, CuckooFilter};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Write;
+use std::hint::black_box;
+use std::time::Instant;
+
+/// False-positive rate the Bloom filter is sized for; about what 8-bit
+/// fingerprints in two buckets of four slots give at full load.
+pub const BLOOM_FP_RATE: f64 = 0.03;
+
+/// The operations measured on every filter.
+pub trait Adapter {
+    fn name(&self) -> &'static str;
+    /// Returns `false` if the filter rejected the key.
+    fn insert(&mut self, key: &[u8]) -> bool;
+    fn contains(&self, key: &[u8]) -> bool;
+    fn memory_bytes(&self) -> usize;
+}
+
+pub struct DakvCuckoo(pub CuckooFilter);
+
+impl Adapter for DakvCuckoo {
+    fn name(&self) -> &'static str {
+        "dakv_cuckoo"
+    }
+
+    fn insert(&mut self, key: &[u8]) -> bool {
+        self.0.add(key).is_ok()
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.0.contains(key)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+pub struct CuckooFilterCrate(pub cuckoofilter::CuckooFilter<DefaultHasher>);
+
+impl Adapter for CuckooFilterCrate {
+    fn name(&self) -> &'static str {
+        "cuckoofilter"
+    }
+
+    fn insert(&mut self, key: &[u8]) -> bool {
+        self.0.add(key).is_ok()
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.0.contains(key)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        self.0.memory_usage()
+    }
+}
+
+pub struct BloomFilterCrate(pub bloomfilter::Bloom<[u8]>);
+
+impl Adapter for BloomFilterCrate {
+    fn name(&self) -> &'static str {
+        "bloomfilter"
+    }
+
+    fn insert(&mut self, key: &[u8]) -> bool {
+        self.0.set(key);
+        true
+    }
+
+    fn contains(&self, key: &[u8]) -> bool {
+        self.0.check(key)
+    }
+
+    fn memory_bytes(&self) -> usize {
+        (self.0.len() / 8) as usize
+    }
+}
+
+/// The keys every adapter is measured on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Workload {
+    pub num_keys: usize,
+    pub key_len: usize,
+    pub seed: u64,
+}
+
+/// Measurements for one adapter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub name: &'static str,
+    pub inserted: usize,
+    pub insert_ns_per_op: f64,
+    pub lookup_ns_per_op: f64,
+    /// Fraction of `num_keys` absent keys reported as present.
+    pub false_positive_rate: f64,
+    pub memory_bytes: usize,
+}
+
+/// Returns adapters for every supported filter, sized for `num_keys`.
+///
+/// A filter that cannot be allocated is `AllocationFailed`, and a Bloom
+/// filter that cannot be sized for `num_keys` is `NotSupported`.
+pub fn adapters(num_keys: usize) -> Result<Vec<Box<dyn Adapter>>, CuckooError> {
+    let bloom = bloomfilter::Bloom::new_for_fp_rate(num_keys.max(1), BLOOM_FP_RATE)
+        .map_err(|_| CuckooError::NotSupported)?;
+    Ok(vec![
+        Box::new(DakvCuckoo(CuckooFilter::try_new(num_keys as u64)?)),
+        Box::new(CuckooFilterCrate(
+            cuckoofilter::CuckooFilter::with_capacity(num_keys),
+        )),
+        Box::new(BloomFilterCrate(bloom)),
+    ])
+}
+
+/// Runs `workload` against every adapter from `adapters`.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::comparative::{run, to_json, Workload};
+///
+/// let results = run(&Workload { num_keys: 1000, key_len: 16, seed: 1 }).unwrap();
+/// assert_eq!(results.len(), 3);
+/// println!("{}", to_json(&results));
+/// ```
+pub fn run(workload: &Workload) -> Result<Vec<BenchResult>, CuckooError> {
+    let mut rng = StdRng::seed_from_u64(workload.seed);
+    let mut gen_keys = || {
+        (0..workload.num_keys)
+            .map(|_| {
+                (0..workload.key_len)
+                    .map(|_| rng.gen())
+                    .collect::<Vec<u8>>()
+            })
+            .collect::<Vec<_>>()
+    };
+    let present = gen_keys();
+    let absent = gen_keys();
+    Ok(adapters(workload.num_keys)?
+        .into_iter()
+        .map(|mut adapter| measure(adapter.as_mut(), &present, &absent))
+        .collect())
+}
+
+fn measure(adapter: &mut dyn Adapter, present: &[Vec<u8>], absent: &[Vec<u8>]) -> BenchResult {
+    let per_op =
+        |start: Instant, ops: usize| start.elapsed().as_nanos() as f64 / ops.max(1) as f64;
+
+    let start = Instant::now();
+    let inserted = present.iter().filter(|k| adapter.insert(k)).count();
+    let insert_ns_per_op = per_op(start, present.len());
+
+    let start = Instant::now();
+    for k in present {
+        black_box(adapter.contains(k));
+    }
+    let lookup_ns_per_op = per_op(start, present.len());
+
+    let false_positives = absent.iter().filter(|k| adapter.contains(k)).count();
+    BenchResult {
+        name: adapter.name(),
+        inserted,
+        insert_ns_per_op,
+        lookup_ns_per_op,
+        false_positive_rate: false_positives as f64 / absent.len().max(1) as f64,
+        memory_bytes: adapter.memory_bytes(),
+    }
+}
+
+/// Renders results as a JSON array with one object per adapter.
+pub fn to_json(results: &[BenchResult]) -> String {
+    let mut out = String::from("[");
+    for (i, r) in results.iter().enumerate() {
+        let sep = if i == 0 { "" } else { "," };
+        // Writing to a `String` cannot fail.
+        let _ = write!(
+            out,
+            "{}{{\"name\":\"{}\",\"inserted\":{},\"insert_ns_per_op\":{:.1},\
+             \"lookup_ns_per_op\":{:.1},\"false_positive_rate\":{},\"memory_bytes\":{}}}",
+            sep,
+            r.name,
+            r.inserted,
+            r.insert_ns_per_op,
+            r.lookup_ns_per_op,
+            r.false_positive_rate,
+            r.memory_bytes
+        );
+    }
+    out.push(']');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fair_workload() {
+        let results = run(&Workload {
+            num_keys: 2000,
+            key_len: 8,
+            seed: 7,
+        })
+        .unwrap();
+        let names = results.iter().map(|r| r.name).collect::<Vec<_>>();
+        assert_eq!(names, ["dakv_cuckoo", "cuckoofilter", "bloomfilter"]);
+        for r in &results {
+            // The other crates seed their kicks randomly and may reject a
+            // few keys this close to capacity.
+            assert!(r.inserted >= 1980, "{}", r.name);
+            assert!(r.false_positive_rate < 0.1, "{}", r.name);
+        }
+        let json = to_json(&results);
+        assert!(json.starts_with("[{\"name\":\"dakv_cuckoo\""));
+    }
+}
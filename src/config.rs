@@ -0,0 +1,289 @@
+use crate::bucket::Bucket;
+use crate::cuckoo_filter::gen_size;
+use std::{error, fmt, mem};
+
+/// Construction parameters of a filter, independent of its contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct CuckooConfig {
+    /// Number of keys the filter is sized for.
+    pub max_num_keys: u64,
+    /// Seed of the eviction RNG; `None` draws one from the OS.
+    pub seed: Option<u64>,
+    /// Maximum copies of one fingerprint per bucket pair; `None` is unlimited.
+    pub max_duplicates: Option<usize>,
+    /// Keeps the fingerprints of every bucket in ascending order, see
+    /// `sorted_buckets`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub sorted_buckets: bool,
+    /// Kicks an insertion tries before it fails; `None` uses the default of
+    /// 500.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_kicks: Option<usize>,
+    /// How a kick picks the fingerprint it evicts.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub eviction_policy: EvictionPolicy,
+}
+
+/// How a kick picks the fingerprint it evicts from a full bucket.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum EvictionPolicy {
+    /// A random slot, as in the original cuckoo filter.
+    #[default]
+    Random,
+    /// Every slot in turn, filter-wide, without drawing random numbers.
+    RoundRobin,
+    /// The slot of the bucket that was filled longest ago, tracked with a
+    /// cursor per bucket at one byte per bucket. It is exact while nothing is
+    /// deleted from the bucket and buckets are not kept sorted; otherwise it
+    /// cycles through the slots of each bucket in turn.
+    LeastRecentlyPlaced,
+}
+
+/// Why a configuration was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The input is malformed, has an unknown or missing field, or a field of
+    /// the wrong type. `line` and `column` are 1-based, when known.
+    Parse {
+        message: String,
+        line: Option<usize>,
+        column: Option<usize>,
+    },
+    /// A field holds a value no filter can be built from.
+    Invalid {
+        field: &'static str,
+        reason: &'static str,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Parse { message, .. } => write!(f, "cannot parse config: {}", message),
+            ConfigError::Invalid { field, reason } => write!(f, "invalid {}: {}", field, reason),
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+impl CuckooConfig {
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooConfig;
+    /// let config = CuckooConfig::new(100);
+    /// assert_eq!(config.max_num_keys, 100);
+    /// ```
+    pub fn new(max_num_keys: u64) -> Self {
+        CuckooConfig {
+            max_num_keys,
+            seed: None,
+            max_duplicates: None,
+            sorted_buckets: false,
+            max_kicks: None,
+            eviction_policy: EvictionPolicy::Random,
+        }
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooConfig;
+    /// let config = CuckooConfig::new(100).seed(42);
+    /// assert_eq!(config.seed, Some(42));
+    /// ```
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooConfig;
+    /// let config = CuckooConfig::new(100).max_duplicates(2);
+    /// assert_eq!(config.max_duplicates, Some(2));
+    /// ```
+    pub fn max_duplicates(mut self, max_duplicates: usize) -> Self {
+        self.max_duplicates = Some(max_duplicates);
+        self
+    }
+
+    /// Keeps the fingerprints of every bucket packed into its leading slots
+    /// in ascending order, so lookups stop at the first larger fingerprint.
+    ///
+    /// Each insert, delete and kick then re-sorts the bucket it changed,
+    /// which costs a few extra compares and moves on every write. Lookups
+    /// save compares only on misses against well-filled buckets, so this
+    /// pays off for read-heavy filters near capacity and is off by default.
+    /// The ordering is not recorded when a filter is serialized.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooFilter};
+    /// let config = CuckooConfig::new(100).sorted_buckets(true);
+    /// let mut cf = CuckooFilter::from_config(&config);
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    pub fn sorted_buckets(mut self, sorted: bool) -> Self {
+        self.sorted_buckets = sorted;
+        self
+    }
+
+    /// Bounds the relocations an insertion tries before it fails. Fewer
+    /// kicks cap insert latency at the cost of failing at a lower load.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooFilter};
+    /// let cf = CuckooFilter::from_config(&CuckooConfig::new(100).max_kicks(50));
+    /// assert_eq!(cf.max_kicks(), 50);
+    /// ```
+    pub fn max_kicks(mut self, max_kicks: usize) -> Self {
+        self.max_kicks = Some(max_kicks);
+        self
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooFilter, EvictionPolicy};
+    /// let config = CuckooConfig::new(100).eviction_policy(EvictionPolicy::RoundRobin);
+    /// let cf = CuckooFilter::from_config(&config);
+    /// assert_eq!(cf.eviction_policy(), EvictionPolicy::RoundRobin);
+    /// ```
+    pub fn eviction_policy(mut self, policy: EvictionPolicy) -> Self {
+        self.eviction_policy = policy;
+        self
+    }
+
+    /// Checks that a filter can be built from this configuration.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ConfigError, CuckooConfig};
+    /// assert!(CuckooConfig::new(100).validate().is_ok());
+    /// assert!(matches!(
+    ///     CuckooConfig::new(0).validate(),
+    ///     Err(ConfigError::Invalid { field: "max_num_keys", .. })
+    /// ));
+    /// ```
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.max_num_keys == 0 {
+            return Err(ConfigError::Invalid {
+                field: "max_num_keys",
+                reason: "must be at least 1",
+            });
+        }
+        if gen_size(self.max_num_keys) > (isize::MAX as usize / mem::size_of::<Bucket>()) as u64 {
+            return Err(ConfigError::Invalid {
+                field: "max_num_keys",
+                reason: "too large for the address space",
+            });
+        }
+        if self.max_duplicates == Some(0) {
+            return Err(ConfigError::Invalid {
+                field: "max_duplicates",
+                reason: "must be at least 1, or absent for no limit",
+            });
+        }
+        Ok(())
+    }
+
+    /// Parses and validates a configuration from a JSON object with the
+    /// fields of this struct. Only `max_num_keys` is required; unknown fields
+    /// are rejected.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ConfigError, CuckooConfig};
+    /// let config = CuckooConfig::from_json(r#"{"max_num_keys": 100, "seed": 42}"#).unwrap();
+    /// assert_eq!(config, CuckooConfig::new(100).seed(42));
+    /// assert!(matches!(
+    ///     CuckooConfig::from_json(r#"{"max_num_keys": 100, "max_duplicates": 0}"#),
+    ///     Err(ConfigError::Invalid { field: "max_duplicates", .. })
+    /// ));
+    /// ```
+    #[cfg(feature = "config-json")]
+    pub fn from_json(input: &str) -> Result<Self, ConfigError> {
+        let config =
+            serde_json::from_str::<CuckooConfig>(input).map_err(|e| ConfigError::Parse {
+                message: e.to_string(),
+                line: Some(e.line()),
+                column: Some(e.column()),
+            })?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Like `from_json`, for YAML.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ConfigError, CuckooConfig};
+    /// let config = CuckooConfig::from_yaml("max_num_keys: 100\nmax_duplicates: 2\n").unwrap();
+    /// assert_eq!(config, CuckooConfig::new(100).max_duplicates(2));
+    /// assert!(matches!(
+    ///     CuckooConfig::from_yaml("max_num_keys: 100\nsede: 1\n"),
+    ///     Err(ConfigError::Parse { .. })
+    /// ));
+    /// ```
+    #[cfg(feature = "config-yaml")]
+    pub fn from_yaml(input: &str) -> Result<Self, ConfigError> {
+        let config = serde_yaml::from_str::<CuckooConfig>(input).map_err(|e| {
+            let location = e.location();
+            ConfigError::Parse {
+                message: e.to_string(),
+                line: location.as_ref().map(|l| l.line()),
+                column: location.as_ref().map(|l| l.column()),
+            }
+        })?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+impl Default for CuckooConfig {
+    fn default() -> Self {
+        CuckooConfig::new(1 << 24)
+    }
+}
+
+#[cfg(all(test, feature = "config-json"))]
+mod tests {
+    use crate::{ConfigError, CuckooConfig, CuckooFilter};
+
+    #[test]
+    fn test_from_json_reports_field() {
+        let config = CuckooConfig::from_json(r#"{"max_num_keys": 1000, "max_duplicates": 4}"#);
+        let cf = CuckooFilter::from_config(&config.unwrap());
+        assert_eq!(cf.capacity(), 2048);
+
+        match CuckooConfig::from_json("{\n  \"max_num_keys\": 10,\n  \"max_keys\": 1\n}") {
+            Err(ConfigError::Parse { message, line, .. }) => {
+                assert!(message.contains("max_keys"));
+                assert_eq!(line, Some(3));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert!(matches!(
+            CuckooConfig::from_json(r#"{"seed": 1}"#),
+            Err(ConfigError::Parse { message, .. }) if message.contains("max_num_keys")
+        ));
+        assert!(matches!(
+            CuckooConfig::from_json(r#"{"max_num_keys": 0}"#),
+            Err(ConfigError::Invalid {
+                field: "max_num_keys",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,257 @@
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+//! Many named filters in one file.
+//!
+//! A container starts with a `CONTAINER_HEADER_LEN`-byte header: the magic
+//! `DKCC`, a version byte, three reserved bytes, then the directory offset
+//! and entry count as u64. The serialized filters, each in the `format`
+//! layout, follow back to back. The directory sits at the end so the file can
+//! be written in one pass; every entry is the name length as u16, the UTF-8
+//! name, the offset and length of the filter as u64, and a copy of the
+//! filter's header, so the size class of every filter is known without
+//! reading it. All integers are little endian.
+use crate::format::{Header, HEADER_LEN};
+use crate::CuckooFilter;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+
+/// The magic bytes every container starts with.
+pub const CONTAINER_MAGIC: [u8; 4] = *b"DKCC";
+
+/// The container layout version described above.
+pub const CONTAINER_VERSION: u8 = 1;
+
+/// Length of the container header in bytes.
+pub const CONTAINER_HEADER_LEN: usize = 24;
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut word = [0; 8];
+    r.read_exact(&mut word)?;
+    Ok(u64::from_le_bytes(word))
+}
+
+/// Directory entry of one filter.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    offset: u64,
+    len: u64,
+    header: Header,
+}
+
+/// Writes filters into a container file.
+///
+/// # Example
+/// ```no_run
+/// use dakv_cuckoo::{ContainerReader, ContainerWriter, CuckooFilter};
+///
+/// let mut cf = CuckooFilter::tiny();
+/// cf.add(b"user:1").unwrap();
+/// let mut writer = ContainerWriter::create("/tmp/tenants.dkcc").unwrap();
+/// writer.add("tenant-42", &cf).unwrap();
+/// writer.finish().unwrap();
+///
+/// let reader = ContainerReader::open("/tmp/tenants.dkcc").unwrap();
+/// let cf = reader.get("tenant-42").unwrap().unwrap();
+/// assert!(cf.contains(b"user:1"));
+/// ```
+pub struct ContainerWriter {
+    out: BufWriter<File>,
+    offset: u64,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl ContainerWriter {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        // Rewritten by `finish` once the directory offset is known.
+        out.write_all(&[0; CONTAINER_HEADER_LEN])?;
+        Ok(ContainerWriter {
+            out,
+            offset: CONTAINER_HEADER_LEN as u64,
+            entries: BTreeMap::new(),
+        })
+    }
+
+    /// Appends `filter` under `name`, which must not be taken yet.
+    pub fn add(&mut self, name: &str, filter: &CuckooFilter) -> io::Result<()> {
+        if self.entries.contains_key(name) || name.len() > usize::from(u16::MAX) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("cannot store filter as {:?}", name),
+            ));
+        }
+        let bytes = filter.to_bytes();
+        let header = Header::parse(&bytes).map_err(invalid)?;
+        self.out.write_all(&bytes)?;
+        let len = bytes.len() as u64;
+        self.entries.insert(
+            name.to_string(),
+            Entry {
+                offset: self.offset,
+                len,
+                header,
+            },
+        );
+        self.offset += len;
+        Ok(())
+    }
+
+    /// Writes the directory and header.
+    pub fn finish(mut self) -> io::Result<()> {
+        for (name, entry) in &self.entries {
+            self.out.write_all(&(name.len() as u16).to_le_bytes())?;
+            self.out.write_all(name.as_bytes())?;
+            self.out.write_all(&entry.offset.to_le_bytes())?;
+            self.out.write_all(&entry.len.to_le_bytes())?;
+            self.out.write_all(&entry.header.encode())?;
+        }
+        let mut header = [0; CONTAINER_HEADER_LEN];
+        header[..4].copy_from_slice(&CONTAINER_MAGIC);
+        header[4] = CONTAINER_VERSION;
+        header[8..16].copy_from_slice(&self.offset.to_le_bytes());
+        header[16..24].copy_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        self.out.seek(SeekFrom::Start(0))?;
+        self.out.write_all(&header)?;
+        self.out.flush()
+    }
+}
+
+/// Reads filters out of a container file on demand.
+///
+/// Opening reads only the directory; each filter is read when asked for.
+pub struct ContainerReader {
+    file: Mutex<File>,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl ContainerReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let mut header = [0; CONTAINER_HEADER_LEN];
+        file.read_exact(&mut header)?;
+        if header[..4] != CONTAINER_MAGIC {
+            return Err(invalid("bad container magic"));
+        }
+        if header[4] != CONTAINER_VERSION {
+            return Err(invalid(format!(
+                "unsupported container version {}",
+                header[4]
+            )));
+        }
+        let mut fields = &header[8..];
+        let dir_offset = read_u64(&mut fields)?;
+        let count = read_u64(&mut fields)?;
+
+        if dir_offset > file.metadata()?.len() {
+            return Err(invalid("directory offset beyond the end of the file"));
+        }
+        file.seek(SeekFrom::Start(dir_offset))?;
+        let mut dir = BufReader::new(&mut file);
+        let mut entries = BTreeMap::new();
+        for _ in 0..count {
+            let mut len = [0; 2];
+            dir.read_exact(&mut len)?;
+            let mut name = vec![0; usize::from(u16::from_le_bytes(len))];
+            dir.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(invalid)?;
+            let offset = read_u64(&mut dir)?;
+            let len = read_u64(&mut dir)?;
+            let mut header = [0; HEADER_LEN];
+            dir.read_exact(&mut header)?;
+            let header = Header::parse(&header).map_err(invalid)?;
+            // Filters precede the directory, so this also bounds what `get`
+            // allocates by the actual file size.
+            if offset.checked_add(len).is_none_or(|end| end > dir_offset) {
+                return Err(invalid(format!("filter {:?} overlaps the directory", name)));
+            }
+            entries.insert(
+                name,
+                Entry {
+                    offset,
+                    len,
+                    header,
+                },
+            );
+        }
+        Ok(ContainerReader {
+            file: Mutex::new(file),
+            entries,
+        })
+    }
+
+    /// Returns the names of all filters, in ascending order.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.entries.keys().map(String::as_str)
+    }
+
+    /// Returns the header of `name`, telling its size class without reading
+    /// the filter.
+    pub fn header(&self, name: &str) -> Option<Header> {
+        self.entries.get(name).map(|e| e.header)
+    }
+
+    /// Reads and decodes the filter stored as `name`.
+    pub fn get(&self, name: &str) -> io::Result<Option<CuckooFilter>> {
+        let entry = match self.entries.get(name) {
+            Some(entry) => *entry,
+            None => return Ok(None),
+        };
+        let len = usize::try_from(entry.len).map_err(invalid)?;
+        let mut bytes = vec![0; len];
+        {
+            let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+            file.seek(SeekFrom::Start(entry.offset))?;
+            file.read_exact(&mut bytes)?;
+        }
+        CuckooFilter::from_bytes(&bytes).map(Some).map_err(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ContainerReader, ContainerWriter, CuckooFilter};
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_round_trip() {
+        let path = env::temp_dir().join(format!("dakv_cuckoo_container_{}", process::id()));
+        let mut small = CuckooFilter::tiny();
+        let mut large = CuckooFilter::with_capacity_and_seed(1024, 1);
+        for i in 0..100u16 {
+            small.add(&i.to_le_bytes()).unwrap();
+            large.add(&(i + 1000).to_le_bytes()).unwrap();
+        }
+        let mut writer = ContainerWriter::create(&path).unwrap();
+        writer.add("tenant-42", &small).unwrap();
+        writer.add("tenant-7", &large).unwrap();
+        assert!(writer.add("tenant-7", &small).is_err());
+        writer.finish().unwrap();
+
+        let reader = ContainerReader::open(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            reader.names().collect::<Vec<_>>(),
+            ["tenant-42", "tenant-7"]
+        );
+        assert_eq!(reader.header("tenant-7").unwrap().num_buckets, 1024);
+        assert!(reader.get("tenant-1").unwrap().is_none());
+        let small2 = reader.get("tenant-42").unwrap().unwrap();
+        let large2 = reader.get("tenant-7").unwrap().unwrap();
+        assert_eq!(small2.size(), 100);
+        for i in 0..100u16 {
+            assert!(small2.contains(&i.to_le_bytes()));
+            assert!(large2.contains(&(i + 1000).to_le_bytes()));
+        }
+        assert_eq!(
+            small2.fingerprints().collect::<Vec<_>>(),
+            small.fingerprints().collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,271 @@
+//! Compatibility with the reference C++ library `efficient/cuckoofilter`.
+//!
+//! Covers `CuckooFilter<uint64_t, 8>` with its default `SingleTable` and
+//! `TwoIndependentMultiplyShift` hash: keys are 64-bit integers hashed as
+//! `(add + multiply * key) >> 64` over 128 bits. The low byte of the hash is
+//! the tag, zero mapped to one, and the high 32 bits pick the bucket. The
+//! alternate bucket XORs in the tag times the MurmurHash2 constant. The table
+//! is `num_buckets` buckets of four one-byte slots, zero marking an empty
+//! slot, which is `SizeInBytes()` bytes of the C++ table without its padding.
+
+// Bucket counts are powers of two, as `from_cpp_table` checks, so
+// `index_hash` masks every bucket index below the count.
+#![allow(clippy::indexing_slicing)]
+
+use crate::bucket::BUCKET_SIZE;
+use crate::cuckoo_filter::{gen_size, MAX_CUCKOO_COUNT};
+use crate::format::FormatError;
+use crate::telemetry;
+use crate::{CResult, CuckooError, Placement};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::mem;
+
+// Constant `AltIndex` multiplies the tag by, taken from MurmurHash2
+const ALT_MULTIPLIER: u32 = 0x5bd1_e995;
+
+/// A filter that places and stores tags exactly like the C++ library
+/// `efficient/cuckoofilter`, for exchanging tables with C++ services.
+///
+/// The C++ hash draws `multiply` and `add` from `std::random_device` when a
+/// filter is built, so both sides must use the same pair: the C++ service
+/// has to export the values it drew, or be built with fixed ones. Keys are
+/// `u64`, like the C++ `ItemType`, and are not visible to a `CuckooFilter`.
+///
+/// As in C++, an insert that runs out of relocations parks the last evicted
+/// tag as the victim, still found by `contains`, and later inserts fail until
+/// a delete makes room. The victim is not part of the table.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::CppCuckooFilter;
+///
+/// let (multiply, add) = (0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c834, 0x1082_276b);
+/// let mut cf = CppCuckooFilter::new(1000, multiply, add);
+/// cf.add(42).unwrap();
+/// let table = cf.to_cpp_table();
+/// assert!(CppCuckooFilter::from_cpp_table(&table, multiply, add).unwrap().contains(42));
+/// ```
+#[derive(Debug, Clone)]
+pub struct CppCuckooFilter {
+    buckets: Vec<[u8; BUCKET_SIZE]>,
+    multiply: u128,
+    add: u128,
+    size: usize,
+    victim: Option<(usize, u8)>,
+    rng: StdRng,
+}
+
+impl CppCuckooFilter {
+    /// Sizes the table like the C++ constructor `CuckooFilter(max_num_keys)`.
+    pub fn new(max_num_keys: u64, multiply: u128, add: u128) -> Self {
+        let buckets = vec![[0; BUCKET_SIZE]; gen_size(max_num_keys) as usize];
+        Self::with_buckets(buckets, multiply, add)
+    }
+
+    fn with_buckets(buckets: Vec<[u8; BUCKET_SIZE]>, multiply: u128, add: u128) -> Self {
+        CppCuckooFilter {
+            size: buckets.iter().flatten().filter(|&&tag| tag != 0).count(),
+            buckets,
+            multiply,
+            add,
+            victim: None,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Reads a table dumped by a C++ filter hashing with `multiply` and `add`.
+    ///
+    /// The C++ filter masks bucket indices, so the table must hold a power of
+    /// two number of buckets; other lengths are `Invalid` of `length`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::format::FormatError;
+    /// use dakv_cuckoo::CppCuckooFilter;
+    ///
+    /// assert!(matches!(
+    ///     CppCuckooFilter::from_cpp_table(&[0; 12], 1, 0),
+    ///     Err(FormatError::Invalid { field: "length", value: 12 })
+    /// ));
+    /// ```
+    pub fn from_cpp_table(table: &[u8], multiply: u128, add: u128) -> Result<Self, FormatError> {
+        if !table.len().is_multiple_of(BUCKET_SIZE)
+            || !(table.len() / BUCKET_SIZE).is_power_of_two()
+        {
+            return Err(FormatError::Invalid {
+                field: "length",
+                value: table.len() as u64,
+            });
+        }
+        let buckets = table
+            .chunks_exact(BUCKET_SIZE)
+            .map(|slots| {
+                let mut bucket = [0; BUCKET_SIZE];
+                bucket.copy_from_slice(slots);
+                bucket
+            })
+            .collect();
+        Ok(Self::with_buckets(buckets, multiply, add))
+    }
+
+    /// Returns the table as the C++ filter stores it, without the victim.
+    pub fn to_cpp_table(&self) -> Vec<u8> {
+        self.buckets.iter().flatten().copied().collect()
+    }
+
+    fn index_hash(&self, hv: u32) -> usize {
+        hv as usize & (self.buckets.len() - 1)
+    }
+
+    fn index_tag(&self, item: u64) -> (usize, u8) {
+        let product = self.multiply.wrapping_mul(u128::from(item));
+        let hash = (self.add.wrapping_add(product) >> 64) as u64;
+        (self.index_hash((hash >> 32) as u32), (hash as u8).max(1))
+    }
+
+    fn alt_index(&self, index: usize, tag: u8) -> usize {
+        self.index_hash(index as u32 ^ u32::from(tag).wrapping_mul(ALT_MULTIPLIER))
+    }
+
+    fn is_victim(&self, tag: u8, i1: usize, i2: usize) -> bool {
+        self.victim
+            .is_some_and(|(index, victim)| victim == tag && (index == i1 || index == i2))
+    }
+
+    /// Adds `item`, filling its first bucket and then relocating tags from
+    /// its alternate one, like C++ `Add`.
+    pub fn add(&mut self, item: u64) -> CResult<CuckooError> {
+        let (i1, tag) = self.index_tag(item);
+        if self.victim.is_some() {
+            return Err(telemetry::record(CuckooError::NotEnoughSpace(Placement {
+                fingerprint: tag,
+                i1,
+                i2: self.alt_index(i1, tag),
+            })));
+        }
+        self.add_tag(i1, tag);
+        Ok(())
+    }
+
+    fn add_tag(&mut self, mut index: usize, mut tag: u8) {
+        for count in 0..MAX_CUCKOO_COUNT {
+            if let Some(slot) = self.buckets[index].iter().position(|&slot| slot == 0) {
+                self.buckets[index][slot] = tag;
+                self.size += 1;
+                return;
+            }
+            if count > 0 {
+                let slot = self.rng.gen_range(0, BUCKET_SIZE);
+                tag = mem::replace(&mut self.buckets[index][slot], tag);
+            }
+            index = self.alt_index(index, tag);
+        }
+        self.victim = Some((index, tag));
+    }
+
+    pub fn contains(&self, item: u64) -> bool {
+        let (i1, tag) = self.index_tag(item);
+        let i2 = self.alt_index(i1, tag);
+        self.is_victim(tag, i1, i2)
+            || self.buckets[i1].contains(&tag)
+            || self.buckets[i2].contains(&tag)
+    }
+
+    /// Removes one copy of `item`'s tag, then retries placing the victim in
+    /// the freed room, like C++ `Delete`.
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: u64) -> bool {
+        let (i1, tag) = self.index_tag(item);
+        let i2 = self.alt_index(i1, tag);
+        for i in [i1, i2] {
+            if let Some(slot) = self.buckets[i].iter_mut().find(|slot| **slot == tag) {
+                *slot = 0;
+                self.size -= 1;
+                if let Some((index, victim)) = self.victim.take() {
+                    self.add_tag(index, victim);
+                }
+                return true;
+            }
+        }
+        if self.is_victim(tag, i1, i2) {
+            self.victim = None;
+            return true;
+        }
+        false
+    }
+
+    /// Returns the number of tags in the table, which like C++ `Size` leaves
+    /// out the victim.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0 && self.victim.is_none()
+    }
+
+    pub fn num_buckets(&self) -> usize {
+        self.buckets.len()
+    }
+
+    /// Returns the bucket index and tag of the victim, if an insert ran out of
+    /// relocations.
+    pub fn victim(&self) -> Option<(usize, u8)> {
+        self.victim
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::CppCuckooFilter;
+
+    const MULTIPLY: u128 = 0x9e37_79b9_7f4a_7c15_f39c_c060_5ced_c834;
+    const ADD: u128 = 0x1082_276b_f3a2_7251_f86c_6a11_d0c1_8e95;
+
+    #[test]
+    fn test_cpp_layout() {
+        // 42 hashes to tag 235 with buckets 26 and 29 of 32.
+        let mut cf = CppCuckooFilter::new(64, MULTIPLY, ADD);
+        assert_eq!(cf.num_buckets(), 32);
+        cf.add(42).unwrap();
+        let mut expected = vec![0; 32 * 4];
+        expected[26 * 4] = 235;
+        assert_eq!(cf.to_cpp_table(), expected);
+
+        expected[26 * 4] = 0;
+        expected[29 * 4 + 2] = 235;
+        let decoded = CppCuckooFilter::from_cpp_table(&expected, MULTIPLY, ADD).unwrap();
+        assert_eq!(decoded.size(), 1);
+        assert!(decoded.contains(42));
+        assert!(CppCuckooFilter::from_cpp_table(&[], MULTIPLY, ADD).is_err());
+    }
+
+    #[test]
+    fn test_cpp_victim() {
+        let mut cf = CppCuckooFilter::new(4000, MULTIPLY, ADD);
+        let mut added = Vec::new();
+        for key in 0..10_000u64 {
+            if cf.add(key).is_err() {
+                break;
+            }
+            added.push(key);
+        }
+        assert!(cf.victim().is_some());
+        assert!(added.iter().all(|&key| cf.contains(key)));
+        let table = cf.to_cpp_table();
+        let decoded = CppCuckooFilter::from_cpp_table(&table, MULTIPLY, ADD).unwrap();
+        assert_eq!(decoded.size(), cf.size());
+
+        #[cfg(not(feature = "no-delete"))]
+        {
+            // The victim moves into the freed room or becomes the new victim.
+            assert!(cf.delete(added[0]));
+            assert_eq!(
+                cf.size(),
+                decoded.size() - usize::from(cf.victim().is_some())
+            );
+            assert!(added[1..].iter().all(|&key| cf.contains(key)));
+        }
+    }
+}
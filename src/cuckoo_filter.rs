@@ -1,40 +1,395 @@
+// Bucket indices are masked to `pow` bits, whose buckets the array always
+// holds, or checked against the bucket count where they come from a caller,
+// as in `load_chunk`; slot indices stay below `BUCKET_SIZE`. Checked access
+// would only slow the hot paths down. `from_bytes`, which parses untrusted
+// input, denies indexing again.
+#![allow(clippy::indexing_slicing)]
+
+use crate::accel;
+use crate::advisor::FULL_LOAD_FACTOR;
+use crate::algorithm::{HashAlgorithm, RuntimeHasher};
+#[cfg(feature = "rkyv")]
+use crate::archive;
+use crate::base64;
+use crate::batch::FilterOp;
 use crate::bucket::Bucket;
 use crate::bucket::BUCKET_SIZE;
-use crate::util::{get_alt_index, get_indices_and_fingerprint, upper_power2};
-use rand::{random, Rng};
+use crate::cancel::Cancellation;
+use crate::config::{ConfigError, CuckooConfig, EvictionPolicy};
+use crate::format::{self, SlotCodec};
+use crate::pipeline::DEFAULT_PIPELINE_DEPTH;
+use crate::quality;
+use crate::snapshot::{self, SnapshotInfo};
+use crate::telemetry;
+use crate::tuning::{self, Tuning, Workload};
+#[cfg(not(feature = "no-delete"))]
+use crate::util::hash_key;
+#[cfg(feature = "uuid")]
+use crate::util::hash_u128;
+use crate::util::{
+    get_alt_index, get_indices_from_hash, upper_power2, FingerIndex, KeyHasherBuilder, MASKS,
+};
+use crate::yielding::{yield_now, YIELD_EVERY};
+use rand::rngs::StdRng;
+use rand::seq::IteratorRandom;
+use rand::{Rng, SeedableRng};
 use std::cmp::max;
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
 use std::mem;
-use std::{iter, result};
+use std::{error, fmt, hint, io, iter, result, slice};
 
 // Maximum number of cuckoo kicks before claiming failure
-const MAX_CUCKOO_COUNT: usize = 500;
+pub(crate) const MAX_CUCKOO_COUNT: usize = 500;
+
+// Layout of `CuckooFilter::tiny`
+const TINY_BUCKETS: usize = 64;
+const TINY_SEED: u64 = 0x5eed;
 
-const DE_BRUIJN64_TAB: [usize; 64] = [
-    0, 1, 56, 2, 57, 49, 28, 3, 61, 58, 42, 50, 38, 29, 17, 4, 62, 47, 59, 36, 45, 43, 51, 22, 53,
-    39, 33, 30, 24, 18, 12, 5, 63, 55, 48, 27, 60, 41, 37, 16, 46, 35, 44, 21, 52, 32, 23, 11, 54,
-    26, 40, 15, 34, 20, 31, 10, 25, 14, 19, 9, 13, 8, 7, 6,
-];
-const DE_BRUIJN64: u64 = 0x03f79d71b4ca8b09;
+// Bytes buffered per write or read by `write_to` and `read_from`
+const STREAM_CHUNK: usize = 64 * 1024;
+
+// Most bucket bytes `scan_dump` returns per chunk, as in RedisBloom
+const SCAN_DUMP_CHUNK: usize = 16 * 1024 * 1024;
 
 pub type CResult<E> = result::Result<(), E>;
 
 #[allow(clippy::enum_variant_names)]
+#[derive(Debug)]
 pub enum CuckooError {
     NotFound,
-    NotEnoughSpace,
+    NotEnoughSpace(Placement),
     NotSupported,
+    /// The bucket pair already holds the configured maximum of this fingerprint.
+    TooManyDuplicates(Placement),
+    /// The bucket array of the requested size could not be allocated.
+    AllocationFailed {
+        buckets: usize,
+    },
+    /// The operation was stopped by its `Cancellation`.
+    Cancelled,
+}
+
+/// Where a failed insertion tried to go.
+///
+/// Only derived values are recorded, never the key itself, so this is safe to
+/// log and lets failures for the same key be correlated across processes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub fingerprint: u8,
+    pub i1: usize,
+    pub i2: usize,
+}
+
+impl fmt::Display for CuckooError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CuckooError::NotFound => write!(f, "item not found"),
+            CuckooError::NotEnoughSpace(p) => write!(
+                f,
+                "not enough space for fingerprint {:#04x} in buckets {} and {}",
+                p.fingerprint, p.i1, p.i2
+            ),
+            CuckooError::NotSupported => write!(f, "operation not supported"),
+            CuckooError::TooManyDuplicates(p) => write!(
+                f,
+                "too many copies of fingerprint {:#04x} in buckets {} and {}",
+                p.fingerprint, p.i1, p.i2
+            ),
+            CuckooError::AllocationFailed { buckets } => {
+                write!(f, "cannot allocate {} buckets", buckets)
+            }
+            CuckooError::Cancelled => write!(f, "operation cancelled"),
+        }
+    }
+}
+
+impl error::Error for CuckooError {}
+
+/// The versions of both candidate buckets of a key at the time of a read.
+#[cfg(feature = "bucket-versions")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReadStamp {
+    i1: usize,
+    v1: u32,
+    i2: usize,
+    v2: u32,
+}
+
+/// A broken structural invariant, reported by `CuckooFilter::check_invariants`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// The index mask addresses more buckets than were allocated.
+    MaskTooWide { pow: usize, num_buckets: usize },
+    /// The recorded size disagrees with the number of occupied slots.
+    SizeMismatch { recorded: usize, counted: usize },
+    /// A fingerprint lives in a bucket no key can map to.
+    Unreachable { bucket: usize, fp: u8 },
+    /// A fingerprint's alternate bucket is not a valid partner of its bucket.
+    IllegalPlacement { bucket: usize, fp: u8 },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantViolation::MaskTooWide { pow, num_buckets } => {
+                write!(f, "mask of {} bits exceeds {} buckets", pow, num_buckets)
+            }
+            InvariantViolation::SizeMismatch { recorded, counted } => {
+                write!(f, "size is {} but {} slots are occupied", recorded, counted)
+            }
+            InvariantViolation::Unreachable { bucket, fp } => {
+                write!(
+                    f,
+                    "fingerprint {:#04x} in unreachable bucket {}",
+                    fp, bucket
+                )
+            }
+            InvariantViolation::IllegalPlacement { bucket, fp } => {
+                write!(
+                    f,
+                    "fingerprint {:#04x} has no valid partner of bucket {}",
+                    fp, bucket
+                )
+            }
+        }
+    }
+}
+
+/// An insertion made by `CuckooFilter::prepare_add` that is still revocable.
+pub struct PreparedInsert<'a, H: BuildHasher = KeyHasherBuilder> {
+    filter: &'a mut CuckooFilter<H>,
+    journal: Vec<(usize, Bucket)>,
+    size: usize,
+    committed: bool,
+}
+
+impl<H: BuildHasher> PreparedInsert<'_, H> {
+    /// Makes the insertion permanent.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+
+    /// Reverts the insertion and every kick it caused.
+    pub fn abort(self) {}
+}
+
+impl<H: BuildHasher> Drop for PreparedInsert<'_, H> {
+    fn drop(&mut self) {
+        if !self.committed {
+            let journal = mem::take(&mut self.journal);
+            self.filter.rollback(journal, self.size);
+        }
+    }
+}
+
+/// What happened to one item of `CuckooFilter::add_all`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemOutcome {
+    /// Stored after the given number of kicks.
+    Inserted { kicks: usize },
+    /// `max_duplicates` copies of the fingerprint were already present, so
+    /// the item was rejected and the filter is unchanged.
+    TooManyDuplicates { placement: Placement },
+    /// No slot was found after `kicks` kicks; the filter is unchanged.
+    Failed { kicks: usize, placement: Placement },
+}
+
+/// Per-item outcomes of `CuckooFilter::add_all`, in input order.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    pub outcomes: Vec<ItemOutcome>,
+}
+
+impl BatchReport {
+    /// Returns the number of items that were stored.
+    pub fn inserted(&self) -> usize {
+        self.outcomes
+            .iter()
+            .filter(|o| matches!(o, ItemOutcome::Inserted { .. }))
+            .count()
+    }
+
+    /// Returns the input positions of the items that could not be stored.
+    pub fn failed(&self) -> impl Iterator<Item = usize> + '_ {
+        self.outcomes
+            .iter()
+            .enumerate()
+            .filter(|(_, o)| matches!(o, ItemOutcome::Failed { .. }))
+            .map(|(i, _)| i)
+    }
+}
+
+/// Why `CuckooFilter::contains` answered the way it did for one key, from
+/// `CuckooFilter::explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Explanation {
+    pub fingerprint: u8,
+    /// The key's primary bucket.
+    pub i1: usize,
+    /// The key's alternate bucket.
+    pub i2: usize,
+    /// Slots of the primary bucket, `0` where empty.
+    pub slots1: [u8; BUCKET_SIZE],
+    /// Slots of the alternate bucket, `0` where empty.
+    pub slots2: [u8; BUCKET_SIZE],
+    /// Whether the alternate bucket was compared at all; it is skipped until
+    /// some fingerprint has been displaced, see `evictions_occurred`.
+    pub probed_alternate: bool,
+    /// The bucket and slot whose fingerprint matched, or `None` if the
+    /// answer was negative.
+    pub matched: Option<(usize, usize)>,
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "fingerprint {:#04x}, bucket {} {:02x?}, bucket {} {:02x?}",
+            self.fingerprint, self.i1, self.slots1, self.i2, self.slots2
+        )?;
+        if !self.probed_alternate {
+            write!(f, " (not probed)")?;
+        }
+        match self.matched {
+            Some((bucket, slot)) => write!(f, ": matched slot {} of bucket {}", slot, bucket),
+            None => write!(f, ": no match"),
+        }
+    }
+}
+
+/// How many slots `CuckooFilter::duplicate_census` found holding repeated
+/// copies of a fingerprint within the same bucket pair.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DuplicateCensus {
+    /// Number of occupied slots.
+    pub occupied: usize,
+    /// Number of `(bucket pair, fingerprint)` groups holding more than one copy.
+    pub groups: usize,
+    /// Slots taken by copies beyond the first of each group, which a filter
+    /// with `set_max_duplicates(Some(1))` would have left free.
+    pub redundant_slots: usize,
+    /// The most copies found in a single group.
+    pub max_copies: usize,
+}
+
+impl DuplicateCensus {
+    /// Returns the fraction of occupied slots that hold redundant copies.
+    pub fn redundant_fraction(&self) -> f64 {
+        if self.occupied == 0 {
+            return 0.0;
+        }
+        self.redundant_slots as f64 / self.occupied as f64
+    }
+}
+
+/// Receives the previous state of every bucket an insertion modifies.
+trait Journal {
+    fn record(&mut self, index: usize, before: Bucket);
+
+    /// Returns the recorded buckets, oldest first.
+    #[cfg(feature = "paranoid")]
+    fn entries(&self) -> &[(usize, Bucket)] {
+        &[]
+    }
+}
+
+impl Journal for () {
+    fn record(&mut self, _: usize, _: Bucket) {}
+}
+
+impl Journal for Vec<(usize, Bucket)> {
+    fn record(&mut self, index: usize, before: Bucket) {
+        self.push((index, before));
+    }
+
+    #[cfg(feature = "paranoid")]
+    fn entries(&self) -> &[(usize, Bucket)] {
+        self
+    }
+}
+
+/// The journal of mutations that need none for themselves, kept only so
+/// `paranoid` builds can check the buckets they touched.
+#[cfg(any(not(feature = "no-delete"), feature = "raw-api"))]
+#[cfg(feature = "paranoid")]
+type CheckJournal = Vec<(usize, Bucket)>;
+#[cfg(any(not(feature = "no-delete"), feature = "raw-api"))]
+#[cfg(not(feature = "paranoid"))]
+type CheckJournal = ();
+
+/// A fingerprint evicted by a failed insertion, together with one of its two
+/// candidate buckets.
+pub(crate) struct Victim {
+    pub fp: u8,
+    pub index: u64,
 }
 
-pub struct CuckooFilter {
+/// `CuckooFilter` under the name of its configuration: 8-bit fingerprints
+/// in buckets of four slots.
+///
+/// Other configurations are `WideCuckooFilter`s, under aliases such as
+/// `CuckooFilter16x4` and `CuckooFilter12x2`; each alias names a layout
+/// compiled for its fingerprint type and bucket size.
+pub type CuckooFilter8x4 = CuckooFilter;
+
+/// A cuckoo filter storing 8-bit fingerprints in buckets of four slots.
+///
+/// Keys are arbitrary byte strings with no minimum length: the empty key and
+/// one to three byte keys are hashed like any other input, and the same key
+/// always maps to the same fingerprint and buckets on every platform.
+///
+/// Keys are hashed with `H`, by default `KeyHasherBuilder`; see
+/// `with_hasher`.
+#[derive(Clone)]
+pub struct CuckooFilter<H = KeyHasherBuilder> {
     buckets: Box<[Bucket]>,
     size: usize,
     pow: usize,
+    rng: StdRng,
+    max_duplicates: Option<usize>,
+    // Set once any fingerprint has been stored outside its primary bucket.
+    displaced: bool,
+    // Keep every bucket in `Bucket::sort` order, see `CuckooConfig::sorted_buckets`.
+    sorted_buckets: bool,
+    max_kicks: usize,
+    evictor: Evictor,
+    hasher: H,
+}
+
+/// Picks the slot a kick evicts, with the state its `EvictionPolicy` needs.
+#[derive(Clone)]
+enum Evictor {
+    Random,
+    // The slot evicted last
+    RoundRobin(usize),
+    // The slot each bucket evicts next
+    LeastRecentlyPlaced(Box<[u8]>),
+}
+
+pub(crate) fn gen_size(max_num_keys: u64) -> u64 {
+    gen_size_for(max_num_keys, BUCKET_SIZE)
+}
+
+/// Returns the load factor up to which filters with `bucket_size` slots per
+/// bucket are sized.
+pub(crate) const fn max_load(bucket_size: usize) -> f64 {
+    match bucket_size {
+        2 => 0.84,
+        4 => 0.96,
+        _ => 0.98,
+    }
 }
 
-fn gen_size(max_num_keys: u64) -> u64 {
-    let mut num_buckets = upper_power2(max(1, max_num_keys / BUCKET_SIZE as u64));
-    let frac = max_num_keys as f64 / num_buckets as f64 / BUCKET_SIZE as f64;
-    if frac > 0.96 {
+/// Returns the power of two number of buckets of `bucket_size` slots that
+/// holds `max_num_keys` below the load at which inserts start failing, which
+/// rises with the bucket size.
+pub(crate) fn gen_size_for(max_num_keys: u64, bucket_size: usize) -> u64 {
+    let max_load = max_load(bucket_size);
+    let mut num_buckets = upper_power2(max(1, max_num_keys / bucket_size as u64));
+    let frac = max_num_keys as f64 / num_buckets as f64 / bucket_size as f64;
+    if frac > max_load {
         num_buckets <<= 1;
     }
     num_buckets
@@ -50,176 +405,3744 @@ impl CuckooFilter {
         Self::with_capacity(gen_size(max_num_keys) as usize)
     }
 
+    /// Like `new`, but reports a failed allocation instead of aborting.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooError, CuckooFilter};
+    /// assert!(CuckooFilter::try_new(100).is_ok());
+    /// assert!(matches!(
+    ///     CuckooFilter::try_new(u64::MAX),
+    ///     Err(CuckooError::AllocationFailed { .. })
+    /// ));
+    /// ```
+    pub fn try_new(max_num_keys: u64) -> Result<Self, CuckooError> {
+        let buckets = usize::try_from(gen_size(max_num_keys)).unwrap_or(usize::MAX);
+        Self::try_with_capacity(buckets)
+    }
+
+    /// Creates a filter with `capacity` buckets, at least one.
+    ///
     /// # Example
     /// ```
     /// use dakv_cuckoo::CuckooFilter;
     /// let cuckoo = CuckooFilter::with_capacity(100);
     /// ```
     pub fn with_capacity(capacity: usize) -> Self {
-        let buck = iter::repeat(Bucket::new())
-            .take(capacity)
-            .collect::<Vec<_>>();
-        CuckooFilter {
-            size: 0,
-            buckets: buck.into_boxed_slice(),
-            pow: trailing_zeros(capacity),
-        }
+        Self::with_capacity_and_rng(capacity, StdRng::from_entropy())
     }
 
+    /// Like `with_capacity`, but reports a failed allocation instead of
+    /// aborting.
+    ///
     /// # Example
     /// ```
     /// use dakv_cuckoo::CuckooFilter;
+    /// let cuckoo = CuckooFilter::try_with_capacity(128).unwrap();
+    /// assert_eq!(cuckoo.capacity(), 512);
+    /// ```
+    pub fn try_with_capacity(capacity: usize) -> Result<Self, CuckooError> {
+        Self::try_with_capacity_and_rng(capacity, StdRng::from_entropy())
+    }
+
+    /// Creates a filter whose evictions are driven by a fixed seed, so the same
+    /// sequence of operations always produces the same bucket layout.
     ///
-    /// let mut cf = CuckooFilter::default();
-    /// cf.add(b"test");
+    /// # Example
     /// ```
-    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
-        let finger = get_indices_and_fingerprint(item, self.pow);
-        if self.insert(finger.fp, finger.i1) || self.insert(finger.fp, finger.i2) {
-            return Ok(());
-        }
-        self.reinsert(finger.fp, rand_index(finger.i1, finger.i2))
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cuckoo = CuckooFilter::with_capacity_and_seed(128, 42);
+    /// ```
+    pub fn with_capacity_and_seed(capacity: usize, seed: u64) -> Self {
+        Self::with_capacity_and_rng(capacity, StdRng::seed_from_u64(seed))
     }
 
-    fn insert(&mut self, fp: u8, i: u64) -> bool {
-        let index = i as usize % self.buckets.len();
-        if self.buckets[index].insert(fp) {
-            self.size += 1;
-            true
-        } else {
-            false
-        }
+    /// Like `new`, but with evictions driven by `seed`: filters built with
+    /// the same arguments from the same sequence of operations serialize to
+    /// identical bytes, in any process.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    ///
+    /// let build = || {
+    ///     let mut cf = CuckooFilter::with_seed(100, 42);
+    ///     for i in 0u32..100 {
+    ///         cf.add(&i.to_le_bytes()).unwrap();
+    ///     }
+    ///     cf.to_bytes()
+    /// };
+    /// assert_eq!(build(), build());
+    /// ```
+    pub fn with_seed(max_num_keys: u64, seed: u64) -> Self {
+        Self::with_capacity_and_seed(gen_size(max_num_keys) as usize, seed)
+    }
+
+    /// A small filter with a fixed seed, for examples, docs and tests.
+    ///
+    /// It holds 64 buckets (256 slots) and behaves identically on every run,
+    /// unlike `default`, which allocates room for 16 million keys and seeds
+    /// evictions randomly.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// let _ = cf.add(b"test");
+    /// assert!(cf.contains(b"test"));
+    /// assert_eq!(cf.capacity(), 256);
+    /// ```
+    pub fn tiny() -> Self {
+        Self::with_capacity_and_seed(TINY_BUCKETS, TINY_SEED)
     }
 
-    fn reinsert(&mut self, mut fp: u8, mut i: u64) -> CResult<CuckooError> {
-        let mut rng = rand::thread_rng();
-        for _ in 0..MAX_CUCKOO_COUNT {
-            let j = rng.gen_range(0, BUCKET_SIZE);
-            mem::swap(&mut fp, &mut self.buckets[i as usize][j]);
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooFilter};
+    /// let cuckoo = CuckooFilter::from_config(&CuckooConfig::new(100).seed(42));
+    /// ```
+    pub fn from_config(config: &CuckooConfig) -> Self {
+        let capacity = gen_size(config.max_num_keys) as usize;
+        Self::with_capacity_and_rng(capacity, Self::config_rng(config)).configured(config)
+    }
 
-            i = get_alt_index(fp, i, self.pow);
-            if self.insert(fp, i) {
-                return Ok(());
-            }
+    /// Like `from_config`, but with `capacity` buckets whatever
+    /// `max_num_keys` says, and reporting a failed allocation instead of
+    /// aborting.
+    pub(crate) fn try_from_config_with_capacity(
+        config: &CuckooConfig,
+        capacity: usize,
+    ) -> Result<Self, CuckooError> {
+        Self::try_with_capacity_and_rng(capacity, Self::config_rng(config))
+            .map(|cf| cf.configured(config))
+    }
+
+    fn config_rng(config: &CuckooConfig) -> StdRng {
+        config
+            .seed
+            .map_or_else(StdRng::from_entropy, StdRng::seed_from_u64)
+    }
+
+    fn configured(mut self, config: &CuckooConfig) -> Self {
+        self.max_duplicates = config.max_duplicates;
+        self.sorted_buckets = config.sorted_buckets;
+        self.max_kicks = config.max_kicks.unwrap_or(MAX_CUCKOO_COUNT);
+        self.set_eviction_policy(config.eviction_policy);
+        self
+    }
+
+    /// Creates a filter sized so that, holding `expected_items`, it reports
+    /// at most a `target_fpr` fraction of absent keys as present.
+    ///
+    /// Each lookup compares against the occupied slots of two buckets, so
+    /// the rate follows the load factor and the fingerprint width. This build
+    /// stores 8-bit fingerprints, which give about 3% at full load; lower
+    /// rates are reached by keeping the load down, so memory grows in inverse
+    /// proportion to the target. The bucket count is rounded up to a power of
+    /// two and the load never exceeds 96%, where inserts start failing.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ConfigError, CuckooFilter};
+    /// let cf = CuckooFilter::with_fp_rate(10_000, 0.01).unwrap();
+    /// assert_eq!(cf.capacity(), 32_768);
+    /// assert!(matches!(
+    ///     CuckooFilter::with_fp_rate(10_000, 0.0),
+    ///     Err(ConfigError::Invalid { field: "target_fpr", .. })
+    /// ));
+    /// ```
+    pub fn with_fp_rate(expected_items: u64, target_fpr: f64) -> Result<Self, ConfigError> {
+        if !(target_fpr > 0.0 && target_fpr < 1.0) {
+            return Err(ConfigError::Invalid {
+                field: "target_fpr",
+                reason: "must be greater than 0 and less than 1",
+            });
+        }
+        let load = quality::load_for_fpr(target_fpr, u32::from(format::FINGERPRINT_BITS))
+            .min(FULL_LOAD_FACTOR);
+        let buckets = expected_items.max(1) as f64 / load / BUCKET_SIZE as f64;
+        // Rounding up to a power of two may double the count.
+        if buckets.ceil() > (isize::MAX as usize / mem::size_of::<Bucket>() / 2) as f64 {
+            return Err(ConfigError::Invalid {
+                field: "target_fpr",
+                reason: "too low to fit the address space",
+            });
         }
-        Err(CuckooError::NotEnoughSpace)
+        Ok(Self::with_capacity(
+            upper_power2(buckets.ceil() as u64) as usize
+        ))
     }
 
+    /// Chooses capacity, bucket size, fingerprint width, kick limit and
+    /// concurrency mode for `workload`, explaining each choice in
+    /// `Tuning::rationale`.
+    ///
+    /// The layout is the one taking the least memory that reaches
+    /// `target_fpr` within `memory_budget`, which may call for a
+    /// `WideCuckooFilter`; concurrent modes keep this build's layout, as
+    /// the concurrent filters wrap `CuckooFilter`.
+    ///
     /// # Example
     /// ```
-    /// use dakv_cuckoo::CuckooFilter;
-    /// let mut cf = CuckooFilter::default();
-    /// cf.add(b"test");
-    /// assert!(cf.contains(b"test"));
+    /// use dakv_cuckoo::{Concurrency, CuckooFilter, Workload};
+    ///
+    /// let tuning = CuckooFilter::tuned_for(&Workload {
+    ///     expected_items: 100_000,
+    ///     insert_rate: 50_000.0,
+    ///     delete_rate: 0.0,
+    ///     read_write_ratio: 1000.0,
+    ///     memory_budget: Some(1 << 20),
+    ///     target_fpr: 0.02,
+    /// })
+    /// .unwrap();
+    /// assert_eq!(tuning.concurrency, Concurrency::Snapshot);
+    /// let cf = tuning.builder().build().unwrap();
+    /// assert_eq!(cf.capacity() as u64, tuning.memory);
     /// ```
-    pub fn contains(&self, data: &[u8]) -> bool {
-        let finger = get_indices_and_fingerprint(data, self.pow);
-        let b1 = self.buckets[finger.i1 as usize];
-        let b2 = self.buckets[finger.i1 as usize];
-        b1.get_fingerprint_index(finger.fp).is_some()
-            || b2.get_fingerprint_index(finger.fp).is_some()
+    pub fn tuned_for(workload: &Workload) -> Result<Tuning, ConfigError> {
+        tuning::tune(workload)
     }
 
+    /// Builds a filter from a stream of keys without collecting them first.
+    ///
+    /// Without an explicit `capacity` the filter is sized from the iterator's
+    /// `size_hint`, which is exact for `ExactSizeIterator`s; iterators that
+    /// give no upper bound get the default capacity. Stops at the first key
+    /// that does not fit.
+    ///
     /// # Example
     /// ```
     /// use dakv_cuckoo::CuckooFilter;
-    /// let mut cf = CuckooFilter::default();
-    /// cf.add(b"test");
-    /// assert!(cf.delete(b"test"));
+    /// let keys = vec![b"a", b"b", b"c"];
+    /// let cf = CuckooFilter::from_iter_with_capacity(keys.iter(), None).unwrap();
+    /// assert!(cf.contains(b"b"));
+    /// assert_eq!(cf.capacity(), 4);
     /// ```
-    pub fn delete(&mut self, data: &[u8]) -> bool {
-        let finger = get_indices_and_fingerprint(data, self.pow);
-        self.remove(finger.fp, finger.i1) || self.remove(finger.fp, finger.i2)
+    pub fn from_iter_with_capacity<I>(iter: I, capacity: Option<u64>) -> Result<Self, CuckooError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let iter = iter.into_iter();
+        let mut cf = match capacity.or_else(|| iter.size_hint().1.map(|n| n as u64)) {
+            Some(max_num_keys) => CuckooFilter::new(max_num_keys),
+            None => CuckooFilter::default(),
+        };
+        for item in iter {
+            cf.add(item.as_ref())?;
+        }
+        Ok(cf)
     }
 
-    fn remove(&mut self, fp: u8, i: u64) -> bool {
-        if self.buckets[i as usize].delete(fp) {
-            self.size -= 1;
-            return true;
+    fn with_capacity_and_rng(capacity: usize, rng: StdRng) -> Self {
+        let capacity = capacity.max(1);
+        let buck = iter::repeat_n(Bucket::new(), capacity).collect::<Vec<_>>();
+        Self::from_buckets(buck, rng)
+    }
+
+    fn try_with_capacity_and_rng(capacity: usize, rng: StdRng) -> Result<Self, CuckooError> {
+        let capacity = capacity.max(1);
+        let mut buck = Vec::new();
+        buck.try_reserve_exact(capacity)
+            .map_err(|_| telemetry::record(CuckooError::AllocationFailed { buckets: capacity }))?;
+        buck.resize(capacity, Bucket::new());
+        Ok(Self::from_buckets(buck, rng))
+    }
+
+    fn from_buckets(buckets: Vec<Bucket>, rng: StdRng) -> Self {
+        CuckooFilter {
+            size: 0,
+            pow: trailing_zeros(buckets.len()),
+            buckets: buckets.into_boxed_slice(),
+            rng,
+            max_duplicates: None,
+            displaced: false,
+            sorted_buckets: false,
+            max_kicks: MAX_CUCKOO_COUNT,
+            evictor: Evictor::Random,
+            hasher: KeyHasherBuilder::default(),
         }
-        false
     }
+}
 
+impl<H: BuildHasher> CuckooFilter<H> {
+    /// Creates a filter like `new` that hashes keys with `hasher`, e.g. a
+    /// faster or a keyed one.
+    ///
+    /// Keys are hashed with a single `Hasher::write` of their bytes. Only
+    /// filters using the default `KeyHasherBuilder` can be serialized, as a
+    /// reader would otherwise look keys up with the wrong hash.
+    ///
     /// # Example
     /// ```
     /// use dakv_cuckoo::CuckooFilter;
-    /// let cuckoo = CuckooFilter::default();
+    /// use std::collections::hash_map::RandomState;
     ///
-    /// println!("size: {}", cuckoo.size());
+    /// let mut cf = CuckooFilter::with_hasher(1000, RandomState::new());
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.contains(b"test"));
     /// ```
-    pub fn size(&self) -> usize {
-        self.size
+    pub fn with_hasher(max_num_keys: u64, hasher: H) -> Self {
+        CuckooFilter::new(max_num_keys).replace_hasher(hasher)
     }
-}
 
-impl Default for CuckooFilter {
-    fn default() -> Self {
-        // About 16 million
-        CuckooFilter::new(1 << 24)
+    /// Returns the hasher keys are hashed with.
+    pub fn hasher(&self) -> &H {
+        &self.hasher
     }
-}
 
-fn rand_index(i1: u64, i2: u64) -> u64 {
-    if random() {
-        i1
-    } else {
-        i2
+    /// Limits how many copies of one fingerprint a bucket pair may hold.
+    ///
+    /// Without a limit, a client repeating a single key can fill both of its
+    /// buckets (eight slots) and push unrelated keys into long eviction
+    /// chains. Once the limit is reached, `add` fails with
+    /// `CuckooError::TooManyDuplicates`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooError, CuckooFilter};
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.set_max_duplicates(Some(2));
+    /// assert!(cf.add(b"test").is_ok());
+    /// assert!(cf.add(b"test").is_ok());
+    /// assert!(matches!(cf.add(b"test"), Err(CuckooError::TooManyDuplicates(_))));
+    /// ```
+    pub fn set_max_duplicates(&mut self, max_duplicates: Option<usize>) {
+        self.max_duplicates = max_duplicates;
     }
-}
 
-fn trailing_zeros(c: usize) -> usize {
-    if c == 0 {
-        return 64;
+    /// Sets how many kicks an insertion tries before it fails. Latency
+    /// sensitive callers can fail fast instead of walking long chains near
+    /// capacity.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.set_max_kicks(50);
+    /// assert_eq!(cf.max_kicks(), 50);
+    /// ```
+    pub fn set_max_kicks(&mut self, max_kicks: usize) {
+        self.max_kicks = max_kicks;
     }
-    let cc = (c as u64 & (c as i64 * (-1)) as u64).wrapping_mul(DE_BRUIJN64);
-    DE_BRUIJN64_TAB[(cc as usize).wrapping_shr(64 - 6)]
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::cuckoo_filter::{gen_size, trailing_zeros};
-    use crate::CuckooFilter;
+    /// Sets how kicks pick the fingerprint to evict, see `EvictionPolicy`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, EvictionPolicy};
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.set_eviction_policy(EvictionPolicy::LeastRecentlyPlaced);
+    /// assert_eq!(cf.eviction_policy(), EvictionPolicy::LeastRecentlyPlaced);
+    /// ```
+    pub fn set_eviction_policy(&mut self, policy: EvictionPolicy) {
+        self.evictor = match policy {
+            EvictionPolicy::Random => Evictor::Random,
+            EvictionPolicy::RoundRobin => Evictor::RoundRobin(BUCKET_SIZE - 1),
+            EvictionPolicy::LeastRecentlyPlaced => {
+                Evictor::LeastRecentlyPlaced(vec![0; self.buckets.len()].into_boxed_slice())
+            }
+        };
+    }
 
-    #[test]
-    fn test_trailing_zeros() {
-        assert_eq!(trailing_zeros(1 << 24), 24);
-        assert_eq!(trailing_zeros(100), 2);
-        assert_eq!(trailing_zeros(64), 6);
-        assert_eq!(trailing_zeros(0), 64);
-        assert_eq!(trailing_zeros(1), 0);
-        assert_eq!(trailing_zeros(3), 0);
-        assert_eq!(trailing_zeros(8), 3);
+    pub fn eviction_policy(&self) -> EvictionPolicy {
+        match self.evictor {
+            Evictor::Random => EvictionPolicy::Random,
+            Evictor::RoundRobin(_) => EvictionPolicy::RoundRobin,
+            Evictor::LeastRecentlyPlaced(_) => EvictionPolicy::LeastRecentlyPlaced,
+        }
     }
 
-    #[test]
-    fn test_gen_size() {
-        assert_eq!(gen_size(100), 32);
-        assert_eq!(gen_size(64), 32);
+    /// Returns the slot of full bucket `i` the next kick evicts.
+    fn victim_slot(&mut self, i: usize) -> usize {
+        match &mut self.evictor {
+            Evictor::Random => self.rng.gen_range(0, BUCKET_SIZE),
+            Evictor::RoundRobin(last) => {
+                *last = (*last + 1) % BUCKET_SIZE;
+                *last
+            }
+            Evictor::LeastRecentlyPlaced(next) => {
+                let j = usize::from(next[i]);
+                next[i] = ((j + 1) % BUCKET_SIZE) as u8;
+                j
+            }
+        }
     }
 
-    #[test]
-    fn test_add() {
-        let mut cf = CuckooFilter::new(100);
-        for _ in 0..8 {
-            let result = cf.add(b"test");
-            assert!(result.is_ok());
+    /// Counts the copies of `finger.fp` stored in its two candidate buckets.
+    pub(crate) fn count_finger(&self, finger: &FingerIndex) -> usize {
+        let count = |i: u64| {
+            self.buckets[i as usize]
+                .fingerprints()
+                .filter(|fp| *fp == finger.fp)
+                .count()
+        };
+        if finger.i1 == finger.i2 {
+            count(finger.i1)
+        } else {
+            count(finger.i1) + count(finger.i2)
         }
-        assert_eq!(cf.size(), 8);
-        for _ in 0..8 {
-            let result = cf.add(b"test");
-            assert!(!result.is_ok());
+    }
+
+    pub(crate) fn check_duplicates(&self, finger: &FingerIndex) -> CResult<CuckooError> {
+        match self.max_duplicates {
+            Some(max) if self.count_finger(finger) >= max => Err(telemetry::record(
+                CuckooError::TooManyDuplicates(finger.placement()),
+            )),
+            _ => Ok(()),
         }
-        assert_eq!(cf.size(), 8);
     }
 
-    #[test]
-    fn test_delete() {
-        let mut cf = CuckooFilter::default();
-        let _ = cf.add(b"test");
-        assert_eq!(cf.size(), 1);
-        assert!(cf.contains(b"test"));
-        assert!(cf.delete(b"test"));
-        assert_eq!(cf.size(), 0);
-        assert!(!cf.contains(b"test"));
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    ///
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test");
+    /// ```
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        let finger = self.finger_of(item);
+        self.add_finger(&finger)
+    }
+
+    /// Adds a UUID, hashing its 128-bit value directly.
+    ///
+    /// UUIDs added this way can only be found with `contains_uuid`, not by
+    /// calling `contains` on their bytes.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// use uuid::Uuid;
+    ///
+    /// let mut cf = CuckooFilter::tiny();
+    /// let id = Uuid::from_u128(0x67e5_5044_10b1_426f_9247_bb68_0e5f_e0c8);
+    /// cf.add_uuid(&id).unwrap();
+    /// assert!(cf.contains_uuid(&id));
+    /// ```
+    #[cfg(feature = "uuid")]
+    pub fn add_uuid(&mut self, id: &uuid::Uuid) -> CResult<CuckooError> {
+        let finger = self.finger(hash_u128(id.as_u128()));
+        self.add_finger(&finger)
+    }
+
+    /// Adds every item, reporting the outcome of each one separately.
+    ///
+    /// Every item is stored as `add` would store it, including a second copy
+    /// of a fingerprint that is already present, unless `max_duplicates`
+    /// rejects it. A failed item does not stop the batch, so only the failed
+    /// subset needs retrying.
+    ///
+    /// Items are hashed `DEFAULT_PIPELINE_DEPTH` ahead of being stored, with
+    /// their buckets prefetched, as `InsertPipeline` does.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, ItemOutcome};
+    ///
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.set_max_duplicates(Some(2));
+    /// let report = cf.add_all(&[&b"a"[..], b"b", b"a", b"a"]);
+    /// assert_eq!(report.inserted(), 3);
+    /// assert!(matches!(report.outcomes[3], ItemOutcome::TooManyDuplicates { .. }));
+    /// assert_eq!(report.failed().count(), 0);
+    /// ```
+    pub fn add_all<I>(&mut self, items: I) -> BatchReport
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        self.add_all_with(items, &Cancellation::default())
+            .unwrap_or_else(|partial| partial)
+    }
+
+    /// Like `add_all`, but gives up once `cancel` says so, checking it every
+    /// `YIELD_EVERY` items.
+    ///
+    /// A stopped batch returns `Err` with the outcomes of the items handled
+    /// so far, which stay inserted; the remaining items are not consumed.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CancelToken, Cancellation, CuckooFilter};
+    ///
+    /// let mut cf = CuckooFilter::new(100_000);
+    /// let token = CancelToken::new();
+    /// token.cancel();
+    /// let keys = (0..50_000u32).map(|i| i.to_le_bytes());
+    /// let partial = cf.add_all_with(keys, &Cancellation::new().token(token)).unwrap_err();
+    /// assert!(partial.outcomes.len() < 50_000);
+    /// assert_eq!(cf.size(), partial.inserted());
+    /// ```
+    pub fn add_all_with<I>(
+        &mut self,
+        items: I,
+        cancel: &Cancellation,
+    ) -> Result<BatchReport, BatchReport>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut report = BatchReport::default();
+        let completed = self.for_each_prefetched(items, cancel, |cf, finger| {
+            let outcome = match cf.check_duplicates(finger) {
+                Err(_) => ItemOutcome::TooManyDuplicates {
+                    placement: finger.placement(),
+                },
+                Ok(()) => match cf.place(finger) {
+                    Ok(kicks) => ItemOutcome::Inserted { kicks },
+                    Err(_) => ItemOutcome::Failed {
+                        kicks: cf.max_kicks,
+                        placement: finger.placement(),
+                    },
+                },
+            };
+            report.outcomes.push(outcome);
+        });
+        if completed {
+            Ok(report)
+        } else {
+            Err(report)
+        }
+    }
+
+    /// Hashes `items` and prefetches their buckets `DEFAULT_PIPELINE_DEPTH`
+    /// items before passing them to `apply`, in order. Returns `false` if
+    /// `cancel` stopped it, after applying every item taken so far.
+    fn for_each_prefetched<I, F>(&mut self, items: I, cancel: &Cancellation, mut apply: F) -> bool
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+        F: FnMut(&mut Self, &FingerIndex),
+    {
+        let mut queue = VecDeque::with_capacity(DEFAULT_PIPELINE_DEPTH);
+        let mut completed = true;
+        for (n, item) in items.into_iter().enumerate() {
+            if n % YIELD_EVERY == 0 && cancel.is_stopped() {
+                completed = false;
+                break;
+            }
+            if queue.len() == DEFAULT_PIPELINE_DEPTH {
+                if let Some(finger) = queue.pop_front() {
+                    apply(self, &finger);
+                }
+            }
+            let finger = self.finger_of(item.as_ref());
+            self.prefetch(&finger);
+            queue.push_back(finger);
+        }
+        for finger in queue {
+            apply(self, &finger);
+        }
+        completed
+    }
+
+    /// Computes the placement of an item, hashing it with the filter's
+    /// hasher.
+    pub(crate) fn finger_of(&self, item: &[u8]) -> FingerIndex {
+        let mut hasher = self.hasher.build_hasher();
+        hasher.write(item);
+        get_indices_from_hash(hasher.finish(), self.pow)
+    }
+
+    /// Computes the placement of an item from its precomputed hash.
+    pub(crate) fn finger(&self, item_hash: u64) -> FingerIndex {
+        get_indices_from_hash(item_hash, self.pow)
+    }
+
+    /// Hints the CPU to start loading both candidate buckets of `finger`.
+    ///
+    /// A no-op unless built with `unsafe-optimizations`.
+    #[cfg_attr(feature = "unsafe-optimizations", allow(unsafe_code))]
+    pub(crate) fn prefetch(&self, finger: &FingerIndex) {
+        #[cfg(all(feature = "unsafe-optimizations", target_arch = "x86_64"))]
+        for &i in &[finger.i1, finger.i2] {
+            use std::arch::x86_64::{_mm_prefetch, _MM_HINT_T0};
+            let bucket: *const Bucket = &self.buckets[i as usize];
+            // SAFETY: prefetching is only a hint and never faults, and the
+            // pointer comes from a live reference anyway.
+            unsafe { _mm_prefetch::<_MM_HINT_T0>(bucket as *const i8) };
+        }
+        #[cfg(not(all(feature = "unsafe-optimizations", target_arch = "x86_64")))]
+        let _ = finger;
+    }
+
+    /// Rebuilds the placement of a fingerprint from either of its buckets.
+    pub(crate) fn finger_at(&self, fp: u8, index: u64) -> FingerIndex {
+        let i2 = get_alt_index(fp, index, self.pow);
+        FingerIndex { fp, i1: index, i2 }
+    }
+
+    /// Returns `true` if `i1` and `i2` are a valid bucket pair for `fp` in
+    /// this filter.
+    pub(crate) fn is_placement(&self, i1: usize, i2: usize, fp: u8) -> bool {
+        i1 < self.reachable_buckets() && get_alt_index(fp, i1 as u64, self.pow) == i2 as u64
+    }
+
+    pub(crate) fn add_finger(&mut self, finger: &FingerIndex) -> CResult<CuckooError> {
+        self.check_duplicates(finger)?;
+        self.place(finger).map(|_| ())
+    }
+
+    /// Inserts a fingerprint, returning how many kicks it took.
+    ///
+    /// On failure every kick is undone, so the filter is left unchanged.
+    pub(crate) fn place(&mut self, finger: &FingerIndex) -> Result<usize, CuckooError> {
+        self.place_tracked(finger).map(|(kicks, _)| kicks)
+    }
+
+    /// Like `place`, but also returns the indices of the buckets it modified,
+    /// in modification order and possibly repeated.
+    pub(crate) fn place_tracked(
+        &mut self,
+        finger: &FingerIndex,
+    ) -> Result<(usize, Vec<usize>), CuckooError> {
+        let (mut journal, size) = (Vec::new(), self.size);
+        match self.place_logged(finger, &mut journal) {
+            Ok(kicks) => {
+                self.assert_invariants("add", &journal, size);
+                Ok((kicks, journal.into_iter().map(|(i, _)| i).collect()))
+            }
+            Err(_) => {
+                self.rollback(journal, size);
+                Err(telemetry::record(CuckooError::NotEnoughSpace(
+                    finger.placement(),
+                )))
+            }
+        }
+    }
+
+    /// Like `place`, but on failure keeps the kicks and hands the fingerprint
+    /// that was left without a slot to `spill`, returning its result.
+    /// `finger` itself is stored in that case, unless `spill` fails: then
+    /// every kick is undone and its error returned, so the filter is left
+    /// unchanged and nothing is lost.
+    pub(crate) fn place_or_spill<T, E>(
+        &mut self,
+        finger: &FingerIndex,
+        spill: impl FnOnce(Victim) -> Result<T, E>,
+    ) -> Result<Option<T>, E> {
+        let (mut journal, size) = (Vec::new(), self.size);
+        let result = match self.place_logged(finger, &mut journal) {
+            Ok(_) => Ok(None),
+            Err(victim) => spill(victim).map(Some),
+        };
+        if result.is_ok() {
+            self.assert_invariants("add", &journal, size);
+        } else {
+            self.rollback(journal, size);
+        }
+        result
+    }
+
+    fn place_logged<J: Journal>(
+        &mut self,
+        finger: &FingerIndex,
+        journal: &mut J,
+    ) -> Result<usize, Victim> {
+        // Always trying i1 first is what lets lookups skip i2 until the
+        // first displacement.
+        if self.insert(finger.fp, finger.i1, journal) {
+            telemetry::record_kicks(0);
+            return Ok(0);
+        }
+        self.displaced = true;
+        if self.insert(finger.fp, finger.i2, journal) {
+            telemetry::record_kicks(0);
+            return Ok(0);
+        }
+        let i = if self.rng.gen() { finger.i1 } else { finger.i2 };
+        let result = self.reinsert(finger.fp, i, journal);
+        telemetry::record_kicks(result.as_ref().map_or(self.max_kicks, |&kicks| kicks));
+        result
+    }
+
+    fn insert<J: Journal>(&mut self, fp: u8, i: u64, journal: &mut J) -> bool {
+        let index = i as usize % self.buckets.len();
+        let before = self.buckets[index];
+        if self.buckets[index].insert(fp) {
+            self.keep_sorted(index);
+            journal.record(index, before);
+            self.size += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn reinsert<J: Journal>(
+        &mut self,
+        mut fp: u8,
+        mut i: u64,
+        journal: &mut J,
+    ) -> Result<usize, Victim> {
+        for kicks in 1..=self.max_kicks {
+            let j = self.victim_slot(i as usize);
+            journal.record(i as usize, self.buckets[i as usize]);
+            // Kicks only start from full buckets, so slot `j` is taken.
+            fp = self.buckets[i as usize].replace(j, fp);
+            self.keep_sorted(i as usize);
+
+            i = get_alt_index(fp, i, self.pow);
+            if self.insert(fp, i, journal) {
+                return Ok(kicks);
+            }
+        }
+        Err(Victim { fp, index: i })
+    }
+
+    /// Applies `ops` in order, either all of them or, if an add fails, none.
+    ///
+    /// Deleting an absent item is not an error.
+    pub(crate) fn apply_ops(&mut self, ops: &[FilterOp]) -> CResult<CuckooError> {
+        let (mut journal, size) = (Vec::new(), self.size);
+        for op in ops {
+            let result = match op {
+                FilterOp::Add(item) => {
+                    let finger = self.finger_of(item);
+                    self.check_duplicates(&finger).and_then(|_| {
+                        self.place_logged(&finger, &mut journal)
+                            .map(|_| ())
+                            .map_err(|_| {
+                                telemetry::record(CuckooError::NotEnoughSpace(finger.placement()))
+                            })
+                    })
+                }
+                #[cfg(not(feature = "no-delete"))]
+                FilterOp::Delete(item) => {
+                    let finger = self.finger_of(item);
+                    let _ = self.remove(finger.fp, finger.i1, &mut journal)
+                        || self.remove(finger.fp, finger.i2, &mut journal);
+                    Ok(())
+                }
+            };
+            if let Err(e) = result {
+                self.rollback(journal, size);
+                return Err(e);
+            }
+        }
+        self.assert_invariants("apply", &journal, size);
+        Ok(())
+    }
+
+    /// Restores the buckets recorded in `journal`, most recent change first.
+    fn rollback(&mut self, journal: Vec<(usize, Bucket)>, size: usize) {
+        for &(i, bucket) in journal.iter().rev() {
+            self.buckets[i] = bucket;
+        }
+        self.size = size;
+        self.assert_invariants("rollback", &journal, size);
+    }
+
+    /// Verifies the structural invariants of the filter:
+    ///
+    /// - the recorded size matches the number of occupied slots,
+    /// - fingerprints only live in buckets addressable by the index mask,
+    /// - the alternate bucket of every fingerprint is addressable too, and
+    ///   maps back to the bucket it is stored in.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::new(100);
+    /// let _ = cf.add(b"test");
+    /// assert!(cf.check_invariants().is_ok());
+    /// ```
+    ///
+    /// It scans every bucket; `paranoid` builds check only the buckets each
+    /// mutation touched.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        self.check_mask()?;
+        (0..self.buckets.len()).try_for_each(|i| self.check_bucket(i))?;
+        let counted = self.buckets.iter().map(Bucket::len).sum();
+        if counted != self.size {
+            return Err(InvariantViolation::SizeMismatch {
+                recorded: self.size,
+                counted,
+            });
+        }
+        Ok(())
+    }
+
+    fn check_mask(&self) -> Result<(), InvariantViolation> {
+        if self.reachable_buckets() > self.buckets.len() {
+            return Err(InvariantViolation::MaskTooWide {
+                pow: self.pow,
+                num_buckets: self.buckets.len(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks the placement of every fingerprint in bucket `i`.
+    fn check_bucket(&self, i: usize) -> Result<(), InvariantViolation> {
+        let reachable = self.reachable_buckets();
+        for fp in self.buckets[i].fingerprints() {
+            if i >= reachable {
+                return Err(InvariantViolation::Unreachable { bucket: i, fp });
+            }
+            let alt = get_alt_index(fp, i as u64, self.pow);
+            if alt as usize >= reachable || get_alt_index(fp, alt, self.pow) != i as u64 {
+                return Err(InvariantViolation::IllegalPlacement { bucket: i, fp });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the buckets recorded in `journal` and that the size moved from
+    /// `size_before` by as many slots as they gained, panicking on a
+    /// violation. Checking only what `op` touched keeps each check as cheap
+    /// as the mutation; `check_invariants` scans the whole filter.
+    #[cfg(feature = "paranoid")]
+    fn assert_invariants<J: Journal>(&self, op: &str, journal: &J, size_before: usize) {
+        let mut first: Vec<(usize, Bucket)> = Vec::new();
+        for &(i, before) in journal.entries() {
+            if first.iter().all(|&(j, _)| j != i) {
+                first.push((i, before));
+            }
+        }
+        let counted = first.iter().fold(size_before, |size, (i, before)| {
+            size + self.buckets[*i].len() - before.len()
+        });
+        let result = self.check_mask().and_then(|_| {
+            first.iter().try_for_each(|&(i, _)| self.check_bucket(i))?;
+            if counted != self.size {
+                return Err(InvariantViolation::SizeMismatch {
+                    recorded: self.size,
+                    counted,
+                });
+            }
+            Ok(())
+        });
+        if let Err(violation) = result {
+            panic!(
+                "cuckoo filter invariant violated after {} (size {}, {} buckets, pow {}): {}",
+                op,
+                self.size,
+                self.buckets.len(),
+                self.pow,
+                violation
+            );
+        }
+    }
+
+    #[cfg(not(feature = "paranoid"))]
+    #[inline(always)]
+    fn assert_invariants<J: Journal>(&self, _op: &str, _journal: &J, _size_before: usize) {}
+
+    /// Number of buckets the index mask can address.
+    fn reachable_buckets(&self) -> usize {
+        if self.buckets.is_empty() {
+            0
+        } else {
+            1 << self.pow
+        }
+    }
+
+    /// Computes a placement for `item` and stores it, returning a guard that
+    /// either keeps the insertion with `commit` or reverts it with `abort`.
+    ///
+    /// The filter is borrowed until then, so nobody can observe the reserved
+    /// slot. Dropping the guard without committing aborts.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::new(100);
+    /// let prepared = cf.prepare_add(b"test").unwrap();
+    /// // ... write the row to the database ...
+    /// prepared.commit();
+    /// assert!(cf.contains(b"test"));
+    ///
+    /// let prepared = cf.prepare_add(b"other").unwrap();
+    /// prepared.abort();
+    /// assert_eq!(cf.size(), 1);
+    /// ```
+    pub fn prepare_add(&mut self, item: &[u8]) -> Result<PreparedInsert<'_, H>, CuckooError> {
+        let finger = self.finger_of(item);
+        self.check_duplicates(&finger)?;
+        let (mut journal, size) = (Vec::new(), self.size);
+        if self.place_logged(&finger, &mut journal).is_err() {
+            self.rollback(journal, size);
+            return Err(telemetry::record(CuckooError::NotEnoughSpace(
+                finger.placement(),
+            )));
+        }
+        self.assert_invariants("prepare_add", &journal, size);
+        Ok(PreparedInsert {
+            filter: self,
+            journal,
+            size,
+            committed: false,
+        })
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test");
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    pub fn contains(&self, data: &[u8]) -> bool {
+        let finger = self.finger_of(data);
+        self.contains_finger(&finger)
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// use uuid::Uuid;
+    ///
+    /// let cf = CuckooFilter::tiny();
+    /// assert!(!cf.contains_uuid(&Uuid::nil()));
+    /// ```
+    #[cfg(feature = "uuid")]
+    pub fn contains_uuid(&self, id: &uuid::Uuid) -> bool {
+        self.contains_finger(&self.finger(hash_u128(id.as_u128())))
+    }
+
+    /// Looks up every item in `items`, comparing the candidate buckets of
+    /// several keys at once on the SIMD path `active_acceleration` reports.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::new(100);
+    /// cf.add(b"a").unwrap();
+    /// assert_eq!(cf.contains_batch(&[b"a", b"b"]), vec![true, false]);
+    /// ```
+    pub fn contains_batch<T: AsRef<[u8]>>(&self, items: &[T]) -> Vec<bool> {
+        let mut out = Vec::with_capacity(items.len());
+        for group in items.chunks(accel::GROUP) {
+            let mut slots = [[0; accel::LANE]; accel::GROUP];
+            let mut fps = [0; accel::GROUP];
+            for ((item, lane), fp) in group.iter().zip(&mut slots).zip(&mut fps) {
+                let finger = self.finger_of(item.as_ref());
+                let (first, second) = lane.split_at_mut(BUCKET_SIZE);
+                first.copy_from_slice(&self.buckets[finger.i1 as usize].data());
+                if self.displaced {
+                    second.copy_from_slice(&self.buckets[finger.i2 as usize].data());
+                }
+                *fp = finger.fp;
+            }
+            out.extend_from_slice(&accel::match_lanes(&slots, &fps)[..group.len()]);
+        }
+        out
+    }
+
+    pub(crate) fn contains_finger(&self, finger: &FingerIndex) -> bool {
+        self.bucket_contains(finger.i1, finger.fp)
+            || (self.displaced && self.bucket_contains(finger.i2, finger.fp))
+    }
+
+    fn bucket_contains(&self, i: u64, fp: u8) -> bool {
+        self.match_slot(i, fp).is_some()
+    }
+
+    fn match_slot(&self, i: u64, fp: u8) -> Option<usize> {
+        let bucket = self.buckets[i as usize];
+        if self.sorted_buckets {
+            bucket.get_fingerprint_index_sorted(fp)
+        } else {
+            bucket.get_fingerprint_index(fp)
+        }
+    }
+
+    /// Reports how `contains` reaches its answer for `item`: the fingerprint,
+    /// both candidate buckets with their slots, and the comparison that
+    /// matched, for debugging unexpected answers.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let explanation = cf.explain(b"test");
+    /// assert_eq!(explanation.matched, Some((explanation.i1, 0)));
+    /// assert_eq!(cf.explain(b"other").matched.is_some(), cf.contains(b"other"));
+    /// println!("{}", explanation);
+    /// ```
+    pub fn explain(&self, item: &[u8]) -> Explanation {
+        let finger = self.finger_of(item);
+        let matched = self
+            .match_slot(finger.i1, finger.fp)
+            .map(|slot| (finger.i1 as usize, slot))
+            .or_else(|| {
+                self.match_slot(finger.i2, finger.fp)
+                    .filter(|_| self.displaced)
+                    .map(|slot| (finger.i2 as usize, slot))
+            });
+        Explanation {
+            fingerprint: finger.fp,
+            i1: finger.i1 as usize,
+            i2: finger.i2 as usize,
+            slots1: self.buckets[finger.i1 as usize].data(),
+            slots2: self.buckets[finger.i2 as usize].data(),
+            probed_alternate: self.displaced,
+            matched,
+        }
+    }
+
+    fn keep_sorted(&mut self, i: usize) {
+        if self.sorted_buckets {
+            self.buckets[i].sort();
+        }
+    }
+
+    /// Returns `true` once any fingerprint has been stored outside its
+    /// primary bucket, either because that bucket was full or through a kick.
+    ///
+    /// Until then every lookup only needs to probe one bucket, which
+    /// `contains` takes advantage of. The flag is never cleared, not even by
+    /// deletes.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::new(1000);
+    /// let _ = cf.add(b"test");
+    /// assert!(!cf.evictions_occurred());
+    /// ```
+    pub fn evictions_occurred(&self) -> bool {
+        self.displaced
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test");
+    /// assert!(cf.delete(b"test"));
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, data: &[u8]) -> bool {
+        let finger = self.finger_of(data);
+        self.delete_finger(&finger)
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// use uuid::Uuid;
+    ///
+    /// let mut cf = CuckooFilter::tiny();
+    /// let id = Uuid::from_u128(42);
+    /// cf.add_uuid(&id).unwrap();
+    /// assert!(cf.delete_uuid(&id));
+    /// ```
+    #[cfg(all(feature = "uuid", not(feature = "no-delete")))]
+    pub fn delete_uuid(&mut self, id: &uuid::Uuid) -> bool {
+        let finger = self.finger(hash_u128(id.as_u128()));
+        self.delete_finger(&finger)
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    pub(crate) fn delete_finger(&mut self, finger: &FingerIndex) -> bool {
+        let (mut journal, size) = (CheckJournal::default(), self.size);
+        let removed = self.remove(finger.fp, finger.i1, &mut journal)
+            || self.remove(finger.fp, finger.i2, &mut journal);
+        self.assert_invariants("delete", &journal, size);
+        removed
+    }
+
+    /// Empties the buckets in `range`, returning how many items they held.
+    #[cfg(not(feature = "no-delete"))]
+    pub(crate) fn clear_buckets(&mut self, range: std::ops::Range<usize>) -> usize {
+        let (mut journal, size) = (CheckJournal::default(), self.size);
+        let mut removed = 0;
+        for i in range {
+            journal.record(i, self.buckets[i]);
+            removed += self.buckets[i].len();
+            self.buckets[i].reset();
+        }
+        self.size -= removed;
+        self.assert_invariants("clear_buckets", &journal, size);
+        removed
+    }
+
+    /// Deletes every item yielded by `items`, returning how many were removed.
+    ///
+    /// Items are hashed and prefetched ahead of being deleted, as in
+    /// `add_all`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// let _ = cf.add(b"a");
+    /// let _ = cf.add(b"b");
+    /// assert_eq!(cf.delete_all_of(&[b"a", b"b", b"c"]), 2);
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete_all_of<I>(&mut self, items: I) -> usize
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut removed = 0;
+        self.for_each_prefetched(items, &Cancellation::default(), |cf, finger| {
+            removed += usize::from(cf.delete_finger(finger));
+        });
+        removed
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    fn remove<J: Journal>(&mut self, fp: u8, i: u64, journal: &mut J) -> bool {
+        let before = self.buckets[i as usize];
+        if self.buckets[i as usize].delete(fp) {
+            self.keep_sorted(i as usize);
+            journal.record(i as usize, before);
+            self.size -= 1;
+            return true;
+        }
+        false
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cuckoo = CuckooFilter::tiny();
+    ///
+    /// println!("size: {}", cuckoo.size());
+    /// ```
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the fingerprint `item` is stored under in this filter.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cf = CuckooFilter::new(100);
+    /// assert_ne!(cf.fingerprint(b"test"), 0);
+    /// ```
+    #[cfg(feature = "raw-api")]
+    pub fn fingerprint(&self, item: &[u8]) -> u8 {
+        self.finger_of(item).fp
+    }
+
+    /// Stores `fp` directly in bucket `bucket_index` without evicting anything.
+    ///
+    /// Returns `false` if `fp` is zero (the empty-slot marker), the index is out
+    /// of range, or the bucket is full. The caller is responsible for placing
+    /// each fingerprint in one of the two candidate buckets of its key, with
+    /// the same bucket count this filter was created with; otherwise lookups
+    /// for that key will miss.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::new(100);
+    /// let fp = cf.fingerprint(b"test");
+    /// assert!(!cf.insert_direct(0, 0));
+    /// assert!(cf.insert_direct(fp, 0));
+    /// assert_eq!(cf.size(), 1);
+    /// ```
+    #[cfg(feature = "raw-api")]
+    pub fn insert_direct(&mut self, fp: u8, bucket_index: usize) -> bool {
+        if fp == 0 || bucket_index >= self.reachable_buckets() {
+            return false;
+        }
+        let (mut journal, size) = (CheckJournal::default(), self.size);
+        let inserted = self.insert(fp, bucket_index as u64, &mut journal);
+        // The caller may be placing the fingerprint in its alternate bucket.
+        self.displaced |= inserted;
+        self.assert_invariants("insert_direct", &journal, size);
+        inserted
+    }
+
+    /// Re-adds a fingerprint stored in bucket `i` of a filter at least as large.
+    pub(crate) fn add_masked(&mut self, i: usize, fp: u8) -> CResult<CuckooError> {
+        // Masking keeps a fingerprint in the same half of its bucket pair,
+        // because the alternate index is derived by xor.
+        let finger = self.finger_at(fp, i as u64 & MASKS[self.pow]);
+        self.add_finger(&finger)
+    }
+
+    /// Records the versions of the buckets `item` maps to.
+    ///
+    /// A lookup made between taking a stamp and a successful `validate` saw a
+    /// consistent view of both buckets; if validation fails the reader should
+    /// retry. Clones keep the version history, so a stamp taken on one
+    /// snapshot can be validated against a later one.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::new(100);
+    /// let stamp = cf.read_stamp(b"test");
+    /// assert!(!cf.contains(b"test"));
+    /// assert!(cf.validate(&stamp));
+    /// let _ = cf.add(b"test");
+    /// assert!(!cf.validate(&stamp));
+    /// ```
+    #[cfg(feature = "bucket-versions")]
+    pub fn read_stamp(&self, item: &[u8]) -> ReadStamp {
+        let (i1, i2) = self.candidate_indices(item);
+        ReadStamp {
+            i1,
+            v1: self.buckets[i1].version(),
+            i2,
+            v2: self.buckets[i2].version(),
+        }
+    }
+
+    /// Returns `true` if neither bucket of `stamp` changed since it was taken.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cf = CuckooFilter::new(100);
+    /// let stamp = cf.read_stamp(b"test");
+    /// assert!(cf.validate(&stamp));
+    /// ```
+    #[cfg(feature = "bucket-versions")]
+    pub fn validate(&self, stamp: &ReadStamp) -> bool {
+        match (self.buckets.get(stamp.i1), self.buckets.get(stamp.i2)) {
+            (Some(b1), Some(b2)) => b1.version() == stamp.v1 && b2.version() == stamp.v2,
+            _ => false,
+        }
+    }
+
+    /// Reads both candidate buckets of every key in `sample_keys`, paging them
+    /// in and priming CPU caches before the filter starts serving traffic.
+    ///
+    /// Returns the number of buckets touched.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cf = CuckooFilter::new(100);
+    /// assert_eq!(cf.warmup(&[b"a", b"b"]), 4);
+    /// ```
+    pub fn warmup<I>(&self, sample_keys: I) -> usize
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut touched = 0;
+        for key in sample_keys {
+            let finger = self.finger_of(key.as_ref());
+            for i in [finger.i1, finger.i2] {
+                hint::black_box(self.buckets[i as usize]);
+                touched += 1;
+            }
+        }
+        touched
+    }
+
+    /// Returns the two buckets `item` may be stored in.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cf = CuckooFilter::new(100);
+    /// let (i1, i2) = cf.candidate_indices(b"test");
+    /// assert!(i1 < 32 && i2 < 32);
+    /// ```
+    pub fn candidate_indices(&self, item: &[u8]) -> (usize, usize) {
+        let finger = self.finger_of(item);
+        (finger.i1 as usize, finger.i2 as usize)
+    }
+
+    pub(crate) fn num_buckets(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub(crate) fn bucket_fingerprints(&self, i: usize) -> impl Iterator<Item = u8> + '_ {
+        self.buckets[i].fingerprints()
+    }
+
+    /// Returns bucket `i` in its serialized form.
+    pub(crate) fn bucket_data(&self, i: usize) -> [u8; BUCKET_SIZE] {
+        self.buckets[i].data()
+    }
+
+    /// Overwrites bucket `i` with its serialized form, e.g. to undo a change.
+    ///
+    /// The data may come from another filter that displaced fingerprints, so
+    /// lookups probe both buckets from then on unless it is empty.
+    pub(crate) fn set_bucket_data(&mut self, i: usize, data: [u8; BUCKET_SIZE]) {
+        let bucket = Bucket::from_data(data);
+        self.displaced |= !bucket.is_empty();
+        self.size = self.size + bucket.len() - self.buckets[i].len();
+        self.buckets[i] = bucket;
+        self.keep_sorted(i);
+    }
+
+    /// Returns the total number of fingerprint slots.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cuckoo = CuckooFilter::new(100);
+    /// assert_eq!(cuckoo.capacity(), 128);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * BUCKET_SIZE
+    }
+
+    /// Returns the width of one fingerprint in bits.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cf = CuckooFilter::tiny();
+    /// assert_eq!(cf.fingerprint_bits(), 8);
+    /// ```
+    pub fn fingerprint_bits(&self) -> u32 {
+        u32::from(format::FINGERPRINT_BITS)
+    }
+
+    /// Returns the number of fingerprint slots per bucket.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cf = CuckooFilter::tiny();
+    /// assert_eq!(cf.bucket_size(), 4);
+    /// ```
+    pub fn bucket_size(&self) -> usize {
+        BUCKET_SIZE
+    }
+
+    /// Returns how many kicks an insertion tries before it fails, 500
+    /// unless configured with `CuckooConfig::max_kicks`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let cf = CuckooFilter::tiny();
+    /// assert_eq!(cf.max_kicks(), 500);
+    /// ```
+    pub fn max_kicks(&self) -> usize {
+        self.max_kicks
+    }
+
+    /// Collects the gauges of this filter and the error and relocation
+    /// counters of the process into one value, which serializes with serde
+    /// under the `serde` feature.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let metrics = cf.metrics_snapshot();
+    /// assert_eq!((metrics.size, metrics.capacity), (1, 256));
+    /// assert!(metrics.false_positive_rate > 0.0);
+    /// ```
+    pub fn metrics_snapshot(&self) -> telemetry::MetricsSnapshot {
+        let load_factor = self.load_factor();
+        telemetry::MetricsSnapshot {
+            size: self.size,
+            capacity: self.capacity(),
+            num_buckets: self.buckets.len(),
+            load_factor,
+            false_positive_rate: quality::expected_fpr(load_factor, self.fingerprint_bits()),
+            fingerprint_bits: self.fingerprint_bits(),
+            max_kicks: self.max_kicks,
+            evictions_occurred: self.displaced,
+            errors: telemetry::error_stats(),
+            kicks: telemetry::kick_stats(),
+        }
+    }
+
+    /// Returns the fraction of occupied slots.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cuckoo = CuckooFilter::new(100);
+    /// let _ = cuckoo.add(b"test");
+    /// assert!(cuckoo.load_factor() > 0.0);
+    /// ```
+    pub fn load_factor(&self) -> f64 {
+        if self.buckets.is_empty() {
+            return 0.0;
+        }
+        self.size as f64 / self.capacity() as f64
+    }
+
+    /// Counts the slots taken by duplicate fingerprints of the same bucket
+    /// pair.
+    ///
+    /// Without a duplicate limit every `add` of a key already present stores
+    /// another copy, and so do distinct keys that collide on fingerprint and
+    /// buckets. The census shows how much capacity that costs, and so whether
+    /// `set_max_duplicates` is worth enabling. It walks every bucket once.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// cf.add(b"test").unwrap();
+    /// cf.add(b"test").unwrap();
+    /// cf.add(b"other").unwrap();
+    /// let census = cf.duplicate_census();
+    /// assert_eq!((census.groups, census.redundant_slots, census.max_copies), (1, 2, 3));
+    /// assert_eq!(census.redundant_fraction(), 0.5);
+    /// ```
+    pub fn duplicate_census(&self) -> DuplicateCensus {
+        let mut census = DuplicateCensus {
+            occupied: self.size,
+            ..DuplicateCensus::default()
+        };
+        for (i, bucket) in self.buckets.iter().enumerate() {
+            for (slot, fp) in bucket.fingerprints().enumerate() {
+                if bucket.fingerprints().take(slot).any(|seen| seen == fp) {
+                    continue;
+                }
+                let finger = self.finger_at(fp, i as u64);
+                // A group spanning two buckets is counted from the lower one.
+                let other = finger.i2 as usize;
+                if other < i && self.buckets[other].fingerprints().any(|seen| seen == fp) {
+                    continue;
+                }
+                let copies = self.count_finger(&finger);
+                if copies > 1 {
+                    census.groups += 1;
+                    census.redundant_slots += copies - 1;
+                    census.max_copies = census.max_copies.max(copies);
+                }
+            }
+        }
+        census
+    }
+
+    /// Iterates over every stored fingerprint as `(bucket_index, fingerprint)`.
+    ///
+    /// The order is always ascending by bucket and then by slot, so two filters
+    /// with the same layout yield identical sequences.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::new(100);
+    /// let _ = cf.add(b"test");
+    /// assert_eq!(cf.fingerprints().count(), 1);
+    /// ```
+    pub fn fingerprints(&self) -> impl Iterator<Item = (usize, u8)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .flat_map(|(i, b)| b.fingerprints().map(move |fp| (i, fp)))
+    }
+
+    /// Picks `n` distinct occupied slots uniformly at random, returned as
+    /// `(bucket_index, fingerprint)` in ascending order.
+    ///
+    /// Returns every entry if the filter holds fewer than `n`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// use rand::SeedableRng;
+    /// use rand::rngs::StdRng;
+    ///
+    /// let mut cf = CuckooFilter::new(100);
+    /// let _ = cf.add(b"test");
+    /// let sample = cf.sample_fingerprints(10, &mut StdRng::seed_from_u64(1));
+    /// assert_eq!(sample, cf.fingerprints().collect::<Vec<_>>());
+    /// ```
+    pub fn sample_fingerprints<R: Rng + ?Sized>(&self, n: usize, rng: &mut R) -> Vec<(usize, u8)> {
+        let mut sample = self.fingerprints().choose_multiple(rng, n);
+        sample.sort_unstable();
+        sample
+    }
+
+    fn encode(&self, sparse_len: Option<usize>) -> Vec<u8> {
+        let data_len = sparse_len.map_or(self.buckets.len() * format::BUCKET_BYTES, |len| 8 + len);
+        let mut out =
+            Vec::with_capacity(format::HEADER_LEN + data_len + format::CHECKSUM_SECTION_LEN);
+        out.extend_from_slice(&self.header(sparse_len.is_some()).encode());
+        if let Some(len) = sparse_len {
+            out.extend_from_slice(&(len as u64).to_le_bytes());
+        }
+        let start = out.len();
+        let mut sparse = sparse_len.map(|_| format::SparseEncoder::new());
+        encode_buckets(&self.buckets, sparse.as_mut(), &mut out);
+        let checksum = format::checksum(&out[start..]);
+        out.extend_from_slice(&format::checksum_section(checksum));
+        out
+    }
+
+    /// Returns the length of the sparse encoding of the buckets if it is
+    /// shorter than the buckets themselves, length prefix included.
+    fn sparse_len(&self) -> Option<usize> {
+        let raw_len = self.buckets.len() * format::BUCKET_BYTES;
+        let mut encoder = format::SparseEncoder::new();
+        let (mut scratch, mut len) = (Vec::new(), 0);
+        for bucket in self.buckets.iter() {
+            encode_buckets(slice::from_ref(bucket), Some(&mut encoder), &mut scratch);
+            len += scratch.len();
+            scratch.clear();
+            if 8 + len >= raw_len {
+                return None;
+            }
+        }
+        Some(len)
+    }
+
+    fn header(&self, sparse: bool) -> format::Header {
+        let sparse = if sparse { format::FLAG_SPARSE } else { 0 };
+        format::Header {
+            flags: format::FLAG_CHECKSUM | sparse,
+            pow: self.pow as u8,
+            num_buckets: self.buckets.len() as u64,
+            size: self.size as u64,
+        }
+    }
+
+    /// Moves the contents of the filter under `hasher`.
+    pub(crate) fn replace_hasher<G>(self, hasher: G) -> CuckooFilter<G> {
+        CuckooFilter {
+            buckets: self.buckets,
+            size: self.size,
+            pow: self.pow,
+            rng: self.rng,
+            max_duplicates: self.max_duplicates,
+            displaced: self.displaced,
+            sorted_buckets: self.sorted_buckets,
+            max_kicks: self.max_kicks,
+            evictor: self.evictor,
+            hasher,
+        }
+    }
+}
+
+impl CuckooFilter {
+    /// Moves `item` from one filter to another, hashing it only once.
+    ///
+    /// Returns `false` if `item` was not found in `from`, or if `to` has no
+    /// room for it; in the latter case `item` is put back into `from`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut hot = CuckooFilter::new(100);
+    /// let mut cold = CuckooFilter::new(1000);
+    /// let _ = hot.add(b"test");
+    /// assert!(CuckooFilter::move_item(&mut hot, &mut cold, b"test"));
+    /// assert!(!hot.contains(b"test"));
+    /// assert!(cold.contains(b"test"));
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn move_item(from: &mut CuckooFilter, to: &mut CuckooFilter, item: &[u8]) -> bool {
+        let item_hash = hash_key(item);
+        let src = from.finger(item_hash);
+        if !from.delete_finger(&src) {
+            return false;
+        }
+        if to.add_finger(&to.finger(item_hash)).is_ok() {
+            return true;
+        }
+        // The slot freed above is still empty, so putting it back cannot fail.
+        let _ = from.insert(src.fp, src.i1, &mut ()) || from.insert(src.fp, src.i2, &mut ());
+        false
+    }
+
+    /// Rebuilds this filter with the parameters of `new_config`.
+    ///
+    /// Shrinking, or keeping the bucket count, only needs the stored
+    /// fingerprints. Growing needs the original keys, which are read from
+    /// `key_source`; without it `CuckooError::NotSupported` is returned.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooFilter};
+    /// let mut cf = CuckooFilter::new(1000);
+    /// let _ = cf.add(b"test");
+    /// let small = cf.reencode(&CuckooConfig::new(100), None::<Vec<&[u8]>>).unwrap();
+    /// assert!(small.contains(b"test"));
+    /// let large = cf.reencode(&CuckooConfig::new(10000), Some(vec![b"test"])).unwrap();
+    /// assert!(large.contains(b"test"));
+    /// ```
+    pub fn reencode<I>(
+        &self,
+        new_config: &CuckooConfig,
+        key_source: Option<I>,
+    ) -> Result<CuckooFilter, CuckooError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut target = self.reencode_target(new_config);
+        if self.can_reencode_into(&target) {
+            for (i, fp) in self.fingerprints() {
+                target.add_masked(i, fp)?;
+            }
+            return Ok(target);
+        }
+        let keys = key_source.ok_or_else(|| telemetry::record(CuckooError::NotSupported))?;
+        for key in keys {
+            target.add(key.as_ref())?;
+        }
+        Ok(target)
+    }
+
+    /// Like `reencode`, but yields to the executor every `YIELD_EVERY`
+    /// entries, so rebuilding a large filter does not stall other tasks on
+    /// the same async runtime thread.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooFilter};
+    /// async fn shrink(cf: &CuckooFilter) -> CuckooFilter {
+    ///     cf.reencode_async(&CuckooConfig::new(100), None::<Vec<&[u8]>>)
+    ///         .await
+    ///         .unwrap()
+    /// }
+    /// ```
+    pub async fn reencode_async<I>(
+        &self,
+        new_config: &CuckooConfig,
+        key_source: Option<I>,
+    ) -> Result<CuckooFilter, CuckooError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        self.reencode_async_with(new_config, key_source, &Cancellation::default())
+            .await
+    }
+
+    /// Like `reencode_async`, but returns `CuckooError::Cancelled` once
+    /// `cancel` says so, checking it at every yield.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{Cancellation, CuckooConfig, CuckooFilter};
+    /// use std::time::Duration;
+    ///
+    /// async fn shrink(cf: &CuckooFilter) -> Option<CuckooFilter> {
+    ///     let cancel = Cancellation::new().timeout(Duration::from_secs(10));
+    ///     cf.reencode_async_with(&CuckooConfig::new(100), None::<Vec<&[u8]>>, &cancel)
+    ///         .await
+    ///         .ok()
+    /// }
+    /// ```
+    pub async fn reencode_async_with<I>(
+        &self,
+        new_config: &CuckooConfig,
+        key_source: Option<I>,
+        cancel: &Cancellation,
+    ) -> Result<CuckooFilter, CuckooError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut target = self.reencode_target(new_config);
+        if self.can_reencode_into(&target) {
+            for (n, (i, fp)) in self.fingerprints().enumerate() {
+                target.add_masked(i, fp)?;
+                if (n + 1) % YIELD_EVERY == 0 {
+                    yield_now().await;
+                    if cancel.is_stopped() {
+                        return Err(telemetry::record(CuckooError::Cancelled));
+                    }
+                }
+            }
+            return Ok(target);
+        }
+        let keys = key_source.ok_or_else(|| telemetry::record(CuckooError::NotSupported))?;
+        for (n, key) in keys.into_iter().enumerate() {
+            target.add(key.as_ref())?;
+            if (n + 1) % YIELD_EVERY == 0 {
+                yield_now().await;
+                if cancel.is_stopped() {
+                    return Err(telemetry::record(CuckooError::Cancelled));
+                }
+            }
+        }
+        Ok(target)
+    }
+
+    pub(crate) fn reencode_target(&self, new_config: &CuckooConfig) -> CuckooFilter {
+        let mut target = CuckooFilter::from_config(new_config);
+        if target.pow <= self.pow {
+            // Masked indexes are primary buckets again only if they were
+            // primary here, so an existing displacement carries over.
+            target.displaced = self.displaced;
+        }
+        target
+    }
+
+    /// Returns `true` if `target` can be filled from this filter's
+    /// fingerprints alone.
+    pub(crate) fn can_reencode_into(&self, target: &CuckooFilter) -> bool {
+        target.pow <= self.pow
+    }
+
+    /// Returns a hash of the parameters and the exact bucket layout.
+    ///
+    /// Two filters with the same digest hold the same fingerprints in the
+    /// same slots, which is what `replay::replay_trace` checks.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut a = CuckooFilter::tiny();
+    /// let mut b = CuckooFilter::tiny();
+    /// assert_eq!(a.digest(), b.digest());
+    /// a.add(b"test").unwrap();
+    /// assert_ne!(a.digest(), b.digest());
+    /// b.add(b"test").unwrap();
+    /// assert_eq!(a.digest(), b.digest());
+    /// ```
+    pub fn digest(&self) -> u64 {
+        seahash::hash(&self.to_bytes())
+    }
+
+    /// Renders the filter metadata as JSON for attaching to support tickets.
+    ///
+    /// With `include_buckets`, the occupied buckets are listed as well, each
+    /// with the fingerprints it holds; empty buckets are left out.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// let _ = cf.add(b"test");
+    /// let json = cf.to_debug_json(true);
+    /// assert!(json.starts_with('{'));
+    /// assert!(json.contains("\"size\":1"));
+    /// ```
+    pub fn to_debug_json(&self, include_buckets: bool) -> String {
+        use fmt::Write;
+
+        let mut out = String::new();
+        let max_duplicates = match self.max_duplicates {
+            Some(max) => max.to_string(),
+            None => "null".to_string(),
+        };
+        // Writing to a `String` cannot fail.
+        let _ = write!(
+            out,
+            "{{\"format_version\":{},\"bucket_size\":{},\"fingerprint_bits\":{},\
+             \"num_buckets\":{},\"reachable_buckets\":{},\"pow\":{},\"size\":{},\
+             \"capacity\":{},\"load_factor\":{},\"max_duplicates\":{}",
+            format::VERSION,
+            BUCKET_SIZE,
+            format::FINGERPRINT_BITS,
+            self.buckets.len(),
+            self.reachable_buckets(),
+            self.pow,
+            self.size,
+            self.capacity(),
+            self.load_factor(),
+            max_duplicates,
+        );
+        if include_buckets {
+            out.push_str(",\"buckets\":[");
+            let occupied = self
+                .buckets
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| !b.is_empty());
+            for (n, (i, bucket)) in occupied.enumerate() {
+                let fps = bucket
+                    .fingerprints()
+                    .map(|fp| fp.to_string())
+                    .collect::<Vec<_>>();
+                let sep = if n == 0 { "" } else { "," };
+                let _ = write!(
+                    out,
+                    "{}{{\"index\":{},\"fingerprints\":[{}]}}",
+                    sep,
+                    i,
+                    fps.join(",")
+                );
+            }
+            out.push(']');
+        }
+        out.push('}');
+        out
+    }
+
+    /// Serializes the filter in the layout described in `format`.
+    ///
+    /// A filter holding few items for its capacity has its buckets stored
+    /// sparse-encoded, whichever is shorter.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let bytes = cf.to_bytes();
+    /// assert_eq!(&bytes[..4], b"DKCF");
+    ///
+    /// let cf = CuckooFilter::new(1_000_000);
+    /// assert!(cf.to_bytes().len() < cf.to_bytes_raw().len() / 1000);
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.encode(self.sparse_len())
+    }
+
+    /// Like `to_bytes`, but yields to the executor after every chunk of
+    /// buckets, so serializing a large filter does not stall other tasks on
+    /// the same async runtime thread. Works on any runtime.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// async fn export(cf: &CuckooFilter) -> Vec<u8> {
+    ///     cf.to_bytes_async().await
+    /// }
+    /// ```
+    pub async fn to_bytes_async(&self) -> Vec<u8> {
+        let sparse_len = self.sparse_len();
+        let mut out = self.header(sparse_len.is_some()).encode().to_vec();
+        if let Some(len) = sparse_len {
+            out.extend_from_slice(&(len as u64).to_le_bytes());
+        }
+        let start = out.len();
+        let mut sparse = sparse_len.map(|_| format::SparseEncoder::new());
+        for buckets in self.buckets.chunks(STREAM_CHUNK / format::BUCKET_BYTES) {
+            encode_buckets(buckets, sparse.as_mut(), &mut out);
+            yield_now().await;
+        }
+        let crc = format::checksum(&out[start..]);
+        out.extend_from_slice(&format::checksum_section(crc));
+        out
+    }
+
+    /// Like `to_bytes`, but always stores the buckets as they are laid out
+    /// in memory, as `MmapCuckooFilter` needs.
+    pub fn to_bytes_raw(&self) -> Vec<u8> {
+        self.encode(None)
+    }
+
+    /// Like `to_bytes_raw`, adding a checksum for every `segment_buckets`
+    /// buckets, so `MmapCuckooFilter::open_quarantining` can set aside
+    /// damaged segments instead of rejecting the whole file.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{format, CuckooFilter};
+    /// let cf = CuckooFilter::new(10_000);
+    /// let bytes = cf.to_bytes_segmented(256);
+    /// assert_ne!(bytes[format::FLAGS_OFFSET] & format::FLAG_SEGMENT_CHECKSUMS, 0);
+    /// assert_eq!(CuckooFilter::from_bytes(&bytes).unwrap().capacity(), cf.capacity());
+    /// ```
+    pub fn to_bytes_segmented(&self, segment_buckets: u32) -> Vec<u8> {
+        let mut out = self.encode(None);
+        out[format::FLAGS_OFFSET] |= format::FLAG_SEGMENT_CHECKSUMS;
+        let end = format::BUCKETS_OFFSET + self.buckets.len() * format::BUCKET_BYTES;
+        let data = &out[format::BUCKETS_OFFSET..end];
+        let section = format::segment_checksums_section(data, segment_buckets);
+        out.extend_from_slice(&section);
+        out
+    }
+
+    /// Loads a filter serialized by `to_bytes`.
+    ///
+    /// Everything the header declares is validated, and sections of flagged
+    /// capabilities that can safely be ignored are skipped. The layout does
+    /// not say whether anything was displaced, so lookups always probe both
+    /// buckets, and evictions are seeded afresh.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::format::FormatError;
+    /// use dakv_cuckoo::CuckooFilter;
+    ///
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let loaded = CuckooFilter::from_bytes(&cf.to_bytes()).unwrap();
+    /// assert!(loaded.contains(b"test"));
+    /// assert!(matches!(CuckooFilter::from_bytes(b"nope"), Err(FormatError::Truncated { .. })));
+    /// ```
+    #[deny(clippy::indexing_slicing)]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, format::FormatError> {
+        let header = format::Header::parse(bytes)?;
+        let raw_len = usize::try_from(header.num_buckets)
+            .ok()
+            .and_then(|n| n.checked_mul(format::BUCKET_BYTES))
+            .ok_or(format::FormatError::Truncated { field: "buckets" })?;
+        let rest = bytes.get(format::BUCKETS_OFFSET..).unwrap_or_default();
+        let sparse = header.flags & format::FLAG_SPARSE != 0;
+        let (stored, sections) = if sparse {
+            let (len, rest) = rest
+                .split_first_chunk::<8>()
+                .ok_or(format::FormatError::Truncated { field: "sparse" })?;
+            let len = format::check_sparse_len(u64::from_le_bytes(*len), raw_len)?;
+            rest.split_at_checked(len)
+                .ok_or(format::FormatError::Truncated { field: "buckets" })?
+        } else {
+            rest.split_at_checked(raw_len)
+                .ok_or(format::FormatError::Truncated { field: "buckets" })?
+        };
+        // Verified first, so damaged data is reported as such rather than
+        // as whatever inconsistency it happens to cause.
+        for (flag, section) in format::sections(header.flags, sections)? {
+            if flag == format::FLAG_CHECKSUM {
+                format::verify_checksum(section, format::checksum(stored))?;
+            }
+        }
+        let decoded;
+        let data = if sparse {
+            let mut raw = vec![0; raw_len];
+            let mut decoder = format::SparseDecoder::new();
+            decoder.push(stored, &mut raw)?;
+            decoder.finish()?;
+            decoded = raw;
+            decoded.as_slice()
+        } else {
+            stored
+        };
+        let buckets = decode_buckets(data)?;
+        let occupied = buckets.iter().map(Bucket::len).sum::<usize>() as u64;
+        if occupied != header.size {
+            return Err(format::FormatError::Mismatch {
+                field: "size",
+                expected: header.size,
+                found: occupied,
+            });
+        }
+        Ok(CuckooFilter {
+            buckets: buckets.into_boxed_slice(),
+            size: occupied as usize,
+            pow: header.pow as usize,
+            rng: StdRng::from_entropy(),
+            max_duplicates: None,
+            displaced: true,
+            sorted_buckets: false,
+            max_kicks: MAX_CUCKOO_COUNT,
+            evictor: Evictor::Random,
+            hasher: KeyHasherBuilder::default(),
+        })
+    }
+
+    /// Encodes `to_bytes` as standard base64, for pasting into text config.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.to_base64().starts_with("REtDRg"));
+    /// ```
+    pub fn to_base64(&self) -> String {
+        base64::encode(&self.to_bytes())
+    }
+
+    /// Loads a filter from `to_base64` output, with the exact bucket layout
+    /// it was encoded with. Whitespace in `text` is ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let loaded = CuckooFilter::from_base64(&cf.to_base64()).unwrap();
+    /// assert_eq!(loaded.to_bytes(), cf.to_bytes());
+    /// assert!(CuckooFilter::from_base64("not base64!").is_err());
+    /// ```
+    pub fn from_base64(text: &str) -> Result<Self, format::FormatError> {
+        Self::from_bytes(&base64::decode(text)?)
+    }
+
+    /// Returns one chunk of a dump and the iterator for the next call,
+    /// mirroring RedisBloom's `CF.SCANDUMP`.
+    ///
+    /// Iterator 0 returns the header; later calls return bucket data of up
+    /// to `SCAN_DUMP_CHUNK` bytes, and `(0, [])` ends the dump. Iterators are
+    /// byte offsets, so an interrupted dump resumes from the last iterator
+    /// received. The filter must not change while it is dumped. Chunks carry
+    /// this crate's layout, so Redis can store them but not query them.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    ///
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let mut copy = CuckooFilter::default();
+    /// let mut iter = 0;
+    /// loop {
+    ///     let (next, chunk) = cf.scan_dump(iter);
+    ///     if next == 0 {
+    ///         break;
+    ///     }
+    ///     copy.load_chunk(next, &chunk).unwrap();
+    ///     iter = next;
+    /// }
+    /// assert!(copy.contains(b"test"));
+    /// ```
+    pub fn scan_dump(&self, iter: u64) -> (u64, Vec<u8>) {
+        self.scan_dump_chunk(iter, SCAN_DUMP_CHUNK)
+    }
+
+    fn scan_dump_chunk(&self, iter: u64, limit: usize) -> (u64, Vec<u8>) {
+        if iter == 0 {
+            let header = format::Header {
+                flags: 0,
+                ..self.header(false)
+            };
+            return (1, header.encode().to_vec());
+        }
+        let raw_len = (self.buckets.len() * format::BUCKET_BYTES) as u64;
+        let start = iter - 1;
+        if start >= raw_len {
+            return (0, Vec::new());
+        }
+        let len = (raw_len - start).min(limit as u64) as usize;
+        let (start, mut chunk) = self.encode_range(start as usize, len);
+        chunk.drain(..start);
+        chunk.truncate(len);
+        (iter + len as u64, chunk)
+    }
+
+    /// Loads one chunk of a `scan_dump`, given the iterator returned with it,
+    /// mirroring RedisBloom's `CF.LOADCHUNK`.
+    ///
+    /// The header chunk replaces this filter with an empty one of the dumped
+    /// shape. Every other chunk overwrites the bytes it covers, so chunks may
+    /// be loaded again after an interruption without harm. A chunk outside
+    /// the bucket array is `Invalid` of `iterator` and changes nothing.
+    pub fn load_chunk(&mut self, iter: u64, chunk: &[u8]) -> Result<(), format::FormatError> {
+        if iter == 1 {
+            let header = format::Header::parse(chunk)?;
+            let num_buckets = usize::try_from(header.num_buckets).unwrap_or(usize::MAX);
+            let mut buckets = Vec::new();
+            buckets
+                .try_reserve_exact(num_buckets)
+                .map_err(|_| format::FormatError::Invalid {
+                    field: "num_buckets",
+                    value: header.num_buckets,
+                })?;
+            buckets.resize(num_buckets, Bucket::new());
+            *self = CuckooFilter {
+                pow: header.pow as usize,
+                displaced: true,
+                ..Self::from_buckets(buckets, StdRng::from_entropy())
+            };
+            return Ok(());
+        }
+        let raw_len = (self.buckets.len() * format::BUCKET_BYTES) as u64;
+        let start = iter
+            .checked_sub(chunk.len() as u64 + 1)
+            .filter(|_| iter - 1 <= raw_len)
+            .ok_or(format::FormatError::Invalid {
+                field: "iterator",
+                value: iter,
+            })?;
+        let (at, mut bytes) = self.encode_range(start as usize, chunk.len());
+        if let Some(dest) = bytes.get_mut(at..at + chunk.len()) {
+            dest.copy_from_slice(chunk);
+        }
+        let first = start as usize / format::BUCKET_BYTES;
+        for (i, bucket) in (first..).zip(decode_buckets(&bytes)?) {
+            self.set_bucket_data(i, bucket.data());
+        }
+        Ok(())
+    }
+
+    /// Encodes the buckets covering `len` bytes of bucket data from offset
+    /// `start`, returning where `start` falls in the result.
+    fn encode_range(&self, start: usize, len: usize) -> (usize, Vec<u8>) {
+        let first = start / format::BUCKET_BYTES;
+        let last = (start + len).div_ceil(format::BUCKET_BYTES);
+        let mut out = Vec::with_capacity((last - first) * format::BUCKET_BYTES);
+        encode_buckets(&self.buckets[first..last], None, &mut out);
+        (start - first * format::BUCKET_BYTES, out)
+    }
+
+    /// Streams the filter to `w` in the layout of `to_bytes`, a fixed-size
+    /// chunk at a time, so large filters are never copied whole.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let mut out = Vec::new();
+    /// cf.write_to(&mut out).unwrap();
+    /// assert_eq!(out, cf.to_bytes());
+    /// ```
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        let sparse_len = self.sparse_len();
+        w.write_all(&self.header(sparse_len.is_some()).encode())?;
+        if let Some(len) = sparse_len {
+            w.write_all(&(len as u64).to_le_bytes())?;
+        }
+        let mut sparse = sparse_len.map(|_| format::SparseEncoder::new());
+        let mut chunk = Vec::with_capacity(STREAM_CHUNK);
+        let mut crc = format::Crc32::new();
+        for buckets in self.buckets.chunks(STREAM_CHUNK / format::BUCKET_BYTES) {
+            chunk.clear();
+            encode_buckets(buckets, sparse.as_mut(), &mut chunk);
+            crc.update(&chunk);
+            w.write_all(&chunk)?;
+        }
+        w.write_all(&format::checksum_section(crc.finish()))
+    }
+
+    /// Loads a filter streamed by `write_to`, validating it like
+    /// `from_bytes`.
+    ///
+    /// Reads exactly one filter, including the sections of ignored
+    /// capabilities, so `r` is left at whatever follows it. Layout errors are
+    /// reported as `io::ErrorKind::InvalidData`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let bytes = cf.to_bytes();
+    /// let loaded = CuckooFilter::read_from(&mut &bytes[..]).unwrap();
+    /// assert!(loaded.contains(b"test"));
+    /// ```
+    pub fn read_from<R: io::Read>(r: &mut R) -> io::Result<Self> {
+        let mut head = [0; format::HEADER_LEN];
+        r.read_exact(&mut head)?;
+        let mut loader = StreamLoader::new(&head)?;
+        if loader.is_sparse() {
+            let mut len = [0; 8];
+            r.read_exact(&mut len)?;
+            loader.begin_sparse(u64::from_le_bytes(len))?;
+        }
+        let mut chunk = vec![0; STREAM_CHUNK];
+        loop {
+            let data = &mut chunk[..loader.next_chunk_len()];
+            if data.is_empty() {
+                break;
+            }
+            r.read_exact(data)?;
+            loader.push(data)?;
+        }
+        for flag in loader.sections() {
+            let mut len = [0; 8];
+            r.read_exact(&mut len)?;
+            let len = u64::from_le_bytes(len);
+            if flag == format::FLAG_CHECKSUM {
+                let mut section = [0; 4];
+                StreamLoader::check_checksum_len(len)?;
+                r.read_exact(&mut section)?;
+                loader.verify_checksum(&section)?;
+            } else if io::copy(&mut io::Read::take(&mut *r, len), &mut io::sink())? != len {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        loader.finish()
+    }
+
+    /// Writes the filter to a snapshot file at `path`, preceded by metadata
+    /// that `SnapshotInfo::peek` reads without loading the filter: the
+    /// creation time, size, capacity, fingerprint width, `hash_seed` and
+    /// `label`.
+    ///
+    /// `hash_seed` records the seed keys were hashed under before being
+    /// added, such as `SeedRotation::seed`; pass 0 for keys added directly.
+    /// Labels longer than 65535 bytes are `io::ErrorKind::InvalidInput`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use dakv_cuckoo::{CuckooFilter, SnapshotInfo};
+    ///
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// cf.save_snapshot("users.dkcs", "users", 0).unwrap();
+    /// assert_eq!(SnapshotInfo::peek("users.dkcs").unwrap().size, 1);
+    /// ```
+    pub fn save_snapshot<P: AsRef<std::path::Path>>(
+        &self,
+        path: P,
+        label: &str,
+        hash_seed: u64,
+    ) -> io::Result<()> {
+        snapshot::save(self, path.as_ref(), label, hash_seed)
+    }
+
+    /// Loads a snapshot written by `save_snapshot`, returning its metadata
+    /// along with the filter.
+    pub fn load_snapshot<P: AsRef<std::path::Path>>(
+        path: P,
+    ) -> io::Result<(SnapshotInfo, CuckooFilter)> {
+        snapshot::load(path.as_ref())
+    }
+
+    /// Like `write_to`, but for async writers, yielding to the executor
+    /// after every chunk so a large filter does not hold up the worker
+    /// thread.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    ///
+    /// async fn export(cf: &CuckooFilter) -> std::io::Result<Vec<u8>> {
+    ///     let mut out = Vec::new();
+    ///     cf.write_to_async(&mut out).await?;
+    ///     Ok(out)
+    /// }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn write_to_async<W>(&self, w: &mut W) -> io::Result<()>
+    where
+        W: tokio::io::AsyncWrite + Unpin,
+    {
+        use tokio::io::AsyncWriteExt;
+
+        let sparse_len = self.sparse_len();
+        w.write_all(&self.header(sparse_len.is_some()).encode())
+            .await?;
+        if let Some(len) = sparse_len {
+            w.write_u64_le(len as u64).await?;
+        }
+        let mut sparse = sparse_len.map(|_| format::SparseEncoder::new());
+        let mut chunk = Vec::with_capacity(STREAM_CHUNK);
+        let mut crc = format::Crc32::new();
+        for buckets in self.buckets.chunks(STREAM_CHUNK / format::BUCKET_BYTES) {
+            chunk.clear();
+            encode_buckets(buckets, sparse.as_mut(), &mut chunk);
+            crc.update(&chunk);
+            w.write_all(&chunk).await?;
+            yield_now().await;
+        }
+        w.write_all(&format::checksum_section(crc.finish())).await
+    }
+
+    /// Like `read_from`, but for async readers, yielding to the executor
+    /// after every chunk.
+    #[cfg(feature = "tokio")]
+    pub async fn read_from_async<R>(r: &mut R) -> io::Result<Self>
+    where
+        R: tokio::io::AsyncRead + Unpin,
+    {
+        use tokio::io::AsyncReadExt;
+
+        let mut head = [0; format::HEADER_LEN];
+        r.read_exact(&mut head).await?;
+        let mut loader = StreamLoader::new(&head)?;
+        if loader.is_sparse() {
+            loader.begin_sparse(r.read_u64_le().await?)?;
+        }
+        let mut chunk = vec![0; STREAM_CHUNK];
+        loop {
+            let data = &mut chunk[..loader.next_chunk_len()];
+            if data.is_empty() {
+                break;
+            }
+            r.read_exact(data).await?;
+            loader.push(data)?;
+            yield_now().await;
+        }
+        for flag in loader.sections() {
+            let len = r.read_u64_le().await?;
+            if flag == format::FLAG_CHECKSUM {
+                let mut section = [0; 4];
+                StreamLoader::check_checksum_len(len)?;
+                r.read_exact(&mut section).await?;
+                loader.verify_checksum(&section)?;
+            } else {
+                let mut section = (&mut *r).take(len);
+                if tokio::io::copy(&mut section, &mut tokio::io::sink()).await? != len {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+            }
+        }
+        loader.finish()
+    }
+
+    /// Writes the filter to a file at `path` in the layout of `to_bytes`
+    /// without blocking the async runtime, and waits until it is durable.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    ///
+    /// async fn checkpoint(cf: &CuckooFilter) -> std::io::Result<CuckooFilter> {
+    ///     cf.save("/tmp/filter.bin").await?;
+    ///     CuckooFilter::load("/tmp/filter.bin").await
+    /// }
+    /// ```
+    #[cfg(feature = "tokio")]
+    pub async fn save<P: AsRef<std::path::Path>>(&self, path: P) -> io::Result<()> {
+        use tokio::io::AsyncWriteExt;
+
+        let mut file = tokio::io::BufWriter::new(tokio::fs::File::create(path).await?);
+        self.write_to_async(&mut file).await?;
+        file.flush().await?;
+        file.get_ref().sync_all().await
+    }
+
+    /// Loads a filter written by `save`, validating it like `from_bytes`.
+    #[cfg(feature = "tokio")]
+    pub async fn load<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        let mut file = tokio::io::BufReader::new(tokio::fs::File::open(path).await?);
+        Self::read_from_async(&mut file).await
+    }
+
+    /// Archives the filter with rkyv, for `ArchivedCuckooFilter::access` to
+    /// query in place.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{ArchivedCuckooFilter, CuckooFilter};
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let bytes = cf.to_archive().unwrap();
+    /// assert!(ArchivedCuckooFilter::access(&bytes).unwrap().contains(b"test"));
+    /// ```
+    #[cfg(feature = "rkyv")]
+    pub fn to_archive(&self) -> Result<rkyv::util::AlignedVec, CuckooError> {
+        let image = archive::FilterImage {
+            pow: self.pow as u8,
+            size: self.size as u64,
+            max_duplicates: self.max_duplicates.map(|max| max as u64),
+            displaced: self.displaced,
+            buckets: self.buckets.iter().map(Bucket::data).collect(),
+        };
+        rkyv::to_bytes::<rkyv::rancor::Error>(&image).map_err(|_| {
+            telemetry::record(CuckooError::AllocationFailed {
+                buckets: self.buckets.len(),
+            })
+        })
+    }
+
+    /// Serializes the filter with postcard, for loading on targets of any
+    /// pointer width.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let bytes = cf.to_postcard().unwrap();
+    /// assert!(CuckooFilter::from_postcard(&bytes).unwrap().contains(b"test"));
+    /// ```
+    #[cfg(feature = "postcard")]
+    pub fn to_postcard(&self) -> Result<Vec<u8>, CuckooError> {
+        let repr = CompactRepr {
+            pow: self.pow as u8,
+            size: self.size as u64,
+            max_duplicates: self
+                .max_duplicates
+                .map(|max| u32::try_from(max).unwrap_or(u32::MAX)),
+            displaced: self.displaced,
+            buckets: self.buckets.iter().map(Bucket::data).collect(),
+        };
+        postcard::to_allocvec(&repr).map_err(|_| {
+            telemetry::record(CuckooError::AllocationFailed {
+                buckets: self.buckets.len(),
+            })
+        })
+    }
+
+    /// Loads a filter written by `to_postcard`, rejecting input that is
+    /// truncated, has trailing bytes or is internally inconsistent.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::format::FormatError;
+    /// use dakv_cuckoo::CuckooFilter;
+    /// let bytes = CuckooFilter::tiny().to_postcard().unwrap();
+    /// assert!(CuckooFilter::from_postcard(&bytes).is_ok());
+    /// assert!(matches!(
+    ///     CuckooFilter::from_postcard(&bytes[..bytes.len() - 1]),
+    ///     Err(FormatError::Truncated { .. })
+    /// ));
+    /// ```
+    #[cfg(feature = "postcard")]
+    pub fn from_postcard(bytes: &[u8]) -> Result<Self, format::FormatError> {
+        let (repr, rest) =
+            postcard::take_from_bytes::<CompactRepr>(bytes).map_err(|e| match e {
+                postcard::Error::DeserializeUnexpectedEnd => {
+                    format::FormatError::Truncated { field: "postcard" }
+                }
+                _ => format::FormatError::Invalid {
+                    field: "postcard",
+                    value: bytes.len() as u64,
+                },
+            })?;
+        if !rest.is_empty() {
+            return Err(format::FormatError::Invalid {
+                field: "trailing bytes",
+                value: rest.len() as u64,
+            });
+        }
+        if repr.pow >= 64 || (repr.buckets.len() as u64) < 1 << repr.pow {
+            return Err(format::FormatError::Invalid {
+                field: "pow",
+                value: u64::from(repr.pow),
+            });
+        }
+        let buckets = repr
+            .buckets
+            .into_iter()
+            .map(Bucket::from_data)
+            .collect::<Vec<_>>();
+        let occupied = buckets.iter().map(Bucket::len).sum::<usize>();
+        if occupied as u64 != repr.size {
+            return Err(format::FormatError::Mismatch {
+                field: "size",
+                expected: repr.size,
+                found: occupied as u64,
+            });
+        }
+        Ok(CuckooFilter {
+            buckets: buckets.into_boxed_slice(),
+            size: occupied,
+            pow: usize::from(repr.pow),
+            rng: StdRng::from_entropy(),
+            max_duplicates: repr.max_duplicates.map(|max| max as usize),
+            displaced: repr.displaced,
+            sorted_buckets: false,
+            max_kicks: MAX_CUCKOO_COUNT,
+            evictor: Evictor::Random,
+            hasher: KeyHasherBuilder::default(),
+        })
+    }
+
+    // The archive has already been validated by `ArchivedCuckooFilter`.
+    #[cfg(feature = "rkyv")]
+    pub(crate) fn from_image_parts(
+        buckets: Vec<Bucket>,
+        size: usize,
+        pow: usize,
+        max_duplicates: Option<usize>,
+        displaced: bool,
+    ) -> Self {
+        CuckooFilter {
+            buckets: buckets.into_boxed_slice(),
+            size,
+            pow,
+            rng: StdRng::from_entropy(),
+            max_duplicates,
+            displaced,
+            sorted_buckets: false,
+            max_kicks: MAX_CUCKOO_COUNT,
+            evictor: Evictor::Random,
+            hasher: KeyHasherBuilder::default(),
+        }
+    }
+}
+
+impl CuckooFilter<RuntimeHasher> {
+    /// Creates a filter hashing keys under the secret `key`, which untrusted
+    /// clients cannot aim at one bucket pair; see `RuntimeHasher::keyed`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    ///
+    /// let mut cf = CuckooFilter::with_key(1000, *b"0123456789abcdef");
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    #[cfg(feature = "siphash")]
+    pub fn with_key(max_num_keys: u64, key: [u8; 16]) -> Self {
+        CuckooFilter::with_hasher(max_num_keys, RuntimeHasher::keyed(key))
+    }
+
+    /// Like `to_bytes`, recording the hash algorithm and seed under
+    /// `format::FLAG_HASH_ALGORITHM`. `CuckooFilter::from_bytes` rejects the
+    /// output, as it would hash keys differently. Of a keyed filter, only a
+    /// check value of the key is recorded.
+    pub fn to_bytes_with_hasher(&self) -> Vec<u8> {
+        let mut out = self.encode(self.sparse_len());
+        out[format::FLAGS_OFFSET] |= format::FLAG_HASH_ALGORITHM;
+        let hasher = self.hasher();
+        let section = match hasher.key_check() {
+            Some(check) => {
+                let id = hasher.algorithm().id() | format::HASH_ALGORITHM_KEYED;
+                format::hash_algorithm_section(id, check)
+            }
+            None => format::hash_algorithm_section(hasher.algorithm().id(), hasher.seed()),
+        };
+        out.extend_from_slice(&section);
+        out
+    }
+
+    /// Loads a filter written by `to_bytes_with_hasher`, hashing keys with
+    /// the recorded algorithm. Algorithms this build was compiled without
+    /// are `Invalid`, and so are keyed filters, which need
+    /// `from_bytes_with_key`.
+    pub fn from_bytes_with_hasher(bytes: &[u8]) -> Result<Self, format::FormatError> {
+        let (filter, id, seed) = split_hash_algorithm(bytes)?;
+        let algorithm = HashAlgorithm::from_id(id).ok_or(format::FormatError::Invalid {
+            field: "hash_algorithm",
+            value: u64::from(id),
+        })?;
+        Ok(filter.replace_hasher(RuntimeHasher::new(algorithm, seed)))
+    }
+
+    /// Loads a keyed filter written by `to_bytes_with_hasher`. A `key` other
+    /// than the one it was built with is a `Mismatch` of `hash_key`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::CuckooFilter;
+    ///
+    /// let bytes = CuckooFilter::with_key(1000, [1; 16]).to_bytes_with_hasher();
+    /// assert!(CuckooFilter::from_bytes_with_key(&bytes, [1; 16]).is_ok());
+    /// assert!(CuckooFilter::from_bytes_with_key(&bytes, [2; 16]).is_err());
+    /// assert!(CuckooFilter::from_bytes_with_hasher(&bytes).is_err());
+    /// ```
+    #[cfg(feature = "siphash")]
+    pub fn from_bytes_with_key(bytes: &[u8], key: [u8; 16]) -> Result<Self, format::FormatError> {
+        let (filter, id, check) = split_hash_algorithm(bytes)?;
+        let hasher = RuntimeHasher::keyed(key);
+        let expected = hasher.algorithm().id() | format::HASH_ALGORITHM_KEYED;
+        if id != expected {
+            return Err(format::FormatError::Mismatch {
+                field: "hash_algorithm",
+                expected: u64::from(expected),
+                found: u64::from(id),
+            });
+        }
+        let computed = hasher.key_check().unwrap_or_default();
+        if check != computed {
+            return Err(format::FormatError::Mismatch {
+                field: "hash_key",
+                expected: check,
+                found: computed,
+            });
+        }
+        Ok(filter.replace_hasher(hasher))
+    }
+}
+
+/// Loads the filter in `bytes` written by `to_bytes_with_hasher`, returning
+/// it with the algorithm id and seed of its hash algorithm section.
+fn split_hash_algorithm(bytes: &[u8]) -> Result<(CuckooFilter, u8, u64), format::FormatError> {
+    let flags = bytes.get(format::FLAGS_OFFSET).copied().unwrap_or(0);
+    // The section of the highest flag bit comes last.
+    let (filter, section) = bytes
+        .len()
+        .checked_sub(format::HASH_ALGORITHM_SECTION_LEN)
+        .filter(|_| flags & format::FLAG_HASH_ALGORITHM != 0)
+        .and_then(|at| bytes.split_at_checked(at))
+        .ok_or(format::FormatError::Truncated {
+            field: "hash_algorithm",
+        })?;
+    let (len, data) = section
+        .split_first_chunk::<8>()
+        .ok_or(format::FormatError::Truncated {
+            field: "hash_algorithm",
+        })?;
+    if u64::from_le_bytes(*len) != data.len() as u64 {
+        return Err(format::FormatError::Invalid {
+            field: "hash_algorithm",
+            value: u64::from_le_bytes(*len),
+        });
+    }
+    let (id, seed) = format::parse_hash_algorithm_section(data)?;
+    let mut filter = filter.to_vec();
+    if let Some(flags) = filter.get_mut(format::FLAGS_OFFSET) {
+        *flags &= !format::FLAG_HASH_ALGORITHM;
+    }
+    // The recorded algorithm, not the build's hash profile, hashes the keys.
+    if let Some(profile) = filter.get_mut(format::HASH_PROFILE_OFFSET) {
+        *profile = format::HASH_PROFILE;
+    }
+    Ok((CuckooFilter::from_bytes(&filter)?, id, seed))
+}
+
+/// Decoding state of `read_from` and `read_from_async`, which only differ
+/// in how they read.
+struct StreamLoader {
+    header: format::Header,
+    num_buckets: usize,
+    buckets: Vec<Bucket>,
+    occupied: u64,
+    crc: format::Crc32,
+    sparse: Option<SparseInput>,
+}
+
+/// Sparse bucket data being decoded, with the number of bytes still to come.
+struct SparseInput {
+    left: u64,
+    decoder: format::SparseDecoder,
+    raw: Vec<u8>,
+}
+
+impl StreamLoader {
+    fn new(head: &[u8; format::HEADER_LEN]) -> io::Result<Self> {
+        let header = format::Header::parse(head).map_err(invalid_data)?;
+        let num_buckets = usize::try_from(header.num_buckets)
+            .map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        let mut buckets = Vec::new();
+        buckets
+            .try_reserve_exact(num_buckets)
+            .map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        Ok(StreamLoader {
+            header,
+            num_buckets,
+            buckets,
+            occupied: 0,
+            crc: format::Crc32::new(),
+            sparse: None,
+        })
+    }
+
+    fn is_sparse(&self) -> bool {
+        self.header.flags & format::FLAG_SPARSE != 0
+    }
+
+    /// Prepares to decode `len` bytes of sparse bucket data.
+    fn begin_sparse(&mut self, len: u64) -> io::Result<()> {
+        let raw_len = self
+            .num_buckets
+            .checked_mul(format::BUCKET_BYTES)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        format::check_sparse_len(len, raw_len).map_err(invalid_data)?;
+        let mut raw = Vec::new();
+        raw.try_reserve_exact(raw_len)
+            .map_err(|_| io::Error::from(io::ErrorKind::OutOfMemory))?;
+        raw.resize(raw_len, 0);
+        self.sparse = Some(SparseInput {
+            left: len,
+            decoder: format::SparseDecoder::new(),
+            raw,
+        });
+        Ok(())
+    }
+
+    /// Returns how many bytes of bucket data to read next, 0 once all are in.
+    fn next_chunk_len(&self) -> usize {
+        match &self.sparse {
+            Some(sparse) => sparse.left.min(STREAM_CHUNK as u64) as usize,
+            None => {
+                (self.num_buckets - self.buckets.len()).min(STREAM_CHUNK / format::BUCKET_BYTES)
+                    * format::BUCKET_BYTES
+            }
+        }
+    }
+
+    fn push(&mut self, data: &[u8]) -> io::Result<()> {
+        self.crc.update(data);
+        match &mut self.sparse {
+            Some(sparse) => {
+                sparse.left -= data.len() as u64;
+                sparse
+                    .decoder
+                    .push(data, &mut sparse.raw)
+                    .map_err(invalid_data)
+            }
+            None => self.push_buckets(data).map_err(invalid_data),
+        }
+    }
+
+    fn push_buckets(&mut self, data: &[u8]) -> Result<(), format::FormatError> {
+        for bucket in decode_buckets(data)? {
+            self.occupied += bucket.len() as u64;
+            self.buckets.push(bucket);
+        }
+        Ok(())
+    }
+
+    /// Returns the flags of the sections following the buckets, in order.
+    fn sections(&self) -> impl Iterator<Item = u8> {
+        let sections = self.header.ignored_flags() | (self.header.flags & format::FLAG_CHECKSUM);
+        (0..8)
+            .map(|bit| 1 << bit)
+            .filter(move |flag| sections & flag != 0)
+    }
+
+    fn check_checksum_len(len: u64) -> io::Result<()> {
+        if len == 4 {
+            Ok(())
+        } else {
+            Err(invalid_data(format::FormatError::Invalid {
+                field: "checksum",
+                value: len,
+            }))
+        }
+    }
+
+    fn verify_checksum(&self, section: &[u8; 4]) -> io::Result<()> {
+        format::verify_checksum(section, self.crc.finish()).map_err(invalid_data)
+    }
+
+    fn finish(mut self) -> io::Result<CuckooFilter> {
+        if let Some(sparse) = self.sparse.take() {
+            sparse.decoder.finish().map_err(invalid_data)?;
+            self.push_buckets(&sparse.raw).map_err(invalid_data)?;
+        }
+        if self.occupied != self.header.size {
+            return Err(invalid_data(format::FormatError::Mismatch {
+                field: "size",
+                expected: self.header.size,
+                found: self.occupied,
+            }));
+        }
+        Ok(CuckooFilter {
+            buckets: self.buckets.into_boxed_slice(),
+            size: self.occupied as usize,
+            pow: self.header.pow as usize,
+            rng: StdRng::from_entropy(),
+            max_duplicates: None,
+            displaced: true,
+            sorted_buckets: false,
+            max_kicks: MAX_CUCKOO_COUNT,
+            evictor: Evictor::Random,
+            hasher: KeyHasherBuilder::default(),
+        })
+    }
+}
+
+/// Appends `buckets` to `out` through the bucket codec, and through
+/// `sparse` if given.
+fn encode_buckets(
+    buckets: &[Bucket],
+    mut sparse: Option<&mut format::SparseEncoder>,
+    out: &mut Vec<u8>,
+) {
+    let mut encoded = Vec::with_capacity(format::BUCKET_BYTES);
+    for bucket in buckets {
+        encoded.clear();
+        format::BucketCodec::encode(&bucket.data(), &mut encoded);
+        match sparse.as_deref_mut() {
+            Some(encoder) => encoder.push(&encoded, out),
+            None => out.extend_from_slice(&encoded),
+        }
+    }
+}
+
+/// Decodes bucket data through the bucket codec.
+fn decode_buckets(data: &[u8]) -> Result<Vec<Bucket>, format::FormatError> {
+    data.chunks(format::BUCKET_BYTES)
+        .map(|bytes| format::BucketCodec::decode(bytes).map(Bucket::from_data))
+        .collect()
+}
+
+fn invalid_data(e: format::FormatError) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+impl Default for CuckooFilter {
+    fn default() -> Self {
+        // About 16 million
+        CuckooFilter::new(1 << 24)
+    }
+}
+
+// The eviction RNG is not part of the state: a deserialized filter answers
+// every query the same way, but draws a fresh seed for future kicks.
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize)]
+struct FilterRef<'a> {
+    pow: usize,
+    size: usize,
+    max_duplicates: Option<usize>,
+    displaced: bool,
+    buckets: &'a [Bucket],
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Deserialize)]
+struct FilterRepr {
+    pow: usize,
+    size: usize,
+    max_duplicates: Option<usize>,
+    displaced: bool,
+    buckets: Vec<Bucket>,
+}
+
+// The postcard layout. Every integer has a fixed type instead of `usize`, so
+// 32-bit and 64-bit targets agree on it.
+#[cfg(feature = "postcard")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CompactRepr {
+    pow: u8,
+    size: u64,
+    max_duplicates: Option<u32>,
+    displaced: bool,
+    buckets: Vec<[u8; BUCKET_SIZE]>,
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for CuckooFilter {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        FilterRef {
+            pow: self.pow,
+            size: self.size,
+            max_duplicates: self.max_duplicates,
+            displaced: self.displaced,
+            buckets: &self.buckets,
+        }
+        .serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CuckooFilter {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        let repr = FilterRepr::deserialize(deserializer)?;
+        if repr.pow >= 64 || repr.buckets.len() < 1 << repr.pow {
+            return Err(D::Error::custom(format_args!(
+                "pow {} does not fit {} buckets",
+                repr.pow,
+                repr.buckets.len()
+            )));
+        }
+        let occupied = repr.buckets.iter().map(Bucket::len).sum::<usize>();
+        if occupied != repr.size {
+            return Err(D::Error::custom(format_args!(
+                "size {} does not match {} occupied slots",
+                repr.size, occupied
+            )));
+        }
+        Ok(CuckooFilter {
+            buckets: repr.buckets.into_boxed_slice(),
+            size: repr.size,
+            pow: repr.pow,
+            rng: StdRng::from_entropy(),
+            max_duplicates: repr.max_duplicates,
+            displaced: repr.displaced,
+            sorted_buckets: false,
+            max_kicks: MAX_CUCKOO_COUNT,
+            evictor: Evictor::Random,
+            hasher: KeyHasherBuilder::default(),
+        })
+    }
+}
+
+fn trailing_zeros(c: usize) -> usize {
+    (c as u64).trailing_zeros() as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bucket::BUCKET_SIZE;
+    use crate::cuckoo_filter::InvariantViolation;
+    use crate::cuckoo_filter::{gen_size, trailing_zeros, MAX_CUCKOO_COUNT, STREAM_CHUNK};
+    use crate::format::{self, FormatError};
+    use crate::util::get_indices_and_fingerprint;
+    use crate::yielding::YIELD_EVERY;
+    use crate::{CuckooConfig, CuckooError, CuckooFilter, EvictionPolicy, ItemOutcome};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use std::collections::hash_map::RandomState;
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(64, 3);
+        for i in 0..200u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let bytes = cf.to_bytes();
+        assert_eq!(
+            bytes.len(),
+            format::HEADER_LEN + 64 * BUCKET_SIZE + format::CHECKSUM_SECTION_LEN
+        );
+        let back = CuckooFilter::from_bytes(&bytes).unwrap();
+        assert_eq!((back.pow, back.size), (cf.pow, cf.size));
+        assert!((0..200u16).all(|i| back.contains(&i.to_le_bytes())));
+
+        assert_eq!(
+            CuckooFilter::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(FormatError::Truncated { field: "checksum" })
+        );
+        let buckets_end = bytes.len() - format::CHECKSUM_SECTION_LEN;
+        assert_eq!(
+            CuckooFilter::from_bytes(&bytes[..buckets_end - 1]).err(),
+            Some(FormatError::Truncated { field: "buckets" })
+        );
+        let mut damaged = bytes.clone();
+        damaged[format::BUCKETS_OFFSET] ^= 0x10;
+        assert!(matches!(
+            CuckooFilter::from_bytes(&damaged),
+            Err(FormatError::Corrupted { .. })
+        ));
+        let err = CuckooFilter::read_from(&mut &damaged[..]).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        // Files written before checksums were added still load.
+        let mut legacy = bytes[..buckets_end].to_vec();
+        legacy[format::FLAGS_OFFSET] = 0;
+        assert!(CuckooFilter::from_bytes(&legacy).is_ok());
+        assert!(CuckooFilter::read_from(&mut &legacy[..]).is_ok());
+        let mut corrupt = bytes.clone();
+        corrupt[format::SIZE_OFFSET] ^= 1;
+        assert!(matches!(
+            CuckooFilter::from_bytes(&corrupt),
+            Err(FormatError::Mismatch { field: "size", .. })
+        ));
+        let mut foreign = bytes;
+        foreign[format::VERSION_OFFSET] = 9;
+        assert_eq!(
+            CuckooFilter::from_bytes(&foreign).err(),
+            Some(FormatError::UnsupportedVersion(9))
+        );
+    }
+
+    #[test]
+    fn test_byte_order_is_fixed() {
+        // Spelled out byte by byte, so a host of either endianness must
+        // produce and accept exactly this.
+        #[rustfmt::skip]
+        let golden = [
+            b'D', b'K', b'C', b'F', 1, format::FLAG_CHECKSUM, 4, 8,
+            2, format::HASH_PROFILE, 0, 0, 0, 0, 0, 0,
+            4, 0, 0, 0, 0, 0, 0, 0,
+            3, 0, 0, 0, 0, 0, 0, 0,
+            0x11, 0, 0, 0, 0, 0, 0, 0, 0x22, 0x33, 0, 0, 0, 0, 0, 0,
+            4, 0, 0, 0, 0, 0, 0, 0,
+            0xbe, 0x3e, 0x2c, 0x86,
+        ];
+        let cf = CuckooFilter::from_bytes(&golden).unwrap();
+        assert_eq!((cf.pow, cf.num_buckets(), cf.size()), (2, 4, 3));
+        assert_eq!(cf.to_bytes_raw(), golden);
+
+        // The same filter is shorter sparse-encoded, so that is what
+        // `to_bytes` and `write_to` produce.
+        #[rustfmt::skip]
+        let sparse = [
+            b'D', b'K', b'C', b'F', 1, format::FLAG_CHECKSUM | format::FLAG_SPARSE, 4, 8,
+            2, format::HASH_PROFILE, 0, 0, 0, 0, 0, 0,
+            4, 0, 0, 0, 0, 0, 0, 0,
+            3, 0, 0, 0, 0, 0, 0, 0,
+            6, 0, 0, 0, 0, 0, 0, 0,
+            0, 0x11, 7, 0x22, 0, 0x33,
+            4, 0, 0, 0, 0, 0, 0, 0,
+            0xb0, 0xd4, 0x8c, 0xf5,
+        ];
+        assert_eq!(cf.to_bytes(), sparse);
+        let mut streamed = Vec::new();
+        cf.write_to(&mut streamed).unwrap();
+        assert_eq!(streamed, sparse);
+        let back = CuckooFilter::from_bytes(&sparse).unwrap();
+        assert_eq!(back.to_bytes_raw(), golden);
+    }
+
+    #[test]
+    fn test_sparse_encoding() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(1 << 16, 3);
+        for i in 0..300u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let bytes = cf.to_bytes();
+        assert_ne!(bytes[format::FLAGS_OFFSET] & format::FLAG_SPARSE, 0);
+        assert!(bytes.len() < cf.to_bytes_raw().len() / 50);
+        let back = CuckooFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(back.to_bytes_raw(), cf.to_bytes_raw());
+        let streamed = CuckooFilter::read_from(&mut &bytes[..]).unwrap();
+        assert_eq!(streamed.to_bytes_raw(), cf.to_bytes_raw());
+
+        let mut damaged = bytes.clone();
+        damaged[format::BUCKETS_OFFSET + 9] ^= 0x40;
+        assert!(matches!(
+            CuckooFilter::from_bytes(&damaged),
+            Err(FormatError::Corrupted { .. })
+        ));
+        // Gaps running past the last slot are rejected, not written out of
+        // bounds.
+        let mut past_end = Vec::new();
+        past_end.extend_from_slice(&bytes[..format::BUCKETS_OFFSET]);
+        let data = [0xff, 0xff, 0xff, 0x7f, 0x11];
+        past_end.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        past_end.extend_from_slice(&data);
+        past_end.extend_from_slice(&format::checksum_section(format::checksum(&data)));
+        assert!(matches!(
+            CuckooFilter::from_bytes(&past_end),
+            Err(FormatError::Invalid {
+                field: "sparse slot",
+                ..
+            })
+        ));
+        assert!(CuckooFilter::read_from(&mut &past_end[..]).is_err());
+
+        // Filters that are mostly full stay raw.
+        let mut cf = CuckooFilter::with_capacity_and_seed(1 << 10, 3);
+        for i in 0..3600u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(cf.to_bytes(), cf.to_bytes_raw());
+    }
+
+    #[test]
+    fn test_scan_dump() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(64, 5);
+        for i in 0..200u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        // Chunks not aligned to buckets, loaded twice and out of order.
+        let (mut iter, mut chunks) = (0, Vec::new());
+        loop {
+            let (next, chunk) = cf.scan_dump_chunk(iter, 10);
+            if next == 0 {
+                break;
+            }
+            chunks.push((next, chunk));
+            iter = next;
+        }
+        assert_eq!(chunks.len(), 1 + (64 * BUCKET_SIZE).div_ceil(10));
+        let mut copy = CuckooFilter::tiny();
+        let (header, data) = chunks.split_first().unwrap();
+        copy.load_chunk(header.0, &header.1).unwrap();
+        for (next, chunk) in data.iter().rev().chain(data) {
+            copy.load_chunk(*next, chunk).unwrap();
+        }
+        assert_eq!(copy.to_bytes(), cf.to_bytes());
+        assert_eq!(cf.scan_dump(iter), (0, Vec::new()));
+
+        assert!(matches!(
+            copy.load_chunk(64 * BUCKET_SIZE as u64 + 2, b"x"),
+            Err(FormatError::Invalid {
+                field: "iterator",
+                ..
+            })
+        ));
+        assert!(copy.load_chunk(3, b"xyz").is_err());
+        assert_eq!(copy.to_bytes(), cf.to_bytes());
+    }
+    #[test]
+    fn test_stream_round_trip() {
+        use std::io::Read;
+
+        let mut cf = CuckooFilter::with_capacity_and_seed(1 << 15, 3);
+        for i in 0..50_000u32 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let mut out = Vec::new();
+        cf.write_to(&mut out).unwrap();
+        assert_eq!(out, cf.to_bytes());
+        out.extend_from_slice(b"next");
+
+        let mut input = &out[..];
+        let back = CuckooFilter::read_from(&mut input).unwrap();
+        assert_eq!((back.pow, back.size), (cf.pow, cf.size));
+        assert!((0..50_000u32).all(|i| back.contains(&i.to_le_bytes())));
+        let mut rest = Vec::new();
+        input.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b"next");
+
+        let short = &out[..out.len() - 5];
+        let err = CuckooFilter::read_from(&mut &short[..]).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[tokio::test]
+    async fn test_async_round_trip() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(1 << 15, 3);
+        for i in 0..50_000u32 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let mut out = Vec::new();
+        cf.write_to_async(&mut out).await.unwrap();
+        assert_eq!(out, cf.to_bytes());
+        let back = CuckooFilter::read_from_async(&mut &out[..]).await.unwrap();
+        assert_eq!(back.to_bytes(), out);
+
+        let path = std::env::temp_dir().join(format!("dakv_cuckoo_async_{}", std::process::id()));
+        cf.save(&path).await.unwrap();
+        assert_eq!(CuckooFilter::load(&path).await.unwrap().to_bytes(), out);
+        std::fs::remove_file(&path).unwrap();
+
+        let short = &out[..out.len() - 5];
+        let err = CuckooFilter::read_from_async(&mut &short[..])
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_corrupted_bytes_never_panic() {
+        use rand::Rng;
+
+        let mut cf = CuckooFilter::with_capacity_and_seed(16, 3);
+        for i in 0..40u8 {
+            cf.add(&[i]).unwrap();
+        }
+        let bytes = cf.to_bytes();
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..5000 {
+            let mut input = bytes.clone();
+            for _ in 0..rng.gen_range(1, 8) {
+                let at = rng.gen_range(0, input.len());
+                input[at] = rng.gen();
+            }
+            input.truncate(rng.gen_range(0, input.len() + 1));
+            if let Ok(back) = CuckooFilter::from_bytes(&input) {
+                back.contains(b"probe");
+            }
+        }
+    }
+
+    #[test]
+    fn test_degenerate_capacity() {
+        let mut cf = CuckooFilter::with_capacity(0);
+        assert_eq!(cf.num_buckets(), 1);
+        cf.add(b"x").unwrap();
+        assert!(cf.contains(b"x"));
+        assert!(CuckooFilter::try_with_capacity(0).is_ok());
+        assert!(matches!(
+            CuckooFilter::try_with_capacity(usize::MAX),
+            Err(CuckooError::AllocationFailed { .. })
+        ));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(64, 3);
+        for i in 0..200u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let json = serde_json::to_string(&cf).unwrap();
+        let back: CuckooFilter = serde_json::from_str(&json).unwrap();
+        assert_eq!((back.pow, back.size), (cf.pow, cf.size));
+        assert_eq!(
+            back.fingerprints().collect::<Vec<_>>(),
+            cf.fingerprints().collect::<Vec<_>>()
+        );
+        assert!((0..200u16).all(|i| back.contains(&i.to_le_bytes())));
+
+        let bad = json.replacen("\"size\":200", "\"size\":199", 1);
+        assert!(serde_json::from_str::<CuckooFilter>(&bad).is_err());
+    }
+
+    #[cfg(feature = "postcard")]
+    #[test]
+    fn test_postcard_round_trip() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(64, 3);
+        for i in 0..200u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        cf.set_max_duplicates(Some(2));
+        let bytes = cf.to_postcard().unwrap();
+        let back = CuckooFilter::from_postcard(&bytes).unwrap();
+        assert_eq!((back.pow, back.size), (cf.pow, cf.size));
+        assert_eq!(back.max_duplicates, Some(2));
+        assert_eq!(
+            back.fingerprints().collect::<Vec<_>>(),
+            cf.fingerprints().collect::<Vec<_>>()
+        );
+
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert!(matches!(
+            CuckooFilter::from_postcard(&trailing),
+            Err(FormatError::Invalid {
+                field: "trailing bytes",
+                value: 1
+            })
+        ));
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn test_sequential_uuids_spread() {
+        // UUIDv7-like: a shared timestamp prefix and a counter in the low bits.
+        let ids = (0..1000u128)
+            .map(|i| uuid::Uuid::from_u128(0x0190_0000_0000_7000_8000_0000_0000_0000 | i))
+            .collect::<Vec<_>>();
+        let mut cf = CuckooFilter::with_capacity_and_seed(512, 1);
+        for id in &ids {
+            cf.add_uuid(id).unwrap();
+        }
+        assert!(ids.iter().all(|id| cf.contains_uuid(id)));
+        let used = cf
+            .fingerprints()
+            .map(|(i, _)| i)
+            .collect::<std::collections::HashSet<_>>();
+        assert!(used.len() > 400);
+        #[cfg(not(feature = "no-delete"))]
+        {
+            assert!(cf.delete_uuid(&ids[0]));
+            assert_eq!(cf.size(), ids.len() - 1);
+        }
+    }
+
+    #[cfg(feature = "unsafe-optimizations")]
+    #[test]
+    fn test_prefetch_every_bucket() {
+        let cf = CuckooFilter::tiny();
+        for i in 0..cf.buckets.len() as u64 {
+            cf.prefetch(&cf.finger_at(1, i));
+        }
+        assert_eq!(cf.size(), 0);
+    }
+
+    #[test]
+    fn test_trailing_zeros() {
+        assert_eq!(trailing_zeros(1 << 24), 24);
+        assert_eq!(trailing_zeros(100), 2);
+        assert_eq!(trailing_zeros(64), 6);
+        assert_eq!(trailing_zeros(0), 64);
+        assert_eq!(trailing_zeros(1), 0);
+        assert_eq!(trailing_zeros(3), 0);
+        assert_eq!(trailing_zeros(8), 3);
+    }
+
+    #[test]
+    fn test_gen_size() {
+        assert_eq!(gen_size(100), 32);
+        assert_eq!(gen_size(64), 32);
+    }
+
+    #[test]
+    fn test_add() {
+        let mut cf = CuckooFilter::new(100);
+        for _ in 0..8 {
+            let result = cf.add(b"test");
+            assert!(result.is_ok());
+        }
+        assert_eq!(cf.size(), 8);
+        let (i1, i2) = cf.candidate_indices(b"test");
+        for _ in 0..8 {
+            let result = cf.add(b"test");
+            match result {
+                Err(CuckooError::NotEnoughSpace(p)) => assert_eq!((p.i1, p.i2), (i1, i2)),
+                _ => panic!("expected NotEnoughSpace"),
+            }
+        }
+        assert_eq!(cf.size(), 8);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-delete"))]
+    fn test_delete() {
+        let mut cf = CuckooFilter::tiny();
+        let _ = cf.add(b"test");
+        assert_eq!(cf.size(), 1);
+        assert!(cf.contains(b"test"));
+        assert!(cf.delete(b"test"));
+        assert_eq!(cf.size(), 0);
+        assert!(!cf.contains(b"test"));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-delete"))]
+    fn test_sorted_buckets() {
+        let config = CuckooConfig::new(1000).seed(5).sorted_buckets(true);
+        let mut cf = CuckooFilter::from_config(&config);
+        for i in 0..900u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        for i in (0..900u16).step_by(3) {
+            assert!(cf.delete(&i.to_le_bytes()));
+        }
+        assert!(cf.buckets.iter().all(|b| {
+            let stored = &b.data()[..b.len()];
+            b.fingerprints().eq(stored.iter().copied()) && stored.windows(2).all(|w| w[0] <= w[1])
+        }));
+        assert!((0..900u16)
+            .filter(|i| i % 3 != 0)
+            .all(|i| cf.contains(&i.to_le_bytes())));
+        assert!(cf.check_invariants().is_ok());
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-delete"))]
+    fn test_delete_all_of() {
+        let mut cf = CuckooFilter::new(100);
+        let keys = (0..20u8).map(|i| vec![i]).collect::<Vec<_>>();
+        for k in &keys {
+            assert!(cf.add(k).is_ok());
+        }
+        assert_eq!(cf.delete_all_of(&keys[..10]), 10);
+        assert_eq!(cf.size(), 10);
+        assert_eq!(cf.delete_all_of(keys.iter().take(10)), 0);
+        assert_eq!(cf.delete_all_of(keys), 10);
+        assert_eq!(cf.size(), 0);
+
+        // Longer than the prefetch window.
+        let keys = (0..1000u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let mut cf = CuckooFilter::new(2000);
+        assert_eq!(cf.add_all(&keys).inserted(), 1000);
+        assert_eq!(cf.delete_all_of(&keys[..500]), 500);
+        assert!(keys[500..].iter().all(|k| cf.contains(k)));
+        assert_eq!(cf.delete_all_of(&keys), 500);
+        assert_eq!(cf.size(), 0);
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-delete"))]
+    fn test_move_item() {
+        let mut from = CuckooFilter::new(100);
+        let mut to = CuckooFilter::new(1);
+        for _ in 0..4 {
+            assert!(to.add(b"full").is_ok());
+        }
+        assert!(!CuckooFilter::move_item(&mut from, &mut to, b"test"));
+        let _ = from.add(b"test");
+        assert!(!CuckooFilter::move_item(&mut from, &mut to, b"test"));
+        assert!(from.contains(b"test"));
+        assert_eq!(from.size(), 1);
+
+        let mut to = CuckooFilter::new(100);
+        assert!(CuckooFilter::move_item(&mut from, &mut to, b"test"));
+        assert_eq!(from.size(), 0);
+        assert_eq!(to.size(), 1);
+        assert!(to.contains(b"test"));
+    }
+
+    #[test]
+    #[cfg(feature = "raw-api")]
+    fn test_insert_direct() {
+        let mut cf = CuckooFilter::new(1);
+        let fp = cf.fingerprint(b"test");
+        assert!(!cf.insert_direct(fp, 1));
+        for _ in 0..4 {
+            assert!(cf.insert_direct(fp, 0));
+        }
+        assert!(!cf.insert_direct(fp, 0));
+        assert_eq!(cf.size(), 4);
+        assert!(cf.contains(b"test"));
+    }
+
+    #[test]
+    fn test_contains_alternate_bucket() {
+        let mut cf = CuckooFilter::new(100);
+        let (i1, i2) = cf.candidate_indices(b"test");
+        assert_ne!(i1, i2);
+        let fillers = (0..u16::MAX)
+            .map(|n| n.to_le_bytes())
+            .filter(|k| cf.candidate_indices(k).0 == i1)
+            .take(4)
+            .collect::<Vec<_>>();
+        for k in &fillers {
+            assert!(cf.add(k).is_ok());
+        }
+        assert!(cf.add(b"test").is_ok());
+        assert!(cf.buckets[i1]
+            .get_fingerprint_index(cf.finger_of(b"test").fp)
+            .is_none());
+        assert!(cf.contains(b"test"));
+    }
+
+    #[test]
+    fn test_reencode() {
+        let mut cf = CuckooFilter::new(1000);
+        let keys = (0..100u8).map(|i| vec![i]).collect::<Vec<_>>();
+        for k in &keys {
+            assert!(cf.add(k).is_ok());
+        }
+        let small = cf
+            .reencode(&CuckooConfig::new(200), None::<&[Vec<u8>]>)
+            .unwrap();
+        assert_eq!(small.size(), 100);
+        assert!(keys.iter().all(|k| small.contains(k)));
+
+        let grow = cf.reencode(&CuckooConfig::new(4000), None::<&[Vec<u8>]>);
+        assert!(matches!(grow, Err(CuckooError::NotSupported)));
+        let large = cf.reencode(&CuckooConfig::new(4000), Some(&keys)).unwrap();
+        assert!(keys.iter().all(|k| large.contains(k)));
+    }
+
+    #[test]
+    #[cfg(feature = "bucket-versions")]
+    fn test_read_stamp() {
+        let mut cf = CuckooFilter::new(100);
+        let _ = cf.add(b"other");
+        let stamp = cf.read_stamp(b"test");
+        let snapshot = cf.clone();
+        let _ = cf.add(b"test");
+        assert!(snapshot.validate(&stamp));
+        assert!(!cf.validate(&stamp));
+        assert!(!CuckooFilter::new(1).validate(&stamp));
+    }
+
+    #[test]
+    fn test_prepare_add() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(4, 1);
+        let keys = (0..14u8).map(|i| [i]).collect::<Vec<_>>();
+        for k in &keys {
+            assert!(cf.add(k).is_ok());
+        }
+        let before = cf.fingerprints().collect::<Vec<_>>();
+        cf.prepare_add(b"aborted").unwrap().abort();
+        drop(cf.prepare_add(b"dropped").unwrap());
+        assert!(cf.fingerprints().eq(before.iter().copied()));
+        assert_eq!(cf.size(), 14);
+
+        cf.prepare_add(b"committed").unwrap().commit();
+        assert_eq!(cf.size(), 15);
+        assert!(cf.contains(b"committed"));
+        assert!(keys.iter().all(|k| cf.contains(k)));
+    }
+
+    #[test]
+    fn test_failed_add_leaves_filter_unchanged() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(4, 1);
+        let mut keys = Vec::new();
+        for i in 0..=u8::MAX {
+            if cf.add(&[i]).is_err() {
+                break;
+            }
+            keys.push([i]);
+        }
+        let before = cf.fingerprints().collect::<Vec<_>>();
+        assert!(cf.add(b"overflow").is_err());
+        assert!(cf.fingerprints().eq(before.iter().copied()));
+        assert!(keys.iter().all(|k| cf.contains(k)));
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(8, 3);
+        for i in 0..40u8 {
+            let _ = cf.add(&[i]);
+        }
+        assert!(cf.check_invariants().is_ok());
+        cf.size += 1;
+        assert!(matches!(
+            cf.check_invariants(),
+            Err(InvariantViolation::SizeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    #[cfg(not(feature = "no-delete"))]
+    fn test_empty_and_short_keys() {
+        let mut cf = CuckooFilter::tiny();
+        let keys: [&[u8]; 5] = [b"", &[0], b"a", b"ab", b"abc"];
+        for k in keys.iter() {
+            assert!(cf.add(k).is_ok());
+        }
+        assert!(keys.iter().all(|k| cf.contains(k)));
+        assert!(!cf.contains(&[0, 0]));
+        assert!(cf.delete(b""));
+        assert!(!cf.contains(b""));
+        assert!(cf.contains(&[0]));
+    }
+
+    #[test]
+    fn test_max_duplicates() {
+        let config = CuckooConfig::new(100).max_duplicates(3);
+        let mut cf = CuckooFilter::from_config(&config);
+        for _ in 0..3 {
+            assert!(cf.add(b"flood").is_ok());
+        }
+        for _ in 0..3 {
+            assert!(matches!(
+                cf.add(b"flood"),
+                Err(CuckooError::TooManyDuplicates(_))
+            ));
+        }
+        assert!(cf.prepare_add(b"flood").is_err());
+        assert_eq!(cf.size(), 3);
+        assert!(cf.add(b"other").is_ok());
+    }
+
+    #[test]
+    fn test_tiny_is_reproducible() {
+        let fill = || {
+            let mut cf = CuckooFilter::tiny();
+            let added = (0..=u8::MAX).take_while(|i| cf.add(&[*i]).is_ok()).count();
+            (added, cf.fingerprints().collect::<Vec<_>>())
+        };
+        assert_eq!(fill(), fill());
+    }
+
+    #[test]
+    fn test_with_fp_rate() {
+        let mut cf = CuckooFilter::with_fp_rate(10_000, 0.01).unwrap();
+        for i in 0..10_000u32 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let probes = 1_000_000..1_200_000u32;
+        let hits = probes
+            .clone()
+            .filter(|i| cf.contains(&i.to_le_bytes()))
+            .count();
+        // Leaves room for sampling noise around the target.
+        assert!((hits as f64 / probes.len() as f64) < 0.0125, "{}", hits);
+
+        // Loose targets are capped by the load inserts can reach.
+        assert_eq!(
+            CuckooFilter::with_fp_rate(10_000, 0.5).unwrap().capacity(),
+            16_384
+        );
+        assert!(CuckooFilter::with_fp_rate(10, f64::NAN).is_err());
+        assert!(CuckooFilter::with_fp_rate(u64::MAX, 1e-9).is_err());
+    }
+
+    #[test]
+    fn test_custom_hasher() {
+        let mut cf = CuckooFilter::with_hasher(1000, RandomState::new());
+        for i in 0..800u32 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(cf.size(), 800);
+        assert!((0..800u32).all(|i| cf.contains(&i.to_le_bytes())));
+        #[cfg(not(feature = "no-delete"))]
+        assert!(cf.delete(&7u32.to_le_bytes()));
+
+        // The default hasher places keys exactly like the plain key hash.
+        let cf = CuckooFilter::new(1000);
+        for key in [&b""[..], b"a", b"test", &[7u8; 100]] {
+            let (ours, plain) = (cf.finger_of(key), get_indices_and_fingerprint(key, cf.pow));
+            assert_eq!((ours.fp, ours.i1, ours.i2), (plain.fp, plain.i1, plain.i2));
+        }
+    }
+
+    #[test]
+    fn test_eviction_policies() {
+        for policy in [
+            EvictionPolicy::Random,
+            EvictionPolicy::RoundRobin,
+            EvictionPolicy::LeastRecentlyPlaced,
+        ] {
+            let config = CuckooConfig::new(1000).seed(3).eviction_policy(policy);
+            let mut cf = CuckooFilter::from_config(&config);
+            assert_eq!(cf.eviction_policy(), policy);
+            let mut n = 0u32;
+            while cf.add(&n.to_le_bytes()).is_ok() {
+                n += 1;
+            }
+            // Every policy still fills the table before inserts fail.
+            assert!(cf.load_factor() > 0.9, "{:?}", policy);
+            assert!((0..n).all(|key| cf.contains(&key.to_le_bytes())));
+        }
+
+        // Failing fast leaves the filter unchanged.
+        let mut cf = CuckooFilter::from_config(&CuckooConfig::new(1000).seed(3));
+        cf.set_max_kicks(50);
+        cf.set_eviction_policy(EvictionPolicy::RoundRobin);
+        let mut n = 0u32;
+        while cf.add(&n.to_le_bytes()).is_ok() {
+            n += 1;
+        }
+        assert_eq!(cf.size(), n as usize);
+        assert!((0..n).all(|key| cf.contains(&key.to_le_bytes())));
+    }
+
+    #[test]
+    fn test_deterministic_layout() {
+        let build = || {
+            let mut cf = CuckooFilter::with_capacity_and_seed(16, 42);
+            for i in 0..80u8 {
+                let _ = cf.add(&[i]);
+            }
+            cf
+        };
+        let (a, b) = (build(), build());
+        assert_eq!(a.size(), b.size());
+        assert!(a.fingerprints().eq(b.fingerprints()));
+    }
+
+    #[test]
+    fn test_add_all() {
+        let mut cf = CuckooFilter::new(1);
+        let keys = (0..20u8).map(|i| [i]).collect::<Vec<_>>();
+        let report = cf.add_all(&keys);
+        assert_eq!(report.outcomes.len(), keys.len());
+        assert_eq!(report.inserted(), cf.size());
+        let failed = report.failed().collect::<Vec<_>>();
+        assert!(!failed.is_empty());
+        for i in failed {
+            assert!(matches!(
+                report.outcomes[i],
+                ItemOutcome::Failed {
+                    kicks: MAX_CUCKOO_COUNT,
+                    ..
+                }
+            ));
+        }
+
+        // A fingerprint already present is stored again, as `add` does, so a
+        // colliding key survives the deletion of the first.
+        let mut cf = CuckooFilter::tiny();
+        assert_eq!(cf.add_all(&[b"a", b"a"]).inserted(), 2);
+        assert_eq!(cf.size(), 2);
+        #[cfg(not(feature = "no-delete"))]
+        {
+            assert!(cf.delete(b"a"));
+            assert!(cf.contains(b"a"));
+        }
+    }
+
+    #[test]
+    fn test_to_debug_json() {
+        let mut cf = CuckooFilter::tiny();
+        assert_eq!(
+            cf.to_debug_json(true),
+            "{\"format_version\":1,\"bucket_size\":4,\"fingerprint_bits\":8,\
+             \"num_buckets\":64,\"reachable_buckets\":64,\"pow\":6,\"size\":0,\
+             \"capacity\":256,\"load_factor\":0,\"max_duplicates\":null,\"buckets\":[]}"
+        );
+        cf.add(b"a").unwrap();
+        cf.add(b"a").unwrap();
+        let (bucket, fp) = cf.fingerprints().next().unwrap();
+        let json = cf.to_debug_json(true);
+        let expected = format!(
+            "\"buckets\":[{{\"index\":{},\"fingerprints\":[{},{}]}}]}}",
+            bucket, fp, fp
+        );
+        assert!(json.ends_with(&expected), "{}", json);
+        assert!(!cf.to_debug_json(false).contains("buckets\":["));
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(256, 8);
+        for i in 0..900u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        assert!(cf.evictions_occurred());
+        for i in 0..5000u16 {
+            let key = i.to_le_bytes();
+            let explanation = cf.explain(&key);
+            assert_eq!(explanation.matched.is_some(), cf.contains(&key));
+            if let Some((bucket, slot)) = explanation.matched {
+                let slots = if bucket == explanation.i1 {
+                    explanation.slots1
+                } else {
+                    explanation.slots2
+                };
+                assert_eq!(slots[slot], explanation.fingerprint);
+            }
+        }
+        let text = cf.explain(b"0").to_string();
+        assert!(text.starts_with("fingerprint 0x"), "{}", text);
+    }
+
+    #[test]
+    fn test_duplicate_census() {
+        let mut cf = CuckooFilter::tiny();
+        assert_eq!(cf.duplicate_census().redundant_fraction(), 0.0);
+        let key = (0..100u8)
+            .map(|i| [i])
+            .find(|k| {
+                let (i1, i2) = cf.candidate_indices(k);
+                i1 != i2
+            })
+            .unwrap();
+        // Fills both candidate buckets, so the group spans two buckets.
+        for _ in 0..2 * BUCKET_SIZE {
+            cf.add(&key).unwrap();
+        }
+        let census = cf.duplicate_census();
+        assert_eq!(census.occupied, 2 * BUCKET_SIZE);
+        assert_eq!(census.groups, 1);
+        assert_eq!(census.redundant_slots, 2 * BUCKET_SIZE - 1);
+        assert_eq!(census.max_copies, 2 * BUCKET_SIZE);
+    }
+
+    #[test]
+    fn test_evictions_occurred() {
+        let mut cf = CuckooFilter::tiny();
+        for i in 0..1000u16 {
+            if cf.evictions_occurred() {
+                break;
+            }
+            cf.add(&i.to_le_bytes()).unwrap();
+            for j in 0..=i {
+                assert!(cf.contains(&j.to_le_bytes()));
+            }
+        }
+        assert!(cf.evictions_occurred());
+        let keys = (0..200u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let mut cf = CuckooFilter::tiny();
+        cf.add_all(&keys);
+        assert!(cf.evictions_occurred());
+        for k in &keys {
+            assert!(cf.contains(k));
+        }
+    }
+
+    #[test]
+    fn test_sample_fingerprints() {
+        let mut cf = CuckooFilter::tiny();
+        let keys = (0..100u8).map(|i| [i]).collect::<Vec<_>>();
+        cf.add_all(&keys);
+        let mut all = cf.fingerprints().collect::<Vec<_>>();
+        all.sort_unstable();
+        let mut rng = StdRng::seed_from_u64(3);
+        let sample = cf.sample_fingerprints(10, &mut rng);
+        assert_eq!(sample.len(), 10);
+        assert!(sample.windows(2).all(|w| w[0] <= w[1]));
+        assert!(sample.iter().all(|e| all.contains(e)));
+        assert_eq!(cf.sample_fingerprints(1000, &mut rng), all);
+    }
+
+    #[test]
+    fn test_from_iter_with_capacity() {
+        let keys = (0..1000u16).map(|i| i.to_le_bytes());
+        let cf = CuckooFilter::from_iter_with_capacity(keys, None).unwrap();
+        assert_eq!(cf.size(), 1000);
+        assert_eq!(cf.capacity(), gen_size(1000) as usize * 4);
+
+        let keys = (0..1000u16).filter(|i| i % 2 == 0).map(|i| i.to_le_bytes());
+        let cf = CuckooFilter::from_iter_with_capacity(keys, Some(500)).unwrap();
+        assert_eq!(cf.capacity(), gen_size(500) as usize * 4);
+        let keys = (0..1000u16).map(|i| i.to_le_bytes());
+        assert!(CuckooFilter::from_iter_with_capacity(keys, Some(100)).is_err());
+    }
+
+    #[test]
+    fn test_reencode_async_yields() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        let mut cf = CuckooFilter::new(8192);
+        let keys = (0..5000u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        cf.add_all(&keys);
+        let config = CuckooConfig::new(6000);
+        let expected = cf.reencode(&config, None::<Vec<&[u8]>>).unwrap();
+
+        let mut fut = Box::pin(cf.reencode_async(&config, None::<Vec<&[u8]>>));
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut pending = 0;
+        let small = loop {
+            match fut.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => break result.unwrap(),
+                Poll::Pending => pending += 1,
+            }
+        };
+        assert_eq!(pending, cf.size() / YIELD_EVERY);
+        assert_eq!(
+            small.fingerprints().count(),
+            expected.fingerprints().count()
+        );
+    }
+
+    #[test]
+    fn test_to_bytes_async_yields() {
+        use std::future::Future;
+        use std::task::{Context, Poll, Waker};
+
+        let mut cx = Context::from_waker(Waker::noop());
+        for &items in &[10u32, 50_000] {
+            let mut cf = CuckooFilter::with_capacity_and_seed(1 << 15, 3);
+            for i in 0..items {
+                cf.add(&i.to_le_bytes()).unwrap();
+            }
+            let mut fut = Box::pin(cf.to_bytes_async());
+            let mut pending = 0;
+            let bytes = loop {
+                match fut.as_mut().poll(&mut cx) {
+                    Poll::Ready(bytes) => break bytes,
+                    Poll::Pending => pending += 1,
+                }
+            };
+            assert_eq!(bytes, cf.to_bytes());
+            assert_eq!(
+                pending,
+                cf.buckets
+                    .len()
+                    .div_ceil(STREAM_CHUNK / format::BUCKET_BYTES)
+            );
+        }
+    }
+
+    #[test]
+    fn test_with_seed() {
+        let build = |seed| {
+            let mut cf = CuckooFilter::with_seed(1000, seed);
+            // Fill to 88% of the slots so inserts have to kick.
+            for i in 0u32..1800 {
+                cf.add(&i.to_le_bytes()).unwrap();
+            }
+            cf
+        };
+        let cf = build(7);
+        assert_eq!(cf.capacity(), CuckooFilter::new(1000).capacity());
+        assert!(cf.evictions_occurred());
+        assert_eq!(cf.to_bytes(), build(7).to_bytes());
+        assert_ne!(cf.to_bytes(), build(8).to_bytes());
     }
 }
@@ -0,0 +1,355 @@
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+//! Incremental replication of a filter.
+//!
+//! A delta starts with a `DELTA_HEADER_LEN`-byte header: the magic `DKCD`, a
+//! version byte, three reserved bytes, then the bucket count, the generation
+//! the delta starts from, the generation it brings the replica to, the item
+//! count at that generation and the number of regions, all as u64. Each
+//! region follows as its index as u64 and the serialized buckets it covers,
+//! `DELTA_REGION_BUCKETS` of them except for a shorter last region. A CRC-32
+//! of everything before it closes the delta. All integers are little endian.
+use crate::format::{self, FormatError, Magic, SlotCodec, BUCKET_BYTES};
+use crate::{CResult, CuckooError, CuckooFilter};
+use std::convert::TryFrom;
+
+/// The magic bytes every delta starts with.
+pub const DELTA_MAGIC: [u8; 4] = *b"DKCD";
+
+/// The delta layout version described above.
+pub const DELTA_VERSION: u8 = 1;
+
+/// Length of the delta header in bytes.
+pub const DELTA_HEADER_LEN: usize = 48;
+
+/// Number of buckets whose changes are tracked, and shipped, together.
+pub const DELTA_REGION_BUCKETS: usize = 64;
+
+/// A filter that remembers which regions of its bucket array changed in
+/// which generation, so replicas can be kept in sync by shipping only those.
+///
+/// Every successful `add` or `delete` starts a new generation. A replica is
+/// seeded from a full snapshot, `to_bytes` of `filter()` together with
+/// `generation()`, and from then on applies the output of `export_delta`
+/// for the generation it is at. Tracking costs 8 bytes per
+/// `DELTA_REGION_BUCKETS` buckets.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{CuckooFilter, DeltaFilter};
+///
+/// let mut primary = DeltaFilter::new(CuckooFilter::new(1000));
+/// let snapshot = CuckooFilter::from_bytes(&primary.filter().to_bytes()).unwrap();
+/// let mut replica = DeltaFilter::with_generation(snapshot, primary.generation());
+///
+/// primary.add(b"test").unwrap();
+/// let delta = primary.export_delta(replica.generation());
+/// replica.apply_delta(&delta).unwrap();
+/// assert!(replica.contains(b"test"));
+/// assert_eq!(replica.generation(), primary.generation());
+/// ```
+pub struct DeltaFilter {
+    filter: CuckooFilter,
+    generation: u64,
+    // Generation of the last change to every region
+    regions: Vec<u64>,
+}
+
+impl DeltaFilter {
+    /// Starts tracking `filter` at generation 0.
+    pub fn new(filter: CuckooFilter) -> Self {
+        Self::with_generation(filter, 0)
+    }
+
+    /// Starts tracking `filter`, a snapshot taken at `generation`.
+    pub fn with_generation(filter: CuckooFilter, generation: u64) -> Self {
+        let num_regions = filter.num_buckets().div_ceil(DELTA_REGION_BUCKETS);
+        DeltaFilter {
+            filter,
+            generation,
+            regions: vec![generation; num_regions],
+        }
+    }
+
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        let finger = self.filter.finger_of(item);
+        self.filter.check_duplicates(&finger)?;
+        let (_, touched) = self.filter.place_tracked(&finger)?;
+        self.mark(&touched);
+        Ok(())
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.filter.contains(item)
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        let finger = self.filter.finger_of(item);
+        if !self.filter.delete_finger(&finger) {
+            return false;
+        }
+        self.mark(&[finger.i1 as usize, finger.i2 as usize]);
+        true
+    }
+
+    fn mark(&mut self, touched: &[usize]) {
+        self.generation += 1;
+        for &i in touched {
+            if let Some(region) = self.regions.get_mut(i / DELTA_REGION_BUCKETS) {
+                *region = self.generation;
+            }
+        }
+    }
+
+    /// Returns the generation of the last change.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn filter(&self) -> &CuckooFilter {
+        &self.filter
+    }
+
+    pub fn into_inner(self) -> CuckooFilter {
+        self.filter
+    }
+
+    /// Serializes every region changed after generation `since`, for a
+    /// replica at that generation.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, DeltaFilter, DELTA_HEADER_LEN};
+    ///
+    /// let mut cf = DeltaFilter::new(CuckooFilter::new(1_000_000));
+    /// let since = cf.generation();
+    /// cf.add(b"test").unwrap();
+    /// // One region of 64 buckets, not the whole filter.
+    /// assert_eq!(cf.export_delta(since).len(), DELTA_HEADER_LEN + 8 + 64 * 4 + 4);
+    /// ```
+    pub fn export_delta(&self, since: u64) -> Vec<u8> {
+        let changed = self
+            .regions
+            .iter()
+            .enumerate()
+            .filter(|&(_, &generation)| generation > since)
+            .map(|(r, _)| r)
+            .collect::<Vec<_>>();
+        let mut out = Vec::with_capacity(
+            DELTA_HEADER_LEN + changed.len() * (8 + DELTA_REGION_BUCKETS * BUCKET_BYTES) + 4,
+        );
+        out.extend_from_slice(&DELTA_MAGIC);
+        out.extend_from_slice(&[DELTA_VERSION, 0, 0, 0]);
+        for word in &[
+            self.filter.num_buckets() as u64,
+            since,
+            self.generation,
+            self.filter.size() as u64,
+            changed.len() as u64,
+        ] {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        for r in changed {
+            out.extend_from_slice(&(r as u64).to_le_bytes());
+            for i in self.region_buckets(r) {
+                format::BucketCodec::encode(&self.filter.bucket_data(i), &mut out);
+            }
+        }
+        let crc = format::checksum(&out);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out
+    }
+
+    /// Applies a delta from `export_delta`, bringing this replica to the
+    /// generation of the primary at export time.
+    ///
+    /// The whole delta is validated before anything is applied, so on error
+    /// the replica is unchanged. A delta starting after this replica's
+    /// generation would leave changes out and is rejected as a `Mismatch` of
+    /// `generation`, as is one older than the replica.
+    pub fn apply_delta(&mut self, bytes: &[u8]) -> Result<(), FormatError> {
+        let (body, crc) = bytes
+            .split_last_chunk::<4>()
+            .ok_or(FormatError::Truncated { field: "checksum" })?;
+        format::verify_checksum(crc, format::checksum(body))?;
+        let (head, mut rest) = body
+            .split_first_chunk::<DELTA_HEADER_LEN>()
+            .ok_or(FormatError::Truncated { field: "header" })?;
+        let (magic, head) = head.split_first_chunk::<4>().unwrap_or((&[0; 4], &[]));
+        if *magic != DELTA_MAGIC {
+            return Err(FormatError::BadMagic(Magic(*magic)));
+        }
+        if head.first() != Some(&DELTA_VERSION) {
+            return Err(FormatError::UnsupportedVersion(
+                head.first().copied().unwrap_or(0),
+            ));
+        }
+        let word = |n: usize| {
+            head.get(4 + 8 * n..12 + 8 * n)
+                .and_then(|b| <[u8; 8]>::try_from(b).ok())
+                .map_or(0, u64::from_le_bytes)
+        };
+        let (num_buckets, since, generation, size, count) =
+            (word(0), word(1), word(2), word(3), word(4));
+        let check = |field: &'static str, expected: u64, found: u64| {
+            if expected == found {
+                Ok(())
+            } else {
+                Err(FormatError::Mismatch {
+                    field,
+                    expected,
+                    found,
+                })
+            }
+        };
+        check("num_buckets", self.filter.num_buckets() as u64, num_buckets)?;
+        if since > self.generation || generation < self.generation {
+            return Err(FormatError::Mismatch {
+                field: "generation",
+                expected: self.generation,
+                found: if since > self.generation {
+                    since
+                } else {
+                    generation
+                },
+            });
+        }
+
+        let mut regions = Vec::new();
+        for _ in 0..count {
+            let (index, tail) = rest
+                .split_first_chunk::<8>()
+                .ok_or(FormatError::Truncated { field: "region" })?;
+            let index = u64::from_le_bytes(*index);
+            let r = usize::try_from(index)
+                .ok()
+                .filter(|&r| r < self.regions.len())
+                .ok_or(FormatError::Invalid {
+                    field: "region",
+                    value: index,
+                })?;
+            let len = self.region_buckets(r).len() * BUCKET_BYTES;
+            let (data, tail) = tail
+                .split_at_checked(len)
+                .ok_or(FormatError::Truncated { field: "region" })?;
+            let buckets = data
+                .chunks(BUCKET_BYTES)
+                .map(format::BucketCodec::decode)
+                .collect::<Result<Vec<_>, _>>()?;
+            regions.push((r, buckets));
+            rest = tail;
+        }
+        if !rest.is_empty() {
+            return Err(FormatError::Invalid {
+                field: "trailing bytes",
+                value: rest.len() as u64,
+            });
+        }
+
+        let mut updated = self.filter.clone();
+        for (r, buckets) in &regions {
+            for (i, &slots) in self.region_buckets(*r).zip(buckets) {
+                updated.set_bucket_data(i, slots);
+            }
+        }
+        check("size", size, updated.size() as u64)?;
+        self.filter = updated;
+        for (r, _) in regions {
+            if let Some(region) = self.regions.get_mut(r) {
+                *region = generation;
+            }
+        }
+        self.generation = generation;
+        Ok(())
+    }
+
+    fn region_buckets(&self, r: usize) -> std::ops::Range<usize> {
+        let start = r * DELTA_REGION_BUCKETS;
+        start..(start + DELTA_REGION_BUCKETS).min(self.filter.num_buckets())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::FormatError;
+    use crate::{CuckooFilter, DeltaFilter};
+
+    fn replica_of(primary: &DeltaFilter) -> DeltaFilter {
+        let snapshot = CuckooFilter::from_bytes(&primary.filter().to_bytes()).unwrap();
+        DeltaFilter::with_generation(snapshot, primary.generation())
+    }
+
+    #[test]
+    fn test_deltas_keep_replica_in_sync() {
+        let mut primary = DeltaFilter::new(CuckooFilter::with_capacity_and_seed(4096, 4));
+        for i in 0..500u16 {
+            primary.add(&i.to_le_bytes()).unwrap();
+        }
+        let mut replica = replica_of(&primary);
+        let mut lagging = replica_of(&primary);
+        for round in 0..5u16 {
+            for i in 0..200u16 {
+                primary
+                    .add(&(1000 + round * 200 + i).to_le_bytes())
+                    .unwrap();
+            }
+            #[cfg(not(feature = "no-delete"))]
+            for i in (round * 100..round * 100 + 50).map(|i: u16| i.to_le_bytes()) {
+                assert!(primary.delete(&i));
+            }
+            replica
+                .apply_delta(&primary.export_delta(replica.generation()))
+                .unwrap();
+            assert_eq!(replica.filter().to_bytes(), primary.filter().to_bytes());
+        }
+        // A replica many generations behind catches up in one delta.
+        let delta = primary.export_delta(lagging.generation());
+        lagging.apply_delta(&delta).unwrap();
+        assert_eq!(lagging.filter().to_bytes(), primary.filter().to_bytes());
+
+        // Re-applying an old delta would regress the replica.
+        let stale = primary.export_delta(0);
+        primary.add(b"newer").unwrap();
+        replica
+            .apply_delta(&primary.export_delta(replica.generation()))
+            .unwrap();
+        assert!(matches!(
+            replica.apply_delta(&stale),
+            Err(FormatError::Mismatch {
+                field: "generation",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_fresh_replica_after_kicks() {
+        let mut primary = DeltaFilter::new(CuckooFilter::with_capacity_and_seed(256, 4));
+        let keys = (0..950u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        for key in &keys {
+            primary.add(key).unwrap();
+        }
+        assert!(primary.filter().evictions_occurred());
+
+        let mut replica = DeltaFilter::new(CuckooFilter::with_capacity_and_seed(256, 4));
+        replica.apply_delta(&primary.export_delta(0)).unwrap();
+        assert!(keys.iter().all(|key| replica.contains(key)));
+    }
+
+    #[test]
+    fn test_damaged_delta_changes_nothing() {
+        let mut primary = DeltaFilter::new(CuckooFilter::with_capacity_and_seed(256, 4));
+        let mut replica = replica_of(&primary);
+        primary.add(b"test").unwrap();
+        let mut delta = primary.export_delta(replica.generation());
+        let last = delta.len() - 5;
+        delta[last] ^= 1;
+        assert!(matches!(
+            replica.apply_delta(&delta),
+            Err(FormatError::Corrupted { .. })
+        ));
+        assert!(replica.apply_delta(&delta[..10]).is_err());
+        assert_eq!(replica.generation(), 0);
+        assert!(!replica.contains(b"test"));
+    }
+}
@@ -0,0 +1,100 @@
+use crate::format::{self, FormatError};
+use crate::CuckooFilter;
+
+/// A filter embedded in a config file: its parameters as readable fields next
+/// to the base64 bucket data.
+///
+/// It derives `Serialize` and `Deserialize`, so it can be a field of any
+/// JSON or YAML config struct. The parameters are checked against the data on
+/// load, which catches hand edits and data pasted into the wrong entry.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmbeddedFilter {
+    pub bucket_size: u8,
+    pub fingerprint_bits: u8,
+    pub num_buckets: u64,
+    pub size: u64,
+    /// `CuckooFilter::to_base64` output.
+    pub data: String,
+}
+
+impl EmbeddedFilter {
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, EmbeddedFilter};
+    /// let mut cf = CuckooFilter::tiny();
+    /// cf.add(b"test").unwrap();
+    /// let embedded = EmbeddedFilter::new(&cf);
+    /// assert_eq!((embedded.num_buckets, embedded.size), (64, 1));
+    /// ```
+    pub fn new(filter: &CuckooFilter) -> Self {
+        EmbeddedFilter {
+            bucket_size: format::BUCKET_SIZE as u8,
+            fingerprint_bits: format::FINGERPRINT_BITS,
+            num_buckets: filter.num_buckets() as u64,
+            size: filter.size() as u64,
+            data: filter.to_base64(),
+        }
+    }
+
+    /// Decodes the filter, failing if any parameter disagrees with the data.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::format::FormatError;
+    /// use dakv_cuckoo::{CuckooFilter, EmbeddedFilter};
+    /// let mut embedded = EmbeddedFilter::new(&CuckooFilter::tiny());
+    /// assert!(embedded.to_filter().is_ok());
+    /// embedded.size = 3;
+    /// assert!(matches!(
+    ///     embedded.to_filter(),
+    ///     Err(FormatError::Mismatch { field: "size", .. })
+    /// ));
+    /// ```
+    pub fn to_filter(&self) -> Result<CuckooFilter, FormatError> {
+        let check = |field: &'static str, expected: u64, found: u64| {
+            if expected == found {
+                Ok(())
+            } else {
+                Err(FormatError::Mismatch {
+                    field,
+                    expected,
+                    found,
+                })
+            }
+        };
+        check(
+            "bucket_size",
+            format::BUCKET_SIZE as u64,
+            u64::from(self.bucket_size),
+        )?;
+        check(
+            "fingerprint_bits",
+            u64::from(format::FINGERPRINT_BITS),
+            u64::from(self.fingerprint_bits),
+        )?;
+        let filter = CuckooFilter::from_base64(&self.data)?;
+        check("num_buckets", filter.num_buckets() as u64, self.num_buckets)?;
+        check("size", filter.size() as u64, self.size)?;
+        Ok(filter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, EmbeddedFilter};
+
+    #[test]
+    fn test_embedded_json_round_trip() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(16, 7);
+        for i in 0..50u8 {
+            cf.add(&[i]).unwrap();
+        }
+        let json = serde_json::to_string(&EmbeddedFilter::new(&cf)).unwrap();
+        assert!(json.starts_with("{\"bucket_size\":4,\"fingerprint_bits\":8,"));
+        let embedded: EmbeddedFilter = serde_json::from_str(&json).unwrap();
+        let loaded = embedded.to_filter().unwrap();
+        assert_eq!(loaded.to_bytes(), cf.to_bytes());
+        assert!((0..50u8).all(|i| loaded.contains(&[i])));
+    }
+}
@@ -0,0 +1,346 @@
+#[cfg(not(feature = "no-delete"))]
+use crate::bucket::BUCKET_SIZE;
+#[cfg(not(feature = "no-delete"))]
+use crate::RebuildProgress;
+use crate::{CResult, CuckooError, CuckooFilter};
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+// Buckets cleared between two progress reports of `evict_older_than`.
+#[cfg(not(feature = "no-delete"))]
+const EVICT_CHUNK_BUCKETS: usize = 4096;
+
+/// A filter that starts over with a fresh generation every `ttl`.
+///
+/// This gives "seen in the current period" semantics without rotation code
+/// in the application. With `keep_previous`, the generation that just ended
+/// keeps answering lookups for one more period, so keys do not vanish
+/// abruptly at the boundary.
+///
+/// Generations are numbered from 0, one per elapsed period, including
+/// periods in which nothing was written.
+pub struct ExpiringFilter<B> {
+    ttl: Duration,
+    builder: B,
+    keep_previous: bool,
+    started: Instant,
+    generation: u64,
+    current: CuckooFilter,
+    previous: Option<CuckooFilter>,
+}
+
+impl<B> ExpiringFilter<B>
+where
+    B: Fn() -> CuckooFilter,
+{
+    /// Creates a filter whose generations are built by `builder` and last `ttl`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, ExpiringFilter};
+    /// use std::time::Duration;
+    /// let cf = ExpiringFilter::new(Duration::from_secs(86400), || CuckooFilter::new(1000));
+    /// ```
+    pub fn new(ttl: Duration, builder: B) -> Self {
+        ExpiringFilter {
+            ttl: ttl.max(Duration::from_nanos(1)),
+            current: builder(),
+            builder,
+            keep_previous: false,
+            started: Instant::now(),
+            generation: 0,
+            previous: None,
+        }
+    }
+
+    /// Keeps the previous generation readable for one extra period.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, ExpiringFilter};
+    /// use std::time::Duration;
+    /// let cf = ExpiringFilter::new(Duration::from_secs(60), || CuckooFilter::new(1000))
+    ///     .keep_previous(true);
+    /// ```
+    pub fn keep_previous(mut self, keep: bool) -> Self {
+        self.keep_previous = keep;
+        self
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, ExpiringFilter};
+    /// use std::time::Duration;
+    /// let mut cf = ExpiringFilter::new(Duration::from_secs(60), || CuckooFilter::new(1000));
+    /// cf.add(b"test");
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        self.add_at(item, Instant::now())
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, ExpiringFilter};
+    /// use std::time::Duration;
+    /// let cf = ExpiringFilter::new(Duration::from_secs(60), || CuckooFilter::new(1000));
+    /// assert!(!cf.contains(b"test"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.contains_at(item, Instant::now())
+    }
+
+    /// Starts a new generation immediately.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, ExpiringFilter};
+    /// use std::time::Duration;
+    /// let mut cf = ExpiringFilter::new(Duration::from_secs(60), || CuckooFilter::new(1000));
+    /// cf.add(b"test");
+    /// cf.rotate();
+    /// assert!(!cf.contains(b"test"));
+    /// ```
+    pub fn rotate(&mut self) {
+        self.rotate_at(Instant::now());
+    }
+
+    /// Returns the generation receiving writes.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, ExpiringFilter};
+    /// use std::time::Duration;
+    /// let cf = ExpiringFilter::new(Duration::from_secs(60), || CuckooFilter::new(1000));
+    /// assert_eq!(cf.current().size(), 0);
+    /// ```
+    pub fn current(&self) -> &CuckooFilter {
+        &self.current
+    }
+
+    /// Returns the number of the generation receiving writes.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, ExpiringFilter};
+    /// use std::time::Duration;
+    /// let mut cf = ExpiringFilter::new(Duration::from_secs(60), || CuckooFilter::new(1000));
+    /// cf.rotate();
+    /// assert_eq!(cf.generation(), 1);
+    /// ```
+    pub fn generation(&self) -> u64 {
+        self.generation_at(Instant::now())
+    }
+
+    /// Removes every item of the generations numbered below `generation`,
+    /// returning how many there were.
+    ///
+    /// Periods that elapsed without writes are caught up on first, so the
+    /// cutoff is compared against the generation `generation()` reports.
+    ///
+    /// This enforces retention without the keys: a retired generation is
+    /// dropped, and if the current one is older it is swept clear in place,
+    /// keeping its settings and receiving writes under its number.
+    /// `progress` is called after each chunk of buckets with how many of
+    /// the buckets to clear are done.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, ExpiringFilter};
+    /// use std::time::Duration;
+    /// let mut cf = ExpiringFilter::new(Duration::from_secs(60), || CuckooFilter::new(1000))
+    ///     .keep_previous(true);
+    /// cf.add(b"old").unwrap();
+    /// cf.rotate();
+    /// cf.add(b"new").unwrap();
+    /// assert_eq!(cf.evict_older_than(1, |_| {}), 1);
+    /// assert!(!cf.contains(b"old"));
+    /// assert!(cf.contains(b"new"));
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn evict_older_than<F>(&mut self, generation: u64, progress: F) -> usize
+    where
+        F: FnMut(RebuildProgress),
+    {
+        self.evict_older_than_at(generation, Instant::now(), progress)
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    fn evict_older_than_at<F>(&mut self, generation: u64, now: Instant, mut progress: F) -> usize
+    where
+        F: FnMut(RebuildProgress),
+    {
+        self.tick_at(now);
+        // `previous`, if any, is generation `self.generation - 1`.
+        let previous = if generation >= self.generation {
+            self.previous.take()
+        } else {
+            None
+        };
+        let num_buckets = if generation > self.generation {
+            self.current.capacity() / BUCKET_SIZE
+        } else {
+            0
+        };
+        let dropped = previous.as_ref().map_or(0, |p| p.capacity() / BUCKET_SIZE);
+        let total = Some(dropped + num_buckets);
+        let mut removed = previous.map_or(0, |p| p.size());
+        if dropped > 0 {
+            progress(RebuildProgress {
+                done: dropped,
+                total,
+            });
+        }
+        for start in (0..num_buckets).step_by(EVICT_CHUNK_BUCKETS) {
+            let end = num_buckets.min(start + EVICT_CHUNK_BUCKETS);
+            removed += self.current.clear_buckets(start..end);
+            progress(RebuildProgress {
+                done: dropped + end,
+                total,
+            });
+        }
+        removed
+    }
+
+    fn add_at(&mut self, item: &[u8], now: Instant) -> CResult<CuckooError> {
+        self.tick_at(now);
+        self.current.add(item)
+    }
+
+    fn generation_at(&self, now: Instant) -> u64 {
+        let periods = u64::try_from(self.periods_since_start(now)).unwrap_or(u64::MAX);
+        self.generation.saturating_add(periods)
+    }
+
+    /// Starts the generation that `now` falls in, if it is not current yet.
+    fn tick_at(&mut self, now: Instant) {
+        let periods = self.periods_since_start(now);
+        if periods > 0 {
+            // Keep the generation boundaries on the original schedule.
+            let into_period = self.elapsed(now).as_nanos() % self.ttl.as_nanos();
+            self.rotate_at(now - Duration::from_nanos(into_period as u64));
+            if periods > 1 {
+                // The generation being retired is older than one full period.
+                self.previous = None;
+                self.generation += u64::try_from(periods - 1).unwrap_or(u64::MAX);
+            }
+        }
+    }
+
+    fn contains_at(&self, item: &[u8], now: Instant) -> bool {
+        match self.periods_since_start(now) {
+            0 => {
+                self.current.contains(item)
+                    || self.previous.as_ref().is_some_and(|p| p.contains(item))
+            }
+            // Rotation is due, so `current` is really the previous generation.
+            1 => self.keep_previous && self.current.contains(item),
+            _ => false,
+        }
+    }
+
+    fn rotate_at(&mut self, now: Instant) {
+        let fresh = (self.builder)();
+        let old = std::mem::replace(&mut self.current, fresh);
+        self.previous = if self.keep_previous { Some(old) } else { None };
+        self.started = now;
+        self.generation += 1;
+    }
+
+    fn elapsed(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.started)
+    }
+
+    fn periods_since_start(&self, now: Instant) -> u128 {
+        self.elapsed(now).as_nanos() / self.ttl.as_nanos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, ExpiringFilter};
+    use std::time::{Duration, Instant};
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_expiry() {
+        let mut cf = ExpiringFilter::new(TTL, || CuckooFilter::new(100));
+        let t0 = cf.started;
+        cf.add_at(b"a", t0).unwrap();
+        assert!(cf.contains_at(b"a", t0 + TTL / 2));
+        assert!(!cf.contains_at(b"a", t0 + TTL));
+
+        cf.add_at(b"b", t0 + TTL * 3 / 2).unwrap();
+        assert_eq!(cf.started, t0 + TTL);
+        assert!(!cf.contains_at(b"a", t0 + TTL * 3 / 2));
+        assert!(cf.contains_at(b"b", t0 + TTL * 3 / 2));
+    }
+
+    #[test]
+    fn test_keep_previous() {
+        let mut cf = ExpiringFilter::new(TTL, || CuckooFilter::new(100)).keep_previous(true);
+        let t0: Instant = cf.started;
+        cf.add_at(b"a", t0).unwrap();
+        assert!(cf.contains_at(b"a", t0 + TTL * 3 / 2));
+        assert!(!cf.contains_at(b"a", t0 + TTL * 2));
+
+        cf.add_at(b"b", t0 + TTL * 3 / 2).unwrap();
+        assert!(cf.contains_at(b"a", t0 + TTL * 3 / 2));
+        assert!(cf.contains_at(b"b", t0 + TTL * 5 / 2));
+        assert!(!cf.contains_at(b"a", t0 + TTL * 5 / 2));
+
+        // Skipping a whole period drops both generations.
+        cf.add_at(b"c", t0 + TTL * 4).unwrap();
+        assert!(!cf.contains_at(b"b", t0 + TTL * 4));
+        assert!(cf.contains_at(b"c", t0 + TTL * 4));
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    #[test]
+    fn test_evict_older_than() {
+        let mut cf = ExpiringFilter::new(TTL, || CuckooFilter::new(100_000)).keep_previous(true);
+        let t0 = cf.started;
+        cf.add_at(b"a", t0).unwrap();
+        cf.add_at(b"b", t0 + TTL * 3 / 2).unwrap();
+        assert_eq!(cf.generation(), 1);
+        assert_eq!(cf.evict_older_than(0, |_| panic!("nothing to evict")), 0);
+
+        let mut reports = Vec::new();
+        assert_eq!(cf.evict_older_than(1, |p| reports.push(p)), 1);
+        assert!(!cf.contains_at(b"a", t0 + TTL * 3 / 2));
+        assert!(cf.contains_at(b"b", t0 + TTL * 3 / 2));
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].fraction(), Some(1.0));
+
+        // Skipped periods count as generations.
+        cf.add_at(b"c", t0 + TTL * 4).unwrap();
+        assert_eq!(cf.generation(), 4);
+        let mut reports = Vec::new();
+        assert_eq!(cf.evict_older_than(5, |p| reports.push(p)), 1);
+        assert_eq!(cf.current().size(), 0);
+        assert!(reports.len() > 2);
+        assert!(reports.windows(2).all(|w| w[0].done < w[1].done));
+        assert_eq!(reports.last().unwrap().fraction(), Some(1.0));
+        assert!(cf.current().check_invariants().is_ok());
+        cf.add_at(b"d", t0 + TTL * 4).unwrap();
+        assert!(cf.contains_at(b"d", t0 + TTL * 4));
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    #[test]
+    fn test_catch_up_without_writes() {
+        let mut cf = ExpiringFilter::new(TTL, || CuckooFilter::new(100)).keep_previous(true);
+        let t0 = cf.started;
+        cf.add_at(b"a", t0).unwrap();
+        assert_eq!(cf.generation_at(t0 + TTL / 2), 0);
+        assert_eq!(cf.generation_at(t0 + TTL * 3 / 2), 1);
+        assert_eq!(cf.generation_at(t0 + TTL * 3), 3);
+
+        // Generation 0 is only the previous one by now, and is evicted.
+        assert_eq!(cf.evict_older_than_at(1, t0 + TTL * 3 / 2, |_| {}), 1);
+        assert_eq!(cf.generation_at(t0 + TTL * 3 / 2), 1);
+        assert!(!cf.contains_at(b"a", t0 + TTL * 3 / 2));
+        assert_eq!(cf.started, t0 + TTL);
+    }
+}
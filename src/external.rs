@@ -0,0 +1,443 @@
+use crate::bucket::BUCKET_SIZE;
+use crate::cuckoo_filter::{gen_size, CuckooError, Placement, MAX_CUCKOO_COUNT};
+use crate::format::{Header, HEADER_LEN};
+use crate::telemetry;
+use crate::util::{get_alt_index, get_indices_and_fingerprint};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Placements held in memory before a sorted run is spilled, 9 MiB worth.
+pub const DEFAULT_CHUNK_RECORDS: usize = 1 << 20;
+
+// Primary bucket index as u64 LE followed by the fingerprint.
+const RECORD_LEN: usize = 9;
+
+// Tells apart the runs of builders sharing a process and a directory.
+static NEXT_BUILDER: AtomicU64 = AtomicU64::new(0);
+
+/// Outcome of `ExternalBuilder::finish`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildReport {
+    pub inserted: u64,
+    /// Number of sorted runs that were spilled to disk.
+    pub runs: usize,
+}
+
+/// Builds a serialized filter that may be larger than memory.
+///
+/// Keys are hashed as they are added and their placements spilled to sorted
+/// runs in a temporary directory. `finish` merges the runs and writes the
+/// filter bucket by bucket, in the layout described by `format`, ready for
+/// `CuckooFilter::from_bytes`. Only the
+/// current chunk and the keys waiting for a later alternate bucket are held
+/// in memory.
+///
+/// Keys that find both buckets full kick fingerprints out of buckets that
+/// were already written, as `CuckooFilter::add` does. Each kick costs a read
+/// and a write of the output file, so size `max_num_keys` with some headroom.
+pub struct ExternalBuilder {
+    pow: usize,
+    num_buckets: u64,
+    dir: PathBuf,
+    id: u64,
+    chunk: Vec<(u64, u8)>,
+    chunk_records: usize,
+    runs: Vec<PathBuf>,
+    added: u64,
+    rng: StdRng,
+}
+
+impl ExternalBuilder {
+    /// Creates a builder for `max_num_keys` keys spilling runs into `dir`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ExternalBuilder;
+    /// let builder = ExternalBuilder::new(1000, std::env::temp_dir());
+    /// ```
+    pub fn new<P: AsRef<Path>>(max_num_keys: u64, dir: P) -> Self {
+        let num_buckets = gen_size(max_num_keys);
+        ExternalBuilder {
+            pow: num_buckets.trailing_zeros() as usize,
+            num_buckets,
+            dir: dir.as_ref().to_path_buf(),
+            id: NEXT_BUILDER.fetch_add(1, Ordering::Relaxed),
+            chunk: Vec::new(),
+            chunk_records: DEFAULT_CHUNK_RECORDS,
+            runs: Vec::new(),
+            added: 0,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Sets how many placements are buffered before a run is spilled.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ExternalBuilder;
+    /// let builder = ExternalBuilder::new(1000, std::env::temp_dir()).chunk_records(4096);
+    /// ```
+    pub fn chunk_records(mut self, records: usize) -> Self {
+        self.chunk_records = records.max(1);
+        self
+    }
+
+    /// # Example
+    /// ```no_run
+    /// use dakv_cuckoo::ExternalBuilder;
+    /// let mut builder = ExternalBuilder::new(1000, std::env::temp_dir());
+    /// builder.add(b"test").unwrap();
+    /// ```
+    pub fn add(&mut self, item: &[u8]) -> io::Result<()> {
+        let finger = get_indices_and_fingerprint(item, self.pow);
+        self.chunk.push((finger.i1, finger.fp));
+        self.added += 1;
+        if self.chunk.len() >= self.chunk_records {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    /// Adds every line of `keys` as one key, without the line terminator.
+    ///
+    /// Reads the input in a single pass, so it may be far larger than memory.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use dakv_cuckoo::ExternalBuilder;
+    /// let mut builder = ExternalBuilder::new(1000, std::env::temp_dir());
+    /// assert_eq!(builder.import(&b"a\nb\nc\n"[..]).unwrap(), 3);
+    /// ```
+    pub fn import<R: BufRead>(&mut self, mut keys: R) -> io::Result<u64> {
+        let mut line = Vec::new();
+        let mut count = 0;
+        while keys.read_until(b'\n', &mut line)? != 0 {
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            self.add(&line)?;
+            line.clear();
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Merges all runs and writes the finished filter to `path`.
+    ///
+    /// Fails with `CuckooError::NotEnoughSpace` if a key finds no slot within
+    /// the kick limit; the file at `path` is then incomplete.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use dakv_cuckoo::ExternalBuilder;
+    /// let mut builder = ExternalBuilder::new(1000, std::env::temp_dir());
+    /// builder.add(b"test").unwrap();
+    /// let report = builder.finish("/tmp/filter.dkcf").unwrap();
+    /// assert_eq!(report.inserted, 1);
+    /// ```
+    pub fn finish<P: AsRef<Path>>(mut self, path: P) -> io::Result<BuildReport> {
+        if !self.chunk.is_empty() {
+            self.spill()?;
+        }
+        let mut runs = self
+            .runs
+            .iter()
+            .map(|p| File::open(p).map(BufReader::new))
+            .collect::<io::Result<Vec<_>>>()?;
+        let mut heads = BinaryHeap::new();
+        for (n, run) in runs.iter_mut().enumerate() {
+            if let Some(record) = read_record(run)? {
+                heads.push(Reverse((record, n)));
+            }
+        }
+
+        // Read access is needed to revisit buckets that were already written.
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        let mut out = BufWriter::new(file);
+        let mut header = Header {
+            flags: 0,
+            pow: self.pow as u8,
+            num_buckets: self.num_buckets,
+            size: 0,
+        };
+        out.write_all(&header.encode())?;
+
+        // Fingerprints whose primary bucket was full, keyed by a later
+        // alternate bucket.
+        let mut deferred = BinaryHeap::new();
+        for b in 0..self.num_buckets {
+            let mut bucket = [0u8; BUCKET_SIZE];
+            // Deferred keys have no other choice left, so they go first.
+            while let Some(&Reverse((i, fp))) = deferred.peek() {
+                if i != b {
+                    break;
+                }
+                deferred.pop();
+                if !put(&mut bucket, fp) {
+                    let primary = get_alt_index(fp, b, self.pow);
+                    self.kick(&mut out, &mut bucket, b, &mut deferred, (primary, fp))?;
+                }
+            }
+            while let Some(&Reverse(((i, fp), n))) = heads.peek() {
+                if i != b {
+                    break;
+                }
+                heads.pop();
+                if let Some(run) = runs.get_mut(n) {
+                    if let Some(record) = read_record(run)? {
+                        heads.push(Reverse((record, n)));
+                    }
+                }
+                if put(&mut bucket, fp) {
+                    continue;
+                }
+                let alt = get_alt_index(fp, b, self.pow);
+                if alt > b {
+                    deferred.push(Reverse((alt, fp)));
+                } else if alt == b || !place_written(&mut out, alt, fp)? {
+                    self.kick(&mut out, &mut bucket, b, &mut deferred, (b, fp))?;
+                }
+            }
+            out.write_all(&bucket)?;
+        }
+
+        header.size = self.added;
+        out.seek(SeekFrom::Start(0))?;
+        out.write_all(&header.encode())?;
+        out.flush()?;
+        Ok(BuildReport {
+            inserted: self.added,
+            runs: self.runs.len(),
+        })
+    }
+
+    /// Makes room for `fp`, whose primary bucket is `primary` and whose
+    /// buckets are both full, by relocating fingerprints among the buckets
+    /// written so far and the `current` one. A fingerprint relocated to a
+    /// later bucket is deferred to it.
+    // Slot `j` is drawn below `BUCKET_SIZE`.
+    #[allow(clippy::indexing_slicing)]
+    fn kick(
+        &mut self,
+        out: &mut BufWriter<File>,
+        current_bucket: &mut [u8; BUCKET_SIZE],
+        current: u64,
+        deferred: &mut BinaryHeap<Reverse<(u64, u8)>>,
+        (primary, mut fp): (u64, u8),
+    ) -> io::Result<()> {
+        let placement = Placement {
+            fingerprint: fp,
+            i1: primary as usize,
+            i2: get_alt_index(fp, primary, self.pow) as usize,
+        };
+        let mut i = current;
+        for kicks in 1..=MAX_CUCKOO_COUNT {
+            // Kicks only visit full buckets, so slot `j` is taken.
+            let j = self.rng.gen_range(0, BUCKET_SIZE);
+            fp = if i == current {
+                mem::replace(&mut current_bucket[j], fp)
+            } else {
+                replace_written(out, i, j, fp)?
+            };
+            i = get_alt_index(fp, i, self.pow);
+            let placed = if i > current {
+                deferred.push(Reverse((i, fp)));
+                true
+            } else if i == current {
+                put(current_bucket, fp)
+            } else {
+                place_written(out, i, fp)?
+            };
+            if placed {
+                telemetry::record_kicks(kicks);
+                return Ok(());
+            }
+        }
+        telemetry::record_kicks(MAX_CUCKOO_COUNT);
+        Err(io::Error::other(telemetry::record(
+            CuckooError::NotEnoughSpace(placement),
+        )))
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        self.chunk.sort_unstable();
+        let path = self.dir.join(format!(
+            "dakv_cuckoo_run_{}_{}_{}",
+            process::id(),
+            self.id,
+            self.runs.len()
+        ));
+        let mut run = BufWriter::new(File::create(&path)?);
+        self.runs.push(path);
+        for &(i, fp) in &self.chunk {
+            run.write_all(&i.to_le_bytes())?;
+            run.write_all(&[fp])?;
+        }
+        run.flush()?;
+        self.chunk.clear();
+        Ok(())
+    }
+}
+
+impl Drop for ExternalBuilder {
+    fn drop(&mut self) {
+        for run in &self.runs {
+            let _ = fs::remove_file(run);
+        }
+    }
+}
+
+fn read_record<R: Read>(run: &mut R) -> io::Result<Option<(u64, u8)>> {
+    let mut record = [0; RECORD_LEN];
+    match run.read_exact(&mut record) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let mut index = [0; 8];
+    index.copy_from_slice(&record[..8]);
+    Ok(Some((u64::from_le_bytes(index), record[8])))
+}
+
+/// Stores `fp` in the first free slot of `bucket`, returning `false` if it is
+/// full.
+fn put(bucket: &mut [u8; BUCKET_SIZE], fp: u8) -> bool {
+    match bucket.iter_mut().find(|s| **s == 0) {
+        Some(slot) => {
+            *slot = fp;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Stores `fp` in an already written bucket, returning `false` if it is full.
+fn place_written(out: &mut BufWriter<File>, bucket: u64, fp: u8) -> io::Result<bool> {
+    let offset = HEADER_LEN as u64 + bucket * BUCKET_SIZE as u64;
+    out.flush()?;
+    let file = out.get_mut();
+    let end = file.stream_position()?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut slots = [0u8; BUCKET_SIZE];
+    file.read_exact(&mut slots)?;
+    let placed = put(&mut slots, fp);
+    if placed {
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&slots)?;
+    }
+    file.seek(SeekFrom::Start(end))?;
+    Ok(placed)
+}
+
+/// Replaces slot `slot` of an already written bucket with `fp`, returning the
+/// fingerprint it held.
+fn replace_written(out: &mut BufWriter<File>, bucket: u64, slot: usize, fp: u8) -> io::Result<u8> {
+    let offset = HEADER_LEN as u64 + (bucket * BUCKET_SIZE as u64) + slot as u64;
+    out.flush()?;
+    let file = out.get_mut();
+    let end = file.stream_position()?;
+    file.seek(SeekFrom::Start(offset))?;
+    let mut old = [0u8];
+    file.read_exact(&mut old)?;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(&[fp])?;
+    file.seek(SeekFrom::Start(end))?;
+    Ok(old[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::{Header, HEADER_LEN};
+    use crate::util::get_indices_and_fingerprint;
+    use crate::{CuckooError, CuckooFilter, ExternalBuilder};
+    use std::{env, fs};
+
+    #[test]
+    fn test_build_with_runs() {
+        let dir = env::temp_dir();
+        let path = dir.join(format!("dakv_cuckoo_external_{}", std::process::id()));
+        let keys = (0..1900u16).map(|i| i.to_string()).collect::<Vec<_>>();
+        let mut builder = ExternalBuilder::new(1000, &dir).chunk_records(256);
+        let input = keys.join("\n");
+        assert_eq!(builder.import(input.as_bytes()).unwrap(), 1900);
+        let report = builder.finish(&path).unwrap();
+        assert_eq!(report.runs, 8);
+        assert_eq!(report.inserted, keys.len() as u64);
+
+        let bytes = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        let header = Header::parse(&bytes).unwrap();
+        assert_eq!(header.size, report.inserted);
+        let buckets = &bytes[HEADER_LEN..];
+        let occupied = buckets.iter().filter(|&&fp| fp != 0).count();
+        assert_eq!(occupied as u64, report.inserted);
+
+        let pow = header.pow as usize;
+        assert!(keys.iter().all(|k| {
+            let f = get_indices_and_fingerprint(k.as_bytes(), pow);
+            [f.i1, f.i2].iter().any(|&i| {
+                let i = i as usize * 4;
+                buckets[i..i + 4].contains(&f.fp)
+            })
+        }));
+
+        let cf = CuckooFilter::from_bytes(&bytes).unwrap();
+        assert_eq!(cf.size(), keys.len());
+        assert!(keys.iter().all(|k| cf.contains(k.as_bytes())));
+    }
+
+    #[test]
+    fn test_builders_share_dir() {
+        let dir = env::temp_dir();
+        let mut builders = (0..2)
+            .map(|_| ExternalBuilder::new(1000, &dir).chunk_records(100))
+            .collect::<Vec<_>>();
+        for (n, builder) in builders.iter_mut().enumerate() {
+            for i in 0..300 {
+                builder.add(format!("{}-{}", n, i).as_bytes()).unwrap();
+            }
+        }
+        let second = builders.pop().unwrap();
+        drop(builders);
+
+        let path = dir.join(format!(
+            "dakv_cuckoo_external_shared_{}",
+            std::process::id()
+        ));
+        let report = second.finish(&path).unwrap();
+        let cf = CuckooFilter::from_bytes(&fs::read(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(report.inserted, 300);
+        assert!((0..300).all(|i| cf.contains(format!("1-{}", i).as_bytes())));
+    }
+
+    #[test]
+    fn test_overfull() {
+        let dir = env::temp_dir();
+        let path = dir.join(format!("dakv_cuckoo_external_full_{}", std::process::id()));
+        let mut builder = ExternalBuilder::new(100, &dir);
+        for i in 0..200u16 {
+            builder.add(&i.to_le_bytes()).unwrap();
+        }
+        let err = builder.finish(&path).unwrap_err();
+        fs::remove_file(&path).unwrap();
+        let err = err.into_inner().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<CuckooError>(),
+            Some(CuckooError::NotEnoughSpace(_))
+        ));
+    }
+}
@@ -0,0 +1,959 @@
+//! The serialized filter layout.
+//!
+//! A serialized filter is a fixed `HEADER_LEN`-byte header followed by the
+//! bucket data: `num_buckets` buckets of `BUCKET_BYTES` each, written by the
+//! `SlotCodec` the header's bucket size and fingerprint width identify. With
+//! this build's `Fingerprint8` codec, that is one byte per fingerprint and
+//! `0` for an empty slot; `WideCuckooFilter` writes its wider fingerprints
+//! and other bucket sizes with `WideCodec`. All integers are little endian.
+//! `describe` returns the same information as data, so loaders in other
+//! languages can be generated instead of reverse-engineered.
+//!
+//! Optional capabilities are announced by bits in the `flags` byte. Each one
+//! that carries data appends a section after the buckets, in flag bit order,
+//! prefixed with its length as a u64. A reader rejects input using a
+//! capability that changes how slots must be read, and skips sections it can
+//! safely do without.
+//!
+//! Writers of this build set `FLAG_CHECKSUM`, appending a CRC-32 of the
+//! bucket data that readers verify, so a damaged file is rejected instead of
+//! answering queries wrongly. Input without the flag is still accepted.
+//!
+//! A filter holding far fewer items than it has slots is mostly zeros, so
+//! writers store its buckets sparse-encoded under `FLAG_SPARSE` whenever that
+//! is shorter. The bucket data is then its encoded length as a u64, followed
+//! by one entry per occupied slot: the number of empty slots since the
+//! previous entry as an unsigned LEB128 varint, then the fingerprint. Slots
+//! after the last entry are empty. The encoding is never longer than the raw
+//! buckets, and the checksum covers the bucket data as stored.
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+use crate::bucket;
+use crate::builder::HashProfile;
+use std::convert::{TryFrom, TryInto};
+use std::{error, fmt};
+
+/// Identifies a serialized filter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Magic(pub [u8; 4]);
+
+/// The magic bytes every serialized filter starts with.
+pub const MAGIC: Magic = Magic(*b"DKCF");
+
+/// The layout version described by this module.
+pub const VERSION: u8 = 1;
+
+/// Length of the header in bytes.
+pub const HEADER_LEN: usize = 32;
+
+/// The serialized form of one bucket, for one bucket variant.
+///
+/// Buckets are written through a codec rather than copied as they are laid
+/// out in memory. A bucket variant with packed bits, counters or tags gets a
+/// codec of its own, identified by the header fields it records, so existing
+/// files keep their meaning and readers reject a layout they have no codec
+/// for instead of misreading it.
+pub trait SlotCodec {
+    /// Slots per bucket, recorded in the header.
+    const BUCKET_SIZE: u8;
+    /// Width of one fingerprint in bits, recorded in the header.
+    const FINGERPRINT_BITS: u8;
+    /// Length of one serialized bucket in bytes.
+    const BUCKET_BYTES: usize;
+    /// The slots of a bucket, as the codec reads and writes them.
+    type Slots;
+
+    /// Appends the serialized form of `slots` to `out`.
+    fn encode(slots: &Self::Slots, out: &mut Vec<u8>);
+
+    /// Reads the slots of one bucket from exactly `BUCKET_BYTES` bytes.
+    fn decode(bytes: &[u8]) -> Result<Self::Slots, FormatError>;
+}
+
+/// Four 8-bit fingerprints per bucket, one byte each, with `0` marking an
+/// empty slot.
+#[derive(Debug, Clone, Copy)]
+pub struct Fingerprint8;
+
+impl SlotCodec for Fingerprint8 {
+    const BUCKET_SIZE: u8 = 4;
+    const FINGERPRINT_BITS: u8 = 8;
+    const BUCKET_BYTES: usize = 4;
+    type Slots = [u8; 4];
+
+    fn encode(slots: &[u8; 4], out: &mut Vec<u8>) {
+        out.extend_from_slice(slots);
+    }
+
+    fn decode(bytes: &[u8]) -> Result<[u8; 4], FormatError> {
+        <[u8; 4]>::try_from(bytes).map_err(|_| FormatError::Truncated { field: "bucket" })
+    }
+}
+
+/// The codec of the buckets of this build.
+pub type BucketCodec = Fingerprint8;
+
+/// Number of fingerprint slots per bucket.
+pub const BUCKET_SIZE: usize = BucketCodec::BUCKET_SIZE as usize;
+
+/// Width of one fingerprint in bits.
+pub const FINGERPRINT_BITS: u8 = BucketCodec::FINGERPRINT_BITS;
+
+/// Length of one serialized bucket in bytes.
+pub const BUCKET_BYTES: usize = BucketCodec::BUCKET_BYTES;
+
+/// Id of the hash keys are hashed with in this build, see `HashProfile`.
+/// Fingerprints and indices depend on it, so readers reject other ids.
+pub const HASH_PROFILE: u8 = HashProfile::active().id();
+
+// The codec must describe the buckets it serializes.
+const _: () = assert!(BUCKET_SIZE == bucket::BUCKET_SIZE);
+
+pub const MAGIC_OFFSET: usize = 0;
+pub const VERSION_OFFSET: usize = 4;
+pub const FLAGS_OFFSET: usize = 5;
+pub const BUCKET_SIZE_OFFSET: usize = 6;
+pub const FINGERPRINT_BITS_OFFSET: usize = 7;
+pub const POW_OFFSET: usize = 8;
+pub const HASH_PROFILE_OFFSET: usize = 9;
+pub const RESERVED_OFFSET: usize = 10;
+pub const NUM_BUCKETS_OFFSET: usize = 16;
+pub const SIZE_OFFSET: usize = 24;
+pub const BUCKETS_OFFSET: usize = HEADER_LEN;
+
+/// Overflow entries that did not fit any bucket follow the buckets.
+pub const FLAG_STASH: u8 = 1 << 0;
+/// Slots hold counters next to fingerprints.
+pub const FLAG_COUNTERS: u8 = 1 << 1;
+/// Slots hold tags next to fingerprints.
+pub const FLAG_TAGS: u8 = 1 << 2;
+/// Per-bucket TTL epochs follow the buckets.
+pub const FLAG_TTL_EPOCHS: u8 = 1 << 3;
+/// A CRC-32 of the bucket data follows the buckets, as u32.
+pub const FLAG_CHECKSUM: u8 = 1 << 4;
+/// The bucket data is sparse-encoded, see `SparseEncoder`.
+pub const FLAG_SPARSE: u8 = 1 << 5;
+/// A CRC-32 per segment of the bucket data follows the buckets, see
+/// `segment_checksums_section`.
+pub const FLAG_SEGMENT_CHECKSUMS: u8 = 1 << 6;
+/// Keys are hashed with the `HashAlgorithm` recorded after the buckets, see
+/// `CuckooFilter::to_bytes_with_hasher`. Readers that do not check it would
+/// look keys up with the wrong hash, so it is never ignorable.
+pub const FLAG_HASH_ALGORITHM: u8 = 1 << 7;
+
+// Capabilities this build knows to carry a section.
+const SECTION_FLAGS: u8 =
+    FLAG_TTL_EPOCHS | FLAG_CHECKSUM | FLAG_SEGMENT_CHECKSUMS | FLAG_HASH_ALGORITHM;
+
+/// Length of the checksum section, including its length prefix.
+pub const CHECKSUM_SECTION_LEN: usize = 8 + 4;
+
+/// Capabilities this build reads natively.
+pub const SUPPORTED_FLAGS: u8 = FLAG_CHECKSUM | FLAG_SPARSE;
+/// Capabilities whose data can be skipped without wrong answers: ignoring
+/// TTL epochs only means expired items still test positive, and segment
+/// checksums only repeat what the whole-data checksum covers.
+pub const IGNORABLE_FLAGS: u8 = FLAG_TTL_EPOCHS | FLAG_SEGMENT_CHECKSUMS;
+
+/// Why serialized bytes could not be loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The input ends before the named field.
+    Truncated {
+        field: &'static str,
+    },
+    BadMagic(Magic),
+    UnsupportedVersion(u8),
+    /// The input uses capabilities this build can neither read nor skip.
+    UnsupportedCapabilities(u8),
+    /// A parameter differs from the one this build is compiled for.
+    Mismatch {
+        field: &'static str,
+        expected: u64,
+        found: u64,
+    },
+    /// A field holds a value outside its valid range.
+    Invalid {
+        field: &'static str,
+        value: u64,
+    },
+    /// The bucket data does not match its checksum.
+    Corrupted {
+        stored: u32,
+        computed: u32,
+    },
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Truncated { field } => write!(f, "input ends before {}", field),
+            FormatError::BadMagic(Magic(m)) => write!(f, "bad magic {:?}", m),
+            FormatError::UnsupportedVersion(v) => {
+                write!(f, "unsupported version {}, expected {}", v, VERSION)
+            }
+            FormatError::UnsupportedCapabilities(bits) => {
+                write!(f, "unsupported capability flags {:#04x}", bits)
+            }
+            FormatError::Mismatch {
+                field,
+                expected,
+                found,
+            } => write!(f, "{} is {}, expected {}", field, found, expected),
+            FormatError::Invalid { field, value } => write!(f, "invalid {} {}", field, value),
+            FormatError::Corrupted { stored, computed } => write!(
+                f,
+                "bucket data is corrupted: checksum is {:#010x}, expected {:#010x}",
+                computed, stored
+            ),
+        }
+    }
+}
+
+impl error::Error for FormatError {}
+
+/// A decoded header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub flags: u8,
+    pub pow: u8,
+    pub num_buckets: u64,
+    pub size: u64,
+}
+
+impl Header {
+    /// Decodes and validates the header at the start of `bytes`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::format::{FormatError, Header};
+    ///
+    /// let header = Header { flags: 0, pow: 6, num_buckets: 64, size: 0 };
+    /// assert_eq!(Header::parse(&header.encode()), Ok(header));
+    /// assert!(matches!(Header::parse(b"DKCF"), Err(FormatError::Truncated { .. })));
+    /// ```
+    pub fn parse(bytes: &[u8]) -> Result<Header, FormatError> {
+        Self::parse_with::<BucketCodec>(bytes)
+    }
+
+    /// Like `parse`, for buckets written by codec `C`.
+    pub fn parse_with<C: SlotCodec>(bytes: &[u8]) -> Result<Header, FormatError> {
+        let bytes = match bytes.get(..HEADER_LEN) {
+            Some(head) => {
+                let mut fixed = [0; HEADER_LEN];
+                fixed.copy_from_slice(head);
+                fixed
+            }
+            None => {
+                let field = FIELDS
+                    .iter()
+                    .find(|f| f.offset + f.len.unwrap_or(0) > bytes.len())
+                    .map_or("header", |f| f.name);
+                return Err(FormatError::Truncated { field });
+            }
+        };
+        let magic = Magic([
+            bytes[MAGIC_OFFSET],
+            bytes[MAGIC_OFFSET + 1],
+            bytes[MAGIC_OFFSET + 2],
+            bytes[MAGIC_OFFSET + 3],
+        ]);
+        if magic != MAGIC {
+            return Err(FormatError::BadMagic(magic));
+        }
+        if bytes[VERSION_OFFSET] != VERSION {
+            return Err(FormatError::UnsupportedVersion(bytes[VERSION_OFFSET]));
+        }
+        let flags = bytes[FLAGS_OFFSET];
+        let unusable = flags & !(SUPPORTED_FLAGS | IGNORABLE_FLAGS);
+        if unusable != 0 {
+            return Err(FormatError::UnsupportedCapabilities(unusable));
+        }
+        expect("bucket_size", bytes[BUCKET_SIZE_OFFSET], C::BUCKET_SIZE)?;
+        expect(
+            "fingerprint_bits",
+            bytes[FINGERPRINT_BITS_OFFSET],
+            C::FINGERPRINT_BITS,
+        )?;
+        expect("hash_profile", bytes[HASH_PROFILE_OFFSET], HASH_PROFILE)?;
+        // Kept zero so later fields can use them without being misread here.
+        let reserved_bits = 8 * (NUM_BUCKETS_OFFSET - RESERVED_OFFSET);
+        let reserved = read_u64(&bytes, RESERVED_OFFSET) & (u64::MAX >> (64 - reserved_bits));
+        if reserved != 0 {
+            return Err(FormatError::Invalid {
+                field: "reserved",
+                value: reserved,
+            });
+        }
+        let pow = bytes[POW_OFFSET];
+        let num_buckets = read_u64(&bytes, NUM_BUCKETS_OFFSET);
+        // Buckets beyond the mask are allowed but never addressed.
+        if pow >= 64 || num_buckets < 1 << pow {
+            return Err(FormatError::Invalid {
+                field: "pow",
+                value: u64::from(pow),
+            });
+        }
+        let size = read_u64(&bytes, SIZE_OFFSET);
+        if num_buckets
+            .checked_mul(u64::from(C::BUCKET_SIZE))
+            .is_some_and(|slots| size > slots)
+        {
+            return Err(FormatError::Invalid {
+                field: "size",
+                value: size,
+            });
+        }
+        Ok(Header {
+            flags,
+            pow,
+            num_buckets,
+            size,
+        })
+    }
+
+    /// Returns the flagged capabilities a reader of this build skips.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::format::{Header, FLAG_TTL_EPOCHS};
+    ///
+    /// let header = Header { flags: FLAG_TTL_EPOCHS, pow: 6, num_buckets: 64, size: 0 };
+    /// assert_eq!(header.ignored_flags(), FLAG_TTL_EPOCHS);
+    /// ```
+    pub fn ignored_flags(&self) -> u8 {
+        self.flags & IGNORABLE_FLAGS & !SUPPORTED_FLAGS
+    }
+
+    /// Encodes the header for this build's bucket size and fingerprint width.
+    pub fn encode(&self) -> [u8; HEADER_LEN] {
+        self.encode_with::<BucketCodec>()
+    }
+
+    /// Encodes the header for buckets written by codec `C`.
+    pub fn encode_with<C: SlotCodec>(&self) -> [u8; HEADER_LEN] {
+        let mut out = [0; HEADER_LEN];
+        out[MAGIC_OFFSET..MAGIC_OFFSET + 4].copy_from_slice(&MAGIC.0);
+        out[VERSION_OFFSET] = VERSION;
+        out[FLAGS_OFFSET] = self.flags;
+        out[BUCKET_SIZE_OFFSET] = C::BUCKET_SIZE;
+        out[FINGERPRINT_BITS_OFFSET] = C::FINGERPRINT_BITS;
+        out[POW_OFFSET] = self.pow;
+        out[HASH_PROFILE_OFFSET] = HASH_PROFILE;
+        out[NUM_BUCKETS_OFFSET..NUM_BUCKETS_OFFSET + 8]
+            .copy_from_slice(&self.num_buckets.to_le_bytes());
+        out[SIZE_OFFSET..SIZE_OFFSET + 8].copy_from_slice(&self.size.to_le_bytes());
+        out
+    }
+}
+
+/// Splits the sections that follow the buckets into `(flag, data)` pairs, in
+/// flag bit order.
+pub fn sections(flags: u8, mut rest: &[u8]) -> Result<Vec<(u8, &[u8])>, FormatError> {
+    let mut out = Vec::new();
+    for bit in 0..8 {
+        let flag = 1 << bit;
+        if flags & flag & SECTION_FLAGS == 0 {
+            continue;
+        }
+        let field = match flag {
+            FLAG_CHECKSUM => "checksum",
+            FLAG_SEGMENT_CHECKSUMS => "segment_checksums",
+            FLAG_HASH_ALGORITHM => "hash_algorithm",
+            _ => "section",
+        };
+        let (len, tail) = rest
+            .split_first_chunk::<8>()
+            .ok_or(FormatError::Truncated { field })?;
+        let (data, tail) = usize::try_from(u64::from_le_bytes(*len))
+            .ok()
+            .and_then(|len| tail.split_at_checked(len))
+            .ok_or(FormatError::Truncated { field })?;
+        out.push((flag, data));
+        rest = tail;
+    }
+    Ok(out)
+}
+
+/// Checks the data of a checksum section against `computed`.
+pub fn verify_checksum(section: &[u8], computed: u32) -> Result<(), FormatError> {
+    let stored = section
+        .try_into()
+        .map(u32::from_le_bytes)
+        .map_err(|_| FormatError::Invalid {
+            field: "checksum",
+            value: section.len() as u64,
+        })?;
+    if stored == computed {
+        Ok(())
+    } else {
+        Err(FormatError::Corrupted { stored, computed })
+    }
+}
+
+/// Returns the CRC-32 of `data`, see `Crc32`.
+pub fn checksum(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.finish()
+}
+
+/// Encodes the checksum section for `checksum`.
+pub fn checksum_section(checksum: u32) -> [u8; CHECKSUM_SECTION_LEN] {
+    let mut out = [0; CHECKSUM_SECTION_LEN];
+    let (len, crc) = out.split_at_mut(8);
+    len.copy_from_slice(&4u64.to_le_bytes());
+    crc.copy_from_slice(&checksum.to_le_bytes());
+    out
+}
+
+/// Encodes the segment checksums section of raw bucket `data`: the length
+/// of a segment in buckets as a u32, then the CRC-32 of every segment. The
+/// last segment may be shorter.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::format::{parse_segment_checksums, segment_checksums_section};
+///
+/// let section = segment_checksums_section(&[0; 40], 4);
+/// let (segment_buckets, checksums) = parse_segment_checksums(&section[8..]).unwrap();
+/// assert_eq!((segment_buckets, checksums.len()), (4, 3));
+/// ```
+pub fn segment_checksums_section(data: &[u8], segment_buckets: u32) -> Vec<u8> {
+    let segment_len = (segment_buckets.max(1) as usize).saturating_mul(BUCKET_BYTES);
+    let segments = data.chunks(segment_len);
+    let len = 4 + 4 * segments.len();
+    let mut out = Vec::with_capacity(8 + len);
+    out.extend_from_slice(&(len as u64).to_le_bytes());
+    out.extend_from_slice(&segment_buckets.max(1).to_le_bytes());
+    for segment in segments {
+        out.extend_from_slice(&checksum(segment).to_le_bytes());
+    }
+    out
+}
+
+/// Splits the data of a segment checksums section into the length of a
+/// segment in buckets and the checksum of every segment.
+pub fn parse_segment_checksums(section: &[u8]) -> Result<(u32, Vec<u32>), FormatError> {
+    let invalid = FormatError::Invalid {
+        field: "segment_checksums",
+        value: section.len() as u64,
+    };
+    let (segment_buckets, checksums) = section
+        .split_first_chunk::<4>()
+        .ok_or_else(|| invalid.clone())?;
+    let segment_buckets = u32::from_le_bytes(*segment_buckets);
+    if segment_buckets == 0 || checksums.len() % 4 != 0 {
+        return Err(invalid);
+    }
+    let checksums = checksums
+        .chunks_exact(4)
+        .map(|crc| crc.try_into().map_or(0, u32::from_le_bytes))
+        .collect();
+    Ok((segment_buckets, checksums))
+}
+
+/// Length of the hash algorithm section, including its length prefix: the
+/// `HashAlgorithm` id as a u8, then the seed as a u64.
+pub const HASH_ALGORITHM_SECTION_LEN: usize = 8 + 1 + 8;
+
+/// Set in the algorithm id of a hash algorithm section if keys were hashed
+/// under a secret key. The seed field then holds a check value of the key,
+/// never the key itself.
+pub const HASH_ALGORITHM_KEYED: u8 = 1 << 7;
+
+/// Encodes the hash algorithm section for algorithm `id` and `seed`.
+pub fn hash_algorithm_section(id: u8, seed: u64) -> [u8; HASH_ALGORITHM_SECTION_LEN] {
+    let mut out = [0; HASH_ALGORITHM_SECTION_LEN];
+    let (len, rest) = out.split_at_mut(8);
+    len.copy_from_slice(&9u64.to_le_bytes());
+    let (algorithm, seed_bytes) = rest.split_at_mut(1);
+    algorithm.copy_from_slice(&[id]);
+    seed_bytes.copy_from_slice(&seed.to_le_bytes());
+    out
+}
+
+/// Splits the data of a hash algorithm section into the algorithm id and
+/// the seed.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::format::{hash_algorithm_section, parse_hash_algorithm_section};
+///
+/// let section = hash_algorithm_section(2, 42);
+/// assert_eq!(parse_hash_algorithm_section(&section[8..]), Ok((2, 42)));
+/// ```
+pub fn parse_hash_algorithm_section(section: &[u8]) -> Result<(u8, u64), FormatError> {
+    match section.split_first_chunk::<1>() {
+        Some((&[id], seed)) => seed
+            .try_into()
+            .map(|seed| (id, u64::from_le_bytes(seed)))
+            .map_err(|_| FormatError::Invalid {
+                field: "hash_algorithm",
+                value: section.len() as u64,
+            }),
+        None => Err(FormatError::Invalid {
+            field: "hash_algorithm",
+            value: 0,
+        }),
+    }
+}
+
+/// Checks the declared length of sparse bucket data against `raw_len`, the
+/// length of the raw buckets, and returns it.
+pub fn check_sparse_len(len: u64, raw_len: usize) -> Result<usize, FormatError> {
+    usize::try_from(len)
+        .ok()
+        .filter(|&len| len <= raw_len)
+        .ok_or(FormatError::Invalid {
+            field: "sparse",
+            value: len,
+        })
+}
+
+/// Incremental encoder of the sparse bucket data of `FLAG_SPARSE`.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::format::{SparseDecoder, SparseEncoder};
+///
+/// let raw = [0x11, 0, 0, 0, 0, 0, 0, 0, 0x22, 0x33, 0, 0, 0, 0, 0, 0];
+/// let mut sparse = Vec::new();
+/// SparseEncoder::new().push(&raw, &mut sparse);
+/// assert_eq!(sparse, [0, 0x11, 7, 0x22, 0, 0x33]);
+///
+/// let mut decoded = [0; 16];
+/// let mut decoder = SparseDecoder::new();
+/// decoder.push(&sparse, &mut decoded).unwrap();
+/// decoder.finish().unwrap();
+/// assert_eq!(decoded, raw);
+/// ```
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SparseEncoder {
+    gap: u64,
+}
+
+impl SparseEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends the encoding of the next raw bucket bytes to `out`.
+    pub fn push(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        for &fp in data {
+            if fp == 0 {
+                self.gap += 1;
+                continue;
+            }
+            let mut gap = self.gap;
+            while gap >= 0x80 {
+                out.push(gap as u8 | 0x80);
+                gap >>= 7;
+            }
+            out.push(gap as u8);
+            out.push(fp);
+            self.gap = 0;
+        }
+    }
+}
+
+/// Incremental decoder of the sparse bucket data of `FLAG_SPARSE`, see
+/// `SparseEncoder`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SparseDecoder {
+    // Slot the next fingerprint goes to
+    pos: u64,
+    // Varint being read and its bit position, 0 between entries
+    gap: u64,
+    shift: u32,
+    // Whether the varint is complete and the fingerprint comes next
+    at_fingerprint: bool,
+}
+
+impl SparseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes the next bytes of sparse bucket data into `out`, the zeroed
+    /// raw bucket data of the whole filter.
+    pub fn push(&mut self, data: &[u8], out: &mut [u8]) -> Result<(), FormatError> {
+        for &b in data {
+            if self.at_fingerprint {
+                let slot = usize::try_from(self.pos)
+                    .ok()
+                    .and_then(|pos| out.get_mut(pos))
+                    .ok_or(FormatError::Invalid {
+                        field: "sparse slot",
+                        value: self.pos,
+                    })?;
+                *slot = b;
+                *self = SparseDecoder {
+                    pos: self.pos + 1,
+                    ..Self::default()
+                };
+            } else if self.shift >= 64 {
+                return Err(FormatError::Invalid {
+                    field: "sparse gap",
+                    value: self.gap,
+                });
+            } else {
+                self.gap |= u64::from(b & 0x7f) << self.shift;
+                self.shift += 7;
+                if b & 0x80 == 0 {
+                    self.pos = self.pos.saturating_add(self.gap);
+                    self.at_fingerprint = true;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the data did not end inside an entry.
+    pub fn finish(&self) -> Result<(), FormatError> {
+        if self.shift == 0 {
+            Ok(())
+        } else {
+            Err(FormatError::Truncated { field: "sparse" })
+        }
+    }
+}
+
+/// Incremental CRC-32 (IEEE 802.3, as in zlib), the checksum of
+/// `FLAG_CHECKSUM`.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::format::Crc32;
+///
+/// let mut crc = Crc32::new();
+/// crc.update(b"1234");
+/// crc.update(b"56789");
+/// assert_eq!(crc.finish(), 0xcbf43926);
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+#[allow(clippy::indexing_slicing)]
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Crc32(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for &b in data {
+            let i = usize::from(self.0 as u8 ^ b);
+            self.0 = CRC_TABLE.get(i).copied().unwrap_or(0) ^ (self.0 >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Crc32::new()
+    }
+}
+
+fn expect(field: &'static str, found: u8, expected: u8) -> Result<(), FormatError> {
+    if found == expected {
+        Ok(())
+    } else {
+        Err(FormatError::Mismatch {
+            field,
+            expected: u64::from(expected),
+            found: u64::from(found),
+        })
+    }
+}
+
+fn read_u64(bytes: &[u8; HEADER_LEN], offset: usize) -> u64 {
+    let mut word = [0; 8];
+    for (i, b) in word.iter_mut().enumerate() {
+        *b = bytes.get(offset + i).copied().unwrap_or(0);
+    }
+    u64::from_le_bytes(word)
+}
+
+/// How the bytes of a `Field` are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    /// Opaque bytes.
+    Bytes,
+    /// A single unsigned byte.
+    U8,
+    /// An unsigned 64-bit little endian integer.
+    U64Le,
+    /// Fingerprints, `BUCKET_SIZE` per bucket.
+    Buckets,
+    /// A section present under the given flag, prefixed with its length as
+    /// a u64.
+    Section(u8),
+}
+
+/// One field of the serialized layout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Field {
+    pub name: &'static str,
+    /// Offset from the start of the input. Sections follow the bucket data
+    /// in flag bit order, each only if its flag is set, so their offset is
+    /// relative to the end of the bucket data or the previous section.
+    pub offset: usize,
+    /// Length in bytes, or `None` if the field runs to the end of the input
+    /// or, for a section, depends on its contents.
+    pub len: Option<usize>,
+    pub kind: FieldKind,
+    pub description: &'static str,
+}
+
+const FIELDS: [Field; 14] = [
+    Field {
+        name: "magic",
+        offset: MAGIC_OFFSET,
+        len: Some(4),
+        kind: FieldKind::Bytes,
+        description: "always \"DKCF\"",
+    },
+    Field {
+        name: "version",
+        offset: VERSION_OFFSET,
+        len: Some(1),
+        kind: FieldKind::U8,
+        description: "layout version",
+    },
+    Field {
+        name: "flags",
+        offset: FLAGS_OFFSET,
+        len: Some(1),
+        kind: FieldKind::U8,
+        description: "capability bits, see FLAG_*",
+    },
+    Field {
+        name: "bucket_size",
+        offset: BUCKET_SIZE_OFFSET,
+        len: Some(1),
+        kind: FieldKind::U8,
+        description: "fingerprint slots per bucket",
+    },
+    Field {
+        name: "fingerprint_bits",
+        offset: FINGERPRINT_BITS_OFFSET,
+        len: Some(1),
+        kind: FieldKind::U8,
+        description: "width of one fingerprint",
+    },
+    Field {
+        name: "pow",
+        offset: POW_OFFSET,
+        len: Some(1),
+        kind: FieldKind::U8,
+        description: "log2 of the number of reachable buckets",
+    },
+    Field {
+        name: "hash_profile",
+        offset: HASH_PROFILE_OFFSET,
+        len: Some(1),
+        kind: FieldKind::U8,
+        description: "hash keys were hashed with, 0 for SeaHash, 1 for the `fast` mixer",
+    },
+    Field {
+        name: "reserved",
+        offset: RESERVED_OFFSET,
+        len: Some(NUM_BUCKETS_OFFSET - RESERVED_OFFSET),
+        kind: FieldKind::Bytes,
+        description: "must be zero",
+    },
+    Field {
+        name: "num_buckets",
+        offset: NUM_BUCKETS_OFFSET,
+        len: Some(8),
+        kind: FieldKind::U64Le,
+        description: "number of buckets that follow the header",
+    },
+    Field {
+        name: "size",
+        offset: SIZE_OFFSET,
+        len: Some(8),
+        kind: FieldKind::U64Le,
+        description: "number of stored fingerprints",
+    },
+    Field {
+        name: "buckets",
+        offset: BUCKETS_OFFSET,
+        len: None,
+        kind: FieldKind::Buckets,
+        description: "num_buckets * bucket_size fingerprints, 0 marks an empty slot; \
+                      sparse-encoded under FLAG_SPARSE",
+    },
+    Field {
+        name: "checksum",
+        offset: 0,
+        len: Some(CHECKSUM_SECTION_LEN),
+        kind: FieldKind::Section(FLAG_CHECKSUM),
+        description: "CRC-32 of the bucket data as stored, as u32",
+    },
+    Field {
+        name: "segment_checksums",
+        offset: 0,
+        len: None,
+        kind: FieldKind::Section(FLAG_SEGMENT_CHECKSUMS),
+        description: "buckets per segment as u32, then a CRC-32 per segment of the raw \
+                      bucket data, as u32",
+    },
+    Field {
+        name: "hash_algorithm",
+        offset: 0,
+        len: Some(HASH_ALGORITHM_SECTION_LEN),
+        kind: FieldKind::Section(FLAG_HASH_ALGORITHM),
+        description: "HashAlgorithm id as u8, with HASH_ALGORITHM_KEYED for keyed hashing, \
+                      then the seed or key check value as u64",
+    },
+];
+
+/// Returns every field of the layout in offset order.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::format;
+///
+/// for field in format::describe() {
+///     println!("{} @ {}: {}", field.name, field.offset, field.description);
+/// }
+/// ```
+pub fn describe() -> &'static [Field] {
+    &FIELDS
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fields_are_contiguous() {
+        let fields = describe();
+        let (header, sections): (Vec<&Field>, Vec<_>) = fields
+            .iter()
+            .partition(|f| !matches!(f.kind, FieldKind::Section(_)));
+        assert_eq!(header[0].offset, 0);
+        for pair in header.windows(2) {
+            assert_eq!(pair[0].offset + pair[0].len.unwrap(), pair[1].offset);
+        }
+        let last = header.last().unwrap();
+        assert_eq!(last.offset, HEADER_LEN);
+        assert_eq!(last.len, None);
+
+        let flags = sections
+            .iter()
+            .map(|f| match f.kind {
+                FieldKind::Section(flag) => flag,
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert!(flags.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(flags.iter().all(|&flag| SECTION_FLAGS & flag != 0));
+    }
+
+    #[test]
+    fn test_slot_codec() {
+        let mut out = Vec::new();
+        BucketCodec::encode(&[0x11, 0, 0x22, 0], &mut out);
+        assert_eq!(out.len(), BUCKET_BYTES);
+        assert_eq!(BucketCodec::decode(&out), Ok([0x11, 0, 0x22, 0]));
+        assert_eq!(
+            BucketCodec::decode(&out[1..]),
+            Err(FormatError::Truncated { field: "bucket" })
+        );
+    }
+
+    #[test]
+    fn test_capability_flags() {
+        let mut header = Header {
+            flags: FLAG_TTL_EPOCHS,
+            pow: 3,
+            num_buckets: 8,
+            size: 1,
+        };
+        assert_eq!(Header::parse(&header.encode()), Ok(header));
+        for &flag in &[FLAG_STASH, FLAG_COUNTERS, FLAG_TAGS, FLAG_HASH_ALGORITHM] {
+            header.flags = flag | FLAG_TTL_EPOCHS;
+            assert_eq!(
+                Header::parse(&header.encode()),
+                Err(FormatError::UnsupportedCapabilities(flag))
+            );
+        }
+    }
+
+    #[test]
+    fn test_header_validation() {
+        let header = Header {
+            flags: 0,
+            pow: 3,
+            num_buckets: 8,
+            size: 0,
+        };
+        let mut bytes = header.encode();
+        bytes[BUCKET_SIZE_OFFSET] = 8;
+        assert!(matches!(
+            Header::parse(&bytes),
+            Err(FormatError::Mismatch {
+                field: "bucket_size",
+                ..
+            })
+        ));
+        let mut bytes = header.encode();
+        bytes[POW_OFFSET] = 4;
+        assert!(matches!(
+            Header::parse(&bytes),
+            Err(FormatError::Invalid { field: "pow", .. })
+        ));
+        let mut bytes = header.encode();
+        bytes[HASH_PROFILE_OFFSET] ^= 1;
+        assert_eq!(
+            Header::parse(&bytes),
+            Err(FormatError::Mismatch {
+                field: "hash_profile",
+                expected: u64::from(HASH_PROFILE),
+                found: u64::from(HASH_PROFILE ^ 1),
+            })
+        );
+        for offset in RESERVED_OFFSET..NUM_BUCKETS_OFFSET {
+            let mut bytes = header.encode();
+            bytes[offset] = 1;
+            assert_eq!(
+                Header::parse(&bytes),
+                Err(FormatError::Invalid {
+                    field: "reserved",
+                    value: 1 << (8 * (offset - RESERVED_OFFSET)),
+                })
+            );
+        }
+        assert_eq!(
+            Header::parse(&bytes[..10]),
+            Err(FormatError::Truncated { field: "reserved" })
+        );
+    }
+}
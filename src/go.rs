@@ -0,0 +1,355 @@
+//! Wire compatibility with the Go package `github.com/seiflotfy/cuckoofilter`.
+//!
+//! Its `Encode` output is the bucket array alone: four one-byte slots per
+//! bucket, zero marking an empty slot, with no header. Keys are hashed with
+//! MetroHash64 seeded with 1337; the fingerprint is the hash modulo 255 plus
+//! one, the first bucket comes from the high 32 bits, and the alternate
+//! bucket XORs in the MetroHash64 of the fingerprint byte.
+
+// Bucket indices are masked to `pow` bits, and `pow` is the number of
+// trailing zeros of the bucket count, so the masked index is always below it.
+// MetroHash reads stay within the bytes its loop still counts as left.
+#![allow(clippy::indexing_slicing)]
+
+use crate::bucket::BUCKET_SIZE;
+use crate::format::FormatError;
+use crate::telemetry;
+use crate::util::MASKS;
+use crate::{CResult, CuckooError, Placement};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+// Seed of every hash the Go package computes
+const SEED: u64 = 1337;
+
+// Relocations before an insert gives up, as in the Go package
+const MAX_KICKS: usize = 500;
+
+const K0: u64 = 0xD6D0_18F5;
+const K1: u64 = 0xA2AA_033B;
+const K2: u64 = 0x6299_2FC1;
+const K3: u64 = 0x30BC_5B29;
+
+const ALT_HASH: [u64; 256] = alt_hash_table();
+
+const fn alt_hash_table() -> [u64; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        table[i] = metro64(&[i as u8], SEED);
+        i += 1;
+    }
+    table
+}
+
+/// Reads `len` bytes of `data` at `at` as a little-endian integer.
+const fn read_le(data: &[u8], at: usize, len: usize) -> u64 {
+    let mut v = 0;
+    let mut i = len;
+    while i > 0 {
+        i -= 1;
+        v = v << 8 | data[at + i] as u64;
+    }
+    v
+}
+
+const fn mix(v: u64, k: u64) -> u64 {
+    v.rotate_right(37).wrapping_mul(k)
+}
+
+/// MetroHash64 of `data`, as computed by `github.com/dgryski/go-metro`.
+const fn metro64(data: &[u8], seed: u64) -> u64 {
+    let mut at = 0;
+    let mut left = data.len();
+    let mut hash = seed.wrapping_add(K2).wrapping_mul(K0);
+    if left >= 32 {
+        let (mut v0, mut v1, mut v2, mut v3) = (hash, hash, hash, hash);
+        while left >= 32 {
+            v0 = v0.wrapping_add(read_le(data, at, 8).wrapping_mul(K0));
+            v0 = v0.rotate_right(29).wrapping_add(v2);
+            v1 = v1.wrapping_add(read_le(data, at + 8, 8).wrapping_mul(K1));
+            v1 = v1.rotate_right(29).wrapping_add(v3);
+            v2 = v2.wrapping_add(read_le(data, at + 16, 8).wrapping_mul(K2));
+            v2 = v2.rotate_right(29).wrapping_add(v0);
+            v3 = v3.wrapping_add(read_le(data, at + 24, 8).wrapping_mul(K3));
+            v3 = v3.rotate_right(29).wrapping_add(v1);
+            at += 32;
+            left -= 32;
+        }
+        v2 ^= mix(v0.wrapping_add(v3).wrapping_mul(K0).wrapping_add(v1), K1);
+        v3 ^= mix(v1.wrapping_add(v2).wrapping_mul(K1).wrapping_add(v0), K0);
+        v0 ^= mix(v0.wrapping_add(v2).wrapping_mul(K0).wrapping_add(v3), K1);
+        v1 ^= mix(v1.wrapping_add(v3).wrapping_mul(K1).wrapping_add(v2), K0);
+        hash = hash.wrapping_add(v0 ^ v1);
+    }
+    if left >= 16 {
+        let mut v0 = hash.wrapping_add(read_le(data, at, 8).wrapping_mul(K2));
+        v0 = v0.rotate_right(29).wrapping_mul(K3);
+        let mut v1 = hash.wrapping_add(read_le(data, at + 8, 8).wrapping_mul(K2));
+        v1 = v1.rotate_right(29).wrapping_mul(K3);
+        v0 ^= v0.wrapping_mul(K0).rotate_right(21).wrapping_add(v1);
+        v1 ^= v1.wrapping_mul(K3).rotate_right(21).wrapping_add(v0);
+        hash = hash.wrapping_add(v1);
+        at += 16;
+        left -= 16;
+    }
+    if left >= 8 {
+        hash = hash.wrapping_add(read_le(data, at, 8).wrapping_mul(K3));
+        hash ^= hash.rotate_right(55).wrapping_mul(K1);
+        at += 8;
+        left -= 8;
+    }
+    if left >= 4 {
+        hash = hash.wrapping_add(read_le(data, at, 4).wrapping_mul(K3));
+        hash ^= hash.rotate_right(26).wrapping_mul(K1);
+        at += 4;
+        left -= 4;
+    }
+    if left >= 2 {
+        hash = hash.wrapping_add(read_le(data, at, 2).wrapping_mul(K3));
+        hash ^= hash.rotate_right(48).wrapping_mul(K1);
+        at += 2;
+        left -= 2;
+    }
+    if left >= 1 {
+        hash = hash.wrapping_add((data[at] as u64).wrapping_mul(K3));
+        hash ^= hash.rotate_right(37).wrapping_mul(K1);
+    }
+    hash ^= hash.rotate_right(28);
+    hash = hash.wrapping_mul(K0);
+    hash ^= hash.rotate_right(29);
+    hash
+}
+
+/// A filter that hashes and serializes exactly like the Go package
+/// `github.com/seiflotfy/cuckoofilter`, for services that exchange filters
+/// with Go code.
+///
+/// Its hashing differs from `CuckooFilter`'s, so keys are only found by the
+/// type that added them; convert filters through the Go encoding only.
+/// Unlike the Go package, an insert that fails after `500` relocations
+/// leaves the filter unchanged instead of dropping a fingerprint.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::GoCuckooFilter;
+///
+/// let mut cf = GoCuckooFilter::new(1000);
+/// cf.add(b"test").unwrap();
+/// // What Go's `cuckoo.Decode` accepts, and `Encode` produces.
+/// let bytes = cf.to_go_encode();
+/// assert_eq!(bytes.len(), 256 * 4);
+/// assert!(GoCuckooFilter::from_go_encode(&bytes).unwrap().contains(b"test"));
+/// ```
+#[derive(Debug, Clone)]
+pub struct GoCuckooFilter {
+    buckets: Vec<[u8; BUCKET_SIZE]>,
+    pow: usize,
+    size: usize,
+    rng: StdRng,
+}
+
+impl GoCuckooFilter {
+    /// Sizes the filter like Go's `NewFilter(capacity)`: the next power of
+    /// two of `capacity`, in buckets of four slots.
+    pub fn new(capacity: u64) -> Self {
+        let slots = capacity
+            .max(1)
+            .checked_next_power_of_two()
+            .unwrap_or(1 << 63);
+        Self::with_buckets(vec![
+            [0; BUCKET_SIZE];
+            (slots as usize / BUCKET_SIZE).max(1)
+        ])
+    }
+
+    fn with_buckets(buckets: Vec<[u8; BUCKET_SIZE]>) -> Self {
+        let size = buckets.iter().flatten().filter(|&&fp| fp != 0).count();
+        GoCuckooFilter {
+            pow: buckets.len().trailing_zeros() as usize,
+            buckets,
+            size,
+            rng: StdRng::from_entropy(),
+        }
+    }
+
+    /// Reads the output of Go's `Filter.Encode`.
+    ///
+    /// Like Go's `Decode`, it rejects input that is empty or not a whole
+    /// number of buckets, as `Invalid` of `length`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::format::FormatError;
+    /// use dakv_cuckoo::GoCuckooFilter;
+    ///
+    /// assert!(matches!(
+    ///     GoCuckooFilter::from_go_encode(&[0; 6]),
+    ///     Err(FormatError::Invalid { field: "length", value: 6 })
+    /// ));
+    /// ```
+    pub fn from_go_encode(bytes: &[u8]) -> Result<Self, FormatError> {
+        if bytes.is_empty() || !bytes.len().is_multiple_of(BUCKET_SIZE) {
+            return Err(FormatError::Invalid {
+                field: "length",
+                value: bytes.len() as u64,
+            });
+        }
+        let buckets = bytes
+            .chunks_exact(BUCKET_SIZE)
+            .map(|slots| {
+                let mut bucket = [0; BUCKET_SIZE];
+                bucket.copy_from_slice(slots);
+                bucket
+            })
+            .collect();
+        Ok(Self::with_buckets(buckets))
+    }
+
+    /// Serializes the filter as Go's `Filter.Encode` does.
+    pub fn to_go_encode(&self) -> Vec<u8> {
+        self.buckets.iter().flatten().copied().collect()
+    }
+
+    fn finger(&self, item: &[u8]) -> (u8, usize, usize) {
+        let hash = metro64(item, SEED);
+        let fp = (hash % 255 + 1) as u8;
+        let i1 = ((hash >> 32) & MASKS[self.pow]) as usize;
+        (fp, i1, self.alt_index(fp, i1))
+    }
+
+    fn alt_index(&self, fp: u8, i: usize) -> usize {
+        let mask = MASKS[self.pow];
+        ((i as u64 & mask) ^ (ALT_HASH[fp as usize] & mask)) as usize
+    }
+
+    fn insert(&mut self, fp: u8, i: usize) -> bool {
+        match self.buckets[i].iter_mut().find(|slot| **slot == 0) {
+            Some(slot) => {
+                *slot = fp;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds `item`, trying its two buckets and then relocating fingerprints
+    /// like Go's `Insert`. Duplicates are stored again, as in Go.
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        let (fp, i1, i2) = self.finger(item);
+        if self.insert(fp, i1) || self.insert(fp, i2) {
+            self.size += 1;
+            return Ok(());
+        }
+        let mut i = if self.rng.gen() { i1 } else { i2 };
+        let mut carried = fp;
+        let mut kicks = Vec::with_capacity(MAX_KICKS);
+        for _ in 0..MAX_KICKS {
+            let slot = self.rng.gen_range(0, BUCKET_SIZE);
+            kicks.push((i, slot));
+            std::mem::swap(&mut self.buckets[i][slot], &mut carried);
+            i = self.alt_index(carried, i);
+            if self.insert(carried, i) {
+                self.size += 1;
+                return Ok(());
+            }
+        }
+        for (i, slot) in kicks.into_iter().rev() {
+            std::mem::swap(&mut self.buckets[i][slot], &mut carried);
+        }
+        Err(telemetry::record(CuckooError::NotEnoughSpace(Placement {
+            fingerprint: fp,
+            i1,
+            i2,
+        })))
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let (fp, i1, i2) = self.finger(item);
+        self.buckets[i1].contains(&fp) || self.buckets[i2].contains(&fp)
+    }
+
+    /// Removes one copy of `item`'s fingerprint, like Go's `Delete`.
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        let (fp, i1, i2) = self.finger(item);
+        for i in [i1, i2] {
+            if let Some(slot) = self.buckets[i].iter_mut().find(|slot| **slot == fp) {
+                *slot = 0;
+                self.size -= 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn num_buckets(&self) -> usize {
+        self.buckets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go::metro64;
+    use crate::GoCuckooFilter;
+
+    #[test]
+    fn test_metro64_reference() {
+        let key = b"012345678901234567890123456789012345678901234567890123456789012";
+        assert_eq!(metro64(key, 0), 0xad4b_7006_ae3d_756b);
+        assert_eq!(metro64(key, 1), 0xdfb8_b9f4_1c48_0d3b);
+        assert_eq!(metro64(b"", 1337), 0xe2f7_00c7_be59_6c30);
+    }
+
+    #[test]
+    fn test_go_layout() {
+        // "hello" hashes to fingerprint 132 with buckets 15 and 12 of 16.
+        let mut cf = GoCuckooFilter::new(64);
+        assert_eq!(cf.num_buckets(), 16);
+        cf.add(b"hello").unwrap();
+        let mut expected = vec![0; 64];
+        expected[15 * 4] = 132;
+        assert_eq!(cf.to_go_encode(), expected);
+
+        expected[15 * 4] = 0;
+        expected[12 * 4 + 3] = 132;
+        let decoded = GoCuckooFilter::from_go_encode(&expected).unwrap();
+        assert_eq!(decoded.size(), 1);
+        assert!(decoded.contains(b"hello"));
+        assert!(GoCuckooFilter::from_go_encode(&[]).is_err());
+    }
+
+    #[test]
+    fn test_go_round_trip() {
+        let mut cf = GoCuckooFilter::new(4096);
+        for i in 0..3500u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let bytes = cf.to_go_encode();
+        let decoded = GoCuckooFilter::from_go_encode(&bytes).unwrap();
+        assert_eq!(decoded.size(), 3500);
+        assert!((0..3500u16).all(|i| decoded.contains(&i.to_le_bytes())));
+        #[cfg(not(feature = "no-delete"))]
+        {
+            let mut decoded = decoded;
+            assert!(decoded.delete(&7u16.to_le_bytes()));
+            assert_eq!(decoded.size(), 3499);
+        }
+
+        // A full filter rejects without losing anything.
+        let mut full = GoCuckooFilter::new(4);
+        let added = (0..100u16)
+            .filter(|i| full.add(&i.to_le_bytes()).is_ok())
+            .count();
+        assert_eq!(added, 4);
+        assert_eq!(full.size(), 4);
+    }
+}
@@ -0,0 +1,241 @@
+//! Compatibility with the `cuckoofilter` crate.
+//!
+//! Covers its `CuckooFilter<H>` with 1-byte fingerprints in buckets of four
+//! slots, 100 marking an empty slot. A key is hashed with `Hash` into a fresh
+//! `H`; the top byte of the hash is the fingerprint, moved to 101 if it is
+//! 100, and the low 32 bits pick the bucket. The alternate bucket XORs in the
+//! hash of the fingerprint as a one-byte array. `ExportedCuckooFilter` holds
+//! the slots back to back and the item count.
+
+// Bucket counts are powers of two, as `from_exported` checks, and every
+// bucket index is masked by the count minus one.
+#![allow(clippy::indexing_slicing)]
+
+use crate::bucket::BUCKET_SIZE;
+use crate::cuckoo_filter::MAX_CUCKOO_COUNT;
+use crate::format::FormatError;
+use crate::telemetry;
+use crate::{CResult, CuckooError, Placement};
+use cuckoofilter::ExportedCuckooFilter;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::mem;
+
+// Slot value the `cuckoofilter` crate reserves for empty slots
+const EMPTY: u8 = 100;
+
+/// A filter that places and stores fingerprints exactly like the
+/// `cuckoofilter` crate, for taking over its persisted filters.
+///
+/// `from_exported` reads the `ExportedCuckooFilter` of a filter built with
+/// the same hasher `H` and answers the same keys, without access to them;
+/// `to_exported` goes back. The fingerprints were derived with that crate's
+/// hashing, so they cannot be moved into a `CuckooFilter`; switching to one
+/// needs the keys, e.g. through `MigratingFilter`.
+///
+/// `DefaultHasher` is only stable within one Rust release, so filters built
+/// with it must be read by a binary compiled with the same toolchain.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::LegacyCuckooFilter;
+///
+/// let mut theirs = cuckoofilter::CuckooFilter::new();
+/// theirs.add(&42u64).unwrap();
+/// let ours: LegacyCuckooFilter = LegacyCuckooFilter::from_exported(&theirs.export()).unwrap();
+/// assert!(ours.contains(&42u64));
+/// ```
+#[derive(Debug, Clone)]
+pub struct LegacyCuckooFilter<H = DefaultHasher> {
+    buckets: Vec<[u8; BUCKET_SIZE]>,
+    size: usize,
+    rng: StdRng,
+    hasher: PhantomData<H>,
+}
+
+impl<H: Hasher + Default> LegacyCuckooFilter<H> {
+    /// Sizes the table like `cuckoofilter::CuckooFilter::with_capacity`.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let buckets = (capacity.next_power_of_two() / BUCKET_SIZE).max(1);
+        Self::with_buckets(vec![[EMPTY; BUCKET_SIZE]; buckets], 0)
+    }
+
+    fn with_buckets(buckets: Vec<[u8; BUCKET_SIZE]>, size: usize) -> Self {
+        LegacyCuckooFilter {
+            buckets,
+            size,
+            rng: StdRng::from_entropy(),
+            hasher: PhantomData,
+        }
+    }
+
+    /// Reads a filter exported by `cuckoofilter::CuckooFilter::export`.
+    ///
+    /// The slots must fill a power of two number of buckets, as the crate
+    /// allocates them, and `length` must match the occupied slots; anything
+    /// else is `Invalid`.
+    ///
+    /// # Example
+    /// ```
+    /// use cuckoofilter::ExportedCuckooFilter;
+    /// use dakv_cuckoo::format::FormatError;
+    /// use dakv_cuckoo::LegacyCuckooFilter;
+    ///
+    /// let exported = ExportedCuckooFilter { values: vec![100; 12], length: 0 };
+    /// let filter: Result<LegacyCuckooFilter, _> = LegacyCuckooFilter::from_exported(&exported);
+    /// assert!(matches!(filter, Err(FormatError::Invalid { field: "values", value: 12 })));
+    /// ```
+    pub fn from_exported(exported: &ExportedCuckooFilter) -> Result<Self, FormatError> {
+        let values = &exported.values;
+        if values.len() % BUCKET_SIZE != 0 || !(values.len() / BUCKET_SIZE).is_power_of_two() {
+            return Err(FormatError::Invalid {
+                field: "values",
+                value: values.len() as u64,
+            });
+        }
+        let occupied = values.iter().filter(|&&slot| slot != EMPTY).count();
+        if exported.length != occupied {
+            return Err(FormatError::Invalid {
+                field: "length",
+                value: exported.length as u64,
+            });
+        }
+        let buckets = values
+            .chunks_exact(BUCKET_SIZE)
+            .map(|slots| {
+                let mut bucket = [0; BUCKET_SIZE];
+                bucket.copy_from_slice(slots);
+                bucket
+            })
+            .collect();
+        Ok(Self::with_buckets(buckets, occupied))
+    }
+
+    /// Returns the filter as `cuckoofilter::CuckooFilter::export` would.
+    pub fn to_exported(&self) -> ExportedCuckooFilter {
+        ExportedCuckooFilter {
+            values: self.buckets.iter().flatten().copied().collect(),
+            length: self.size,
+        }
+    }
+
+    fn hash<T: ?Sized + Hash>(data: &T) -> u64 {
+        let mut hasher = H::default();
+        data.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn index_fp<T: ?Sized + Hash>(&self, data: &T) -> (usize, u8) {
+        let hash = Self::hash(data);
+        let fp = match (hash >> 56) as u8 {
+            EMPTY => EMPTY + 1,
+            fp => fp,
+        };
+        (hash as u32 as usize & (self.buckets.len() - 1), fp)
+    }
+
+    fn alt_index(&self, index: usize, fp: u8) -> usize {
+        (index ^ Self::hash(&[fp]) as u32 as usize) & (self.buckets.len() - 1)
+    }
+
+    fn put(&mut self, index: usize, fp: u8) -> bool {
+        match self.buckets[index].iter_mut().find(|slot| **slot == EMPTY) {
+            Some(slot) => {
+                *slot = fp;
+                self.size += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds `data`, relocating fingerprints like the crate's `add`. As there,
+    /// an insert that runs out of relocations drops the last evicted
+    /// fingerprint, so a previously added key may be lost.
+    pub fn add<T: ?Sized + Hash>(&mut self, data: &T) -> CResult<CuckooError> {
+        let (i1, fp) = self.index_fp(data);
+        let i2 = self.alt_index(i1, fp);
+        if self.put(i1, fp) || self.put(i2, fp) {
+            return Ok(());
+        }
+        let (mut index, mut fp) = (if self.rng.gen() { i1 } else { i2 }, fp);
+        for _ in 0..MAX_CUCKOO_COUNT {
+            let slot = self.rng.gen_range(0, BUCKET_SIZE);
+            fp = mem::replace(&mut self.buckets[index][slot], fp);
+            index = self.alt_index(index, fp);
+            if self.put(index, fp) {
+                return Ok(());
+            }
+        }
+        Err(telemetry::record(CuckooError::NotEnoughSpace(Placement {
+            fingerprint: fp,
+            i1,
+            i2,
+        })))
+    }
+
+    pub fn contains<T: ?Sized + Hash>(&self, data: &T) -> bool {
+        let (i1, fp) = self.index_fp(data);
+        self.buckets[i1].contains(&fp) || self.buckets[self.alt_index(i1, fp)].contains(&fp)
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete<T: ?Sized + Hash>(&mut self, data: &T) -> bool {
+        let (i1, fp) = self.index_fp(data);
+        for i in [i1, self.alt_index(i1, fp)] {
+            if let Some(slot) = self.buckets[i].iter_mut().find(|slot| **slot == fp) {
+                *slot = EMPTY;
+                self.size -= 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    pub fn num_buckets(&self) -> usize {
+        self.buckets.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::LegacyCuckooFilter;
+    use std::collections::hash_map::DefaultHasher;
+
+    #[test]
+    fn test_legacy_round_trip() {
+        let mut theirs = cuckoofilter::CuckooFilter::<DefaultHasher>::with_capacity(4096);
+        for key in 0..1000u64 {
+            theirs.add(&key).unwrap();
+        }
+        let exported = theirs.export();
+        let mut ours = LegacyCuckooFilter::<DefaultHasher>::from_exported(&exported).unwrap();
+        assert_eq!((ours.size(), ours.num_buckets()), (1000, 1024));
+        assert!((0..1000u64).all(|key| ours.contains(&key)));
+        assert_eq!(ours.to_exported().values, exported.values);
+
+        // Below capacity nothing is kicked, so both place keys identically.
+        for key in [b"a".as_ref(), b"bc", b""] {
+            theirs.add(key).unwrap();
+            ours.add(key).unwrap();
+        }
+        assert_eq!(ours.to_exported().values, theirs.export().values);
+        #[cfg(not(feature = "no-delete"))]
+        {
+            assert!(ours.delete(&7u64));
+            assert!(theirs.delete(&7u64));
+            assert_eq!(ours.to_exported().values, theirs.export().values);
+        }
+    }
+}
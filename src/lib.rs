@@ -1,8 +1,139 @@
+// The default build is entirely safe code; fast paths that need `unsafe`
+// are opted into per item under `unsafe-optimizations`, and `mmap` allows
+// the one call that maps a file.
+#![cfg_attr(
+    not(any(feature = "unsafe-optimizations", feature = "mmap")),
+    forbid(unsafe_code)
+)]
+#![cfg_attr(
+    any(feature = "unsafe-optimizations", feature = "mmap"),
+    deny(unsafe_code)
+)]
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
 #![allow(clippy::unreadable_literal)]
 #![allow(clippy::neg_multiply)]
 
+mod accel;
+mod advisor;
+mod algorithm;
+#[cfg(feature = "rkyv")]
+mod archive;
+mod base64;
+mod batch;
 mod bucket;
+mod builder;
+mod cancel;
+#[cfg(feature = "comparative-bench")]
+pub mod comparative;
+mod config;
+mod container;
+#[cfg(feature = "cpp-compat")]
+mod cpp;
 mod cuckoo_filter;
+mod delta;
+#[cfg(feature = "serde")]
+mod embed;
+mod expiring;
+mod external;
+pub mod format;
+mod go;
+#[cfg(feature = "cuckoofilter-compat")]
+mod legacy;
+mod membership;
+mod migrate;
+#[cfg(feature = "mmap")]
+mod mmap;
+mod negative_cache;
+mod paged;
+mod pending;
+#[cfg(feature = "mmap")]
+mod persistent;
+mod pipeline;
+mod publisher;
+mod quality;
+mod rebuild;
+mod redact;
+pub mod replay;
+mod rotation;
+mod saturation;
+mod scalable;
+#[cfg(feature = "segmented")]
+mod segmented;
+mod sharded;
+mod shared;
+pub mod simulate;
+mod snapshot;
+mod spill;
+mod telemetry;
+#[cfg(not(feature = "no-delete"))]
+mod tombstone;
+mod trace;
+mod tuning;
 mod util;
+mod wide;
+mod yielding;
 
-pub use cuckoo_filter::CuckooFilter;
+pub use accel::{active_acceleration, force_acceleration, Acceleration, ACCELERATION_ENV};
+pub use advisor::CapacityAdvisor;
+pub use algorithm::{HashAlgorithm, RuntimeHashState, RuntimeHasher};
+#[cfg(feature = "rkyv")]
+pub use archive::ArchivedCuckooFilter;
+pub use batch::{FilterBatch, FilterOp, WriteBatch};
+pub use builder::{CuckooFilterBuilder, HashProfile};
+pub use cancel::{CancelToken, Cancellation};
+pub use config::{ConfigError, CuckooConfig, EvictionPolicy};
+pub use container::{
+    ContainerReader, ContainerWriter, CONTAINER_HEADER_LEN, CONTAINER_MAGIC, CONTAINER_VERSION,
+};
+#[cfg(feature = "cpp-compat")]
+pub use cpp::CppCuckooFilter;
+#[cfg(feature = "bucket-versions")]
+pub use cuckoo_filter::ReadStamp;
+pub use cuckoo_filter::{
+    BatchReport, CResult, CuckooError, CuckooFilter, CuckooFilter8x4, DuplicateCensus,
+    Explanation, InvariantViolation, ItemOutcome, Placement, PreparedInsert,
+};
+pub use delta::{DeltaFilter, DELTA_HEADER_LEN, DELTA_MAGIC, DELTA_REGION_BUCKETS, DELTA_VERSION};
+#[cfg(feature = "serde")]
+pub use embed::EmbeddedFilter;
+pub use expiring::ExpiringFilter;
+pub use external::{BuildReport, ExternalBuilder, DEFAULT_CHUNK_RECORDS};
+pub use go::GoCuckooFilter;
+#[cfg(feature = "cuckoofilter-compat")]
+pub use legacy::LegacyCuckooFilter;
+pub use membership::{MembershipFilter, MockFilter};
+pub use migrate::MigratingFilter;
+#[cfg(feature = "mmap")]
+pub use mmap::{Lookup, MmapCuckooFilter};
+pub use negative_cache::{CacheStats, CachedCuckooFilter};
+pub use paged::{PagedFilter, PagedFilterBuilder, PAGE_SIZE, PAGE_SLOTS};
+pub use pending::PendingSet;
+#[cfg(feature = "mmap")]
+pub use persistent::{PersistError, PersistentCuckooFilter};
+pub use pipeline::{InsertPipeline, DEFAULT_PIPELINE_DEPTH};
+pub use publisher::{SnapshotArtifact, SnapshotPublisher};
+pub use quality::{BoundedFilter, Quality};
+pub use rebuild::{RebuildProgress, RebuildTask};
+pub use redact::{
+    clear_key_redactor, display_key, set_key_redactor, DisplayKey, KeyRedactor, SaltedHash,
+};
+pub use rotation::SeedRotation;
+pub use saturation::{SaturatingFilter, SaturationError, SaturationPolicy};
+pub use scalable::{GrowthPolicy, ScalableCuckooFilter};
+#[cfg(feature = "segmented")]
+pub use segmented::SegmentedCuckooFilter;
+pub use sharded::ShardedCuckooFilter;
+pub use shared::SharedCuckooFilter;
+pub use snapshot::{SnapshotInfo, SNAPSHOT_HEADER_LEN, SNAPSHOT_MAGIC, SNAPSHOT_VERSION};
+pub use spill::SpillFilter;
+pub use telemetry::{error_stats, kick_stats, ErrorStats, KickStats, MetricsSnapshot};
+#[cfg(not(feature = "no-delete"))]
+pub use tombstone::TombstoneFilter;
+pub use trace::{TraceEntry, TraceOp, TracingFilter};
+pub use tuning::{Concurrency, Tuning, Workload};
+pub use util::{KeyHasher, KeyHasherBuilder};
+pub use wide::{
+    AnyCuckooFilter, CuckooFilter12x2, CuckooFilter16x4, Fingerprint, Fingerprint12, WideCodec,
+    WideCuckooFilter,
+};
+pub use yielding::YIELD_EVERY;
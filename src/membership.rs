@@ -0,0 +1,244 @@
+use crate::{
+    CachedCuckooFilter, CuckooFilter, ExpiringFilter, PendingSet, ShardedCuckooFilter,
+    SharedCuckooFilter,
+};
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
+
+/// The read side of an approximate membership filter.
+///
+/// The trait is object safe, so read paths can take `&dyn MembershipFilter`
+/// and be tested against a `MockFilter`.
+pub trait MembershipFilter {
+    /// Returns `true` if `item` may be present, `false` if it is definitely absent.
+    fn contains(&self, item: &[u8]) -> bool;
+
+    /// Returns the number of stored items.
+    fn size(&self) -> usize;
+}
+
+impl MembershipFilter for CuckooFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        CuckooFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        CuckooFilter::size(self)
+    }
+}
+
+impl MembershipFilter for SharedCuckooFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        SharedCuckooFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        SharedCuckooFilter::size(self)
+    }
+}
+
+impl MembershipFilter for ShardedCuckooFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        ShardedCuckooFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        ShardedCuckooFilter::size(self)
+    }
+}
+
+impl MembershipFilter for PendingSet {
+    fn contains(&self, item: &[u8]) -> bool {
+        PendingSet::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        self.snapshot().size() + self.pending()
+    }
+}
+
+impl MembershipFilter for CachedCuckooFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        CachedCuckooFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        self.filter().size()
+    }
+}
+
+#[cfg(feature = "segmented")]
+impl MembershipFilter for crate::SegmentedCuckooFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        crate::SegmentedCuckooFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        crate::SegmentedCuckooFilter::size(self)
+    }
+}
+
+#[cfg(not(feature = "no-delete"))]
+impl MembershipFilter for crate::TombstoneFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        crate::TombstoneFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        crate::TombstoneFilter::size(self)
+    }
+}
+
+impl MembershipFilter for crate::ScalableCuckooFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        crate::ScalableCuckooFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        crate::ScalableCuckooFilter::size(self)
+    }
+}
+
+impl MembershipFilter for crate::SaturatingFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        crate::SaturatingFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        crate::SaturatingFilter::size(self)
+    }
+}
+
+impl MembershipFilter for crate::AnyCuckooFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        crate::AnyCuckooFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        crate::AnyCuckooFilter::size(self)
+    }
+}
+
+impl MembershipFilter for crate::BoundedFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        crate::BoundedFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        crate::BoundedFilter::size(self)
+    }
+}
+
+impl MembershipFilter for crate::TracingFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        crate::TracingFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        self.filter().size()
+    }
+}
+
+impl<B> MembershipFilter for ExpiringFilter<B>
+where
+    B: Fn() -> CuckooFilter,
+{
+    fn contains(&self, item: &[u8]) -> bool {
+        ExpiringFilter::contains(self, item)
+    }
+
+    fn size(&self) -> usize {
+        self.current().size()
+    }
+}
+
+/// A `MembershipFilter` with canned answers that records every lookup.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{MembershipFilter, MockFilter};
+///
+/// fn is_known(filter: &dyn MembershipFilter, user: &[u8]) -> bool {
+///     filter.contains(user)
+/// }
+///
+/// let mut mock = MockFilter::new(false);
+/// mock.answer(b"alice", true);
+/// assert!(is_known(&mock, b"alice"));
+/// assert!(!is_known(&mock, b"bob"));
+/// assert_eq!(mock.calls(), vec![b"alice".to_vec(), b"bob".to_vec()]);
+/// ```
+pub struct MockFilter {
+    default: bool,
+    answers: HashMap<Vec<u8>, bool>,
+    size: usize,
+    calls: Mutex<Vec<Vec<u8>>>,
+}
+
+impl MockFilter {
+    /// Creates a mock answering `default` for every item without a canned answer.
+    pub fn new(default: bool) -> Self {
+        MockFilter {
+            default,
+            answers: HashMap::new(),
+            size: 0,
+            calls: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Makes `contains(item)` return `present`.
+    pub fn answer(&mut self, item: &[u8], present: bool) -> &mut Self {
+        self.answers.insert(item.to_vec(), present);
+        self
+    }
+
+    /// Makes `size()` return `size`.
+    pub fn set_size(&mut self, size: usize) -> &mut Self {
+        self.size = size;
+        self
+    }
+
+    /// Returns every item passed to `contains`, in call order.
+    pub fn calls(&self) -> Vec<Vec<u8>> {
+        self.calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl MembershipFilter for MockFilter {
+    fn contains(&self, item: &[u8]) -> bool {
+        self.calls
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(item.to_vec());
+        self.answers.get(item).copied().unwrap_or(self.default)
+    }
+
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, MembershipFilter, MockFilter};
+
+    #[test]
+    fn test_trait_objects() {
+        let mut cf = CuckooFilter::new(100);
+        let _ = cf.add(b"test");
+        let mut mock = MockFilter::new(true);
+        mock.answer(b"test", false).set_size(7);
+        let filters: Vec<&dyn MembershipFilter> = vec![&cf, &mock];
+        let answers = filters
+            .iter()
+            .map(|f| f.contains(b"test"))
+            .collect::<Vec<_>>();
+        assert_eq!(answers, vec![true, false]);
+        assert_eq!(filters[1].size(), 7);
+        assert!(mock.contains(b"other"));
+        assert_eq!(mock.calls().len(), 2);
+    }
+}
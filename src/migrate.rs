@@ -0,0 +1,193 @@
+use crate::telemetry;
+use crate::{CResult, CuckooConfig, CuckooError, CuckooFilter, RebuildProgress};
+
+/// Moves a live filter to a new configuration without a maintenance window.
+///
+/// During the migration every write goes to both the old and the new filter
+/// and a lookup hits if either filter has the item, so answers stay correct
+/// while the new filter is backfilled from `keys`, a few keys per `step`.
+/// Once the backfill is complete, `cutover` retires the old filter and
+/// lookups use the new one alone.
+///
+/// The backfill may add a key that was already written during the window,
+/// storing it twice. A later delete then only removes one copy, so the key
+/// can keep testing positive, like a false positive would.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{CuckooConfig, CuckooFilter, MigratingFilter};
+///
+/// let mut old = CuckooFilter::new(100);
+/// old.add(b"a").unwrap();
+/// let keys = vec![b"a".to_vec()];
+/// let mut cf = MigratingFilter::new(old, &CuckooConfig::new(10000), keys);
+/// cf.add(b"b").unwrap();
+/// while !cf.is_backfilled() {
+///     cf.step(64).unwrap();
+/// }
+/// cf.cutover().unwrap();
+/// assert!(cf.contains(b"a") && cf.contains(b"b"));
+/// ```
+pub struct MigratingFilter<I> {
+    old: Option<CuckooFilter>,
+    new: CuckooFilter,
+    keys: I,
+    done: usize,
+    total: Option<usize>,
+    backfilled: bool,
+}
+
+impl<I> MigratingFilter<I>
+where
+    I: Iterator,
+    I::Item: AsRef<[u8]>,
+{
+    /// Starts migrating `old` to a filter built from `new_config`, backfilled
+    /// from `keys`, which must yield every key stored in `old`.
+    pub fn new<K>(old: CuckooFilter, new_config: &CuckooConfig, keys: K) -> Self
+    where
+        K: IntoIterator<IntoIter = I>,
+    {
+        let keys = keys.into_iter();
+        let total = match keys.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(upper),
+            _ => None,
+        };
+        MigratingFilter {
+            old: Some(old),
+            new: CuckooFilter::from_config(new_config),
+            keys,
+            done: 0,
+            total,
+            backfilled: false,
+        }
+    }
+
+    /// Adds `item` to the new filter and, until cutover, to the old one.
+    ///
+    /// Only a failure of the new filter is reported. The old one is on its
+    /// way out, and lookups consult both anyway.
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        self.new.add(item)?;
+        if let Some(old) = &mut self.old {
+            let _ = old.add(item);
+        }
+        Ok(())
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooFilter, MigratingFilter};
+    ///
+    /// let mut old = CuckooFilter::new(100);
+    /// old.add(b"a").unwrap();
+    /// let cf = MigratingFilter::new(old, &CuckooConfig::new(1000), vec![b"a"]);
+    /// assert!(cf.contains(b"a"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.new.contains(item) || self.old.as_ref().is_some_and(|old| old.contains(item))
+    }
+
+    /// Deletes `item` from both filters, returning `true` if either held it.
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        let from_new = self.new.delete(item);
+        let from_old = self.old.as_mut().is_some_and(|old| old.delete(item));
+        from_new || from_old
+    }
+
+    /// Backfills at most `max_keys` keys into the new filter.
+    ///
+    /// An error leaves the migration where it failed; lookups keep working
+    /// from both filters.
+    pub fn step(&mut self, max_keys: usize) -> Result<RebuildProgress, CuckooError> {
+        for _ in 0..max_keys {
+            match self.keys.next() {
+                Some(key) => {
+                    self.new.add(key.as_ref())?;
+                    self.done += 1;
+                }
+                None => {
+                    self.backfilled = true;
+                    break;
+                }
+            }
+        }
+        Ok(self.progress())
+    }
+
+    /// Returns how many keys have been backfilled.
+    pub fn progress(&self) -> RebuildProgress {
+        RebuildProgress {
+            done: self.done,
+            total: self.total,
+        }
+    }
+
+    /// Returns `true` once `keys` is exhausted.
+    pub fn is_backfilled(&self) -> bool {
+        self.backfilled
+    }
+
+    /// Returns `true` until `cutover` has retired the old filter.
+    pub fn is_migrating(&self) -> bool {
+        self.old.is_some()
+    }
+
+    /// Retires the old filter, so lookups only consult the new one.
+    ///
+    /// Returns `CuckooError::NotSupported`, leaving both filters in place, if
+    /// the backfill is not complete.
+    pub fn cutover(&mut self) -> Result<Option<CuckooFilter>, CuckooError> {
+        if !self.backfilled {
+            return Err(telemetry::record(CuckooError::NotSupported));
+        }
+        Ok(self.old.take())
+    }
+
+    /// Returns the new filter.
+    pub fn new_filter(&self) -> &CuckooFilter {
+        &self.new
+    }
+
+    /// Returns the old filter, until cutover.
+    pub fn old_filter(&self) -> Option<&CuckooFilter> {
+        self.old.as_ref()
+    }
+
+    /// Unwraps the new filter, dropping the old one.
+    pub fn into_inner(self) -> CuckooFilter {
+        self.new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooConfig, CuckooError, CuckooFilter, MigratingFilter};
+
+    #[test]
+    fn test_migration_keeps_answers() {
+        let keys = (0..500u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let mut old = CuckooFilter::new(600);
+        for k in &keys {
+            old.add(k).unwrap();
+        }
+        let mut cf = MigratingFilter::new(old, &CuckooConfig::new(5000), keys.iter());
+        assert!(matches!(cf.cutover(), Err(CuckooError::NotSupported)));
+
+        let mut written = 1000u16;
+        while !cf.is_backfilled() {
+            let progress = cf.step(64).unwrap();
+            assert_eq!(progress.total, Some(500));
+            cf.add(&written.to_le_bytes()).unwrap();
+            written += 1;
+            assert!(keys.iter().all(|k| cf.contains(k)));
+        }
+        assert_eq!(cf.progress().done, 500);
+        assert!(cf.cutover().unwrap().is_some());
+        assert!(!cf.is_migrating());
+        assert!(keys.iter().all(|k| cf.contains(k)));
+        assert!((1000..written).all(|i| cf.contains(&i.to_le_bytes())));
+        assert!(cf.into_inner().capacity() > 5000);
+    }
+}
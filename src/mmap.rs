@@ -0,0 +1,424 @@
+use crate::bucket::BUCKET_SIZE;
+use crate::format::{self, FormatError};
+use crate::paged::touch_page;
+use crate::util::get_indices_and_fingerprint;
+use memmap2::Mmap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// A read-only filter answering queries straight from a memory-mapped file
+/// written by `CuckooFilter::to_bytes_raw`. Sparse-encoded output of
+/// `to_bytes` or `write_to` cannot be mapped and is rejected.
+///
+/// Opening one reads the header and checks the file length, but never copies
+/// the bucket array, so the page cache holds the only copy and every process
+/// mapping the same file shares it. The checksum is not verified on open, as
+/// that would touch every page; call `verify` for that.
+///
+/// The file must not be modified or truncated while it is mapped. Publish a
+/// new version by writing a fresh file and renaming it over the old one;
+/// existing mappings keep the old data.
+pub struct MmapCuckooFilter {
+    map: Mmap,
+    pow: usize,
+    num_buckets: usize,
+    size: usize,
+    // Length of a checksummed segment in bytes; 0 until `check_segments`.
+    segment_len: usize,
+    checksums: Vec<u32>,
+    // Segments whose data failed their checksum.
+    quarantined: BTreeSet<usize>,
+    // Copies of segments healed by `repair_from`, read instead of the map.
+    repaired: HashMap<usize, Box<[u8]>>,
+}
+
+/// The answer of `MmapCuckooFilter::lookup`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lookup {
+    /// The item is definitely absent.
+    Absent,
+    /// The item may be present, with the filter's false positive rate.
+    Present,
+    /// A bucket the item maps to is quarantined, so nothing is known.
+    Maybe,
+}
+
+impl MmapCuckooFilter {
+    /// Maps the filter file at `path`.
+    ///
+    /// Layout errors are reported as `io::ErrorKind::InvalidData`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, MmapCuckooFilter};
+    ///
+    /// let mut cf = CuckooFilter::new(100);
+    /// cf.add(b"test").unwrap();
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_mmap_doc_{}", std::process::id()));
+    /// std::fs::write(&path, cf.to_bytes_raw()).unwrap();
+    /// let mapped = MmapCuckooFilter::open(&path).unwrap();
+    /// assert!(mapped.contains(b"test"));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_file(&File::open(path)?)
+    }
+
+    /// Maps an already opened filter file.
+    #[allow(unsafe_code)]
+    pub fn from_file(file: &File) -> io::Result<Self> {
+        // SAFETY: the mapping is only read through `&[u8]`, which is sound
+        // as long as nobody modifies the file while it is mapped, a
+        // requirement documented on the type.
+        let map = unsafe { Mmap::map(file)? };
+        let (pow, num_buckets, size) =
+            Self::layout(&map).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(MmapCuckooFilter {
+            map,
+            pow,
+            num_buckets,
+            size,
+            segment_len: 0,
+            checksums: Vec::new(),
+            quarantined: BTreeSet::new(),
+            repaired: HashMap::new(),
+        })
+    }
+
+    /// Maps a file written by `CuckooFilter::to_bytes_segmented` and checks
+    /// every segment against its checksum, reading the whole file.
+    ///
+    /// Damaged segments are quarantined instead of failing the open: lookups
+    /// touching them answer `Lookup::Maybe`, and `contains` answers `true`,
+    /// until `repair_from` heals them. Files without segment checksums are
+    /// reported as `io::ErrorKind::InvalidData`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, MmapCuckooFilter};
+    ///
+    /// let mut cf = CuckooFilter::new(10_000);
+    /// cf.add(b"test").unwrap();
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_mmap_quarantine_{}", std::process::id()));
+    /// std::fs::write(&path, cf.to_bytes_segmented(256)).unwrap();
+    /// let mapped = MmapCuckooFilter::open_quarantining(&path).unwrap();
+    /// assert!(mapped.quarantined().is_empty());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn open_quarantining<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut filter = Self::open(path)?;
+        filter
+            .check_segments()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(filter)
+    }
+
+    fn check_segments(&mut self) -> Result<(), FormatError> {
+        let data = self.buckets();
+        let sections = self
+            .map
+            .get(format::BUCKETS_OFFSET + data.len()..)
+            .unwrap_or_default();
+        let flags = self.map.get(format::FLAGS_OFFSET).copied().unwrap_or(0);
+        let (segment_buckets, checksums) = format::sections(flags, sections)?
+            .into_iter()
+            .find(|&(flag, _)| flag == format::FLAG_SEGMENT_CHECKSUMS)
+            .ok_or(FormatError::Invalid {
+                field: "segment_checksums",
+                value: 0,
+            })
+            .and_then(|(_, section)| format::parse_segment_checksums(section))?;
+        let segment_len = (segment_buckets as usize).saturating_mul(BUCKET_SIZE);
+        let segments = data.len().div_ceil(segment_len);
+        if checksums.len() != segments {
+            return Err(FormatError::Mismatch {
+                field: "segment_checksums",
+                expected: segments as u64,
+                found: checksums.len() as u64,
+            });
+        }
+        let quarantined = data
+            .chunks(segment_len)
+            .zip(&checksums)
+            .enumerate()
+            .filter(|(_, (segment, &crc))| format::checksum(segment) != crc)
+            .map(|(i, _)| i)
+            .collect();
+        self.segment_len = segment_len;
+        self.checksums = checksums;
+        self.quarantined = quarantined;
+        Ok(())
+    }
+
+    fn layout(bytes: &[u8]) -> Result<(usize, usize, usize), FormatError> {
+        let header = format::Header::parse(bytes)?;
+        if header.flags & format::FLAG_SPARSE != 0 {
+            return Err(FormatError::UnsupportedCapabilities(format::FLAG_SPARSE));
+        }
+        let (_, sections) = usize::try_from(header.num_buckets)
+            .ok()
+            .and_then(|n| n.checked_mul(BUCKET_SIZE))
+            .and_then(|len| bytes.get(format::BUCKETS_OFFSET..)?.split_at_checked(len))
+            .ok_or(FormatError::Truncated { field: "buckets" })?;
+        format::sections(header.flags, sections)?;
+        Ok((
+            usize::from(header.pow),
+            header.num_buckets as usize,
+            header.size as usize,
+        ))
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, MmapCuckooFilter};
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_mmap_contains_{}", std::process::id()));
+    /// std::fs::write(&path, CuckooFilter::new(100).to_bytes_raw()).unwrap();
+    /// let mapped = MmapCuckooFilter::open(&path).unwrap();
+    /// assert!(!mapped.contains(b"test"));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.lookup(item) != Lookup::Absent
+    }
+
+    /// Like `contains`, telling apart items that may be present from those
+    /// whose buckets are quarantined.
+    pub fn lookup(&self, item: &[u8]) -> Lookup {
+        let finger = get_indices_and_fingerprint(item, self.pow);
+        let mut answer = Lookup::Absent;
+        for i in [finger.i1, finger.i2] {
+            match self.bucket(i as usize) {
+                Some(slots) if slots.contains(&finger.fp) => return Lookup::Present,
+                Some(_) => {}
+                None => answer = Lookup::Maybe,
+            }
+        }
+        answer
+    }
+
+    /// Returns the quarantined segments in ascending order.
+    pub fn quarantined(&self) -> Vec<usize> {
+        self.quarantined.iter().copied().collect()
+    }
+
+    /// Heals quarantined segments with the buckets `other`, a replica of the
+    /// same filter, holds in their place, and returns how many were healed.
+    ///
+    /// A segment is only taken over if it matches the checksum this file
+    /// stored for it, so a diverged or equally damaged replica leaves it
+    /// quarantined. Healed segments are kept in memory; the file itself is
+    /// not modified.
+    pub fn repair_from(&mut self, other: &MmapCuckooFilter) -> Result<usize, FormatError> {
+        if other.num_buckets != self.num_buckets {
+            return Err(FormatError::Mismatch {
+                field: "num_buckets",
+                expected: self.num_buckets as u64,
+                found: other.num_buckets as u64,
+            });
+        }
+        let pending: Vec<usize> = self.quarantined.iter().copied().collect();
+        let mut healed = 0;
+        for segment in pending {
+            let start = segment * self.segment_len / BUCKET_SIZE;
+            let end = (start + self.segment_len / BUCKET_SIZE).min(self.num_buckets);
+            let mut data = Vec::with_capacity((end - start) * BUCKET_SIZE);
+            for i in start..end {
+                match other.bucket(i) {
+                    Some(slots) => data.extend_from_slice(slots),
+                    None => break,
+                }
+            }
+            if data.len() == (end - start) * BUCKET_SIZE
+                && self.checksums.get(segment) == Some(&format::checksum(&data))
+            {
+                self.quarantined.remove(&segment);
+                self.repaired.insert(segment, data.into_boxed_slice());
+                healed += 1;
+            }
+        }
+        Ok(healed)
+    }
+
+    /// Reads both candidate buckets of every key in `keys_by_frequency`,
+    /// hottest first, so after a deploy the pages serving most lookups are
+    /// resident before the long tail is paged in.
+    ///
+    /// Returns the number of distinct pages read.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, MmapCuckooFilter};
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_mmap_warm_{}", std::process::id()));
+    /// std::fs::write(&path, CuckooFilter::new(100).to_bytes_raw()).unwrap();
+    /// let mapped = MmapCuckooFilter::open(&path).unwrap();
+    /// assert_eq!(mapped.prioritized_warmup(["hot", "warm", "cold"]), 1);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn prioritized_warmup<I>(&self, keys_by_frequency: I) -> usize
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut touched = HashSet::new();
+        let mut pages = 0;
+        for key in keys_by_frequency {
+            let finger = get_indices_and_fingerprint(key.as_ref(), self.pow);
+            for i in [finger.i1, finger.i2] {
+                let offset = format::BUCKETS_OFFSET + i as usize * BUCKET_SIZE;
+                if touch_page(&self.map, offset, &mut touched) {
+                    pages += 1;
+                }
+            }
+        }
+        pages
+    }
+
+    /// Returns the number of stored items, as recorded in the header.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Returns the total number of fingerprint slots.
+    pub fn capacity(&self) -> usize {
+        self.num_buckets * BUCKET_SIZE
+    }
+
+    /// Checks the bucket data against the stored checksum and the recorded
+    /// size, reading every page of the file.
+    ///
+    /// Files written before checksums were added have no checksum, so only
+    /// the size is checked for them.
+    pub fn verify(&self) -> Result<(), FormatError> {
+        let data = self.buckets();
+        let sections = self
+            .map
+            .get(format::BUCKETS_OFFSET + data.len()..)
+            .unwrap_or_default();
+        let flags = self.map.get(format::FLAGS_OFFSET).copied().unwrap_or(0);
+        for (flag, section) in format::sections(flags, sections)? {
+            if flag == format::FLAG_CHECKSUM {
+                format::verify_checksum(section, format::checksum(data))?;
+            }
+        }
+        let occupied = data.iter().filter(|&&fp| fp != 0).count();
+        if occupied != self.size {
+            return Err(FormatError::Mismatch {
+                field: "size",
+                expected: self.size as u64,
+                found: occupied as u64,
+            });
+        }
+        Ok(())
+    }
+
+    fn buckets(&self) -> &[u8] {
+        let end = format::BUCKETS_OFFSET + self.num_buckets * BUCKET_SIZE;
+        self.map
+            .get(format::BUCKETS_OFFSET..end)
+            .unwrap_or_default()
+    }
+
+    /// Returns the slots of bucket `i`, or `None` if it is quarantined.
+    fn bucket(&self, i: usize) -> Option<&[u8]> {
+        let start = i * BUCKET_SIZE;
+        if self.segment_len == 0 {
+            return self.buckets().get(start..start + BUCKET_SIZE);
+        }
+        let (segment, offset) = (start / self.segment_len, start % self.segment_len);
+        let data = match self.repaired.get(&segment) {
+            Some(data) => data,
+            None if self.quarantined.contains(&segment) => return None,
+            None => self
+                .buckets()
+                .get(segment * self.segment_len..)
+                .unwrap_or_default(),
+        };
+        data.get(offset..offset + BUCKET_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{format, CuckooFilter, Lookup, MmapCuckooFilter};
+    use std::{env, fs, io, process};
+
+    #[test]
+    fn test_mmap_matches_filter() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(256, 5);
+        for i in 0..800u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let path = env::temp_dir().join(format!("dakv_cuckoo_mmap_{}", process::id()));
+        fs::write(&path, cf.to_bytes_raw()).unwrap();
+        let mapped = MmapCuckooFilter::open(&path).unwrap();
+        assert_eq!(mapped.size(), 800);
+        assert_eq!(mapped.capacity(), cf.capacity());
+        assert!(mapped.verify().is_ok());
+        for i in 0..2000u16 {
+            assert_eq!(
+                mapped.contains(&i.to_le_bytes()),
+                cf.contains(&i.to_le_bytes())
+            );
+        }
+        drop(mapped);
+
+        let mut bytes = cf.to_bytes_raw();
+        bytes.truncate(bytes.len() - 1);
+        fs::write(&path, &bytes).unwrap();
+        let err = MmapCuckooFilter::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        fs::write(&path, CuckooFilter::new(1000).to_bytes()).unwrap();
+        let err = MmapCuckooFilter::open(&path).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_quarantine_and_repair() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(4096, 9);
+        for i in 0..3000u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let dir = env::temp_dir();
+        let good = dir.join(format!("dakv_cuckoo_mmap_replica_{}", process::id()));
+        let bad = dir.join(format!("dakv_cuckoo_mmap_damaged_{}", process::id()));
+        let bytes = cf.to_bytes_segmented(64);
+        fs::write(&good, &bytes).unwrap();
+        let mut damaged = bytes.clone();
+        damaged[format::BUCKETS_OFFSET + 64 * 4 * 3 + 5] ^= 0xff;
+        fs::write(&bad, &damaged).unwrap();
+
+        assert!(MmapCuckooFilter::open(&bad).unwrap().verify().is_err());
+        let mut mapped = MmapCuckooFilter::open_quarantining(&bad).unwrap();
+        assert_eq!(mapped.quarantined(), vec![3]);
+        let lookups: Vec<_> = (0..3000u16)
+            .map(|i| mapped.lookup(&i.to_le_bytes()))
+            .collect();
+        assert!(lookups.contains(&Lookup::Maybe));
+        assert!(!lookups.contains(&Lookup::Absent));
+        assert!((0..3000u16).all(|i| mapped.contains(&i.to_le_bytes())));
+
+        let replica = MmapCuckooFilter::open_quarantining(&good).unwrap();
+        assert_eq!(mapped.repair_from(&replica), Ok(1));
+        assert!(mapped.quarantined().is_empty());
+        for i in 0..6000u16 {
+            let expected = if cf.contains(&i.to_le_bytes()) {
+                Lookup::Present
+            } else {
+                Lookup::Absent
+            };
+            assert_eq!(mapped.lookup(&i.to_le_bytes()), expected);
+        }
+
+        fs::write(&good, cf.to_bytes_raw()).unwrap();
+        let err = MmapCuckooFilter::open_quarantining(&good).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&good).unwrap();
+        fs::remove_file(&bad).unwrap();
+    }
+}
@@ -0,0 +1,226 @@
+use crate::{CResult, CuckooError, CuckooFilter};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Hit and miss counters of a `CachedCuckooFilter`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered by the cache without probing the filter.
+    pub hits: u64,
+    /// Lookups that had to probe the filter.
+    pub misses: u64,
+}
+
+/// A filter paired with a bounded, exact LRU cache of keys known to be absent.
+///
+/// Repeated lookups of the same missing key are answered from the cache. This
+/// costs a copy of every cached key, so it only pays off for request storms
+/// concentrated on few keys.
+pub struct CachedCuckooFilter {
+    filter: CuckooFilter,
+    cache: Mutex<NegativeCache>,
+}
+
+struct NegativeCache {
+    capacity: usize,
+    tick: u64,
+    // Last access tick per key; `order` may hold stale ticks for a key.
+    entries: HashMap<Vec<u8>, u64>,
+    order: VecDeque<(u64, Vec<u8>)>,
+    stats: CacheStats,
+}
+
+impl NegativeCache {
+    fn touch(&mut self, item: &[u8]) {
+        self.tick += 1;
+        self.entries.insert(item.to_vec(), self.tick);
+        self.order.push_back((self.tick, item.to_vec()));
+        while self.entries.len() > self.capacity {
+            match self.order.pop_front() {
+                Some((tick, key)) if self.entries.get(&key) == Some(&tick) => {
+                    self.entries.remove(&key);
+                }
+                Some(_) => {}
+                None => break,
+            }
+        }
+        if self.order.len() > 4 * self.capacity.max(1) {
+            let entries = &self.entries;
+            self.order
+                .retain(|(tick, key)| entries.get(key) == Some(tick));
+        }
+    }
+}
+
+impl CachedCuckooFilter {
+    /// Wraps `filter` with a cache holding at most `capacity` absent keys.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CachedCuckooFilter, CuckooFilter};
+    /// let cf = CachedCuckooFilter::new(CuckooFilter::new(100), 1000);
+    /// ```
+    pub fn new(filter: CuckooFilter, capacity: usize) -> Self {
+        CachedCuckooFilter {
+            filter,
+            cache: Mutex::new(NegativeCache {
+                capacity,
+                tick: 0,
+                entries: HashMap::new(),
+                order: VecDeque::new(),
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CachedCuckooFilter, CuckooFilter};
+    /// let mut cf = CachedCuckooFilter::new(CuckooFilter::new(100), 1000);
+    /// cf.add(b"test");
+    /// ```
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        self.lock().entries.remove(item);
+        self.filter.add(item)
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CachedCuckooFilter, CuckooFilter};
+    /// let cf = CachedCuckooFilter::new(CuckooFilter::new(100), 1000);
+    /// assert!(!cf.contains(b"test"));
+    /// assert!(!cf.contains(b"test"));
+    /// assert_eq!(cf.cache_stats().hits, 1);
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let mut cache = self.lock();
+        if cache.entries.contains_key(item) {
+            cache.stats.hits += 1;
+            cache.touch(item);
+            return false;
+        }
+        cache.stats.misses += 1;
+        let found = self.filter.contains(item);
+        if !found && cache.capacity > 0 {
+            cache.touch(item);
+        }
+        found
+    }
+
+    /// Deleting never makes a key present, so cached answers stay valid.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CachedCuckooFilter, CuckooFilter};
+    /// let mut cf = CachedCuckooFilter::new(CuckooFilter::new(100), 1000);
+    /// cf.add(b"test");
+    /// assert!(cf.delete(b"test"));
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        self.filter.delete(item)
+    }
+
+    /// Seeds the cache from `keys_by_frequency`, hottest first, such as the
+    /// top of an access log, so a fresh deploy answers its most requested
+    /// absent keys without probing the filter.
+    ///
+    /// Keys the filter reports as present are skipped. Up to `capacity`
+    /// absent keys are cached, ranked so the hottest are evicted last; the
+    /// rest of the input is not read. Returns the number of keys cached.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CachedCuckooFilter, CuckooFilter};
+    /// let mut cf = CachedCuckooFilter::new(CuckooFilter::new(100), 2);
+    /// cf.add(b"present").unwrap();
+    /// assert_eq!(cf.prioritized_warmup(["hot", "present", "warm", "cold"]), 2);
+    /// assert!(!cf.contains(b"hot"));
+    /// assert_eq!(cf.cache_stats().hits, 1);
+    /// ```
+    pub fn prioritized_warmup<I>(&self, keys_by_frequency: I) -> usize
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let capacity = self.lock().capacity;
+        let absent = keys_by_frequency
+            .into_iter()
+            .filter(|key| !self.filter.contains(key.as_ref()))
+            .take(capacity)
+            .map(|key| key.as_ref().to_vec())
+            .collect::<Vec<_>>();
+        let mut cache = self.lock();
+        // Coldest first, so the hottest keys are the most recently used.
+        for key in absent.iter().rev() {
+            cache.touch(key);
+        }
+        absent.len()
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CachedCuckooFilter, CuckooFilter};
+    /// let cf = CachedCuckooFilter::new(CuckooFilter::new(100), 1000);
+    /// println!("hits: {}", cf.cache_stats().hits);
+    /// ```
+    pub fn cache_stats(&self) -> CacheStats {
+        self.lock().stats
+    }
+
+    /// Returns the wrapped filter.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CachedCuckooFilter, CuckooFilter};
+    /// let cf = CachedCuckooFilter::new(CuckooFilter::new(100), 1000);
+    /// assert_eq!(cf.filter().size(), 0);
+    /// ```
+    pub fn filter(&self) -> &CuckooFilter {
+        &self.filter
+    }
+
+    fn lock(&self) -> MutexGuard<'_, NegativeCache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CacheStats, CachedCuckooFilter, CuckooFilter};
+
+    #[test]
+    fn test_add_invalidates() {
+        let mut cf = CachedCuckooFilter::new(CuckooFilter::new(100), 10);
+        assert!(!cf.contains(b"test"));
+        assert!(cf.add(b"test").is_ok());
+        assert!(cf.contains(b"test"));
+        assert_eq!(cf.cache_stats(), CacheStats { hits: 0, misses: 2 });
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let cf = CachedCuckooFilter::new(CuckooFilter::new(100), 2);
+        assert!(!cf.contains(b"a"));
+        assert!(!cf.contains(b"b"));
+        assert!(!cf.contains(b"a"));
+        assert!(!cf.contains(b"c"));
+        // "b" was the least recently used key.
+        assert!(!cf.contains(b"a"));
+        assert!(!cf.contains(b"b"));
+        assert_eq!(cf.cache_stats(), CacheStats { hits: 2, misses: 4 });
+    }
+
+    #[test]
+    fn test_prioritized_warmup() {
+        let cf = CachedCuckooFilter::new(CuckooFilter::new(100), 2);
+        assert!(!cf.contains(b"stale"));
+        assert_eq!(cf.prioritized_warmup(["a", "b", "c"]), 2);
+        // The warmed keys displaced the older entry, and the coldest of them
+        // goes first.
+        assert!(!cf.contains(b"d"));
+        assert!(!cf.contains(b"a"));
+        assert!(!cf.contains(b"b"));
+        assert_eq!(cf.cache_stats(), CacheStats { hits: 1, misses: 3 });
+    }
+}
@@ -0,0 +1,319 @@
+// Header fields sit at constant offsets of the first 32 bytes, which `new`
+// checks are present; `locate` maps keys below the page count, pages are
+// read with `get`, and index entries are addressed by the top 8 bits of a
+// 24-bit fingerprint.
+#![allow(clippy::indexing_slicing)]
+
+use crate::format::{FormatError, Magic};
+use crate::util::hash_key;
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::{hint, io};
+
+/// Size of a page, and so of every read a `PagedFilter` lookup makes.
+pub const PAGE_SIZE: usize = 4096;
+
+// Each page starts with one cumulative entry count per value of the top
+// fingerprint byte, followed by the low two bytes of every fingerprint.
+const INDEX_ENTRIES: usize = 256;
+const INDEX_LEN: usize = INDEX_ENTRIES * 2;
+
+/// Number of fingerprints a page holds.
+pub const PAGE_SLOTS: usize = (PAGE_SIZE - INDEX_LEN) / 2;
+
+const MAGIC: [u8; 4] = *b"DKPF";
+const VERSION: u8 = 1;
+
+// Fill pages are sized for, leaving room for keys hashing unevenly
+const TARGET_FILL: f64 = 0.85;
+
+/// Maps a key hash to its page and a 24-bit fingerprint, taken from
+/// independent bits.
+fn locate(hash: u64, num_pages: u64) -> (u64, u32) {
+    let page = (u128::from(hash >> 32) * u128::from(num_pages)) >> 32;
+    (page as u64, hash as u32 & 0xff_ffff)
+}
+
+/// Reads the byte at `offset` of `bytes` unless its page is in `touched`,
+/// faulting the page in if `bytes` is mapped. Returns `true` if it was read.
+pub(crate) fn touch_page(bytes: &[u8], offset: usize, touched: &mut HashSet<usize>) -> bool {
+    if !touched.insert(offset / PAGE_SIZE) {
+        return false;
+    }
+    hint::black_box(bytes.get(offset).copied());
+    true
+}
+
+/// Collects keys for a `PagedFilter`, which is built once and then only
+/// queried.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{PagedFilter, PagedFilterBuilder};
+///
+/// let mut builder = PagedFilterBuilder::new();
+/// builder.add(b"test");
+/// let filter = PagedFilter::new(builder.build()).unwrap();
+/// assert!(filter.contains(b"test"));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct PagedFilterBuilder {
+    hashes: Vec<u64>,
+}
+
+impl PagedFilterBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, item: &[u8]) {
+        self.hashes.push(hash_key(item));
+    }
+
+    /// Serializes the filter, using as few pages as fit every key.
+    pub fn build(&self) -> Vec<u8> {
+        let target = PAGE_SLOTS as f64 * TARGET_FILL;
+        let mut num_pages = ((self.hashes.len() as f64 / target).ceil() as u64).max(1);
+        loop {
+            if let Some(pages) = self.fill(num_pages) {
+                return encode(&pages, self.hashes.len() as u64);
+            }
+            num_pages += num_pages / 8 + 1;
+        }
+    }
+
+    /// Writes `build` output to `w`.
+    pub fn write_to<W: io::Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(&self.build())
+    }
+
+    /// Sorts the fingerprints into `num_pages` pages, or returns `None` if
+    /// one of them overflows.
+    fn fill(&self, num_pages: u64) -> Option<Vec<Vec<u32>>> {
+        let mut pages = vec![Vec::new(); usize::try_from(num_pages).ok()?];
+        for &hash in &self.hashes {
+            let (page, fp) = locate(hash, num_pages);
+            pages[page as usize].push(fp);
+        }
+        for page in &mut pages {
+            page.sort_unstable();
+            page.dedup();
+            if page.len() > PAGE_SLOTS {
+                return None;
+            }
+        }
+        Some(pages)
+    }
+}
+
+fn encode(pages: &[Vec<u32>], size: u64) -> Vec<u8> {
+    let mut out = vec![0; PAGE_SIZE * (pages.len() + 1)];
+    out[..4].copy_from_slice(&MAGIC);
+    out[4] = VERSION;
+    out[8..12].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+    out[16..24].copy_from_slice(&(pages.len() as u64).to_le_bytes());
+    out[24..32].copy_from_slice(&size.to_le_bytes());
+    for (fps, page) in pages
+        .iter()
+        .zip(out[PAGE_SIZE..].chunks_exact_mut(PAGE_SIZE))
+    {
+        let (index, slots) = page.split_at_mut(INDEX_LEN);
+        let mut counts = [0u16; INDEX_ENTRIES];
+        for (&fp, slot) in fps.iter().zip(slots.chunks_exact_mut(2)) {
+            counts[(fp >> 16) as usize] += 1;
+            slot.copy_from_slice(&(fp as u16).to_le_bytes());
+        }
+        let mut end = 0;
+        for (count, entry) in counts.iter().zip(index.chunks_exact_mut(2)) {
+            end += count;
+            entry.copy_from_slice(&end.to_le_bytes());
+        }
+    }
+    out
+}
+
+/// A read-only filter laid out in page-sized buckets, for filters served
+/// from disk rather than RAM.
+///
+/// Every key maps to a single page of `PAGE_SIZE` bytes holding up to
+/// `PAGE_SLOTS` fingerprints, with a small index at its start, so a lookup
+/// reads exactly one page, where the standard layout may need two cache
+/// lines in different places. Fingerprints are 24 bits, for a false positive
+/// rate around 1e-4 at the fill `PagedFilterBuilder` aims for.
+///
+/// The bytes are typically a memory-mapped file, see `open`. Damaged pages
+/// are never read out of bounds, but may answer wrongly.
+#[derive(Debug, Clone)]
+pub struct PagedFilter<T> {
+    bytes: T,
+    num_pages: u64,
+    size: u64,
+}
+
+impl<T: AsRef<[u8]>> PagedFilter<T> {
+    /// Validates the header and the length of `bytes`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::format::FormatError;
+    /// use dakv_cuckoo::PagedFilter;
+    ///
+    /// assert!(matches!(PagedFilter::new(b"nope"), Err(FormatError::Truncated { .. })));
+    /// ```
+    pub fn new(bytes: T) -> Result<Self, FormatError> {
+        let data = bytes.as_ref();
+        let header = data
+            .get(..32)
+            .ok_or(FormatError::Truncated { field: "header" })?;
+        let read = |at: usize, len: usize| {
+            header[at..at + len]
+                .iter()
+                .rev()
+                .fold(0, |v, &b| v << 8 | u64::from(b))
+        };
+        if header[..4] != MAGIC {
+            let mut magic = [0; 4];
+            magic.copy_from_slice(&header[..4]);
+            return Err(FormatError::BadMagic(Magic(magic)));
+        }
+        if header[4] != VERSION {
+            return Err(FormatError::UnsupportedVersion(header[4]));
+        }
+        let page_size = read(8, 4);
+        if page_size != PAGE_SIZE as u64 {
+            return Err(FormatError::Mismatch {
+                field: "page_size",
+                expected: PAGE_SIZE as u64,
+                found: page_size,
+            });
+        }
+        let num_pages = read(16, 8);
+        if num_pages == 0 {
+            return Err(FormatError::Invalid {
+                field: "num_pages",
+                value: 0,
+            });
+        }
+        if num_pages
+            .checked_add(1)
+            .and_then(|n| n.checked_mul(PAGE_SIZE as u64))
+            .is_none_or(|len| len > data.len() as u64)
+        {
+            return Err(FormatError::Truncated { field: "pages" });
+        }
+        let size = read(24, 8);
+        Ok(PagedFilter {
+            bytes,
+            num_pages,
+            size,
+        })
+    }
+
+    /// Looks `item` up, reading only its page.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let (page, fp) = locate(hash_key(item), self.num_pages);
+        let start = (page as usize + 1) * PAGE_SIZE;
+        let page = match self.bytes.as_ref().get(start..start + PAGE_SIZE) {
+            Some(page) => page,
+            None => return false,
+        };
+        let read_u16 = |at: usize| {
+            page.get(at..at + 2)
+                .map_or(0, |b| usize::from(u16::from_le_bytes([b[0], b[1]])))
+        };
+        let hi = (fp >> 16) as usize;
+        let begin = if hi == 0 { 0 } else { read_u16(2 * (hi - 1)) };
+        let end = read_u16(2 * hi);
+        let low = fp as u16;
+        page.get(INDEX_LEN + 2 * begin..INDEX_LEN + 2 * end)
+            .unwrap_or_default()
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+            .take_while(|&stored| stored <= low)
+            .any(|stored| stored == low)
+    }
+
+    /// Reads the page of every key in `keys_by_frequency`, hottest first, so
+    /// after a deploy the pages serving most lookups are resident before the
+    /// long tail is paged in.
+    ///
+    /// Returns the number of distinct pages read.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{PagedFilter, PagedFilterBuilder};
+    ///
+    /// let filter = PagedFilter::new(PagedFilterBuilder::new().build()).unwrap();
+    /// assert_eq!(filter.prioritized_warmup(["hot", "warm", "cold"]), 1);
+    /// ```
+    pub fn prioritized_warmup<I>(&self, keys_by_frequency: I) -> usize
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut touched = HashSet::new();
+        keys_by_frequency
+            .into_iter()
+            .filter(|key| {
+                let (page, _) = locate(hash_key(key.as_ref()), self.num_pages);
+                let offset = (page as usize + 1) * PAGE_SIZE;
+                touch_page(self.bytes.as_ref(), offset, &mut touched)
+            })
+            .count()
+    }
+
+    /// Returns the number of keys the filter was built from.
+    pub fn size(&self) -> usize {
+        self.size as usize
+    }
+
+    pub fn num_pages(&self) -> usize {
+        self.num_pages as usize
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl PagedFilter<memmap2::Mmap> {
+    /// Maps the filter file at `path`, so lookups read pages on demand and
+    /// every process mapping the file shares them.
+    ///
+    /// The file must not be modified while it is mapped. Layout errors are
+    /// reported as `io::ErrorKind::InvalidData`.
+    #[allow(unsafe_code)]
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: the mapping is only read through `&[u8]`, which is sound
+        // as long as nobody modifies the file while it is mapped, a
+        // requirement documented above.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::new(map).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::paged::{PAGE_SIZE, PAGE_SLOTS};
+    use crate::{PagedFilter, PagedFilterBuilder};
+
+    #[test]
+    fn test_paged_filter() {
+        let mut builder = PagedFilterBuilder::new();
+        for i in 0..20_000u32 {
+            builder.add(&i.to_le_bytes());
+        }
+        let bytes = builder.build();
+        assert_eq!(bytes.len() % PAGE_SIZE, 0);
+        let filter = PagedFilter::new(&bytes[..]).unwrap();
+        assert_eq!(filter.size(), 20_000);
+        assert!(filter.num_pages() > 20_000 / PAGE_SLOTS);
+        assert!((0..20_000u32).all(|i| filter.contains(&i.to_le_bytes())));
+        let false_positives = (20_000..220_000u32)
+            .filter(|i| filter.contains(&i.to_le_bytes()))
+            .count();
+        assert!(false_positives < 100, "{}", false_positives);
+
+        assert!(PagedFilter::new(&bytes[..bytes.len() - 1]).is_err());
+        let empty = PagedFilterBuilder::new().build();
+        assert!(!PagedFilter::new(empty).unwrap().contains(b"test"));
+    }
+}
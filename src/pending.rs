@@ -0,0 +1,133 @@
+use crate::CuckooFilter;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// Read-your-writes view over a periodically replaced, read-only filter.
+///
+/// Keys added locally are remembered exactly until a refreshed snapshot
+/// reports them, so they stay visible while replication catches up.
+pub struct PendingSet {
+    frozen: Arc<CuckooFilter>,
+    pending: HashSet<Vec<u8>>,
+}
+
+impl PendingSet {
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, PendingSet};
+    /// use std::sync::Arc;
+    /// let set = PendingSet::new(Arc::new(CuckooFilter::new(100)));
+    /// ```
+    pub fn new(frozen: Arc<CuckooFilter>) -> Self {
+        PendingSet {
+            frozen,
+            pending: HashSet::new(),
+        }
+    }
+
+    /// Records a key written locally but not yet part of the snapshot.
+    ///
+    /// The key is recorded even if the snapshot already reports it, as that
+    /// may be a false positive; `refresh` forgets it once a newer snapshot
+    /// reports it.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, PendingSet};
+    /// use std::sync::Arc;
+    /// let mut set = PendingSet::new(Arc::new(CuckooFilter::new(100)));
+    /// set.add(b"test");
+    /// assert!(set.contains(b"test"));
+    /// ```
+    pub fn add(&mut self, item: &[u8]) {
+        self.pending.insert(item.to_vec());
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, PendingSet};
+    /// use std::sync::Arc;
+    /// let set = PendingSet::new(Arc::new(CuckooFilter::new(100)));
+    /// assert!(!set.contains(b"test"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.pending.contains(item) || self.frozen.contains(item)
+    }
+
+    /// Swaps in a newer snapshot and forgets the pending keys it contains.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, PendingSet};
+    /// use std::sync::Arc;
+    /// let mut set = PendingSet::new(Arc::new(CuckooFilter::new(100)));
+    /// set.add(b"test");
+    ///
+    /// let mut replicated = CuckooFilter::new(100);
+    /// let _ = replicated.add(b"test");
+    /// set.refresh(Arc::new(replicated));
+    /// assert_eq!(set.pending(), 0);
+    /// assert!(set.contains(b"test"));
+    /// ```
+    pub fn refresh(&mut self, frozen: Arc<CuckooFilter>) {
+        self.pending.retain(|item| !frozen.contains(item));
+        self.frozen = frozen;
+    }
+
+    /// Returns the number of keys not yet visible in the snapshot.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, PendingSet};
+    /// use std::sync::Arc;
+    /// let set = PendingSet::new(Arc::new(CuckooFilter::new(100)));
+    /// assert_eq!(set.pending(), 0);
+    /// ```
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the current snapshot.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, PendingSet};
+    /// use std::sync::Arc;
+    /// let set = PendingSet::new(Arc::new(CuckooFilter::new(100)));
+    /// assert_eq!(set.snapshot().size(), 0);
+    /// ```
+    pub fn snapshot(&self) -> &Arc<CuckooFilter> {
+        &self.frozen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, PendingSet};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_refresh_keeps_unreplicated_keys() {
+        let mut set = PendingSet::new(Arc::new(CuckooFilter::new(100)));
+        set.add(b"a");
+        set.add(b"b");
+        let mut replicated = CuckooFilter::new(100);
+        let _ = replicated.add(b"a");
+        set.refresh(Arc::new(replicated));
+        assert_eq!(set.pending(), 1);
+        assert!(set.contains(b"a"));
+        assert!(set.contains(b"b"));
+    }
+    #[test]
+    fn test_add_ignores_snapshot_hits() {
+        // A hit in the frozen snapshot may be a false positive, and the key
+        // must survive a refresh to a snapshot that really lacks it.
+        let mut frozen = CuckooFilter::new(100);
+        let _ = frozen.add(b"a");
+        let mut set = PendingSet::new(Arc::new(frozen));
+        set.add(b"a");
+        assert_eq!(set.pending(), 1);
+        set.refresh(Arc::new(CuckooFilter::new(100)));
+        assert!(set.contains(b"a"));
+    }
+}
@@ -0,0 +1,478 @@
+// Header fields are read at constant offsets once the header has parsed,
+// and the map is checked on open to hold every bucket, which is all the
+// bucket indices of the filter can address.
+#![allow(clippy::indexing_slicing)]
+
+use crate::bucket::BUCKET_SIZE;
+use crate::format::{self, FormatError};
+use crate::{CResult, CuckooError, CuckooFilter};
+use memmap2::MmapMut;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::{error, fmt};
+
+// A log entry: bucket index, then the bucket's new contents
+const LOG_ENTRY_LEN: usize = 8 + BUCKET_SIZE;
+
+/// Why a `PersistentCuckooFilter` rejected an insert.
+#[derive(Debug)]
+pub enum PersistError {
+    Filter(CuckooError),
+    /// The write-ahead log could not be written; the filter is unchanged.
+    Io(io::Error),
+}
+
+impl fmt::Display for PersistError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistError::Filter(e) => e.fmt(f),
+            PersistError::Io(e) => write!(f, "logging failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for PersistError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PersistError::Filter(e) => Some(e),
+            PersistError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<CuckooError> for PersistError {
+    fn from(e: CuckooError) -> Self {
+        PersistError::Filter(e)
+    }
+}
+
+impl From<io::Error> for PersistError {
+    fn from(e: io::Error) -> Self {
+        PersistError::Io(e)
+    }
+}
+
+/// A `CuckooFilter` with a write-through copy of its buckets in a
+/// memory-mapped file, so every `add` and `delete` survives a restart of the
+/// process without an explicit export.
+///
+/// The file has the layout of `CuckooFilter::to_bytes_raw`, minus the checksum,
+/// which would have to be recomputed over the whole array on every change.
+/// Lookups and mutations work on a heap copy of the filter, loaded from the
+/// whole file on open, and the buckets a mutation touched are then copied
+/// into the map. The bucket array is therefore held twice, once on the heap
+/// and once in the page cache, and opening costs a full read of the file;
+/// to share one read-only copy instead, see `MmapCuckooFilter`. The kernel
+/// writes dirty pages back on its own, and `flush` or `sync` force it. After
+/// a crash of the machine, the size in the header may lag the buckets, so it
+/// is recounted on open.
+///
+/// A crash of the machine between two `sync` calls can also leave only some
+/// of the dirty pages written. Opening the filter with a write-ahead log,
+/// see `open_or_create_with_log`, makes it recoverable from that.
+///
+/// Only one `PersistentCuckooFilter` may have a file open at a time, and
+/// nothing else may write to it meanwhile.
+pub struct PersistentCuckooFilter {
+    filter: CuckooFilter,
+    map: MmapMut,
+    file: File,
+    log: Option<File>,
+}
+
+impl PersistentCuckooFilter {
+    /// Opens the filter file at `path`, or creates one sized for `max_keys`
+    /// if it does not exist or is empty. The size of an existing file is
+    /// kept.
+    ///
+    /// Files written by `to_bytes` can be opened as well; their checksum is
+    /// verified and then dropped, and sparse-encoded buckets are expanded in
+    /// place. Layout errors are reported as
+    /// `io::ErrorKind::InvalidData`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::PersistentCuckooFilter;
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_persistent_doc_{}", std::process::id()));
+    /// # let _ = std::fs::remove_file(&path);
+    /// let mut cf = PersistentCuckooFilter::open_or_create(&path, 1000).unwrap();
+    /// cf.add(b"test").unwrap();
+    /// drop(cf);
+    /// let cf = PersistentCuckooFilter::open_or_create(&path, 1000).unwrap();
+    /// assert!(cf.contains(b"test"));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn open_or_create<P: AsRef<Path>>(path: P, max_keys: u64) -> io::Result<Self> {
+        Self::map(Self::open_file(path, max_keys)?, None)
+    }
+
+    /// Like `open_or_create`, but with a write-ahead log at `log_path`.
+    ///
+    /// Every `add` and `delete` first appends the new contents of the
+    /// buckets it modifies to the log and waits until they are durable; only
+    /// then are they written to the map. On open, the log is replayed over
+    /// the bucket file, which restores every logged operation however few
+    /// dirty pages made it to disk before a crash. A record torn by the crash
+    /// is discarded. `checkpoint` empties the log.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::PersistentCuckooFilter;
+    ///
+    /// let dir = std::env::temp_dir();
+    /// let path = dir.join(format!("dakv_cuckoo_logged_doc_{}", std::process::id()));
+    /// let log = dir.join(format!("dakv_cuckoo_logged_doc_{}.wal", std::process::id()));
+    /// # let _ = std::fs::remove_file(&path);
+    /// # let _ = std::fs::remove_file(&log);
+    /// let mut cf = PersistentCuckooFilter::open_or_create_with_log(&path, &log, 1000).unwrap();
+    /// cf.add(b"test").unwrap();
+    /// cf.checkpoint().unwrap();
+    /// assert_eq!(std::fs::metadata(&log).unwrap().len(), 0);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// # std::fs::remove_file(&log).unwrap();
+    /// ```
+    pub fn open_or_create_with_log<P: AsRef<Path>, L: AsRef<Path>>(
+        path: P,
+        log_path: L,
+        max_keys: u64,
+    ) -> io::Result<Self> {
+        let file = Self::open_file(path, max_keys)?;
+        let log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(log_path)?;
+        Self::map(file, Some(log))
+    }
+
+    fn open_file<P: AsRef<Path>>(path: P, max_keys: u64) -> io::Result<File> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if file.metadata()?.len() == 0 {
+            let filter = CuckooFilter::new(max_keys);
+            let mut bytes = filter.to_bytes_raw();
+            bytes.truncate(format::BUCKETS_OFFSET + filter.num_buckets() * BUCKET_SIZE);
+            bytes[format::FLAGS_OFFSET] = 0;
+            (&file).write_all(&bytes)?;
+            file.sync_all()?;
+        } else {
+            // Sparse-encoded buckets cannot be mapped, so they are expanded
+            // in place first.
+            let mut head = [0; format::HEADER_LEN];
+            (&file).read_exact(&mut head)?;
+            if head[format::FLAGS_OFFSET] & format::FLAG_SPARSE != 0 {
+                let mut bytes = head.to_vec();
+                (&file).read_to_end(&mut bytes)?;
+                let filter = CuckooFilter::from_bytes(&bytes)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                file.set_len(0)?;
+                (&file).seek(SeekFrom::Start(0))?;
+                (&file).write_all(&filter.to_bytes_raw())?;
+                file.sync_all()?;
+            }
+        }
+        Ok(file)
+    }
+
+    #[allow(unsafe_code)]
+    fn map(file: File, mut log: Option<File>) -> io::Result<Self> {
+        let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+        // SAFETY: the map is only accessed through slices while `self` is
+        // alive, which is sound as long as nothing else writes to the file,
+        // a requirement documented on the type.
+        let mut map = unsafe { MmapMut::map_mut(&file)? };
+        let header = format::Header::parse(&map).map_err(invalid)?;
+        let end = usize::try_from(header.num_buckets)
+            .ok()
+            .and_then(|n| n.checked_mul(BUCKET_SIZE))
+            .and_then(|len| len.checked_add(format::BUCKETS_OFFSET))
+            .filter(|&end| end <= map.len())
+            .ok_or_else(|| invalid(FormatError::Truncated { field: "buckets" }))?;
+        let (head, rest) = map.split_at_mut(format::BUCKETS_OFFSET);
+        let (data, sections) = rest.split_at_mut(end - format::BUCKETS_OFFSET);
+        for (flag, section) in format::sections(header.flags, sections).map_err(invalid)? {
+            if flag == format::FLAG_CHECKSUM {
+                format::verify_checksum(section, format::checksum(data)).map_err(invalid)?;
+            }
+        }
+        if let Some(log) = &mut log {
+            let mut records = Vec::new();
+            log.seek(SeekFrom::Start(0))?;
+            log.read_to_end(&mut records)?;
+            let valid = replay(&records, data).map_err(invalid)?;
+            // Drop a torn tail, so new records follow the last good one.
+            log.set_len(valid as u64)?;
+            log.seek(SeekFrom::End(0))?;
+        }
+        let occupied = data.iter().filter(|&&fp| fp != 0).count() as u64;
+        head[format::FLAGS_OFFSET] =
+            header.flags & !(format::FLAG_CHECKSUM | format::FLAG_SEGMENT_CHECKSUMS);
+        head[format::SIZE_OFFSET..format::SIZE_OFFSET + 8]
+            .copy_from_slice(&occupied.to_le_bytes());
+        let filter = CuckooFilter::from_bytes(&map[..end]).map_err(invalid)?;
+        Ok(PersistentCuckooFilter {
+            filter,
+            map,
+            file,
+            log,
+        })
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::PersistentCuckooFilter;
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_persistent_add_{}", std::process::id()));
+    /// # let _ = std::fs::remove_file(&path);
+    /// let mut cf = PersistentCuckooFilter::open_or_create(&path, 1000).unwrap();
+    /// assert!(cf.add(b"test").is_ok());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn add(&mut self, item: &[u8]) -> CResult<PersistError> {
+        let finger = self.filter.finger_of(item);
+        self.filter.check_duplicates(&finger)?;
+        let (_, touched) = self.filter.place_tracked(&finger)?;
+        self.commit(&touched)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.filter.contains(item)
+    }
+
+    /// Deletes `item`, returning `Ok(true)` if it was present.
+    ///
+    /// An error means the write-ahead log could not be written, and the
+    /// filter is unchanged.
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> io::Result<bool> {
+        let finger = self.filter.finger_of(item);
+        if !self.filter.delete_finger(&finger) {
+            return Ok(false);
+        }
+        self.commit(&[finger.i1 as usize, finger.i2 as usize])?;
+        Ok(true)
+    }
+
+    /// Logs and then maps the buckets modified in the in-memory filter. If
+    /// logging fails, they are restored from the map instead.
+    fn commit(&mut self, touched: &[usize]) -> io::Result<()> {
+        if let Err(e) = self.append_log(touched) {
+            for &i in touched {
+                let start = format::BUCKETS_OFFSET + i * BUCKET_SIZE;
+                let mut data = [0; BUCKET_SIZE];
+                data.copy_from_slice(&self.map[start..start + BUCKET_SIZE]);
+                self.filter.set_bucket_data(i, data);
+            }
+            return Err(e);
+        }
+        for &i in touched {
+            let start = format::BUCKETS_OFFSET + i * BUCKET_SIZE;
+            self.map[start..start + BUCKET_SIZE].copy_from_slice(&self.filter.bucket_data(i));
+        }
+        let size = self.filter.size() as u64;
+        self.map[format::SIZE_OFFSET..format::SIZE_OFFSET + 8]
+            .copy_from_slice(&size.to_le_bytes());
+        Ok(())
+    }
+
+    fn append_log(&mut self, touched: &[usize]) -> io::Result<()> {
+        let log = match &mut self.log {
+            Some(log) => log,
+            None => return Ok(()),
+        };
+        let mut record = Vec::with_capacity(4 + touched.len() * LOG_ENTRY_LEN + 4);
+        record.extend_from_slice(&(touched.len() as u32).to_le_bytes());
+        for &i in touched {
+            record.extend_from_slice(&(i as u64).to_le_bytes());
+            record.extend_from_slice(&self.filter.bucket_data(i));
+        }
+        let crc = format::checksum(&record);
+        record.extend_from_slice(&crc.to_le_bytes());
+        log.write_all(&record)?;
+        log.sync_data()
+    }
+
+    pub fn size(&self) -> usize {
+        self.filter.size()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.filter.capacity()
+    }
+
+    /// Returns the in-memory filter, e.g. to serialize a checksummed copy.
+    pub fn filter(&self) -> &CuckooFilter {
+        &self.filter
+    }
+
+    /// Starts writing dirty pages back to disk without waiting for it.
+    pub fn flush(&self) -> io::Result<()> {
+        self.map.flush_async()
+    }
+
+    /// Writes dirty pages back to disk and waits until they are durable.
+    pub fn sync(&self) -> io::Result<()> {
+        self.map.flush()?;
+        self.file.sync_data()
+    }
+
+    /// Makes the bucket file durable with `sync`, then empties the
+    /// write-ahead log, whose records are no longer needed for recovery.
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        self.sync()?;
+        if let Some(log) = &mut self.log {
+            log.set_len(0)?;
+            log.seek(SeekFrom::Start(0))?;
+            log.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+/// Applies the complete records at the start of `log` to the bucket data,
+/// stopping at the first torn or damaged one. Returns the length of the
+/// records applied.
+fn replay(log: &[u8], data: &mut [u8]) -> Result<usize, FormatError> {
+    let num_buckets = data.len() / BUCKET_SIZE;
+    let mut rest = log;
+    while let Some((entries, tail)) = split_record(rest) {
+        for entry in entries.chunks_exact(LOG_ENTRY_LEN) {
+            let (index, bucket) = entry
+                .split_first_chunk::<8>()
+                .ok_or(FormatError::Truncated { field: "log" })?;
+            let index = u64::from_le_bytes(*index);
+            let slots = usize::try_from(index)
+                .ok()
+                .filter(|&i| i < num_buckets)
+                .and_then(|i| data.get_mut(i * BUCKET_SIZE..(i + 1) * BUCKET_SIZE))
+                .ok_or(FormatError::Invalid {
+                    field: "log bucket index",
+                    value: index,
+                })?;
+            slots.copy_from_slice(bucket);
+        }
+        rest = tail;
+    }
+    Ok(log.len() - rest.len())
+}
+
+/// Splits the first record off `log` into its entries and the remainder, or
+/// returns `None` if it is incomplete or fails its checksum.
+fn split_record(log: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (count, rest) = log.split_first_chunk::<4>()?;
+    let len = (u32::from_le_bytes(*count) as usize).checked_mul(LOG_ENTRY_LEN)?;
+    let (entries, rest) = rest.split_at_checked(len)?;
+    let (crc, rest) = rest.split_first_chunk::<4>()?;
+    let body = log.get(..4 + len)?;
+    if format::checksum(body) == u32::from_le_bytes(*crc) {
+        Some((entries, rest))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, PersistentCuckooFilter};
+    use std::io::Write;
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_persistent_survives_reopen() {
+        let path = env::temp_dir().join(format!("dakv_cuckoo_persistent_{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let mut cf = PersistentCuckooFilter::open_or_create(&path, 1000).unwrap();
+        for i in 0..700u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        #[cfg(not(feature = "no-delete"))]
+        for i in (0..700u16).step_by(3) {
+            assert!(cf.delete(&i.to_le_bytes()).unwrap());
+        }
+        cf.sync().unwrap();
+        let expected = cf.filter().to_bytes();
+        drop(cf);
+
+        // The file loads like any serialized filter, minus the checksum.
+        let loaded = CuckooFilter::from_bytes(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(
+            loaded.size(),
+            CuckooFilter::from_bytes(&expected).unwrap().size()
+        );
+
+        // Reopening ignores `max_keys` and keeps the stored buckets.
+        let cf = PersistentCuckooFilter::open_or_create(&path, 10).unwrap();
+        assert_eq!(cf.filter().to_bytes(), expected);
+        assert_eq!(cf.capacity(), loaded.capacity());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_persistent_opens_exported_filter() {
+        let mut cf = CuckooFilter::new(100);
+        cf.add(b"test").unwrap();
+        let path =
+            env::temp_dir().join(format!("dakv_cuckoo_persistent_export_{}", process::id()));
+        fs::write(&path, cf.to_bytes()).unwrap();
+        let mut persistent = PersistentCuckooFilter::open_or_create(&path, 100).unwrap();
+        assert!(persistent.contains(b"test"));
+        persistent.add(b"more").unwrap();
+        drop(persistent);
+        let loaded = CuckooFilter::from_bytes(&fs::read(&path).unwrap()).unwrap();
+        assert!(loaded.contains(b"test") && loaded.contains(b"more"));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_log_recovers_lost_pages() {
+        let dir = env::temp_dir();
+        let path = dir.join(format!("dakv_cuckoo_logged_{}", process::id()));
+        let log = dir.join(format!("dakv_cuckoo_logged_{}.wal", process::id()));
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&log);
+        let mut cf = PersistentCuckooFilter::open_or_create_with_log(&path, &log, 1000).unwrap();
+        for i in 0..300u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        cf.checkpoint().unwrap();
+        assert_eq!(fs::metadata(&log).unwrap().len(), 0);
+        let checkpointed = fs::read(&path).unwrap();
+        for i in 300..600u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        #[cfg(not(feature = "no-delete"))]
+        for i in (0..600u16).step_by(5) {
+            assert!(cf.delete(&i.to_le_bytes()).unwrap());
+        }
+        let expected = cf.filter().to_bytes();
+        drop(cf);
+
+        // As if no page written since the checkpoint reached the disk, and
+        // the crash tore the last log record.
+        fs::write(&path, &checkpointed).unwrap();
+        let logged = fs::metadata(&log).unwrap().len();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&log)
+            .unwrap()
+            .write_all(&[2, 0, 0, 0, 7])
+            .unwrap();
+
+        let mut cf = PersistentCuckooFilter::open_or_create_with_log(&path, &log, 1000).unwrap();
+        assert_eq!(cf.filter().to_bytes(), expected);
+        assert_eq!(fs::metadata(&log).unwrap().len(), logged);
+        cf.add(b"after").unwrap();
+        cf.checkpoint().unwrap();
+        assert_eq!(fs::metadata(&log).unwrap().len(), 0);
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&log).unwrap();
+    }
+}
@@ -0,0 +1,170 @@
+use crate::util::FingerIndex;
+use crate::{BatchReport, CuckooError, CuckooFilter, ItemOutcome};
+use std::collections::VecDeque;
+
+/// Number of keys in flight when no depth is given.
+pub const DEFAULT_PIPELINE_DEPTH: usize = 16;
+
+/// Batches inserts so the memory latency of one key overlaps with others.
+///
+/// Every pushed key is hashed straight away and its two candidate buckets are
+/// prefetched; the key is only placed once `depth` newer keys have been
+/// pushed, by which time its buckets are usually in cache. Outcomes are
+/// reported in push order, with the semantics of `CuckooFilter::add`.
+///
+/// Prefetching needs the `unsafe-optimizations` feature; without it keys are
+/// still queued, but nothing is gained over calling `add` directly.
+///
+/// Dropping the pipeline applies whatever is still queued.
+pub struct InsertPipeline<'a> {
+    filter: &'a mut CuckooFilter,
+    queue: VecDeque<FingerIndex>,
+    depth: usize,
+    report: BatchReport,
+}
+
+impl<'a> InsertPipeline<'a> {
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, InsertPipeline};
+    /// let mut cf = CuckooFilter::new(1000);
+    /// let pipeline = InsertPipeline::new(&mut cf);
+    /// ```
+    pub fn new(filter: &'a mut CuckooFilter) -> Self {
+        Self::with_depth(filter, DEFAULT_PIPELINE_DEPTH)
+    }
+
+    /// Creates a pipeline keeping up to `depth` keys in flight.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, InsertPipeline};
+    /// let mut cf = CuckooFilter::new(1000);
+    /// let pipeline = InsertPipeline::with_depth(&mut cf, 32);
+    /// ```
+    pub fn with_depth(filter: &'a mut CuckooFilter, depth: usize) -> Self {
+        let depth = depth.max(1);
+        InsertPipeline {
+            filter,
+            queue: VecDeque::with_capacity(depth),
+            depth,
+            report: BatchReport::default(),
+        }
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, InsertPipeline};
+    /// let mut cf = CuckooFilter::new(1000);
+    /// let mut pipeline = InsertPipeline::new(&mut cf);
+    /// pipeline.push(b"test");
+    /// ```
+    pub fn push(&mut self, item: &[u8]) {
+        if self.queue.len() == self.depth {
+            self.apply_oldest();
+        }
+        let finger = self.filter.finger_of(item);
+        self.filter.prefetch(&finger);
+        self.queue.push_back(finger);
+    }
+
+    /// Applies every queued key and returns the outcomes of all pushed keys.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, InsertPipeline};
+    /// let mut cf = CuckooFilter::new(1000);
+    /// let mut pipeline = InsertPipeline::new(&mut cf);
+    /// pipeline.push(b"a");
+    /// pipeline.push(b"b");
+    /// assert_eq!(pipeline.finish().inserted(), 2);
+    /// assert!(cf.contains(b"a"));
+    /// ```
+    pub fn finish(mut self) -> BatchReport {
+        self.flush();
+        std::mem::take(&mut self.report)
+    }
+
+    fn flush(&mut self) {
+        while !self.queue.is_empty() {
+            self.apply_oldest();
+        }
+    }
+
+    fn apply_oldest(&mut self) {
+        let finger = match self.queue.pop_front() {
+            Some(finger) => finger,
+            None => return,
+        };
+        let filter = &mut *self.filter;
+        let outcome = match filter
+            .check_duplicates(&finger)
+            .and_then(|_| filter.place(&finger))
+        {
+            Ok(kicks) => ItemOutcome::Inserted { kicks },
+            Err(CuckooError::TooManyDuplicates(placement)) => {
+                ItemOutcome::TooManyDuplicates { placement }
+            }
+            Err(_) => ItemOutcome::Failed {
+                kicks: filter.max_kicks(),
+                placement: finger.placement(),
+            },
+        };
+        self.report.outcomes.push(outcome);
+    }
+}
+
+impl Drop for InsertPipeline<'_> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, InsertPipeline, ItemOutcome};
+
+    #[test]
+    fn test_matches_add() {
+        let keys = (0..1200u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let mut direct = CuckooFilter::with_capacity_and_seed(256, 7);
+        let mut piped = CuckooFilter::with_capacity_and_seed(256, 7);
+        let expected = keys.iter().filter(|k| direct.add(&k[..]).is_ok()).count();
+
+        let mut pipeline = InsertPipeline::with_depth(&mut piped, 8);
+        for k in &keys {
+            pipeline.push(k);
+        }
+        let report = pipeline.finish();
+        assert_eq!(report.outcomes.len(), keys.len());
+        assert_eq!(report.inserted(), expected);
+        assert_eq!(
+            piped.fingerprints().collect::<Vec<_>>(),
+            direct.fingerprints().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_drop_applies_queue() {
+        let mut cf = CuckooFilter::new(100);
+        {
+            let mut pipeline = InsertPipeline::new(&mut cf);
+            pipeline.push(b"test");
+        }
+        assert!(cf.contains(b"test"));
+    }
+    #[test]
+    fn test_duplicate_limit() {
+        let mut cf = CuckooFilter::new(100);
+        cf.set_max_duplicates(Some(1));
+        let mut pipeline = InsertPipeline::new(&mut cf);
+        pipeline.push(b"a");
+        pipeline.push(b"a");
+        let report = pipeline.finish();
+        assert_eq!(report.inserted(), 1);
+        assert!(matches!(
+            report.outcomes[1],
+            ItemOutcome::TooManyDuplicates { .. }
+        ));
+    }
+}
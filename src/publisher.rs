@@ -0,0 +1,154 @@
+use crate::{CuckooFilter, SharedCuckooFilter};
+use std::io;
+use std::sync::{Arc, Condvar, Mutex, PoisonError, Weak};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A serialized snapshot handed to the `SnapshotPublisher` callback.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotArtifact {
+    /// Starts at 1 and grows by one with every published snapshot.
+    pub sequence: u64,
+    /// Number of items in the snapshot.
+    pub size: usize,
+    /// The snapshot in the layout of `CuckooFilter::to_bytes`.
+    pub bytes: Vec<u8>,
+}
+
+/// Periodically serializes the current snapshot of a `SharedCuckooFilter` on
+/// a background thread and passes it to a callback, e.g. to upload it for
+/// read replicas.
+///
+/// Writers are never blocked: each round only clones the snapshot handle.
+/// Rounds in which no update was published since the last artifact are
+/// skipped. Dropping the publisher, or calling `stop`, ends the thread after
+/// the round in progress.
+pub struct SnapshotPublisher {
+    stopped: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl SnapshotPublisher {
+    /// Starts publishing `shared` every `interval`, beginning immediately.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SharedCuckooFilter, SnapshotPublisher};
+    /// use std::sync::mpsc;
+    /// use std::time::Duration;
+    ///
+    /// let shared = SharedCuckooFilter::new(CuckooFilter::new(100));
+    /// shared.update(|f| f.add(b"test").is_ok());
+    /// let (tx, rx) = mpsc::channel();
+    /// let publisher = SnapshotPublisher::start(shared, Duration::from_secs(60), move |artifact| {
+    ///     let _ = tx.send(artifact);
+    /// })
+    /// .unwrap();
+    /// let artifact = rx.recv().unwrap();
+    /// assert!(CuckooFilter::from_bytes(&artifact.bytes).unwrap().contains(b"test"));
+    /// publisher.stop();
+    /// ```
+    pub fn start<F>(shared: SharedCuckooFilter, interval: Duration, publish: F) -> io::Result<Self>
+    where
+        F: FnMut(SnapshotArtifact) + Send + 'static,
+    {
+        let stopped = Arc::new((Mutex::new(false), Condvar::new()));
+        let signal = Arc::clone(&stopped);
+        let handle = thread::Builder::new()
+            .name("cuckoo-snapshot".into())
+            .spawn(move || run(&shared, interval, publish, &signal))?;
+        Ok(SnapshotPublisher {
+            stopped,
+            handle: Some(handle),
+        })
+    }
+
+    /// Stops the background thread and waits for it to exit.
+    pub fn stop(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let (lock, wakeup) = &*self.stopped;
+        *lock.lock().unwrap_or_else(PoisonError::into_inner) = true;
+        wakeup.notify_all();
+        if let Some(handle) = self.handle.take() {
+            // A panicking callback already ended the thread; there is
+            // nothing left to clean up.
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for SnapshotPublisher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run<F>(
+    shared: &SharedCuckooFilter,
+    interval: Duration,
+    mut publish: F,
+    stopped: &(Mutex<bool>, Condvar),
+) where
+    F: FnMut(SnapshotArtifact),
+{
+    // Only a weak handle, so a published snapshot is freed once replaced.
+    // It still pins the allocation, so its address cannot be reused.
+    let mut last: Weak<CuckooFilter> = Weak::new();
+    let mut sequence = 0;
+    loop {
+        let snapshot = shared.snapshot();
+        if !Weak::ptr_eq(&last, &Arc::downgrade(&snapshot)) {
+            sequence += 1;
+            publish(SnapshotArtifact {
+                sequence,
+                size: snapshot.size(),
+                bytes: snapshot.to_bytes(),
+            });
+            last = Arc::downgrade(&snapshot);
+        }
+        drop(snapshot);
+
+        let (lock, wakeup) = stopped;
+        let guard = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let (guard, _) = wakeup
+            .wait_timeout_while(guard, interval, |stopped| !*stopped)
+            .unwrap_or_else(PoisonError::into_inner);
+        if *guard {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, SharedCuckooFilter, SnapshotPublisher};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn test_publishes_only_changes() {
+        let shared = SharedCuckooFilter::new(CuckooFilter::new(100));
+        let (tx, rx) = mpsc::channel();
+        let publisher =
+            SnapshotPublisher::start(shared.clone(), Duration::from_millis(5), move |artifact| {
+                let _ = tx.send(artifact);
+            })
+            .unwrap();
+        let first = rx.recv().unwrap();
+        assert_eq!((first.sequence, first.size), (1, 0));
+
+        shared.update(|f| f.add(b"test").is_ok());
+        let second = rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!((second.sequence, second.size), (2, 1));
+        assert!(CuckooFilter::from_bytes(&second.bytes)
+            .unwrap()
+            .contains(b"test"));
+
+        // Nothing changed, so later rounds publish nothing.
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        publisher.stop();
+    }
+}
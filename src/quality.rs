@@ -0,0 +1,183 @@
+use crate::advisor::FULL_LOAD_FACTOR;
+use crate::bucket::BUCKET_SIZE;
+use crate::{CResult, CuckooError, CuckooFilter};
+
+/// How well a `BoundedFilter` currently answers lookups.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quality {
+    /// Number of items in the filter.
+    pub size: usize,
+    /// Fraction of occupied slots.
+    pub load_factor: f64,
+    /// Expected fraction of absent keys reported present at the current load.
+    pub false_positive_rate: f64,
+    /// Estimated number of inserts left before they start failing.
+    pub remaining: usize,
+    /// Number of inserts rejected because no slot was free.
+    pub rejected: u64,
+}
+
+/// Returns the false positive rate of a filter with `fingerprint_bits`-bit
+/// fingerprints at `load_factor`: a lookup compares against the occupied
+/// slots of two buckets, each matching with probability `2^-bits`.
+pub(crate) fn expected_fpr(load_factor: f64, fingerprint_bits: u32) -> f64 {
+    expected_fpr_in(load_factor, fingerprint_bits, BUCKET_SIZE)
+}
+
+/// Like `expected_fpr`, for buckets of `bucket_size` slots.
+pub(crate) fn expected_fpr_in(load_factor: f64, fingerprint_bits: u32, bucket_size: usize) -> f64 {
+    let compared = 2.0 * bucket_size as f64 * load_factor.clamp(0.0, 1.0);
+    1.0 - (1.0 - 0.5f64.powi(fingerprint_bits as i32)).powf(compared)
+}
+
+/// Returns the highest load factor at which a filter with
+/// `fingerprint_bits`-bit fingerprints stays within `fpr`, the inverse of
+/// `expected_fpr`.
+pub(crate) fn load_for_fpr(fpr: f64, fingerprint_bits: u32) -> f64 {
+    load_for_fpr_in(fpr, fingerprint_bits, BUCKET_SIZE)
+}
+
+/// Like `load_for_fpr`, for buckets of `bucket_size` slots.
+pub(crate) fn load_for_fpr_in(fpr: f64, fingerprint_bits: u32, bucket_size: usize) -> f64 {
+    let miss = 1.0 - 0.5f64.powi(fingerprint_bits as i32);
+    ((1.0 - fpr).ln() / miss.ln() / (2.0 * bucket_size as f64)).min(1.0)
+}
+
+/// A filter of fixed capacity that reports how its accuracy degrades as an
+/// input stream of unknown length fills it.
+///
+/// Memory never grows; instead `current_quality` tracks the false positive
+/// rate the load has driven the filter to, and `should_split` tells a
+/// planner when it has passed `max_fpr`, so the rest of the stream can go to
+/// another shard.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::BoundedFilter;
+///
+/// let mut filter = BoundedFilter::new(1000, 0.01);
+/// let mut shards = Vec::new();
+/// for key in 0..5000u32 {
+///     if filter.should_split() {
+///         shards.push(std::mem::replace(&mut filter, BoundedFilter::new(1000, 0.01)));
+///     }
+///     filter.add(&key.to_le_bytes()).unwrap();
+/// }
+/// // Each shard of 2048 slots stays below 1% for about 650 keys.
+/// assert_eq!(shards.len(), 7);
+/// ```
+pub struct BoundedFilter {
+    filter: CuckooFilter,
+    max_fpr: f64,
+    rejected: u64,
+}
+
+impl BoundedFilter {
+    /// Creates a filter with room for `capacity` items that should be split
+    /// once its false positive rate exceeds `max_fpr`.
+    pub fn new(capacity: u64, max_fpr: f64) -> Self {
+        BoundedFilter {
+            filter: CuckooFilter::new(capacity),
+            max_fpr,
+            rejected: 0,
+        }
+    }
+
+    /// Adds `item`, counting rejections in `Quality::rejected`.
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        let result = self.filter.add(item);
+        if result.is_err() {
+            self.rejected += 1;
+        }
+        result
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.filter.contains(item)
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        self.filter.delete(item)
+    }
+
+    pub fn size(&self) -> usize {
+        self.filter.size()
+    }
+
+    pub fn max_fpr(&self) -> f64 {
+        self.max_fpr
+    }
+
+    /// Returns the accuracy and headroom of the filter at its current load.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::BoundedFilter;
+    ///
+    /// let mut filter = BoundedFilter::new(1000, 0.01);
+    /// assert_eq!(filter.current_quality().false_positive_rate, 0.0);
+    /// filter.add(b"test").unwrap();
+    /// assert!(filter.current_quality().false_positive_rate > 0.0);
+    /// ```
+    pub fn current_quality(&self) -> Quality {
+        let load_factor = self.filter.load_factor();
+        let full = (self.filter.capacity() as f64 * FULL_LOAD_FACTOR) as usize;
+        Quality {
+            size: self.filter.size(),
+            load_factor,
+            false_positive_rate: expected_fpr(load_factor, self.filter.fingerprint_bits()),
+            remaining: full.saturating_sub(self.filter.size()),
+            rejected: self.rejected,
+        }
+    }
+
+    /// Returns `true` once the false positive rate has passed `max_fpr` or an
+    /// insert has been rejected.
+    pub fn should_split(&self) -> bool {
+        let quality = self.current_quality();
+        quality.rejected > 0 || quality.false_positive_rate > self.max_fpr
+    }
+
+    pub fn filter(&self) -> &CuckooFilter {
+        &self.filter
+    }
+
+    pub fn into_inner(self) -> CuckooFilter {
+        self.filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::quality::{expected_fpr, load_for_fpr};
+    use crate::BoundedFilter;
+
+    #[test]
+    fn test_expected_fpr() {
+        assert_eq!(expected_fpr(0.0, 8), 0.0);
+        // Eight full slots at 8 bits: 1 - (255/256)^8.
+        assert!((expected_fpr(1.0, 8) - 0.030_85).abs() < 1e-4);
+        assert!(expected_fpr(0.5, 8) < expected_fpr(0.9, 8));
+        assert!((expected_fpr(load_for_fpr(0.01, 8), 8) - 0.01).abs() < 1e-12);
+        assert_eq!(load_for_fpr(0.5, 8), 1.0);
+    }
+
+    #[test]
+    fn test_quality_degrades() {
+        let mut filter = BoundedFilter::new(1000, 0.02);
+        let mut last = filter.current_quality();
+        let mut added = 0u32;
+        while !filter.should_split() {
+            filter.add(&added.to_le_bytes()).unwrap();
+            added += 1;
+            let quality = filter.current_quality();
+            assert!(quality.false_positive_rate > last.false_positive_rate);
+            assert_eq!(quality.remaining + 1, last.remaining);
+            last = quality;
+        }
+        assert!(last.false_positive_rate > 0.02);
+        assert_eq!(last.rejected, 0);
+        assert!((0..added).all(|key| filter.contains(&key.to_le_bytes())));
+    }
+}
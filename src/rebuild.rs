@@ -0,0 +1,269 @@
+use crate::telemetry;
+use crate::{Cancellation, CuckooConfig, CuckooError, CuckooFilter, YIELD_EVERY};
+use std::iter;
+
+/// How far a `RebuildTask` has got.
+///
+/// Units are buckets of the source filter when rebuilding from
+/// fingerprints, and keys when rebuilding from a key source.
+/// `ExpiringFilter::evict_older_than` reports buckets cleared.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RebuildProgress {
+    pub done: usize,
+    /// `None` if the key source does not know its length.
+    pub total: Option<usize>,
+}
+
+impl RebuildProgress {
+    /// Returns the completed fraction between `0.0` and `1.0`, if known.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::RebuildProgress;
+    /// let progress = RebuildProgress { done: 1, total: Some(4) };
+    /// assert_eq!(progress.fraction(), Some(0.25));
+    /// ```
+    pub fn fraction(&self) -> Option<f64> {
+        self.total.map(|total| match total {
+            0 => 1.0,
+            total => self.done as f64 / total as f64,
+        })
+    }
+}
+
+enum Source<I> {
+    Buckets { next: usize },
+    Keys { keys: I, total: Option<usize> },
+}
+
+/// A `CuckooFilter::reencode` split into bounded steps.
+///
+/// Each `step` does a fixed amount of work, so a single-threaded deployment
+/// can interleave the rebuild with serving lookups from the source filter.
+/// Like `reencode`, shrinking works from the stored fingerprints, while
+/// growing needs the original keys.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{CuckooConfig, CuckooFilter, RebuildTask};
+///
+/// let mut cf = CuckooFilter::new(1000);
+/// cf.add(b"test").unwrap();
+/// let mut task = RebuildTask::new(&cf, &CuckooConfig::new(100)).unwrap();
+/// while !task.is_done() {
+///     task.step(16).unwrap();
+///     // ... serve lookups from `cf` in between.
+/// }
+/// assert!(task.finish().unwrap().contains(b"test"));
+/// ```
+pub struct RebuildTask<'a, I = iter::Empty<&'a [u8]>> {
+    source: &'a CuckooFilter,
+    target: CuckooFilter,
+    from: Source<I>,
+    done: usize,
+    finished: bool,
+}
+
+impl<'a> RebuildTask<'a> {
+    /// Starts rebuilding `source` from its fingerprints.
+    ///
+    /// Returns `CuckooError::NotSupported` if `new_config` needs more buckets;
+    /// use `with_keys` for that.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooError, CuckooFilter, RebuildTask};
+    /// let cf = CuckooFilter::new(1000);
+    /// assert!(RebuildTask::new(&cf, &CuckooConfig::new(100)).is_ok());
+    /// assert!(matches!(
+    ///     RebuildTask::new(&cf, &CuckooConfig::new(10000)),
+    ///     Err(CuckooError::NotSupported)
+    /// ));
+    /// ```
+    pub fn new(source: &'a CuckooFilter, new_config: &CuckooConfig) -> Result<Self, CuckooError> {
+        let target = source.reencode_target(new_config);
+        if !source.can_reencode_into(&target) {
+            return Err(telemetry::record(CuckooError::NotSupported));
+        }
+        Ok(RebuildTask {
+            source,
+            target,
+            from: Source::Buckets { next: 0 },
+            done: 0,
+            finished: false,
+        })
+    }
+}
+
+impl<'a, I> RebuildTask<'a, I>
+where
+    I: Iterator,
+    I::Item: AsRef<[u8]>,
+{
+    /// Starts rebuilding `source` by re-adding every key of `keys`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooFilter, RebuildTask};
+    /// let mut cf = CuckooFilter::new(100);
+    /// cf.add(b"test").unwrap();
+    /// let keys = vec![b"test"];
+    /// let mut task = RebuildTask::with_keys(&cf, &CuckooConfig::new(10000), keys.iter());
+    /// assert_eq!(task.step(10).unwrap().total, Some(1));
+    /// assert!(task.finish().unwrap().contains(b"test"));
+    /// ```
+    pub fn with_keys<K>(source: &'a CuckooFilter, new_config: &CuckooConfig, keys: K) -> Self
+    where
+        K: IntoIterator<IntoIter = I>,
+    {
+        let keys = keys.into_iter();
+        let total = match keys.size_hint() {
+            (lower, Some(upper)) if lower == upper => Some(upper),
+            _ => None,
+        };
+        RebuildTask {
+            source,
+            target: CuckooFilter::from_config(new_config),
+            from: Source::Keys { keys, total },
+            done: 0,
+            finished: false,
+        }
+    }
+
+    /// Processes at most `max_units` buckets or keys.
+    ///
+    /// An error leaves the task where it failed; the target is unusable.
+    pub fn step(&mut self, max_units: usize) -> Result<RebuildProgress, CuckooError> {
+        match &mut self.from {
+            Source::Buckets { next } => {
+                let end = next
+                    .saturating_add(max_units)
+                    .min(self.source.num_buckets());
+                for i in *next..end {
+                    for fp in self.source.bucket_fingerprints(i) {
+                        self.target.add_masked(i, fp)?;
+                    }
+                    *next = i + 1;
+                    self.done += 1;
+                }
+                self.finished = *next == self.source.num_buckets();
+            }
+            Source::Keys { keys, .. } => {
+                for _ in 0..max_units {
+                    match keys.next() {
+                        Some(key) => {
+                            self.target.add(key.as_ref())?;
+                            self.done += 1;
+                        }
+                        None => {
+                            self.finished = true;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(self.progress())
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, CuckooFilter, RebuildTask};
+    /// let cf = CuckooFilter::new(100);
+    /// let task = RebuildTask::new(&cf, &CuckooConfig::new(100)).unwrap();
+    /// assert_eq!(task.progress().done, 0);
+    /// ```
+    pub fn progress(&self) -> RebuildProgress {
+        let total = match &self.from {
+            Source::Buckets { .. } => Some(self.source.num_buckets()),
+            Source::Keys { total, .. } => *total,
+        };
+        RebuildProgress {
+            done: self.done,
+            total,
+        }
+    }
+
+    /// Returns `true` once every bucket or key has been processed.
+    pub fn is_done(&self) -> bool {
+        self.finished
+    }
+
+    /// Runs steps of `YIELD_EVERY` units until the task is done or `cancel`
+    /// says to stop, in which case it returns `CuckooError::Cancelled` and
+    /// the task can be resumed later.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CancelToken, Cancellation, CuckooConfig, CuckooFilter, RebuildTask};
+    ///
+    /// let cf = CuckooFilter::new(100_000);
+    /// let mut task = RebuildTask::new(&cf, &CuckooConfig::new(1000)).unwrap();
+    /// let token = CancelToken::new();
+    /// token.cancel();
+    /// assert!(task.run(&Cancellation::new().token(token)).is_err());
+    /// assert!(task.run(&Cancellation::new()).is_ok());
+    /// assert!(task.is_done());
+    /// ```
+    pub fn run(&mut self, cancel: &Cancellation) -> Result<RebuildProgress, CuckooError> {
+        while !self.finished {
+            if cancel.is_stopped() {
+                return Err(telemetry::record(CuckooError::Cancelled));
+            }
+            self.step(YIELD_EVERY)?;
+        }
+        Ok(self.progress())
+    }
+
+    /// Runs the remaining steps and returns the rebuilt filter.
+    pub fn finish(mut self) -> Result<CuckooFilter, CuckooError> {
+        while !self.finished {
+            self.step(usize::MAX)?;
+        }
+        Ok(self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooConfig, CuckooFilter, RebuildTask};
+
+    #[test]
+    fn test_steps_match_reencode() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(256, 1);
+        for i in 0..500u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        let config = CuckooConfig::new(600).seed(2);
+        let expected = cf.reencode(&config, None::<Vec<&[u8]>>).unwrap();
+
+        let mut task = RebuildTask::new(&cf, &config).unwrap();
+        let mut steps = 0;
+        while !task.is_done() {
+            let progress = task.step(10).unwrap();
+            assert!(progress.done <= 10 * (steps + 1));
+            steps += 1;
+        }
+        assert_eq!(steps, 26);
+        assert_eq!(task.progress().fraction(), Some(1.0));
+        let rebuilt = task.finish().unwrap();
+        assert_eq!(
+            rebuilt.fingerprints().collect::<Vec<_>>(),
+            expected.fingerprints().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_grow_from_keys() {
+        let keys = (0..300u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let mut cf = CuckooFilter::with_capacity_and_seed(128, 1);
+        for k in &keys {
+            cf.add(k).unwrap();
+        }
+        let mut task = RebuildTask::with_keys(&cf, &CuckooConfig::new(5000), keys.iter());
+        assert_eq!(task.step(100).unwrap().fraction(), Some(1.0 / 3.0));
+        let grown = task.finish().unwrap();
+        assert!(grown.capacity() > cf.capacity());
+        assert!(keys.iter().all(|k| grown.contains(k)));
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+use std::sync::{PoisonError, RwLock};
+
+/// Turns keys into something safe to print.
+///
+/// Once installed with `set_key_redactor`, every `Debug` or `Display` output
+/// of this crate that would show a key goes through it instead.
+pub trait KeyRedactor: Send + Sync {
+    fn redact(&self, key: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result;
+}
+
+/// Prints keys as a salted hash truncated to 32 bits.
+///
+/// The same key always prints the same way for a given salt, so output can
+/// still be correlated, but short of guessing keys it cannot be reversed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SaltedHash {
+    salt: u64,
+}
+
+impl SaltedHash {
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::SaltedHash;
+    /// let redactor = SaltedHash::new(0x5eed);
+    /// ```
+    pub fn new(salt: u64) -> Self {
+        SaltedHash { salt }
+    }
+}
+
+impl KeyRedactor for SaltedHash {
+    fn redact(&self, key: &[u8], f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let hash = seahash::hash_seeded(key, self.salt, !self.salt, 0, 0);
+        write!(f, "key#{:08x}", hash >> 32)
+    }
+}
+
+static REDACTOR: RwLock<Option<Box<dyn KeyRedactor>>> = RwLock::new(None);
+
+/// Installs `redactor` for all output involving keys, replacing any
+/// previous one.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{display_key, set_key_redactor, SaltedHash};
+/// set_key_redactor(SaltedHash::new(42));
+/// assert!(display_key(b"user:1").to_string().starts_with("key#"));
+/// ```
+pub fn set_key_redactor<R: KeyRedactor + 'static>(redactor: R) {
+    *REDACTOR.write().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(redactor));
+}
+
+/// Goes back to printing keys as escaped bytes.
+pub fn clear_key_redactor() {
+    *REDACTOR.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Formats `key` the way this crate's own output does, for use in logs of
+/// code built around the filter.
+///
+/// Without a redactor, keys print as escaped byte strings.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::display_key;
+/// assert_eq!(display_key(b"a\n").to_string(), "b\"a\\n\"");
+/// ```
+pub fn display_key(key: &[u8]) -> DisplayKey<'_> {
+    DisplayKey(key)
+}
+
+/// A key formatted through the installed `KeyRedactor`, see `display_key`.
+pub struct DisplayKey<'a>(&'a [u8]);
+
+impl fmt::Display for DisplayKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let redactor = REDACTOR.read().unwrap_or_else(PoisonError::into_inner);
+        if let Some(r) = redactor.as_ref() {
+            return r.redact(self.0, f);
+        }
+        f.write_str("b\"")?;
+        for &b in self.0 {
+            write!(f, "{}", std::ascii::escape_default(b))?;
+        }
+        f.write_str("\"")
+    }
+}
+
+impl fmt::Debug for DisplayKey<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{clear_key_redactor, display_key, set_key_redactor, FilterOp, SaltedHash};
+
+    // The redactor is global, so everything touching it lives in one test.
+    #[test]
+    fn test_redaction() {
+        let op = FilterOp::Add(b"secret".to_vec());
+        assert_eq!(format!("{:?}", op), "Add(b\"secret\")");
+
+        set_key_redactor(SaltedHash::new(7));
+        let redacted = format!("{:?}", op);
+        assert!(!redacted.contains("secret"));
+        assert_eq!(redacted, format!("Add({})", display_key(b"secret")));
+        assert_eq!(display_key(b"secret").to_string().len(), "key#".len() + 8);
+
+        set_key_redactor(SaltedHash::new(8));
+        assert_ne!(format!("{:?}", op), redacted);
+        clear_key_redactor();
+        assert_eq!(display_key(b"secret").to_string(), "b\"secret\"");
+    }
+}
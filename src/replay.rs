@@ -0,0 +1,117 @@
+//! Re-execution of recorded operation logs, for reproducing bugs.
+use crate::{CuckooFilter, TraceEntry, TraceOp};
+
+/// The outcome of `replay_trace`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    /// Number of entries re-executed.
+    pub replayed: usize,
+    /// Positions of the entries whose outcome differs from the recorded one,
+    /// or whose buckets do not exist in the target filter.
+    pub diverged: Vec<usize>,
+    /// `CuckooFilter::digest` of the filter after the last entry.
+    pub digest: u64,
+}
+
+impl ReplayReport {
+    /// Returns `true` if every entry behaved as recorded and the filter ended
+    /// in the state with digest `expected`.
+    pub fn reproduces(&self, expected: u64) -> bool {
+        self.diverged.is_empty() && self.digest == expected
+    }
+}
+
+/// Re-executes a recorded operation log against `filter`.
+///
+/// The log is typically a `TracingFilter::dump`, which only holds derived
+/// values, so no keys are needed. To reproduce a production state, `filter`
+/// must start out like the original did: same bucket count, same eviction
+/// seed (see `CuckooFilter::with_capacity_and_seed`) and the same
+/// `CuckooConfig` options; and the log must cover its whole history, so the
+/// trace has to be long enough not to have wrapped.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::replay::replay_trace;
+/// use dakv_cuckoo::{CuckooFilter, TracingFilter};
+///
+/// let mut traced = TracingFilter::new(CuckooFilter::with_capacity_and_seed(16, 7), 1024);
+/// for i in 0..40u8 {
+///     let _ = traced.add(&[i]);
+/// }
+/// let mut fresh = CuckooFilter::with_capacity_and_seed(16, 7);
+/// let report = replay_trace(&mut fresh, &traced.dump());
+/// assert!(report.reproduces(traced.filter().digest()));
+/// ```
+pub fn replay_trace<'a, I>(filter: &mut CuckooFilter, log: I) -> ReplayReport
+where
+    I: IntoIterator<Item = &'a TraceEntry>,
+{
+    let mut report = ReplayReport {
+        replayed: 0,
+        diverged: Vec::new(),
+        digest: 0,
+    };
+    for (n, entry) in log.into_iter().enumerate() {
+        report.replayed += 1;
+        if entry.fingerprint == 0 || !filter.is_placement(entry.i1, entry.i2, entry.fingerprint) {
+            report.diverged.push(n);
+            continue;
+        }
+        let finger = filter.finger_at(entry.fingerprint, entry.i1 as u64);
+        let ok = match entry.op {
+            TraceOp::Add => filter.add_finger(&finger).is_ok(),
+            TraceOp::Contains => filter.contains_finger(&finger),
+            #[cfg(not(feature = "no-delete"))]
+            TraceOp::Delete => filter.delete_finger(&finger),
+        };
+        if ok != entry.ok {
+            report.diverged.push(n);
+        }
+    }
+    report.digest = filter.digest();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::replay::replay_trace;
+    use crate::{CuckooFilter, TraceEntry, TraceOp, TracingFilter};
+
+    #[test]
+    fn test_replay_reproduces_state() {
+        let mut traced = TracingFilter::new(CuckooFilter::with_capacity_and_seed(8, 11), 4096);
+        for i in 0..40u8 {
+            let _ = traced.add(&[i]);
+            traced.contains(&[i / 2]);
+        }
+        #[cfg(not(feature = "no-delete"))]
+        for i in (0..40u8).step_by(4) {
+            traced.delete(&[i]);
+        }
+        let log = traced.dump();
+        let mut fresh = CuckooFilter::with_capacity_and_seed(8, 11);
+        let report = replay_trace(&mut fresh, &log);
+        assert_eq!(report.replayed, log.len());
+        assert!(report.reproduces(traced.filter().digest()));
+
+        // A differently seeded filter evicts differently.
+        let mut other = CuckooFilter::with_capacity_and_seed(8, 12);
+        assert!(!replay_trace(&mut other, &log).reproduces(traced.filter().digest()));
+    }
+
+    #[test]
+    fn test_foreign_entries_diverge() {
+        let mut cf = CuckooFilter::with_capacity_and_seed(8, 1);
+        let entry = TraceEntry {
+            op: TraceOp::Add,
+            fingerprint: 9,
+            i1: 1000,
+            i2: 1001,
+            ok: true,
+        };
+        let report = replay_trace(&mut cf, &[entry]);
+        assert_eq!(report.diverged, [0]);
+        assert_eq!(cf.size(), 0);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{CResult, CuckooError, CuckooFilter};
+
+/// A filter generation whose keys are hashed under a secret seed.
+struct Generation {
+    seed: u64,
+    filter: CuckooFilter,
+}
+
+impl Generation {
+    /// Replaces `item` by a keyed hash of it, so where a key lands depends
+    /// on the seed and cannot be predicted without it.
+    fn key(&self, item: &[u8]) -> [u8; 8] {
+        seahash::hash_seeded(item, self.seed, !self.seed, self.seed.rotate_left(32), 0)
+            .to_le_bytes()
+    }
+}
+
+/// Rotates the hash seed of a long-lived filter.
+///
+/// Keys are hashed under the current seed before they reach the filter.
+/// `rotate` starts a new generation under a new seed; from then on writes go
+/// to the new generation only, while lookups consult both until `finish`
+/// retires the old one. The transition window has to be long enough for the
+/// new generation to be repopulated, by regular writes or a backfill through
+/// `add`.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{CuckooFilter, SeedRotation};
+///
+/// let mut cf = SeedRotation::new(0x5eed, CuckooFilter::new(1000));
+/// cf.add(b"a").unwrap();
+/// cf.rotate(0xf00d, CuckooFilter::new(1000));
+/// assert!(cf.contains(b"a"));
+/// cf.add(b"a").unwrap();
+/// cf.finish();
+/// assert!(cf.contains(b"a"));
+/// ```
+pub struct SeedRotation {
+    current: Generation,
+    previous: Option<Generation>,
+}
+
+impl SeedRotation {
+    /// Starts with `filter`, which must be empty or hold keys added under
+    /// `seed` by a `SeedRotation`.
+    pub fn new(seed: u64, filter: CuckooFilter) -> Self {
+        SeedRotation {
+            current: Generation { seed, filter },
+            previous: None,
+        }
+    }
+
+    /// Adds `item` to the current generation.
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        let key = self.current.key(item);
+        self.current.filter.add(&key)
+    }
+
+    /// Looks `item` up in the current generation and, during a transition
+    /// window, in the previous one.
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.current.filter.contains(&self.current.key(item))
+            || self
+                .previous
+                .as_ref()
+                .is_some_and(|p| p.filter.contains(&p.key(item)))
+    }
+
+    /// Deletes `item` from both generations, returning `true` if either
+    /// held it.
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        let key = self.current.key(item);
+        let from_current = self.current.filter.delete(&key);
+        let from_previous = self.previous.as_mut().is_some_and(|p| {
+            let key = p.key(item);
+            p.filter.delete(&key)
+        });
+        from_current || from_previous
+    }
+
+    /// Starts a transition window: `filter` becomes the current generation
+    /// under `seed`, and the current one is kept for lookups.
+    ///
+    /// A window still open is closed first, so at most two generations are
+    /// ever consulted. Returns the generation that was retired that way.
+    pub fn rotate(&mut self, seed: u64, filter: CuckooFilter) -> Option<CuckooFilter> {
+        let old = std::mem::replace(&mut self.current, Generation { seed, filter });
+        self.previous.replace(old).map(|g| g.filter)
+    }
+
+    /// Closes the transition window, retiring the previous generation and
+    /// its seed.
+    pub fn finish(&mut self) -> Option<CuckooFilter> {
+        self.previous.take().map(|g| g.filter)
+    }
+
+    /// Returns `true` during a transition window.
+    pub fn is_rotating(&self) -> bool {
+        self.previous.is_some()
+    }
+
+    /// Returns the seed new keys are hashed under.
+    pub fn seed(&self) -> u64 {
+        self.current.seed
+    }
+
+    /// Returns the generation receiving writes.
+    pub fn current(&self) -> &CuckooFilter {
+        &self.current.filter
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, SeedRotation};
+
+    #[test]
+    fn test_seed_rotation() {
+        let mut cf = SeedRotation::new(1, CuckooFilter::with_capacity_and_seed(64, 1));
+        for i in 0..100u8 {
+            cf.add(&[i]).unwrap();
+        }
+        assert!(cf
+            .rotate(2, CuckooFilter::with_capacity_and_seed(64, 1))
+            .is_none());
+        assert_eq!(cf.seed(), 2);
+        assert!(cf.is_rotating());
+        for i in 0..50u8 {
+            cf.add(&[i]).unwrap();
+        }
+        assert!((0..100u8).all(|i| cf.contains(&[i])));
+
+        let old = cf.finish().unwrap();
+        assert_eq!(old.size(), 100);
+        assert!(!cf.is_rotating());
+        assert!((0..50u8).all(|i| cf.contains(&[i])));
+        let stale = (50..100u8).filter(|&i| cf.contains(&[i])).count();
+        assert!(stale < 10, "{}", stale);
+
+        // The same keys land differently under a different seed.
+        let mut other = SeedRotation::new(3, CuckooFilter::with_capacity_and_seed(64, 1));
+        for i in 0..50u8 {
+            other.add(&[i]).unwrap();
+        }
+        assert_ne!(other.current().digest(), cf.current().digest());
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    #[test]
+    fn test_delete_reaches_both_generations() {
+        let mut cf = SeedRotation::new(1, CuckooFilter::new(100));
+        cf.add(b"a").unwrap();
+        cf.rotate(2, CuckooFilter::new(100));
+        cf.add(b"a").unwrap();
+        assert!(cf.delete(b"a"));
+        assert!(!cf.contains(b"a"));
+        assert!(!cf.delete(b"a"));
+    }
+}
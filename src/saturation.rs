@@ -0,0 +1,269 @@
+use crate::{
+    CResult, CuckooConfig, CuckooError, CuckooFilter, ExpiringFilter, GrowthPolicy,
+    ScalableCuckooFilter, SpillFilter,
+};
+use std::path::PathBuf;
+use std::time::Duration;
+use std::{error, fmt, io};
+
+/// What a `SaturatingFilter` does when an insert finds no slot.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SaturationPolicy {
+    /// Fail with `CuckooError::NotEnoughSpace`, like a plain `CuckooFilter`.
+    Error,
+    /// Keep two generations of `ttl` each, as `ExpiringFilter` does, and
+    /// rotate early when the current one is full, dropping the oldest keys.
+    EvictOldest { ttl: Duration },
+    /// Add sub-filters as `ScalableCuckooFilter` does, optionally up to
+    /// `max_capacity` slots in total.
+    Overflow {
+        growth: GrowthPolicy,
+        max_capacity: Option<usize>,
+    },
+    /// Append fingerprints that do not fit to the segment file at `path`, as
+    /// `SpillFilter` does.
+    Spill { path: PathBuf },
+}
+
+/// Why a `SaturatingFilter` rejected an insert.
+#[derive(Debug)]
+pub enum SaturationError {
+    Filter(CuckooError),
+    /// The spill segment could not be written.
+    Io(io::Error),
+}
+
+impl fmt::Display for SaturationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SaturationError::Filter(e) => e.fmt(f),
+            SaturationError::Io(e) => write!(f, "spill failed: {}", e),
+        }
+    }
+}
+
+impl error::Error for SaturationError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            SaturationError::Filter(e) => Some(e),
+            SaturationError::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<CuckooError> for SaturationError {
+    fn from(e: CuckooError) -> Self {
+        SaturationError::Filter(e)
+    }
+}
+
+impl From<io::Error> for SaturationError {
+    fn from(e: io::Error) -> Self {
+        SaturationError::Io(e)
+    }
+}
+
+type Builder = Box<dyn Fn() -> CuckooFilter + Send + Sync>;
+
+enum Backend {
+    Error(CuckooFilter),
+    EvictOldest(Box<ExpiringFilter<Builder>>),
+    Overflow(ScalableCuckooFilter),
+    Spill(SpillFilter),
+}
+
+/// A filter whose behavior at capacity is fixed by a `SaturationPolicy`.
+///
+/// Every policy is backed by the wrapper that implements it, so the choice
+/// is made once, at construction, and can be tested like any other setting.
+pub struct SaturatingFilter {
+    backend: Backend,
+    policy: SaturationPolicy,
+}
+
+impl SaturatingFilter {
+    /// Builds a filter from `config` with the given policy.
+    ///
+    /// Only `SaturationPolicy::Spill` can fail, when its segment cannot be
+    /// created.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, SaturatingFilter, SaturationPolicy};
+    /// let cf = SaturatingFilter::new(&CuckooConfig::new(100), SaturationPolicy::Error).unwrap();
+    /// ```
+    pub fn new(config: &CuckooConfig, policy: SaturationPolicy) -> io::Result<Self> {
+        let filter = CuckooFilter::from_config(config);
+        let backend = match &policy {
+            SaturationPolicy::Error => Backend::Error(filter),
+            SaturationPolicy::EvictOldest { ttl } => {
+                let config = *config;
+                let builder: Builder = Box::new(move || CuckooFilter::from_config(&config));
+                let cf = ExpiringFilter::new(*ttl, builder).keep_previous(true);
+                Backend::EvictOldest(Box::new(cf))
+            }
+            SaturationPolicy::Overflow {
+                growth,
+                max_capacity,
+            } => {
+                let mut cf = ScalableCuckooFilter::from_config(config).growth(*growth);
+                if let Some(max) = max_capacity {
+                    cf = cf.max_capacity(*max);
+                }
+                Backend::Overflow(cf)
+            }
+            SaturationPolicy::Spill { path } => Backend::Spill(SpillFilter::create(filter, path)?),
+        };
+        Ok(SaturatingFilter { backend, policy })
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, SaturatingFilter, SaturationPolicy};
+    /// let cf = SaturatingFilter::new(&CuckooConfig::new(100), SaturationPolicy::Error).unwrap();
+    /// assert_eq!(cf.policy(), &SaturationPolicy::Error);
+    /// ```
+    pub fn policy(&self) -> &SaturationPolicy {
+        &self.policy
+    }
+
+    /// Adds `item`, applying the policy if the filter is full.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, SaturatingFilter, SaturationPolicy};
+    /// let mut cf = SaturatingFilter::new(&CuckooConfig::new(100), SaturationPolicy::Error).unwrap();
+    /// cf.add(b"test").unwrap();
+    /// ```
+    pub fn add(&mut self, item: &[u8]) -> CResult<SaturationError> {
+        match &mut self.backend {
+            Backend::Error(cf) => Ok(cf.add(item)?),
+            Backend::EvictOldest(cf) => match cf.add(item) {
+                Err(CuckooError::NotEnoughSpace(_)) => {
+                    cf.rotate();
+                    Ok(cf.add(item)?)
+                }
+                result => Ok(result?),
+            },
+            Backend::Overflow(cf) => Ok(cf.add(item)?),
+            Backend::Spill(cf) => Ok(cf.add(item)?),
+        }
+    }
+
+    /// Returns `true` if `item` may be present.
+    ///
+    /// A spill segment that cannot be read answers `true`, so read errors
+    /// never cause false negatives.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, SaturatingFilter, SaturationPolicy};
+    /// let mut cf = SaturatingFilter::new(&CuckooConfig::new(100), SaturationPolicy::Error).unwrap();
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        match &self.backend {
+            Backend::Error(cf) => cf.contains(item),
+            Backend::EvictOldest(cf) => cf.contains(item),
+            Backend::Overflow(cf) => cf.contains(item),
+            Backend::Spill(cf) => cf.contains(item).unwrap_or(true),
+        }
+    }
+
+    /// Returns the number of stored items; for `EvictOldest`, only those of
+    /// the current generation.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, SaturatingFilter, SaturationPolicy};
+    /// let cf = SaturatingFilter::new(&CuckooConfig::new(100), SaturationPolicy::Error).unwrap();
+    /// assert_eq!(cf.size(), 0);
+    /// ```
+    pub fn size(&self) -> usize {
+        match &self.backend {
+            Backend::Error(cf) => cf.size(),
+            Backend::EvictOldest(cf) => cf.current().size(),
+            Backend::Overflow(cf) => cf.size(),
+            Backend::Spill(cf) => cf.size(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        CuckooConfig, CuckooError, GrowthPolicy, SaturatingFilter, SaturationError,
+        SaturationPolicy,
+    };
+    use std::time::Duration;
+    use std::{env, fs, process};
+
+    fn fill(cf: &mut SaturatingFilter) -> (usize, usize) {
+        let results = (0..2000u16)
+            .map(|i| cf.add(&i.to_le_bytes()))
+            .collect::<Vec<_>>();
+        let failed = results.iter().filter(|r| r.is_err()).count();
+        (results.len() - failed, failed)
+    }
+
+    #[test]
+    fn test_policies_at_capacity() {
+        let config = CuckooConfig::new(100).seed(1);
+
+        let mut error = SaturatingFilter::new(&config, SaturationPolicy::Error).unwrap();
+        let (_, failed) = fill(&mut error);
+        assert!(failed > 0);
+        assert!(matches!(
+            error.add(b"one more"),
+            Err(SaturationError::Filter(_))
+        ));
+
+        let evict = SaturationPolicy::EvictOldest {
+            ttl: Duration::from_secs(3600),
+        };
+        let mut evict = SaturatingFilter::new(&config, evict).unwrap();
+        assert_eq!(fill(&mut evict).1, 0);
+        assert!(evict.contains(&1999u16.to_le_bytes()));
+        assert!(evict.size() < 2000);
+        let found = (0..2000u16)
+            .filter(|i| evict.contains(&i.to_le_bytes()))
+            .count();
+        assert!(found > evict.size());
+
+        let overflow = SaturationPolicy::Overflow {
+            growth: GrowthPolicy::Doubling,
+            max_capacity: None,
+        };
+        let mut overflow = SaturatingFilter::new(&config, overflow).unwrap();
+        assert_eq!(fill(&mut overflow).1, 0);
+        assert_eq!(overflow.size(), 2000);
+
+        let path = env::temp_dir().join(format!("dakv_cuckoo_saturation_{}", process::id()));
+        let spill = SaturationPolicy::Spill { path: path.clone() };
+        let mut spill = SaturatingFilter::new(&config, spill).unwrap();
+        assert_eq!(fill(&mut spill).1, 0);
+        assert!((0..2000u16).all(|i| spill.contains(&i.to_le_bytes())));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_overflow_keeps_config() {
+        let config = CuckooConfig::new(100).seed(1).max_duplicates(1);
+        let overflow = SaturationPolicy::Overflow {
+            growth: GrowthPolicy::Doubling,
+            max_capacity: None,
+        };
+        let mut cf = SaturatingFilter::new(&config, overflow).unwrap();
+        for i in 0..2000u16 {
+            match cf.add(&i.to_le_bytes()) {
+                Ok(()) | Err(SaturationError::Filter(CuckooError::TooManyDuplicates(_))) => {}
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert!(matches!(
+            cf.add(&1999u16.to_le_bytes()),
+            Err(SaturationError::Filter(CuckooError::TooManyDuplicates(_)))
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+use crate::bucket::BUCKET_SIZE;
+use crate::{CResult, CuckooConfig, CuckooError, CuckooFilter};
+
+/// How much capacity each new sub-filter of a `ScalableCuckooFilter` adds.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GrowthPolicy {
+    /// Every sub-filter has as many slots as all previous ones together, so
+    /// the total doubles.
+    #[default]
+    Doubling,
+    /// Every sub-filter grows the total by `factor`, e.g. `1.5`.
+    ///
+    /// Sub-filters still get a power-of-two bucket count, rounded up, so
+    /// factors below two grow in uneven steps.
+    Factor(f64),
+}
+
+/// A filter that grows by adding sub-filters when the newest one is full.
+///
+/// Lookups probe every sub-filter, so the false-positive rate rises with
+/// each one added. With a capacity ceiling, growth stops once the sub-filters
+/// hold that many slots in total and inserts start failing again.
+pub struct ScalableCuckooFilter {
+    config: CuckooConfig,
+    filters: Vec<CuckooFilter>,
+    policy: GrowthPolicy,
+    max_capacity: Option<usize>,
+}
+
+impl ScalableCuckooFilter {
+    /// Creates a filter whose first sub-filter is sized for `initial_keys`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ScalableCuckooFilter;
+    /// let cf = ScalableCuckooFilter::new(1000);
+    /// assert_eq!(cf.num_filters(), 1);
+    /// ```
+    pub fn new(initial_keys: u64) -> Self {
+        Self::from_config(&CuckooConfig::new(initial_keys))
+    }
+
+    /// Creates a filter whose first sub-filter is sized for
+    /// `config.max_num_keys`. Every sub-filter takes the seed, duplicate
+    /// limit, kick limit and eviction policy of `config`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, ScalableCuckooFilter};
+    /// let cf = ScalableCuckooFilter::from_config(&CuckooConfig::new(1000).seed(42));
+    /// assert_eq!(cf.num_filters(), 1);
+    /// ```
+    pub fn from_config(config: &CuckooConfig) -> Self {
+        ScalableCuckooFilter {
+            config: *config,
+            filters: vec![CuckooFilter::from_config(config)],
+            policy: GrowthPolicy::default(),
+            max_capacity: None,
+        }
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{GrowthPolicy, ScalableCuckooFilter};
+    /// let cf = ScalableCuckooFilter::new(1000).growth(GrowthPolicy::Factor(1.5));
+    /// ```
+    pub fn growth(mut self, policy: GrowthPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Stops growing once the sub-filters hold `slots` slots in total.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ScalableCuckooFilter;
+    /// let cf = ScalableCuckooFilter::new(1000).max_capacity(1 << 20);
+    /// ```
+    pub fn max_capacity(mut self, slots: usize) -> Self {
+        self.max_capacity = Some(slots);
+        self
+    }
+
+    /// Adds `item`, growing first if the newest sub-filter is full.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ScalableCuckooFilter;
+    /// let mut cf = ScalableCuckooFilter::new(10);
+    /// for i in 0..100u8 {
+    ///     cf.add(&[i]).unwrap();
+    /// }
+    /// assert!(cf.num_filters() > 1);
+    /// ```
+    // `filters` starts with one filter and only grows.
+    #[allow(clippy::indexing_slicing)]
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        let last = self.filters.len() - 1;
+        match self.filters[last].add(item) {
+            Err(CuckooError::NotEnoughSpace(p)) => match self.grow() {
+                Some(filter) => filter.add(item),
+                None => Err(CuckooError::NotEnoughSpace(p)),
+            },
+            result => result,
+        }
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ScalableCuckooFilter;
+    /// let mut cf = ScalableCuckooFilter::new(10);
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        self.filters.iter().any(|f| f.contains(item))
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ScalableCuckooFilter;
+    /// let mut cf = ScalableCuckooFilter::new(10);
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.delete(b"test"));
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        self.filters.iter_mut().rev().any(|f| f.delete(item))
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ScalableCuckooFilter;
+    /// let cf = ScalableCuckooFilter::new(10);
+    /// assert_eq!(cf.size(), 0);
+    /// ```
+    pub fn size(&self) -> usize {
+        self.filters.iter().map(CuckooFilter::size).sum()
+    }
+
+    /// Returns the total number of slots over all sub-filters.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ScalableCuckooFilter;
+    /// let cf = ScalableCuckooFilter::new(100);
+    /// assert_eq!(cf.capacity(), 128);
+    /// ```
+    pub fn capacity(&self) -> usize {
+        self.filters.iter().map(CuckooFilter::capacity).sum()
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ScalableCuckooFilter;
+    /// let cf = ScalableCuckooFilter::new(100);
+    /// assert_eq!(cf.num_filters(), 1);
+    /// ```
+    pub fn num_filters(&self) -> usize {
+        self.filters.len()
+    }
+
+    /// Appends the next sub-filter, shrunk to fit under the ceiling, or
+    /// returns `None` if not even one bucket fits or it cannot be allocated.
+    fn grow(&mut self) -> Option<&mut CuckooFilter> {
+        let total = self.capacity() / BUCKET_SIZE;
+        let added = match self.policy {
+            GrowthPolicy::Doubling => total,
+            // Float-to-int casts saturate, so NaN or huge factors stay in range.
+            GrowthPolicy::Factor(factor) => (total as f64 * (factor - 1.0)).ceil() as usize,
+        };
+        let mut buckets = added
+            .max(1)
+            .checked_next_power_of_two()
+            .unwrap_or(1 << (usize::BITS - 1));
+        if let Some(max) = self.max_capacity {
+            let room = max.saturating_sub(self.capacity()) / BUCKET_SIZE;
+            if room == 0 {
+                return None;
+            }
+            while buckets > room {
+                buckets /= 2;
+            }
+        }
+        let filter = CuckooFilter::try_from_config_with_capacity(&self.config, buckets).ok()?;
+        self.filters.push(filter);
+        self.filters.last_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{GrowthPolicy, ScalableCuckooFilter};
+
+    #[test]
+    fn test_growth_policies() {
+        let keys = (0..2000u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        let mut doubling = ScalableCuckooFilter::new(100);
+        let mut slow = ScalableCuckooFilter::new(100).growth(GrowthPolicy::Factor(1.5));
+        for k in &keys {
+            doubling.add(k).unwrap();
+            slow.add(k).unwrap();
+        }
+        assert!(slow.num_filters() > doubling.num_filters());
+        for k in &keys {
+            assert!(doubling.contains(k) && slow.contains(k));
+        }
+    }
+
+    #[test]
+    fn test_ceiling() {
+        let mut cf = ScalableCuckooFilter::new(100).max_capacity(300);
+        let added = (0..1000u16)
+            .filter(|i| cf.add(&i.to_le_bytes()).is_ok())
+            .count();
+        assert!(cf.capacity() <= 300);
+        assert_eq!(cf.size(), added);
+        assert!(added < 300);
+    }
+}
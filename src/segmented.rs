@@ -0,0 +1,313 @@
+// Bucket indices are masked to the reachable buckets, which the segments
+// always cover, and split into a segment number and an index masked to the
+// segment length.
+#![allow(clippy::indexing_slicing)]
+
+use crate::bucket::{Bucket, BUCKET_SIZE};
+use crate::cuckoo_filter::{gen_size, CResult, CuckooError, MAX_CUCKOO_COUNT};
+use crate::telemetry;
+use crate::util::{get_alt_index, get_indices_from_hash, hash_key, FingerIndex};
+use parking_lot::RwLock;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// Attempts at finding and applying a kick path before giving up, since a
+// concurrent writer can invalidate a path between search and application.
+const MAX_PATH_ATTEMPTS: usize = 8;
+
+type Segment = Box<[Bucket]>;
+
+/// A filter whose bucket array is split into segments with one read-write
+/// lock each.
+///
+/// Lookups read-lock the (at most two) segments holding their buckets, so
+/// readers only wait for writers touching the same segments. Evictions first
+/// search a kick path without holding any write lock, then apply it backwards
+/// one move at a time, each under the locks of just the two segments it
+/// touches. Every move copies a fingerprint into its alternate bucket before
+/// freeing the old slot while both are locked, so readers never miss an item
+/// that is being moved. Segments are always locked in index order, which
+/// keeps this deadlock free.
+pub struct SegmentedCuckooFilter {
+    segments: Box<[RwLock<Segment>]>,
+    pow: usize,
+    // log2 of the number of buckets per segment.
+    shift: usize,
+    size: AtomicUsize,
+}
+
+impl SegmentedCuckooFilter {
+    /// Creates a filter for `max_num_keys` keys split into about
+    /// `num_segments` segments.
+    ///
+    /// The segment count is rounded up to a power of two and capped at the
+    /// number of buckets.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::SegmentedCuckooFilter;
+    /// let cf = SegmentedCuckooFilter::new(1000, 16);
+    /// assert_eq!(cf.num_segments(), 16);
+    /// ```
+    pub fn new(max_num_keys: u64, num_segments: usize) -> Self {
+        let num_buckets = gen_size(max_num_keys) as usize;
+        let num_segments = num_segments.max(1).next_power_of_two().min(num_buckets);
+        let per_segment = num_buckets / num_segments;
+        let segments = (0..num_segments)
+            .map(|_| RwLock::new(vec![Bucket::new(); per_segment].into_boxed_slice()))
+            .collect::<Vec<_>>();
+        SegmentedCuckooFilter {
+            segments: segments.into_boxed_slice(),
+            pow: num_buckets.trailing_zeros() as usize,
+            shift: per_segment.trailing_zeros() as usize,
+            size: AtomicUsize::new(0),
+        }
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::SegmentedCuckooFilter;
+    /// let cf = SegmentedCuckooFilter::new(1000, 4);
+    /// assert_eq!(cf.num_segments(), 4);
+    /// ```
+    pub fn num_segments(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::SegmentedCuckooFilter;
+    /// let cf = SegmentedCuckooFilter::new(1000, 4);
+    /// cf.add(b"test");
+    /// ```
+    pub fn add(&self, item: &[u8]) -> CResult<CuckooError> {
+        let item_hash = hash_key(item);
+        let finger = get_indices_from_hash(item_hash, self.pow);
+        let (i1, i2) = (finger.i1 as usize, finger.i2 as usize);
+        let mut seed = item_hash | 1;
+        for _ in 0..MAX_PATH_ATTEMPTS {
+            {
+                let mut pair = self.write_pair(i1, i2);
+                if pair.bucket_mut(i1).insert(finger.fp) || pair.bucket_mut(i2).insert(finger.fp) {
+                    self.size.fetch_add(1, Ordering::Relaxed);
+                    return Ok(());
+                }
+            }
+            // Neither bucket has room: free a slot in one of them and retry.
+            if let Some(path) = self.find_path(&finger, &mut seed) {
+                self.apply_path(&path);
+            }
+        }
+        Err(telemetry::record(CuckooError::NotEnoughSpace(
+            finger.placement(),
+        )))
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::SegmentedCuckooFilter;
+    /// let cf = SegmentedCuckooFilter::new(1000, 4);
+    /// cf.add(b"test");
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let finger = get_indices_from_hash(hash_key(item), self.pow);
+        let (i1, i2) = (finger.i1 as usize, finger.i2 as usize);
+        let pair = self.read_pair(i1, i2);
+        pair.bucket(i1).get_fingerprint_index(finger.fp).is_some()
+            || pair.bucket(i2).get_fingerprint_index(finger.fp).is_some()
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::SegmentedCuckooFilter;
+    /// let cf = SegmentedCuckooFilter::new(1000, 4);
+    /// cf.add(b"test");
+    /// assert!(cf.delete(b"test"));
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&self, item: &[u8]) -> bool {
+        let finger = get_indices_from_hash(hash_key(item), self.pow);
+        let (i1, i2) = (finger.i1 as usize, finger.i2 as usize);
+        let mut pair = self.write_pair(i1, i2);
+        let removed =
+            pair.bucket_mut(i1).delete(finger.fp) || pair.bucket_mut(i2).delete(finger.fp);
+        if removed {
+            self.size.fetch_sub(1, Ordering::Relaxed);
+        }
+        removed
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::SegmentedCuckooFilter;
+    /// let cf = SegmentedCuckooFilter::new(1000, 4);
+    /// cf.add(b"test");
+    /// assert_eq!(cf.size(), 1);
+    /// ```
+    pub fn size(&self) -> usize {
+        self.size.load(Ordering::Relaxed)
+    }
+
+    /// Walks a random kick chain from one of `finger`'s buckets until it
+    /// reaches a bucket with a free slot, holding only short read locks.
+    ///
+    /// Returns the `(bucket, slot, fingerprint)` of every move, in chain order.
+    fn find_path(&self, finger: &FingerIndex, seed: &mut u64) -> Option<Vec<(usize, usize, u8)>> {
+        let mut path = Vec::new();
+        let mut i = if next_random(seed) & 1 == 0 {
+            finger.i1
+        } else {
+            finger.i2
+        } as usize;
+        for _ in 0..MAX_CUCKOO_COUNT {
+            let slot = next_random(seed) as usize % BUCKET_SIZE;
+            let bucket = self.segments[i >> self.shift].read()[i & self.bucket_mask()];
+            if !bucket.is_occupied(slot) {
+                // Freed since the caller found both buckets full.
+                return Some(path);
+            }
+            let fp = bucket[slot];
+            path.push((i, slot, fp));
+            i = get_alt_index(fp, i as u64, self.pow) as usize;
+            let segment = self.segments[i >> self.shift].read();
+            if !segment[i & self.bucket_mask()].is_full() {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    /// Applies the moves of `path` from the last to the first, so that every
+    /// move goes into a slot that is already free.
+    ///
+    /// Stops at the first move a concurrent writer has invalidated; all moves
+    /// applied until then are valid on their own.
+    fn apply_path(&self, path: &[(usize, usize, u8)]) {
+        for &(from, slot, fp) in path.iter().rev() {
+            let to = get_alt_index(fp, from as u64, self.pow) as usize;
+            let mut pair = self.write_pair(from, to);
+            let from_bucket = pair.bucket(from);
+            if !from_bucket.is_occupied(slot) || from_bucket[slot] != fp {
+                return;
+            }
+            if !pair.bucket_mut(to).insert(fp) {
+                return;
+            }
+            pair.bucket_mut(from).take(slot);
+        }
+    }
+
+    fn bucket_mask(&self) -> usize {
+        (1 << self.shift) - 1
+    }
+
+    fn read_pair(&self, a: usize, b: usize) -> Pair<impl Deref<Target = Segment> + '_> {
+        self.pair(a, b, |s| s.read())
+    }
+
+    fn write_pair(&self, a: usize, b: usize) -> Pair<impl DerefMut<Target = Segment> + '_> {
+        self.pair(a, b, |s| s.write())
+    }
+
+    /// Locks the segments of buckets `a` and `b` in segment order.
+    fn pair<'a, G, F>(&'a self, a: usize, b: usize, lock: F) -> Pair<G>
+    where
+        F: Fn(&'a RwLock<Segment>) -> G,
+    {
+        let (sa, sb) = (a >> self.shift, b >> self.shift);
+        let (lo, hi) = (sa.min(sb), sa.max(sb));
+        let first = lock(&self.segments[lo]);
+        let second = if hi == lo {
+            None
+        } else {
+            Some(lock(&self.segments[hi]))
+        };
+        Pair {
+            first: (lo, first),
+            second,
+            shift: self.shift,
+        }
+    }
+}
+
+/// The locked segments of a bucket pair.
+struct Pair<G> {
+    first: (usize, G),
+    second: Option<G>,
+    shift: usize,
+}
+
+impl<G: Deref<Target = Segment>> Pair<G> {
+    fn bucket(&self, i: usize) -> &Bucket {
+        let segment = match &self.second {
+            Some(second) if i >> self.shift != self.first.0 => second,
+            _ => &self.first.1,
+        };
+        &segment[i & ((1 << self.shift) - 1)]
+    }
+}
+
+impl<G: DerefMut<Target = Segment>> Pair<G> {
+    fn bucket_mut(&mut self, i: usize) -> &mut Bucket {
+        let segment = match &mut self.second {
+            Some(second) if i >> self.shift != self.first.0 => second,
+            _ => &mut self.first.1,
+        };
+        &mut segment[i & ((1 << self.shift) - 1)]
+    }
+}
+
+/// xorshift64, enough to pick kick slots without a shared generator.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::SegmentedCuckooFilter;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_fills_like_cuckoo_filter() {
+        let cf = SegmentedCuckooFilter::new(1000, 8);
+        let keys = (0..900u16).map(|i| i.to_le_bytes()).collect::<Vec<_>>();
+        for k in &keys {
+            cf.add(k).unwrap();
+        }
+        assert_eq!(cf.size(), keys.len());
+        for k in &keys {
+            assert!(cf.contains(k));
+        }
+    }
+
+    #[test]
+    fn test_concurrent_writers_and_readers() {
+        let cf = Arc::new(SegmentedCuckooFilter::new(4000, 8));
+        let handles = (0..4u8)
+            .map(|t| {
+                let cf = Arc::clone(&cf);
+                thread::spawn(move || {
+                    for i in 0..800u16 {
+                        let key = [t, i as u8, (i >> 8) as u8];
+                        cf.add(&key).unwrap();
+                        assert!(cf.contains(&key));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(cf.size(), 3200);
+        for t in 0..4u8 {
+            for i in 0..800u16 {
+                assert!(cf.contains(&[t, i as u8, (i >> 8) as u8]));
+            }
+        }
+    }
+}
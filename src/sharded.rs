@@ -0,0 +1,212 @@
+use crate::cuckoo_filter::{CResult, CuckooError};
+use crate::util::hash_key;
+use crate::CuckooFilter;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::{panic, thread};
+
+/// A filter split into independently locked shards.
+///
+/// Each key is routed to one shard by the high half of its hash, so writers
+/// working on different shards never contend.
+pub struct ShardedCuckooFilter {
+    shards: Box<[Mutex<CuckooFilter>]>,
+}
+
+impl ShardedCuckooFilter {
+    /// Creates `num_shards` shards sharing `max_num_keys` between them.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ShardedCuckooFilter;
+    /// let cf = ShardedCuckooFilter::new(4, 1000);
+    /// ```
+    pub fn new(num_shards: usize, max_num_keys: u64) -> Self {
+        let (num_shards, per_shard) = split(num_shards, max_num_keys);
+        ShardedCuckooFilter {
+            shards: (0..num_shards)
+                .map(|_| Mutex::new(CuckooFilter::new(per_shard)))
+                .collect(),
+        }
+    }
+
+    /// Like `new`, but allocates every shard on its own thread after calling
+    /// `bind(shard_index)` there.
+    ///
+    /// `bind` is expected to pin the calling thread to a NUMA node (e.g. with
+    /// `sched_setaffinity`); the shard's memory is then first touched from
+    /// that node, so the kernel places its pages locally. Worker threads
+    /// serving a shard should call the same hook with the index returned by
+    /// `shard_of`.
+    ///
+    /// A shard that cannot be allocated is `AllocationFailed`. A panic in
+    /// `bind` is resumed on the calling thread.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ShardedCuckooFilter;
+    /// let cf = ShardedCuckooFilter::with_affinity(2, 1000, |shard| {
+    ///     println!("binding shard {} to node {}", shard, shard % 2);
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn with_affinity<F>(
+        num_shards: usize,
+        max_num_keys: u64,
+        bind: F,
+    ) -> Result<Self, CuckooError>
+    where
+        F: Fn(usize) + Sync,
+    {
+        let (num_shards, per_shard) = split(num_shards, max_num_keys);
+        let bind = &bind;
+        let shards = thread::scope(|s| {
+            let handles = (0..num_shards)
+                .map(|shard| {
+                    s.spawn(move || {
+                        bind(shard);
+                        CuckooFilter::try_new(per_shard).map(Mutex::new)
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect::<Result<Vec<_>, _>>()
+        })?;
+        Ok(ShardedCuckooFilter {
+            shards: shards.into_boxed_slice(),
+        })
+    }
+
+    /// Returns the index of the shard responsible for `item`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ShardedCuckooFilter;
+    /// let cf = ShardedCuckooFilter::new(4, 1000);
+    /// assert!(cf.shard_of(b"test") < cf.num_shards());
+    /// ```
+    pub fn shard_of(&self, item: &[u8]) -> usize {
+        self.shard_index(hash_key(item))
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ShardedCuckooFilter;
+    /// let cf = ShardedCuckooFilter::new(4, 1000);
+    /// assert_eq!(cf.num_shards(), 4);
+    /// ```
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ShardedCuckooFilter;
+    /// let cf = ShardedCuckooFilter::new(4, 1000);
+    /// cf.add(b"test");
+    /// ```
+    pub fn add(&self, item: &[u8]) -> CResult<CuckooError> {
+        let item_hash = hash_key(item);
+        let mut shard = self.lock(item_hash);
+        let finger = shard.finger(item_hash);
+        shard.add_finger(&finger)
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ShardedCuckooFilter;
+    /// let cf = ShardedCuckooFilter::new(4, 1000);
+    /// cf.add(b"test");
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let item_hash = hash_key(item);
+        let shard = self.lock(item_hash);
+        shard.contains_finger(&shard.finger(item_hash))
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ShardedCuckooFilter;
+    /// let cf = ShardedCuckooFilter::new(4, 1000);
+    /// cf.add(b"test");
+    /// assert!(cf.delete(b"test"));
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&self, item: &[u8]) -> bool {
+        let item_hash = hash_key(item);
+        let mut shard = self.lock(item_hash);
+        let finger = shard.finger(item_hash);
+        shard.delete_finger(&finger)
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::ShardedCuckooFilter;
+    /// let cf = ShardedCuckooFilter::new(4, 1000);
+    /// println!("size: {}", cf.size());
+    /// ```
+    pub fn size(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|s| s.lock().unwrap_or_else(PoisonError::into_inner).size())
+            .sum()
+    }
+
+    fn shard_index(&self, item_hash: u64) -> usize {
+        ((item_hash >> 32) % self.shards.len() as u64) as usize
+    }
+
+    // `shard_index` is reduced modulo the number of shards.
+    #[allow(clippy::indexing_slicing)]
+    fn lock(&self, item_hash: u64) -> MutexGuard<'_, CuckooFilter> {
+        self.shards[self.shard_index(item_hash)]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Returns the number of shards, at least one, and the keys each is sized for.
+fn split(num_shards: usize, max_num_keys: u64) -> (usize, u64) {
+    let num_shards = num_shards.max(1);
+    (
+        num_shards,
+        (max_num_keys / num_shards as u64).saturating_add(1),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooError, ShardedCuckooFilter};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_affinity_hook() {
+        let bound = AtomicUsize::new(0);
+        let cf = ShardedCuckooFilter::with_affinity(3, 300, |shard| {
+            bound.fetch_or(1 << shard, Ordering::SeqCst);
+        })
+        .unwrap();
+        assert_eq!(bound.load(Ordering::SeqCst), 0b111);
+        assert_eq!(cf.num_shards(), 3);
+    }
+
+    #[test]
+    fn test_affinity_allocation_failure() {
+        assert!(matches!(
+            ShardedCuckooFilter::with_affinity(2, u64::MAX, |_| {}),
+            Err(CuckooError::AllocationFailed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_add_contains() {
+        let cf = ShardedCuckooFilter::new(4, 1000);
+        for i in 0..100u8 {
+            assert!(cf.add(&[i]).is_ok());
+        }
+        assert_eq!(cf.size(), 100);
+        assert!(cf.contains(&[7]));
+    }
+}
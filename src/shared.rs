@@ -0,0 +1,141 @@
+use crate::CuckooFilter;
+use std::mem;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+
+/// A cheaply clonable, thread-safe handle around a `CuckooFilter`.
+///
+/// Readers work on an immutable snapshot; writers apply whole batches to a
+/// private copy which is swapped in atomically, so a reader never observes
+/// half of a batch.
+///
+/// Taking a snapshot is not lock-free: it holds a read lock for as long as it
+/// takes to clone an `Arc`. Writers hold the write lock only to swap that
+/// `Arc`, never while applying a batch or freeing the replaced filter, so a
+/// reader waits at most for one pointer store.
+///
+/// The filter lives on the process heap, not in shared memory, so it cannot
+/// be handed to another process as a file descriptor; a successor process
+/// has to rebuild or deserialize it.
+#[derive(Clone)]
+pub struct SharedCuckooFilter {
+    current: Arc<RwLock<Arc<CuckooFilter>>>,
+    writer: Arc<Mutex<()>>,
+}
+
+impl SharedCuckooFilter {
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SharedCuckooFilter};
+    /// let shared = SharedCuckooFilter::new(CuckooFilter::new(100));
+    /// ```
+    pub fn new(filter: CuckooFilter) -> Self {
+        SharedCuckooFilter {
+            current: Arc::new(RwLock::new(Arc::new(filter))),
+            writer: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Returns the filter as of the last completed `update`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SharedCuckooFilter};
+    /// let shared = SharedCuckooFilter::new(CuckooFilter::new(100));
+    /// let snapshot = shared.snapshot();
+    /// assert_eq!(snapshot.size(), 0);
+    /// ```
+    pub fn snapshot(&self) -> Arc<CuckooFilter> {
+        let guard = self.current.read().unwrap_or_else(PoisonError::into_inner);
+        Arc::clone(&guard)
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SharedCuckooFilter};
+    /// let shared = SharedCuckooFilter::new(CuckooFilter::new(100));
+    /// shared.update(|f| f.add(b"test").is_ok());
+    /// assert!(shared.contains(b"test"));
+    /// ```
+    pub fn contains(&self, data: &[u8]) -> bool {
+        self.snapshot().contains(data)
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SharedCuckooFilter};
+    /// let shared = SharedCuckooFilter::new(CuckooFilter::new(100));
+    /// println!("size: {}", shared.size());
+    /// ```
+    pub fn size(&self) -> usize {
+        self.snapshot().size()
+    }
+
+    /// Applies a batch of mutations to a copy of the current filter and
+    /// publishes the result once `f` returns.
+    ///
+    /// Concurrent updates are serialized; readers keep seeing the previous
+    /// snapshot until the batch is published.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SharedCuckooFilter};
+    /// let shared = SharedCuckooFilter::new(CuckooFilter::new(100));
+    /// let handle = shared.clone();
+    /// handle.update(|f| {
+    ///     let _ = f.add(b"a");
+    ///     let _ = f.add(b"b");
+    /// });
+    /// assert_eq!(shared.size(), 2);
+    /// ```
+    pub fn update<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&mut CuckooFilter) -> R,
+    {
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut next = CuckooFilter::clone(&self.snapshot());
+        let result = f(&mut next);
+        let next = Arc::new(next);
+        let previous = {
+            let mut guard = self.current.write().unwrap_or_else(PoisonError::into_inner);
+            mem::replace(&mut *guard, next)
+        };
+        // The last reference may go here, so free it outside the lock.
+        drop(previous);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, SharedCuckooFilter};
+    use std::thread;
+
+    #[test]
+    fn test_snapshot_isolation() {
+        let shared = SharedCuckooFilter::new(CuckooFilter::new(100));
+        let before = shared.snapshot();
+        shared.update(|f| f.add(b"test").is_ok());
+        assert!(!before.contains(b"test"));
+        assert!(shared.contains(b"test"));
+        assert_eq!(shared.size(), 1);
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        let shared = SharedCuckooFilter::new(CuckooFilter::new(1000));
+        let handles = (0..4u8)
+            .map(|t| {
+                let shared = shared.clone();
+                thread::spawn(move || {
+                    for i in 0..10u8 {
+                        shared.update(|f| f.add(&[t, i]).is_ok());
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(shared.size(), 40);
+    }
+}
@@ -0,0 +1,89 @@
+//! Synthetic workloads for capacity planning.
+use crate::{CuckooConfig, CuckooFilter};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::BTreeMap;
+
+/// Number of absent keys probed to estimate the false-positive rate.
+pub const FPR_PROBES: usize = 10_000;
+
+/// Outcome of `fill_to_failure`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimReport {
+    /// Keys inserted before the first failure.
+    pub inserted: usize,
+    /// Total number of slots in the filter.
+    pub capacity: usize,
+    /// `inserted / capacity` at the point of failure.
+    pub load_factor: f64,
+    /// Maps a kick count to the number of inserts that needed that many kicks.
+    pub kick_histogram: BTreeMap<usize, usize>,
+    /// Fraction of `FPR_PROBES` absent keys reported as present.
+    pub false_positive_rate: f64,
+}
+
+/// Inserts random keys into a filter built from `config` until an insert fails.
+///
+/// The keys are drawn from `rng_seed`, which also seeds evictions unless
+/// `config.seed` is set, so a given pair of arguments always yields the same
+/// report.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::simulate::fill_to_failure;
+/// use dakv_cuckoo::CuckooConfig;
+///
+/// let report = fill_to_failure(&CuckooConfig::new(1000), 42);
+/// assert!(report.load_factor > 0.9);
+/// ```
+pub fn fill_to_failure(config: &CuckooConfig, rng_seed: u64) -> SimReport {
+    let mut config = *config;
+    config.seed = config.seed.or(Some(rng_seed));
+    let mut cf = CuckooFilter::from_config(&config);
+    let mut rng = StdRng::seed_from_u64(rng_seed);
+    let mut kick_histogram = BTreeMap::new();
+
+    let mut inserted = 0;
+    while let Ok(kicks) = cf.place(&cf.finger_of(&key(0, rng.gen()))) {
+        *kick_histogram.entry(kicks).or_insert(0) += 1;
+        inserted += 1;
+    }
+
+    let false_positives = (0..FPR_PROBES)
+        .filter(|_| cf.contains(&key(1, rng.gen())))
+        .count();
+    SimReport {
+        inserted,
+        capacity: cf.capacity(),
+        load_factor: inserted as f64 / cf.capacity() as f64,
+        kick_histogram,
+        false_positive_rate: false_positives as f64 / FPR_PROBES as f64,
+    }
+}
+
+/// Tags keys so inserted and probed keys never collide.
+fn key(tag: u8, n: u64) -> [u8; 9] {
+    let mut k = [tag; 9];
+    k[1..].copy_from_slice(&n.to_le_bytes());
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulate::fill_to_failure;
+    use crate::CuckooConfig;
+
+    #[test]
+    fn test_fill_to_failure() {
+        let config = CuckooConfig::new(1000);
+        let report = fill_to_failure(&config, 7);
+        assert_eq!(report.capacity, 2048);
+        assert_eq!(
+            report.kick_histogram.values().sum::<usize>(),
+            report.inserted
+        );
+        assert!(report.load_factor > 0.9);
+        assert!(report.false_positive_rate < 0.1);
+        assert_eq!(report, fill_to_failure(&config, 7));
+    }
+}
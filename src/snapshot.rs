@@ -0,0 +1,212 @@
+#![cfg_attr(not(test), deny(clippy::indexing_slicing))]
+//! Snapshot files: a filter together with metadata describing it.
+//!
+//! A snapshot starts with a `SNAPSHOT_HEADER_LEN`-byte header: the magic
+//! `DKCS`, a version byte, three reserved bytes and the metadata length as
+//! u64. The metadata follows: the creation time in seconds since the Unix
+//! epoch, the item count, the capacity in slots and the hash seed as u64,
+//! the fingerprint width as u8, then the label length as u16 and the UTF-8
+//! label. Readers skip metadata beyond the fields they know, so fields can
+//! be appended without a new version. The filter follows in the `format`
+//! layout. All integers are little endian.
+use crate::CuckooFilter;
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The magic bytes every snapshot starts with.
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"DKCS";
+
+/// The snapshot layout version described above.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Length of the snapshot header in bytes.
+pub const SNAPSHOT_HEADER_LEN: usize = 16;
+
+// Length of the metadata fields before the label
+const FIXED_META_LEN: usize = 4 * 8 + 1 + 2;
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+/// The metadata of a snapshot file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotInfo {
+    /// When the snapshot was written, to the second.
+    pub created: SystemTime,
+    /// Number of items in the filter.
+    pub size: u64,
+    /// Number of fingerprint slots in the filter.
+    pub capacity: u64,
+    pub fingerprint_bits: u8,
+    /// The seed keys were hashed under before reaching the filter, such as
+    /// `SeedRotation::seed`, or 0 if they were added directly.
+    pub hash_seed: u64,
+    pub label: String,
+}
+
+impl SnapshotInfo {
+    /// Reads the metadata of the snapshot at `path` without reading the
+    /// filter, so stale snapshots can be found cheaply.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use dakv_cuckoo::SnapshotInfo;
+    ///
+    /// let info = SnapshotInfo::peek("/var/lib/filters/users.dkcs").unwrap();
+    /// println!("{}: {} items, written {:?}", info.label, info.size, info.created);
+    /// ```
+    pub fn peek<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::read_from(&mut BufReader::new(File::open(path)?))
+    }
+
+    fn of(filter: &CuckooFilter, label: &str, hash_seed: u64) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        SnapshotInfo {
+            created: UNIX_EPOCH + Duration::from_secs(now.as_secs()),
+            size: filter.size() as u64,
+            capacity: filter.capacity() as u64,
+            fingerprint_bits: filter.fingerprint_bits() as u8,
+            hash_seed,
+            label: label.to_string(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let created = self
+            .created
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let mut meta = Vec::with_capacity(FIXED_META_LEN + self.label.len());
+        for word in &[created, self.size, self.capacity, self.hash_seed] {
+            meta.extend_from_slice(&word.to_le_bytes());
+        }
+        meta.push(self.fingerprint_bits);
+        meta.extend_from_slice(&(self.label.len() as u16).to_le_bytes());
+        meta.extend_from_slice(self.label.as_bytes());
+
+        let mut out = Vec::with_capacity(SNAPSHOT_HEADER_LEN + meta.len());
+        out.extend_from_slice(&SNAPSHOT_MAGIC);
+        out.extend_from_slice(&[SNAPSHOT_VERSION, 0, 0, 0]);
+        out.extend_from_slice(&(meta.len() as u64).to_le_bytes());
+        out.extend_from_slice(&meta);
+        out
+    }
+
+    /// Reads the header and metadata, leaving `r` at the filter.
+    fn read_from<R: Read>(r: &mut R) -> io::Result<Self> {
+        let mut header = [0; SNAPSHOT_HEADER_LEN];
+        r.read_exact(&mut header)?;
+        let (magic, rest) = header.split_first_chunk::<4>().unwrap_or((&[0; 4], &[]));
+        if *magic != SNAPSHOT_MAGIC {
+            return Err(invalid("bad snapshot magic"));
+        }
+        if rest.first() != Some(&SNAPSHOT_VERSION) {
+            return Err(invalid(format!(
+                "unsupported snapshot version {}",
+                rest.first().copied().unwrap_or(0)
+            )));
+        }
+        let meta_len = rest
+            .last_chunk::<8>()
+            .map_or(0, |len| u64::from_le_bytes(*len));
+        if meta_len < FIXED_META_LEN as u64 {
+            return Err(invalid("snapshot metadata is truncated"));
+        }
+        let mut fixed = [0; FIXED_META_LEN];
+        r.read_exact(&mut fixed)?;
+        let word = |n: usize| {
+            fixed
+                .get(8 * n..8 * n + 8)
+                .and_then(|b| <[u8; 8]>::try_from(b).ok())
+                .map_or(0, u64::from_le_bytes)
+        };
+        let fingerprint_bits = fixed.get(32).copied().unwrap_or(0);
+        let label_len = fixed
+            .last_chunk::<2>()
+            .map_or(0, |len| u16::from_le_bytes(*len));
+        if meta_len < (FIXED_META_LEN + usize::from(label_len)) as u64 {
+            return Err(invalid("snapshot label runs past its metadata"));
+        }
+        let mut label = vec![0; usize::from(label_len)];
+        r.read_exact(&mut label)?;
+        let label = String::from_utf8(label).map_err(invalid)?;
+        let extra = meta_len - (FIXED_META_LEN + label.len()) as u64;
+        if io::copy(&mut Read::take(&mut *r, extra), &mut io::sink())? != extra {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(SnapshotInfo {
+            created: UNIX_EPOCH + Duration::from_secs(word(0)),
+            size: word(1),
+            capacity: word(2),
+            hash_seed: word(3),
+            fingerprint_bits,
+            label,
+        })
+    }
+}
+
+/// Writes `filter` with its metadata to a new file at `path` and waits until
+/// it is durable.
+pub(crate) fn save(
+    filter: &CuckooFilter,
+    path: &Path,
+    label: &str,
+    hash_seed: u64,
+) -> io::Result<()> {
+    if label.len() > usize::from(u16::MAX) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "snapshot label is longer than 65535 bytes",
+        ));
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&SnapshotInfo::of(filter, label, hash_seed).encode())?;
+    filter.write_to(&mut out)?;
+    out.flush()?;
+    out.get_ref().sync_all()
+}
+
+/// Reads a snapshot written by `save`.
+pub(crate) fn load(path: &Path) -> io::Result<(SnapshotInfo, CuckooFilter)> {
+    let mut r = BufReader::new(File::open(path)?);
+    let info = SnapshotInfo::read_from(&mut r)?;
+    Ok((info, CuckooFilter::read_from(&mut r)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, SnapshotInfo};
+    use std::time::{Duration, SystemTime};
+    use std::{env, fs, process};
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let path = env::temp_dir().join(format!("dakv_cuckoo_snapshot_{}", process::id()));
+        let mut cf = CuckooFilter::tiny();
+        for i in 0..100u16 {
+            cf.add(&i.to_le_bytes()).unwrap();
+        }
+        cf.save_snapshot(&path, "users-eu", 42).unwrap();
+
+        let info = SnapshotInfo::peek(&path).unwrap();
+        assert_eq!(info.label, "users-eu");
+        assert_eq!((info.size, info.capacity), (100, 256));
+        assert_eq!((info.fingerprint_bits, info.hash_seed), (8, 42));
+        let age = SystemTime::now().duration_since(info.created).unwrap();
+        assert!(age < Duration::from_secs(60));
+
+        let (loaded_info, loaded) = CuckooFilter::load_snapshot(&path).unwrap();
+        assert_eq!(loaded_info, info);
+        assert_eq!(loaded.to_bytes(), cf.to_bytes());
+
+        fs::write(&path, b"DKCF").unwrap();
+        assert!(SnapshotInfo::peek(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,377 @@
+use crate::cuckoo_filter::Victim;
+use crate::CuckooFilter;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// Bucket index as u64 LE followed by the fingerprint, or 0 once deleted.
+const RECORD_LEN: u64 = 9;
+const FP_OFFSET: usize = 8;
+
+/// A filter that never drops membership information once memory is full.
+///
+/// Fingerprints which find no slot are appended to an on-disk overflow segment.
+/// An in-memory index lists the records of each bucket that has overflowed,
+/// so lookups only read the segment when one of their candidate buckets did,
+/// and then only that bucket's records.
+///
+/// Deleting an item removes it from memory if it is there, and otherwise
+/// zeroes the fingerprint of one of its spilled records in place. Zeroed
+/// records are skipped when the segment is reopened.
+pub struct SpillFilter {
+    filter: CuckooFilter,
+    segment: Mutex<File>,
+    // Record numbers in the segment, by the bucket index they were spilled from.
+    index: HashMap<u64, Vec<u64>>,
+    records: u64,
+    spilled: usize,
+}
+
+impl SpillFilter {
+    /// Wraps `filter`, creating (or truncating) the overflow segment at `path`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SpillFilter};
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_spill_create_{}", std::process::id()));
+    /// let cf = SpillFilter::create(CuckooFilter::new(100), &path).unwrap();
+    /// assert_eq!(cf.spilled(), 0);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn create<P: AsRef<Path>>(filter: CuckooFilter, path: P) -> io::Result<Self> {
+        let segment = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        Ok(SpillFilter {
+            filter,
+            segment: Mutex::new(segment),
+            index: HashMap::new(),
+            records: 0,
+            spilled: 0,
+        })
+    }
+
+    /// Wraps `filter`, keeping the entries already spilled to the overflow
+    /// segment at `path`, which is created if it does not exist.
+    ///
+    /// `filter` must hold what the in-memory filter held when the segment
+    /// was last written, for example as saved with `filter().to_bytes()`;
+    /// the segment only stores what did not fit into it. A record torn by a
+    /// crash while it was being appended is discarded.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SpillFilter};
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_spill_open_{}", std::process::id()));
+    /// let mut cf = SpillFilter::create(CuckooFilter::new(16), &path).unwrap();
+    /// for i in 0..100u8 {
+    ///     cf.add(&[i]).unwrap();
+    /// }
+    /// let saved = cf.filter().to_bytes();
+    /// drop(cf);
+    ///
+    /// let cf = SpillFilter::open(CuckooFilter::from_bytes(&saved).unwrap(), &path).unwrap();
+    /// assert!((0..100u8).all(|i| cf.contains(&[i]).unwrap()));
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn open<P: AsRef<Path>>(filter: CuckooFilter, path: P) -> io::Result<Self> {
+        let mut segment = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let records = segment.metadata()?.len() / RECORD_LEN;
+        segment.set_len(records * RECORD_LEN)?;
+        segment.seek(SeekFrom::Start(0))?;
+
+        let (mut index, mut spilled) = (HashMap::<u64, Vec<u64>>::new(), 0);
+        let mut reader = BufReader::new(&mut segment);
+        let mut record = [0; RECORD_LEN as usize];
+        for n in 0..records {
+            reader.read_exact(&mut record)?;
+            let (bucket, fp) = decode(&record);
+            if fp != 0 {
+                index.entry(bucket).or_default().push(n);
+                spilled += 1;
+            }
+        }
+        drop(reader);
+        Ok(SpillFilter {
+            filter,
+            segment: Mutex::new(segment),
+            index,
+            records,
+            spilled,
+        })
+    }
+
+    /// Adds `item`, spilling a fingerprint to disk if the filter is full.
+    ///
+    /// If writing the spilled fingerprint fails, the filter is left as it
+    /// was and the error is returned.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SpillFilter};
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_spill_add_{}", std::process::id()));
+    /// let mut cf = SpillFilter::create(CuckooFilter::new(100), &path).unwrap();
+    /// cf.add(b"test").unwrap();
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn add(&mut self, item: &[u8]) -> io::Result<()> {
+        let finger = self.filter.finger_of(item);
+        let (segment, n) = (&self.segment, self.records);
+        let spilled = self.filter.place_or_spill(&finger, |victim| {
+            append(segment, n, &victim).map(|_| victim)
+        })?;
+        if let Some(victim) = spilled {
+            self.index.entry(victim.index).or_default().push(n);
+            self.records += 1;
+            self.spilled += 1;
+        }
+        Ok(())
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SpillFilter};
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_spill_contains_{}", std::process::id()));
+    /// let mut cf = SpillFilter::create(CuckooFilter::new(100), &path).unwrap();
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.contains(b"test").unwrap());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> io::Result<bool> {
+        let finger = self.filter.finger_of(item);
+        if self.filter.contains_finger(&finger) {
+            return Ok(true);
+        }
+        Ok(self
+            .find_spilled(finger.fp, &[finger.i1, finger.i2])?
+            .is_some())
+    }
+
+    /// Deletes `item` from the in-memory filter or, failing that, from the
+    /// overflow segment.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SpillFilter};
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_spill_delete_{}", std::process::id()));
+    /// let mut cf = SpillFilter::create(CuckooFilter::new(100), &path).unwrap();
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.delete(b"test").unwrap());
+    /// assert!(!cf.contains(b"test").unwrap());
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> io::Result<bool> {
+        let finger = self.filter.finger_of(item);
+        if self.filter.delete_finger(&finger) {
+            return Ok(true);
+        }
+        let (bucket, n) = match self.find_spilled(finger.fp, &[finger.i1, finger.i2])? {
+            Some(found) => found,
+            None => return Ok(false),
+        };
+        let mut segment = self.lock();
+        segment.seek(SeekFrom::Start(n * RECORD_LEN + FP_OFFSET as u64))?;
+        segment.write_all(&[0])?;
+        drop(segment);
+
+        if let Some(records) = self.index.get_mut(&bucket) {
+            records.retain(|&r| r != n);
+            if records.is_empty() {
+                self.index.remove(&bucket);
+            }
+        }
+        self.spilled -= 1;
+        Ok(true)
+    }
+
+    /// Returns the number of fingerprints in memory and on disk.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SpillFilter};
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_spill_size_{}", std::process::id()));
+    /// let cf = SpillFilter::create(CuckooFilter::new(100), &path).unwrap();
+    /// assert_eq!(cf.size(), 0);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn size(&self) -> usize {
+        self.filter.size() + self.spilled
+    }
+
+    /// Returns the number of fingerprints stored in the overflow segment.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, SpillFilter};
+    ///
+    /// let path = std::env::temp_dir().join(format!("dakv_cuckoo_spill_spilled_{}", std::process::id()));
+    /// let cf = SpillFilter::create(CuckooFilter::new(100), &path).unwrap();
+    /// assert_eq!(cf.spilled(), 0);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn spilled(&self) -> usize {
+        self.spilled
+    }
+
+    /// Returns the in-memory filter, without the spilled fingerprints.
+    pub fn filter(&self) -> &CuckooFilter {
+        &self.filter
+    }
+
+    /// Returns the bucket and record number of a spilled `fp` from one of
+    /// `buckets`.
+    fn find_spilled(&self, fp: u8, buckets: &[u64]) -> io::Result<Option<(u64, u64)>> {
+        let mut record = [0; RECORD_LEN as usize];
+        let mut segment = self.lock();
+        for &bucket in buckets {
+            for &n in self.index.get(&bucket).into_iter().flatten() {
+                segment.seek(SeekFrom::Start(n * RECORD_LEN))?;
+                segment.read_exact(&mut record)?;
+                if decode(&record) == (bucket, fp) {
+                    return Ok(Some((bucket, n)));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, File> {
+        self.segment.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Writes `victim` as record number `n`, overwriting whatever a failed
+/// earlier append left there.
+fn append(segment: &Mutex<File>, n: u64, victim: &Victim) -> io::Result<()> {
+    let mut record = [0; RECORD_LEN as usize];
+    record[..8].copy_from_slice(&victim.index.to_le_bytes());
+    record[FP_OFFSET] = victim.fp;
+    let mut segment = segment.lock().unwrap_or_else(PoisonError::into_inner);
+    segment.seek(SeekFrom::Start(n * RECORD_LEN))?;
+    segment.write_all(&record)
+}
+
+fn decode(record: &[u8; RECORD_LEN as usize]) -> (u64, u8) {
+    let mut index = [0; 8];
+    index.copy_from_slice(&record[..8]);
+    (u64::from_le_bytes(index), record[FP_OFFSET])
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, SpillFilter};
+    use std::fs::{self, File};
+    use std::sync::Mutex;
+    use std::{env, io};
+
+    fn keys() -> Vec<[u8; 2]> {
+        (0..200u16).map(|i| i.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn test_spill_keeps_membership() {
+        let path = env::temp_dir().join(format!("dakv_cuckoo_spill_{}", std::process::id()));
+        let mut cf = SpillFilter::create(CuckooFilter::new(16), &path).unwrap();
+        let keys = keys();
+        for k in &keys {
+            cf.add(k).unwrap();
+        }
+        assert_eq!(cf.size(), keys.len());
+        assert!(cf.spilled() > 0);
+        for k in &keys {
+            assert!(cf.contains(k).unwrap());
+        }
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_reopen() {
+        let path =
+            env::temp_dir().join(format!("dakv_cuckoo_spill_reopen_{}", std::process::id()));
+        let mut cf = SpillFilter::create(CuckooFilter::new(16), &path).unwrap();
+        let keys = keys();
+        for k in &keys {
+            cf.add(k).unwrap();
+        }
+        let (size, saved) = (cf.size(), cf.filter().to_bytes());
+        drop(cf);
+        // A torn append is dropped on open.
+        let mut segment = fs::OpenOptions::new().append(true).open(&path).unwrap();
+        io::Write::write_all(&mut segment, &[1, 2, 3]).unwrap();
+        drop(segment);
+
+        let filter = CuckooFilter::from_bytes(&saved).unwrap();
+        let mut cf = SpillFilter::open(filter, &path).unwrap();
+        assert_eq!(cf.size(), size);
+        assert!(keys.iter().all(|k| cf.contains(k).unwrap()));
+        cf.add(b"after").unwrap();
+        assert!(cf.contains(b"after").unwrap());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    #[test]
+    fn test_delete_spilled() {
+        let path =
+            env::temp_dir().join(format!("dakv_cuckoo_spill_delete_{}", std::process::id()));
+        let mut cf = SpillFilter::create(CuckooFilter::new(16), &path).unwrap();
+        let keys = keys();
+        for k in &keys {
+            cf.add(k).unwrap();
+        }
+        assert!(cf.spilled() > 0);
+        for k in &keys {
+            assert!(cf.delete(k).unwrap());
+        }
+        assert_eq!((cf.size(), cf.spilled()), (0, 0));
+
+        let saved = cf.filter().to_bytes();
+        drop(cf);
+        let cf = SpillFilter::open(CuckooFilter::from_bytes(&saved).unwrap(), &path).unwrap();
+        assert_eq!(cf.size(), 0);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_failed_spill_loses_nothing() {
+        let path =
+            env::temp_dir().join(format!("dakv_cuckoo_spill_failed_{}", std::process::id()));
+        let mut cf = SpillFilter::create(CuckooFilter::new(16), &path).unwrap();
+        let keys = keys();
+        let mut added = 0;
+        while cf.spilled() == 0 {
+            cf.add(&keys[added]).unwrap();
+            added += 1;
+        }
+        // Appends to a read-only handle fail.
+        cf.segment = Mutex::new(File::open(&path).unwrap());
+        let (before, spilled) = loop {
+            let before = cf.filter().to_bytes_raw();
+            if cf.add(&keys[added]).is_err() {
+                break (before, cf.spilled());
+            }
+            added += 1;
+        };
+        assert_eq!(cf.filter().to_bytes_raw(), before);
+        assert_eq!(cf.spilled(), spilled);
+        assert!(keys[..added].iter().all(|k| cf.contains(k).unwrap()));
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,187 @@
+use crate::CuckooError;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const KINDS: usize = 6;
+
+static COUNTS: [AtomicU64; KINDS] = [
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+    AtomicU64::new(0),
+];
+
+static PLACEMENTS: AtomicU64 = AtomicU64::new(0);
+static RELOCATING: AtomicU64 = AtomicU64::new(0);
+static KICKS: AtomicU64 = AtomicU64::new(0);
+static LONGEST: AtomicU64 = AtomicU64::new(0);
+
+/// Counts of the errors returned in this process, by `CuckooError` variant.
+///
+/// The counters only grow, so alerting should look at their rate. A rising
+/// `not_enough_space` means filters are genuinely full; `allocation_failed`
+/// and `not_supported` usually point at a misconfigured capacity or feature
+/// set instead.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ErrorStats {
+    /// Inserts that found no free slot, even after relocating fingerprints.
+    pub not_enough_space: u64,
+    /// Inserts refused by the limit set with `set_max_duplicates`.
+    pub too_many_duplicates: u64,
+    /// Bucket arrays or serialized images that could not be allocated.
+    pub allocation_failed: u64,
+    /// Operations this filter or build cannot perform.
+    pub not_supported: u64,
+    /// Operations stopped by their `Cancellation`.
+    pub cancelled: u64,
+    pub not_found: u64,
+}
+
+fn kind(error: &CuckooError) -> usize {
+    match error {
+        CuckooError::NotEnoughSpace(_) => 0,
+        CuckooError::TooManyDuplicates(_) => 1,
+        CuckooError::AllocationFailed { .. } => 2,
+        CuckooError::NotSupported => 3,
+        CuckooError::Cancelled => 4,
+        CuckooError::NotFound => 5,
+    }
+}
+
+/// Counts `error` and returns it, at the place it is first raised.
+pub(crate) fn record(error: CuckooError) -> CuckooError {
+    if let Some(count) = COUNTS.get(kind(&error)) {
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+    error
+}
+
+/// Counts one attempt to place a fingerprint that took `kicks` relocations,
+/// whether or not it found a slot.
+pub(crate) fn record_kicks(kicks: usize) {
+    PLACEMENTS.fetch_add(1, Ordering::Relaxed);
+    if kicks > 0 {
+        RELOCATING.fetch_add(1, Ordering::Relaxed);
+        KICKS.fetch_add(kicks as u64, Ordering::Relaxed);
+        LONGEST.fetch_max(kicks as u64, Ordering::Relaxed);
+    }
+}
+
+/// How much relocation the inserts of this process needed.
+///
+/// Kick chains grow sharply as filters approach capacity, so a rising
+/// `kicks` per placement is an early warning before inserts start failing.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct KickStats {
+    /// Attempts to place a fingerprint, successful or not.
+    pub placements: u64,
+    /// Placements that had to relocate other fingerprints.
+    pub relocating_placements: u64,
+    /// Relocations performed in total.
+    pub kicks: u64,
+    /// The longest relocation chain of a single placement.
+    pub longest_chain: u64,
+}
+
+/// Returns the relocation counters of this process.
+pub fn kick_stats() -> KickStats {
+    KickStats {
+        placements: PLACEMENTS.load(Ordering::Relaxed),
+        relocating_placements: RELOCATING.load(Ordering::Relaxed),
+        kicks: KICKS.load(Ordering::Relaxed),
+        longest_chain: LONGEST.load(Ordering::Relaxed),
+    }
+}
+
+/// The gauges of one filter together with the counters of this process, for
+/// dumping into a metrics or debug endpoint as one document.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MetricsSnapshot {
+    pub size: usize,
+    /// Number of fingerprint slots.
+    pub capacity: usize,
+    pub num_buckets: usize,
+    pub load_factor: f64,
+    /// Expected fraction of absent keys reported present at the current load.
+    pub false_positive_rate: f64,
+    pub fingerprint_bits: u32,
+    pub max_kicks: usize,
+    /// Whether any fingerprint has left its primary bucket, see
+    /// `CuckooFilter::evictions_occurred`.
+    pub evictions_occurred: bool,
+    /// Errors raised in this process, see `error_stats`.
+    pub errors: ErrorStats,
+    /// Relocations in this process, see `kick_stats`.
+    pub kicks: KickStats,
+}
+
+/// Returns how often every kind of error has been raised in this process.
+///
+/// An error is counted once, where it is raised, even if a wrapper such as
+/// `ScalableCuckooFilter` recovers from it or it is passed up through
+/// several layers. Failures while constructing a filter are included, which
+/// per-filter counters could not report.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::{error_stats, CuckooFilter};
+///
+/// let before = error_stats().not_enough_space;
+/// let mut cf = CuckooFilter::with_capacity(1);
+/// for i in 0..16u8 {
+///     let _ = cf.add(&[i]);
+/// }
+/// assert!(error_stats().not_enough_space > before);
+/// ```
+pub fn error_stats() -> ErrorStats {
+    let count = |kind: usize| COUNTS.get(kind).map_or(0, |c| c.load(Ordering::Relaxed));
+    ErrorStats {
+        not_enough_space: count(0),
+        too_many_duplicates: count(1),
+        allocation_failed: count(2),
+        not_supported: count(3),
+        cancelled: count(4),
+        not_found: count(5),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{error_stats, kick_stats, CuckooError, CuckooFilter};
+
+    #[test]
+    fn test_kick_stats() {
+        let before = kick_stats();
+        let mut cf = CuckooFilter::with_capacity(4);
+        while cf.add(&cf.size().to_le_bytes()).is_ok() {}
+        let after = kick_stats();
+        assert!(after.placements > before.placements + 8);
+        assert!(after.kicks >= before.kicks + cf.max_kicks() as u64);
+        assert!(after.longest_chain >= cf.max_kicks() as u64);
+    }
+
+    #[test]
+    fn test_error_stats() {
+        // Other tests run concurrently, so only growth is checked.
+        let before = error_stats();
+        assert!(matches!(
+            CuckooFilter::try_new(u64::MAX),
+            Err(CuckooError::AllocationFailed { .. })
+        ));
+        let mut cf = CuckooFilter::with_capacity(1);
+        cf.set_max_duplicates(Some(1));
+        cf.add(b"test").unwrap();
+        assert!(cf.add(b"test").is_err());
+        for i in 0..16u8 {
+            let _ = cf.add(&[i]);
+        }
+        let after = error_stats();
+        assert!(after.allocation_failed > before.allocation_failed);
+        assert!(after.too_many_duplicates > before.too_many_duplicates);
+        assert!(after.not_enough_space > before.not_enough_space);
+    }
+}
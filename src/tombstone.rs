@@ -0,0 +1,185 @@
+use crate::util::FingerIndex;
+use crate::{CResult, CuckooError, CuckooFilter};
+use std::collections::HashMap;
+
+/// A filter that records deletes as tombstones instead of clearing slots.
+///
+/// Replicas receiving the same add and delete events in different orders
+/// end up agreeing: a delete that arrives before its add leaves a tombstone
+/// that cancels the add once it shows up. An item is present while its
+/// bucket pair holds more copies of its fingerprint than there are
+/// tombstones for it. `compact` clears slots that have a matching tombstone.
+pub struct TombstoneFilter {
+    filter: CuckooFilter,
+    // Keyed by the smaller bucket of the pair, which identifies it uniquely
+    // together with the fingerprint.
+    tombstones: HashMap<(u64, u8), usize>,
+}
+
+impl TombstoneFilter {
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TombstoneFilter};
+    /// let cf = TombstoneFilter::new(CuckooFilter::new(100));
+    /// ```
+    pub fn new(filter: CuckooFilter) -> Self {
+        TombstoneFilter {
+            filter,
+            tombstones: HashMap::new(),
+        }
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TombstoneFilter};
+    /// let mut cf = TombstoneFilter::new(CuckooFilter::new(100));
+    /// cf.add(b"test");
+    /// ```
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        let finger = self.filter.finger_of(item);
+        self.filter.add_finger(&finger)
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TombstoneFilter};
+    /// let mut cf = TombstoneFilter::new(CuckooFilter::new(100));
+    /// cf.add(b"test");
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let finger = self.filter.finger_of(item);
+        self.filter.count_finger(&finger) > self.tombstones_for(&finger)
+    }
+
+    /// Records a tombstone for `item`, returning `true` if it was present.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TombstoneFilter};
+    /// let mut cf = TombstoneFilter::new(CuckooFilter::new(100));
+    /// assert!(!cf.delete(b"test"));
+    /// cf.add(b"test");
+    /// assert!(!cf.contains(b"test"));
+    /// ```
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        let present = self.contains(item);
+        let finger = self.filter.finger_of(item);
+        *self.tombstones.entry(Self::key(&finger)).or_insert(0) += 1;
+        present
+    }
+
+    /// Clears every slot cancelled by a tombstone, returning how many were
+    /// freed.
+    ///
+    /// Tombstones without a matching slot are kept, as their add may still
+    /// arrive.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TombstoneFilter};
+    /// let mut cf = TombstoneFilter::new(CuckooFilter::new(100));
+    /// cf.add(b"test");
+    /// cf.delete(b"test");
+    /// assert_eq!(cf.compact(), 1);
+    /// assert_eq!(cf.tombstones(), 0);
+    /// ```
+    pub fn compact(&mut self) -> usize {
+        let mut freed = 0;
+        let filter = &mut self.filter;
+        self.tombstones.retain(|&(i, fp), count| {
+            let finger = filter.finger_at(fp, i);
+            while *count > 0 && filter.delete_finger(&finger) {
+                *count -= 1;
+                freed += 1;
+            }
+            *count > 0
+        });
+        freed
+    }
+
+    /// Returns the number of pending tombstones.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TombstoneFilter};
+    /// let mut cf = TombstoneFilter::new(CuckooFilter::new(100));
+    /// cf.delete(b"test");
+    /// assert_eq!(cf.tombstones(), 1);
+    /// ```
+    pub fn tombstones(&self) -> usize {
+        self.tombstones.values().sum()
+    }
+
+    /// Returns the number of stored items not cancelled by a tombstone.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TombstoneFilter};
+    /// let mut cf = TombstoneFilter::new(CuckooFilter::new(100));
+    /// cf.add(b"test");
+    /// cf.delete(b"test");
+    /// assert_eq!(cf.size(), 0);
+    /// ```
+    pub fn size(&self) -> usize {
+        let cancelled = self
+            .tombstones
+            .iter()
+            .map(|(&(i, fp), &count)| {
+                let finger = self.filter.finger_at(fp, i);
+                count.min(self.filter.count_finger(&finger))
+            })
+            .sum::<usize>();
+        self.filter.size() - cancelled
+    }
+
+    fn tombstones_for(&self, finger: &FingerIndex) -> usize {
+        self.tombstones
+            .get(&Self::key(finger))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    fn key(finger: &FingerIndex) -> (u64, u8) {
+        (finger.i1.min(finger.i2), finger.fp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, TombstoneFilter};
+
+    #[test]
+    fn test_out_of_order_replicas_converge() {
+        let mut a = TombstoneFilter::new(CuckooFilter::tiny());
+        let mut b = TombstoneFilter::new(CuckooFilter::tiny());
+
+        a.add(b"x").unwrap();
+        a.add(b"y").unwrap();
+        a.delete(b"x");
+
+        b.delete(b"x");
+        b.add(b"y").unwrap();
+        b.add(b"x").unwrap();
+
+        for cf in [&mut a, &mut b].iter_mut() {
+            assert!(!cf.contains(b"x"));
+            assert!(cf.contains(b"y"));
+            assert_eq!(cf.size(), 1);
+            assert_eq!(cf.compact(), 1);
+            assert_eq!(cf.tombstones(), 0);
+            assert!(!cf.contains(b"x"));
+        }
+    }
+
+    #[test]
+    fn test_early_tombstone_survives_compaction() {
+        let mut cf = TombstoneFilter::new(CuckooFilter::tiny());
+        cf.delete(b"x");
+        assert_eq!(cf.compact(), 0);
+        cf.add(b"x").unwrap();
+        assert!(!cf.contains(b"x"));
+        assert_eq!(cf.compact(), 1);
+        assert_eq!(cf.size(), 0);
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{CResult, CuckooError, CuckooFilter};
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Mutex, PoisonError};
+
+/// The kind of operation a `TraceEntry` records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceOp {
+    Add,
+    Contains,
+    #[cfg(not(feature = "no-delete"))]
+    Delete,
+}
+
+/// One operation as the filter saw it.
+///
+/// Only derived values are kept, never the key, like in `Placement`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    pub op: TraceOp,
+    pub fingerprint: u8,
+    pub i1: usize,
+    pub i2: usize,
+    /// Whether an add or delete succeeded, or a lookup found the item.
+    pub ok: bool,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} fingerprint {:#04x} buckets {} and {}: {}",
+            self.op,
+            self.fingerprint,
+            self.i1,
+            self.i2,
+            if self.ok { "ok" } else { "failed" }
+        )
+    }
+}
+
+/// A filter that remembers its last operations for postmortems.
+///
+/// The trace is a ring buffer of fixed length, so memory stays bounded
+/// however long the filter lives; `dump` returns it oldest first. When a
+/// lookup gives an answer that should be impossible, the dump shows what the
+/// filter actually did with that fingerprint.
+pub struct TracingFilter {
+    filter: CuckooFilter,
+    trace: Mutex<VecDeque<TraceEntry>>,
+    len: usize,
+}
+
+impl TracingFilter {
+    /// Wraps `filter`, keeping the last `len` operations.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TracingFilter};
+    /// let cf = TracingFilter::new(CuckooFilter::new(100), 1024);
+    /// ```
+    pub fn new(filter: CuckooFilter, len: usize) -> Self {
+        TracingFilter {
+            filter,
+            trace: Mutex::new(VecDeque::with_capacity(len)),
+            len,
+        }
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TracingFilter};
+    /// let mut cf = TracingFilter::new(CuckooFilter::new(100), 16);
+    /// cf.add(b"test").unwrap();
+    /// ```
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        let finger = self.filter.finger_of(item);
+        let result = self.filter.add_finger(&finger);
+        self.record(
+            TraceOp::Add,
+            finger.fp,
+            finger.i1,
+            finger.i2,
+            result.is_ok(),
+        );
+        result
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TracingFilter};
+    /// let mut cf = TracingFilter::new(CuckooFilter::new(100), 16);
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.contains(b"test"));
+    /// ```
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let finger = self.filter.finger_of(item);
+        let found = self.filter.contains_finger(&finger);
+        self.record(TraceOp::Contains, finger.fp, finger.i1, finger.i2, found);
+        found
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TracingFilter};
+    /// let mut cf = TracingFilter::new(CuckooFilter::new(100), 16);
+    /// cf.add(b"test").unwrap();
+    /// assert!(cf.delete(b"test"));
+    /// ```
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        let finger = self.filter.finger_of(item);
+        let deleted = self.filter.delete_finger(&finger);
+        self.record(TraceOp::Delete, finger.fp, finger.i1, finger.i2, deleted);
+        deleted
+    }
+
+    /// Returns the recorded operations, oldest first.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TraceOp, TracingFilter};
+    /// let mut cf = TracingFilter::new(CuckooFilter::new(100), 16);
+    /// cf.add(b"test").unwrap();
+    /// cf.contains(b"test");
+    /// let ops = cf.dump().iter().map(|e| e.op).collect::<Vec<_>>();
+    /// assert_eq!(ops, [TraceOp::Add, TraceOp::Contains]);
+    /// ```
+    pub fn dump(&self) -> Vec<TraceEntry> {
+        let trace = self.trace.lock().unwrap_or_else(PoisonError::into_inner);
+        trace.iter().copied().collect()
+    }
+
+    /// Forgets all recorded operations.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TracingFilter};
+    /// let mut cf = TracingFilter::new(CuckooFilter::new(100), 16);
+    /// cf.contains(b"test");
+    /// cf.clear_trace();
+    /// assert!(cf.dump().is_empty());
+    /// ```
+    pub fn clear_trace(&self) {
+        self.trace
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooFilter, TracingFilter};
+    /// let cf = TracingFilter::new(CuckooFilter::new(100), 16);
+    /// assert_eq!(cf.filter().size(), 0);
+    /// ```
+    pub fn filter(&self) -> &CuckooFilter {
+        &self.filter
+    }
+
+    /// Unwraps the filter, dropping the trace.
+    pub fn into_inner(self) -> CuckooFilter {
+        self.filter
+    }
+
+    fn record(&self, op: TraceOp, fingerprint: u8, i1: u64, i2: u64, ok: bool) {
+        if self.len == 0 {
+            return;
+        }
+        let mut trace = self.trace.lock().unwrap_or_else(PoisonError::into_inner);
+        if trace.len() == self.len {
+            trace.pop_front();
+        }
+        trace.push_back(TraceEntry {
+            op,
+            fingerprint,
+            i1: i1 as usize,
+            i2: i2 as usize,
+            ok,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CuckooFilter, TraceOp, TracingFilter};
+
+    #[test]
+    fn test_ring_keeps_latest() {
+        let mut cf = TracingFilter::new(CuckooFilter::tiny(), 3);
+        cf.add(b"a").unwrap();
+        cf.add(b"b").unwrap();
+        assert!(cf.contains(b"a"));
+        cf.contains(b"missing");
+
+        let trace = cf.dump();
+        assert_eq!(trace.len(), 3);
+        assert_eq!(trace[0].op, TraceOp::Add);
+        assert_eq!(trace[1].op, TraceOp::Contains);
+        assert!(trace[1].ok);
+
+        let finger = cf.filter().finger_of(b"b");
+        assert_eq!(
+            (trace[0].fingerprint, trace[0].i1, trace[0].i2),
+            (finger.fp, finger.i1 as usize, finger.i2 as usize)
+        );
+        assert!(trace[0].to_string().starts_with("Add fingerprint"));
+    }
+
+    #[test]
+    fn test_zero_length_records_nothing() {
+        let mut cf = TracingFilter::new(CuckooFilter::tiny(), 0);
+        cf.add(b"a").unwrap();
+        assert!(cf.dump().is_empty());
+        assert!(cf.contains(b"a"));
+    }
+}
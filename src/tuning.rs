@@ -0,0 +1,311 @@
+use crate::bucket::BUCKET_SIZE;
+use crate::cuckoo_filter::{max_load, MAX_CUCKOO_COUNT};
+use crate::format::FINGERPRINT_BITS;
+use crate::quality::{expected_fpr_in, load_for_fpr_in};
+use crate::util::upper_power2;
+use crate::{ConfigError, CuckooConfig, CuckooFilterBuilder};
+
+// Operations per second a single owner serves without contention mattering.
+const EXCLUSIVE_OPS: f64 = 100_000.0;
+// Lookups per write above which copying the filter for each write batch pays.
+const SNAPSHOT_READ_WRITE_RATIO: f64 = 100.0;
+// Kick limit of filters that stay at most half as full as they could be.
+const SPARSE_MAX_KICKS: usize = 100;
+// Bucket sizes and fingerprint widths considered, the built-in one first.
+const LAYOUTS: [(usize, u32); 9] = [
+    (4, 8),
+    (4, 16),
+    (4, 32),
+    (2, 8),
+    (2, 16),
+    (2, 32),
+    (8, 8),
+    (8, 16),
+    (8, 32),
+];
+
+/// How a filter will be used, the input of `CuckooFilter::tuned_for`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Workload {
+    /// Number of items the filter holds at its fullest.
+    pub expected_items: u64,
+    /// Inserts per second.
+    pub insert_rate: f64,
+    /// Deletes per second.
+    pub delete_rate: f64,
+    /// Lookups per insert or delete.
+    pub read_write_ratio: f64,
+    /// Bytes the fingerprints may take; `None` is unlimited.
+    pub memory_budget: Option<u64>,
+    /// Highest acceptable fraction of absent keys reported present.
+    pub target_fpr: f64,
+}
+
+/// How a tuned filter is shared between threads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Concurrency {
+    /// A `CuckooFilter` owned by one thread, or behind one lock.
+    Exclusive,
+    /// A `SharedCuckooFilter`, whose readers never wait and whose writers
+    /// copy the filter for every batch.
+    Snapshot,
+    /// A `SegmentedCuckooFilter`, whose readers only wait for writers on the
+    /// same segments.
+    Segmented,
+    /// A `ShardedCuckooFilter`, whose writers on different shards never
+    /// contend.
+    Sharded,
+}
+
+/// The settings `CuckooFilter::tuned_for` chose for a `Workload`, with the
+/// reason for each.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    /// Capacity, kick limit and the other settings of a `CuckooFilter`.
+    pub config: CuckooConfig,
+    /// Slots per bucket.
+    pub bucket_size: usize,
+    /// Width of one fingerprint.
+    pub fingerprint_bits: u32,
+    pub concurrency: Concurrency,
+    /// Bytes the fingerprints take.
+    pub memory: u64,
+    /// Expected false positive rate holding `Workload::expected_items`.
+    pub false_positive_rate: f64,
+    /// One sentence per setting, explaining the choice.
+    pub rationale: Vec<String>,
+}
+
+impl Tuning {
+    /// Returns a builder with these settings. Its `build_any` builds the
+    /// chosen layout; `build` fails unless it is the built-in one.
+    pub fn builder(&self) -> CuckooFilterBuilder {
+        CuckooFilterBuilder::from_config(self.config)
+            .bucket_size(self.bucket_size)
+            .fingerprint_bits(self.fingerprint_bits)
+    }
+}
+
+// One bucket size and fingerprint width sized for a workload.
+struct Layout {
+    bucket_size: usize,
+    fingerprint_bits: u32,
+    num_buckets: u64,
+    load: f64,
+    memory: u64,
+    fpr: f64,
+}
+
+impl Layout {
+    fn size(workload: &Workload, bucket_size: usize, fingerprint_bits: u32) -> Option<Self> {
+        let target = load_for_fpr_in(workload.target_fpr, fingerprint_bits, bucket_size)
+            .min(max_load(bucket_size));
+        let buckets = (workload.expected_items as f64 / target / bucket_size as f64).ceil();
+        if buckets > (1u64 << 62) as f64 {
+            return None;
+        }
+        let num_buckets = upper_power2((buckets as u64).max(1));
+        let slots = num_buckets.checked_mul(bucket_size as u64)?;
+        let load = workload.expected_items as f64 / slots as f64;
+        Some(Layout {
+            bucket_size,
+            fingerprint_bits,
+            num_buckets,
+            load,
+            memory: slots.checked_mul(u64::from(fingerprint_bits))? / 8,
+            fpr: expected_fpr_in(load, fingerprint_bits, bucket_size),
+        })
+    }
+}
+
+fn check_rate(field: &'static str, rate: f64) -> Result<(), ConfigError> {
+    if rate >= 0.0 {
+        Ok(())
+    } else {
+        Err(ConfigError::Invalid {
+            field,
+            reason: "must not be negative",
+        })
+    }
+}
+
+pub(crate) fn tune(workload: &Workload) -> Result<Tuning, ConfigError> {
+    if workload.expected_items == 0 {
+        return Err(ConfigError::Invalid {
+            field: "expected_items",
+            reason: "must be positive",
+        });
+    }
+    if !(workload.target_fpr > 0.0 && workload.target_fpr < 1.0) {
+        return Err(ConfigError::Invalid {
+            field: "target_fpr",
+            reason: "must be greater than 0 and less than 1",
+        });
+    }
+    check_rate("insert_rate", workload.insert_rate)?;
+    check_rate("delete_rate", workload.delete_rate)?;
+    check_rate("read_write_ratio", workload.read_write_ratio)?;
+    let mut rationale = Vec::new();
+
+    let writes = workload.insert_rate + workload.delete_rate;
+    let ops = writes * (1.0 + workload.read_write_ratio);
+    let concurrency = if ops < EXCLUSIVE_OPS {
+        rationale.push(format!(
+            "Exclusive: {:.0} operations per second are served by a single owner.",
+            ops
+        ));
+        Concurrency::Exclusive
+    } else if workload.read_write_ratio >= SNAPSHOT_READ_WRITE_RATIO {
+        rationale.push(format!(
+            "Snapshot: at {:.0} lookups per write, copying the filter per write batch \
+             buys lookups that never wait.",
+            workload.read_write_ratio
+        ));
+        Concurrency::Snapshot
+    } else if workload.read_write_ratio >= 1.0 {
+        rationale.push(
+            "Segmented: lookups dominate but writes are frequent, so readers should only \
+             wait for writers on the same segments."
+                .to_string(),
+        );
+        Concurrency::Segmented
+    } else {
+        rationale.push(format!(
+            "Sharded: {:.0} writes per second outnumber lookups, so writers should not \
+             contend.",
+            writes
+        ));
+        Concurrency::Sharded
+    };
+
+    // The concurrent filters all wrap the built-in layout.
+    let builtin = [(BUCKET_SIZE, u32::from(FINGERPRINT_BITS))];
+    let layouts: &[(usize, u32)] = if concurrency == Concurrency::Exclusive {
+        &LAYOUTS
+    } else {
+        &builtin
+    };
+    let candidates: Vec<Layout> = layouts
+        .iter()
+        .filter_map(|&(bucket_size, bits)| Layout::size(workload, bucket_size, bits))
+        .collect();
+    if candidates.is_empty() {
+        return Err(ConfigError::Invalid {
+            field: "target_fpr",
+            reason: "too low to fit the address space",
+        });
+    }
+    let budget = workload.memory_budget.unwrap_or(u64::MAX);
+    let layout = candidates
+        .into_iter()
+        .filter(|layout| layout.memory <= budget)
+        .min_by(|a, b| a.memory.cmp(&b.memory).then(a.fpr.total_cmp(&b.fpr)))
+        .ok_or(ConfigError::Invalid {
+            field: "memory_budget",
+            reason: "too small for expected_items at target_fpr",
+        })?;
+    rationale.push(format!(
+        "Layout: {} slots per bucket of {}-bit fingerprints is the smallest that reaches \
+         the target, {} bytes at {:.1}% load for a false positive rate of {:.2e}.",
+        layout.bucket_size,
+        layout.fingerprint_bits,
+        layout.memory,
+        layout.load * 100.0,
+        layout.fpr
+    ));
+
+    let max_keys = layout.num_buckets as f64 * layout.bucket_size as f64;
+    let max_keys = (max_keys * max_load(layout.bucket_size)) as u64;
+    rationale.push(format!(
+        "Capacity: {} buckets hold up to {} keys before inserts start failing.",
+        layout.num_buckets, max_keys
+    ));
+    let headroom = layout.load / max_load(layout.bucket_size);
+    let max_kicks = if headroom <= 0.5 {
+        rationale.push(format!(
+            "Kick limit: {}, since at {:.0}% of the usable load long eviction chains \
+             point at a hot spot rather than a full table.",
+            SPARSE_MAX_KICKS,
+            headroom * 100.0
+        ));
+        SPARSE_MAX_KICKS
+    } else {
+        rationale.push(format!(
+            "Kick limit: the default {}, since at {:.0}% of the usable load inserts need \
+             long eviction chains.",
+            MAX_CUCKOO_COUNT,
+            headroom * 100.0
+        ));
+        MAX_CUCKOO_COUNT
+    };
+
+    Ok(Tuning {
+        config: CuckooConfig::new(max_keys.max(1)).max_kicks(max_kicks),
+        bucket_size: layout.bucket_size,
+        fingerprint_bits: layout.fingerprint_bits,
+        concurrency,
+        memory: layout.memory,
+        false_positive_rate: layout.fpr,
+        rationale,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Concurrency, ConfigError, CuckooFilter, Workload};
+
+    fn workload() -> Workload {
+        Workload {
+            expected_items: 1_000_000,
+            insert_rate: 100.0,
+            delete_rate: 10.0,
+            read_write_ratio: 10.0,
+            memory_budget: None,
+            target_fpr: 0.01,
+        }
+    }
+
+    #[test]
+    fn test_tuned_for() {
+        let tuning = CuckooFilter::tuned_for(&workload()).unwrap();
+        assert_eq!(tuning.concurrency, Concurrency::Exclusive);
+        assert_eq!((tuning.bucket_size, tuning.fingerprint_bits), (4, 16));
+        assert_eq!(tuning.memory, 2 * 1024 * 1024);
+        assert!(tuning.false_positive_rate < 0.01);
+        assert_eq!(tuning.rationale.len(), 4);
+        assert!(tuning.builder().build().is_err());
+        let cf = tuning.builder().build_any().unwrap();
+        assert_eq!((cf.bucket_size(), cf.fingerprint_bits()), (4, 16));
+        assert_eq!(cf.capacity() as u64 * 2, tuning.memory);
+
+        // Concurrent modes keep the built-in layout, at a lower load.
+        let busy = Workload {
+            insert_rate: 500_000.0,
+            read_write_ratio: 0.5,
+            ..workload()
+        };
+        let tuning = CuckooFilter::tuned_for(&busy).unwrap();
+        assert_eq!(tuning.concurrency, Concurrency::Sharded);
+        let cf = tuning.builder().build().unwrap();
+        assert_eq!(cf.capacity(), 4 * 1024 * 1024);
+        assert_eq!(cf.max_kicks(), 100);
+        assert!(tuning.false_positive_rate < 0.01);
+
+        let tight = Workload {
+            memory_budget: Some(1024 * 1024),
+            ..workload()
+        };
+        assert!(matches!(
+            CuckooFilter::tuned_for(&tight),
+            Err(ConfigError::Invalid {
+                field: "memory_budget",
+                ..
+            })
+        ));
+        let negative = Workload {
+            delete_rate: -1.0,
+            ..workload()
+        };
+        assert!(CuckooFilter::tuned_for(&negative).is_err());
+    }
+}
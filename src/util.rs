@@ -1,6 +1,7 @@
-use seahash::hash;
+use crate::cuckoo_filter::Placement;
+use std::hash::{BuildHasherDefault, Hasher};
 
-const MASKS: [u64; 65] = [
+pub const MASKS: [u64; 65] = [
     0,
     1,
     3,
@@ -349,19 +350,118 @@ pub struct FingerIndex {
     pub i2: u64,
 }
 
+impl FingerIndex {
+    pub fn placement(&self) -> Placement {
+        Placement {
+            fingerprint: self.fp,
+            i1: self.i1 as usize,
+            i2: self.i2 as usize,
+        }
+    }
+}
+
+// `fp` indexes a table of 256 entries, and `pow` is below 64 in every filter.
+#[allow(clippy::indexing_slicing)]
 pub fn get_alt_index(fp: u8, i: u64, pow: usize) -> u64 {
     let mask = MASKS[pow];
     let hash = ALT_HASH[fp as usize] & mask;
     (i & mask) ^ hash
 }
 
-pub fn get_fingerprint(data: &[u8]) -> u8 {
-    (hash(data) % 255 + 1) as u8
+fn fingerprint_from_hash(hash: u64) -> u8 {
+    (hash % 255 + 1) as u8
+}
+
+/// Hashes a key with the reference hash (SeaHash), which is bit-exact on
+/// every platform and across releases.
+#[cfg(any(not(feature = "fast"), feature = "portable-strict"))]
+pub fn hash_key(data: &[u8]) -> u64 {
+    seahash::hash(data)
+}
+
+/// Multiplies `a` by `b` and folds the 128-bit product into 64 bits.
+#[cfg(any(
+    all(feature = "fast", not(feature = "portable-strict")),
+    feature = "uuid"
+))]
+fn mum(a: u64, b: u64) -> u64 {
+    let r = u128::from(a) * u128::from(b);
+    (r as u64) ^ ((r >> 64) as u64)
+}
+
+/// Hashes a key with a wyhash-style multiply-mix, trading the stability
+/// guarantee of the reference hash for throughput.
+#[cfg(all(feature = "fast", not(feature = "portable-strict")))]
+pub fn hash_key(data: &[u8]) -> u64 {
+    const P0: u64 = 0xa076_1d64_78bd_642f;
+    const P1: u64 = 0xe703_7ed1_a0b4_28db;
+    const P2: u64 = 0x8ebc_6af0_9c88_c6e3;
+    const P3: u64 = 0x5899_65cc_7537_4cc3;
+
+    let len = data.len() as u64;
+    let mut seed = P0 ^ len;
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut word = [0; 8];
+        word.copy_from_slice(chunk);
+        seed = mum(u64::from_le_bytes(word) ^ P1, seed ^ P2);
+    }
+    let rest = chunks.remainder();
+    let mut word = [0; 8];
+    for (w, &b) in word.iter_mut().zip(rest) {
+        *w = b;
+    }
+    seed = mum(u64::from_le_bytes(word) ^ P3, seed ^ rest.len() as u64 ^ P1);
+    mum(seed ^ P0, len ^ P1)
+}
+
+/// Hashes a 128-bit key, such as a UUID, with two multiply-mix rounds.
+///
+/// Both halves reach every output bit, so keys differing only in their
+/// random bits, like UUIDv7s from the same millisecond, spread as well as
+/// random ones. It does not agree with `hash_key` over the same 16 bytes.
+#[cfg(feature = "uuid")]
+pub fn hash_u128(key: u128) -> u64 {
+    const P0: u64 = 0xa076_1d64_78bd_642f;
+    const P1: u64 = 0xe703_7ed1_a0b4_28db;
+    const P2: u64 = 0x8ebc_6af0_9c88_c6e3;
+
+    let (hi, lo) = ((key >> 64) as u64, key as u64);
+    mum(mum(lo ^ P0, hi ^ P1) ^ P2, hi ^ lo ^ P0)
+}
+
+/// The `Hasher` a `CuckooFilter` hashes keys with by default.
+///
+/// A single `write` hashes its bytes with the hash the `fast` and
+/// `portable-strict` features select, exactly as filters without a custom
+/// hasher always have; further writes are mixed in.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyHasher {
+    hash: u64,
 }
 
+impl Hasher for KeyHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        self.hash = self.hash.rotate_left(5) ^ hash_key(bytes);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+/// The default `BuildHasher` of `CuckooFilter`.
+pub type KeyHasherBuilder = BuildHasherDefault<KeyHasher>;
+
 pub fn get_indices_and_fingerprint(data: &[u8], pow: usize) -> FingerIndex {
-    let index_hash = hash(data);
-    let fp = get_fingerprint(data);
+    get_indices_from_hash(hash_key(data), pow)
+}
+
+/// Derives the fingerprint and both candidate buckets from an already computed
+/// key hash, so one hashing pass can serve filters of different sizes.
+#[allow(clippy::indexing_slicing)]
+pub fn get_indices_from_hash(index_hash: u64, pow: usize) -> FingerIndex {
+    let fp = fingerprint_from_hash(index_hash);
     let i1 = index_hash & MASKS[pow];
     let i2 = get_alt_index(fp, i1, pow);
     FingerIndex { fp, i1, i2 }
@@ -369,7 +469,9 @@ pub fn get_indices_and_fingerprint(data: &[u8], pow: usize) -> FingerIndex {
 
 #[cfg(test)]
 mod tests {
-    use crate::util::upper_power2;
+    use crate::util::{
+        get_indices_and_fingerprint, get_indices_from_hash, hash_key, upper_power2,
+    };
 
     #[test]
     fn test_upper_power2() {
@@ -378,4 +480,41 @@ mod tests {
         assert_eq!(upper_power2(1 << 10), 1024);
         assert_eq!(upper_power2((1 << 10) + 1), 2048);
     }
+
+    #[test]
+    fn test_indices_from_hash() {
+        let a = get_indices_and_fingerprint(b"test", 5);
+        let b = get_indices_from_hash(hash_key(b"test"), 5);
+        assert_eq!((a.fp, a.i1, a.i2), (b.fp, b.i1, b.i2));
+    }
+
+    #[test]
+    #[cfg(any(not(feature = "fast"), feature = "portable-strict"))]
+    fn test_short_keys_are_stable() {
+        // Pinned so that a change in hashing is caught before it breaks
+        // persisted filters; zero-length keys are valid input.
+        let cases: [(&[u8], u8, u64, u64); 5] = [
+            (b"", 36, 57, 30),
+            (&[0], 152, 61, 23),
+            (b"a", 90, 13, 51),
+            (b"ab", 133, 31, 28),
+            (b"abc", 53, 6, 46),
+        ];
+        for (key, fp, i1, i2) in cases.iter() {
+            let f = get_indices_and_fingerprint(key, 6);
+            assert_eq!((f.fp, f.i1, f.i2), (*fp, *i1, *i2), "key {:?}", key);
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "fast", not(feature = "portable-strict")))]
+    fn test_fast_hash_distinguishes_lengths() {
+        let keys: [&[u8]; 6] = [b"", &[0], &[0, 0], &[0; 8], &[0; 9], b"abcdefghijklmnop"];
+        let hashes = keys
+            .iter()
+            .map(|k| hash_key(k))
+            .collect::<std::collections::HashSet<_>>();
+        assert_eq!(hashes.len(), keys.len());
+        assert_eq!(hash_key(b"test"), hash_key(b"test"));
+    }
 }
@@ -0,0 +1,758 @@
+// As in `CuckooFilter`, bucket indices are masked to `pow` bits, whose
+// buckets the array always holds, and slot indices stay below `B`.
+#![allow(clippy::indexing_slicing)]
+
+use crate::cuckoo_filter::{gen_size_for, MAX_CUCKOO_COUNT};
+use crate::format::{self, FormatError, Header, SlotCodec};
+use crate::telemetry;
+use crate::util::hash_key;
+use crate::{CResult, CuckooConfig, CuckooError, CuckooFilter, Placement};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::convert::TryFrom;
+use std::fmt;
+use std::marker::PhantomData;
+use std::mem;
+
+/// A fingerprint type a `WideCuckooFilter` can store.
+pub trait Fingerprint: Copy + Default + Eq + fmt::Debug {
+    /// Width in bits, recorded in the header.
+    const BITS: u8;
+
+    /// Derives a fingerprint from 32 bits of a key's hash, never `0`, which
+    /// marks an empty slot.
+    fn from_hash(hash: u32) -> Self;
+
+    /// Returns the fingerprint widened to 64 bits.
+    fn to_u64(self) -> u64;
+
+    /// Returns the fingerprint held in the low `BITS` bits of `bits`.
+    fn from_u64(bits: u64) -> Self;
+}
+
+impl Fingerprint for u8 {
+    const BITS: u8 = 8;
+
+    fn from_hash(hash: u32) -> Self {
+        (hash % 255 + 1) as u8
+    }
+
+    fn to_u64(self) -> u64 {
+        u64::from(self)
+    }
+
+    fn from_u64(bits: u64) -> Self {
+        bits as u8
+    }
+}
+
+/// A 12-bit fingerprint, kept in the low bits of a `u16`.
+///
+/// Buckets pack its bits without padding, so two slots take three bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Fingerprint12(u16);
+
+impl Fingerprint for Fingerprint12 {
+    const BITS: u8 = 12;
+
+    fn from_hash(hash: u32) -> Self {
+        Fingerprint12((hash % 0xfff + 1) as u16)
+    }
+
+    fn to_u64(self) -> u64 {
+        u64::from(self.0)
+    }
+
+    fn from_u64(bits: u64) -> Self {
+        Fingerprint12((bits & 0xfff) as u16)
+    }
+}
+
+impl Fingerprint for u16 {
+    const BITS: u8 = 16;
+
+    fn from_hash(hash: u32) -> Self {
+        (hash % u32::from(u16::MAX) + 1) as u16
+    }
+
+    fn to_u64(self) -> u64 {
+        u64::from(self)
+    }
+
+    fn from_u64(bits: u64) -> Self {
+        bits as u16
+    }
+}
+
+impl Fingerprint for u32 {
+    const BITS: u8 = 32;
+
+    fn from_hash(hash: u32) -> Self {
+        hash.max(1)
+    }
+
+    fn to_u64(self) -> u64 {
+        u64::from(self)
+    }
+
+    fn from_u64(bits: u64) -> Self {
+        bits as u32
+    }
+}
+
+/// Buckets of `B` fingerprints of type `F`, packed little endian: the first
+/// slot takes the lowest bits, with `0` marking an empty slot. Byte-wide
+/// fingerprints thus lie in consecutive little endian words.
+#[derive(Debug, Clone, Copy)]
+pub struct WideCodec<F, const B: usize>(PhantomData<F>);
+
+impl<F: Fingerprint, const B: usize> SlotCodec for WideCodec<F, B> {
+    const BUCKET_SIZE: u8 = B as u8;
+    const FINGERPRINT_BITS: u8 = F::BITS;
+    const BUCKET_BYTES: usize = (B * F::BITS as usize).div_ceil(8);
+    type Slots = [F; B];
+
+    fn encode(slots: &[F; B], out: &mut Vec<u8>) {
+        // At most 7 pending bits plus one 32-bit fingerprint.
+        let (mut acc, mut pending) = (0u64, 0);
+        for slot in slots {
+            acc |= slot.to_u64() << pending;
+            pending += F::BITS;
+            while pending >= 8 {
+                out.push(acc as u8);
+                acc >>= 8;
+                pending -= 8;
+            }
+        }
+        if pending > 0 {
+            out.push(acc as u8);
+        }
+    }
+
+    fn decode(bytes: &[u8]) -> Result<[F; B], FormatError> {
+        if bytes.len() != Self::BUCKET_BYTES {
+            return Err(FormatError::Truncated { field: "bucket" });
+        }
+        let mut slots = [F::default(); B];
+        let (mut acc, mut pending, mut bytes) = (0u64, 0, bytes.iter());
+        for slot in &mut slots {
+            while pending < F::BITS {
+                acc |= u64::from(*bytes.next().unwrap_or(&0)) << pending;
+                pending += 8;
+            }
+            *slot = F::from_u64(acc);
+            acc >>= F::BITS;
+            pending -= F::BITS;
+        }
+        Ok(slots)
+    }
+}
+
+// Spreads a fingerprint over the bucket index bits for the alternate bucket
+const ALT_MULTIPLIER: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// A cuckoo filter with a choice of fingerprint type `F` (`u8`,
+/// `Fingerprint12`, `u16` or `u32`) and `B` slots per bucket (2, 4 or 8),
+/// for deployments that need
+/// other trade-offs than the 8-bit fingerprints in four-slot buckets of
+/// `CuckooFilter`.
+///
+/// Every extra fingerprint bit halves the false positive rate at the same
+/// load: at full load `WideCuckooFilter<u16>` reports about 0.012% of absent
+/// keys as present and `WideCuckooFilter<u32>` about 0.0000002%, for two and
+/// four times the memory. Larger buckets fill further before inserts fail,
+/// about 84%, 95% and 98% for 2, 4 and 8 slots, but compare a lookup against
+/// more slots, so the rate grows with `B`. Keys hash differently from a
+/// `CuckooFilter`, so the two do not share contents.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::WideCuckooFilter;
+///
+/// let mut cf = WideCuckooFilter::<u16>::new(1000);
+/// cf.add(b"test").unwrap();
+/// assert!(cf.contains(b"test"));
+/// let loaded = WideCuckooFilter::<u16>::from_bytes(&cf.to_bytes()).unwrap();
+/// assert!(loaded.contains(b"test"));
+///
+/// let mut compact = WideCuckooFilter::<u8, 8>::new(1000);
+/// compact.add(b"test").unwrap();
+/// assert_eq!(compact.bucket_size(), 8);
+/// ```
+#[derive(Debug, Clone)]
+pub struct WideCuckooFilter<F, const B: usize = 4> {
+    buckets: Vec<[F; B]>,
+    pow: usize,
+    size: usize,
+    rng: StdRng,
+    max_kicks: usize,
+}
+
+/// 16-bit fingerprints in buckets of four slots, for false positive rates
+/// around 0.01%.
+pub type CuckooFilter16x4 = WideCuckooFilter<u16, 4>;
+
+/// 12-bit fingerprints in buckets of two slots, three bytes per bucket.
+///
+/// Two-slot buckets fill to about 84% but compare a lookup against only four
+/// slots, so at full load about 0.1% of absent keys are reported as present,
+/// for 12 bits per slot.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::CuckooFilter12x2;
+///
+/// let mut cf = CuckooFilter12x2::new(1000);
+/// cf.add(b"test").unwrap();
+/// assert_eq!((cf.bucket_size(), cf.fingerprint_bits()), (2, 12));
+/// assert!(CuckooFilter12x2::from_bytes(&cf.to_bytes()).unwrap().contains(b"test"));
+/// ```
+pub type CuckooFilter12x2 = WideCuckooFilter<Fingerprint12, 2>;
+
+impl<F: Fingerprint, const B: usize> WideCuckooFilter<F, B> {
+    /// Creates a filter sized for `max_num_keys` like `CuckooFilter::new`,
+    /// taking the load its bucket size reaches into account.
+    pub fn new(max_num_keys: u64) -> Self {
+        const { assert!(B == 2 || B == 4 || B == 8, "buckets hold 2, 4 or 8 slots") };
+        let num_buckets = gen_size_for(max_num_keys, B) as usize;
+        Self::with_buckets(vec![[F::default(); B]; num_buckets])
+    }
+
+    /// Creates a filter sized for `config.max_num_keys`, with its seed and
+    /// kick limit. A `WideCuckooFilter` has no duplicate limit, sorted
+    /// buckets or eviction policy; those settings are ignored.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::{CuckooConfig, WideCuckooFilter};
+    ///
+    /// let config = CuckooConfig::new(1000).seed(7).max_kicks(100);
+    /// let cf = WideCuckooFilter::<u16, 8>::from_config(&config);
+    /// assert_eq!(cf.max_kicks(), 100);
+    /// ```
+    pub fn from_config(config: &CuckooConfig) -> Self {
+        let mut cf = Self::new(config.max_num_keys);
+        if let Some(seed) = config.seed {
+            cf.rng = StdRng::seed_from_u64(seed);
+        }
+        cf.max_kicks = config.max_kicks.unwrap_or(MAX_CUCKOO_COUNT);
+        cf
+    }
+
+    fn with_buckets(buckets: Vec<[F; B]>) -> Self {
+        WideCuckooFilter {
+            size: 0,
+            pow: buckets.len().trailing_zeros() as usize,
+            buckets,
+            rng: StdRng::from_entropy(),
+            max_kicks: MAX_CUCKOO_COUNT,
+        }
+    }
+
+    fn mask(&self) -> usize {
+        (1 << self.pow) - 1
+    }
+
+    fn index_fp(&self, item: &[u8]) -> (usize, F) {
+        let hash = hash_key(item);
+        (
+            hash as usize & self.mask(),
+            F::from_hash((hash >> 32) as u32),
+        )
+    }
+
+    fn alt_index(&self, index: usize, fp: F) -> usize {
+        (index ^ fp.to_u64().wrapping_mul(ALT_MULTIPLIER) as usize) & self.mask()
+    }
+
+    fn put(&mut self, index: usize, fp: F) -> bool {
+        match self.buckets[index]
+            .iter_mut()
+            .find(|slot| **slot == F::default())
+        {
+            Some(slot) => {
+                *slot = fp;
+                self.size += 1;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Adds `item`, relocating fingerprints as `CuckooFilter::add` does and
+    /// leaving the filter unchanged if no slot is found. The `Placement` of
+    /// the error carries the low byte of the fingerprint.
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        let (i1, fp) = self.index_fp(item);
+        let i2 = self.alt_index(i1, fp);
+        if self.put(i1, fp) || self.put(i2, fp) {
+            return Ok(());
+        }
+        let mut journal = Vec::new();
+        let (mut index, mut victim) = (if self.rng.gen() { i1 } else { i2 }, fp);
+        for _ in 0..self.max_kicks {
+            let slot = self.rng.gen_range(0, B);
+            journal.push((index, slot, self.buckets[index][slot]));
+            victim = mem::replace(&mut self.buckets[index][slot], victim);
+            index = self.alt_index(index, victim);
+            if self.put(index, victim) {
+                return Ok(());
+            }
+        }
+        for (index, slot, before) in journal.into_iter().rev() {
+            self.buckets[index][slot] = before;
+        }
+        Err(telemetry::record(CuckooError::NotEnoughSpace(Placement {
+            fingerprint: fp.to_u64() as u8,
+            i1,
+            i2,
+        })))
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        let (i1, fp) = self.index_fp(item);
+        self.buckets[i1].contains(&fp) || self.buckets[self.alt_index(i1, fp)].contains(&fp)
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        let (i1, fp) = self.index_fp(item);
+        for i in [i1, self.alt_index(i1, fp)] {
+            if let Some(slot) = self.buckets[i].iter_mut().find(|slot| **slot == fp) {
+                *slot = F::default();
+                self.size -= 1;
+                return true;
+            }
+        }
+        false
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.size == 0
+    }
+
+    /// Returns the number of fingerprint slots.
+    pub fn capacity(&self) -> usize {
+        self.buckets.len() * B
+    }
+
+    pub fn bucket_size(&self) -> usize {
+        B
+    }
+
+    /// Returns how many fingerprints an insertion may relocate before it
+    /// fails.
+    pub fn max_kicks(&self) -> usize {
+        self.max_kicks
+    }
+
+    /// Returns the width of one fingerprint in bits.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::WideCuckooFilter;
+    /// assert_eq!(WideCuckooFilter::<u32>::new(100).fingerprint_bits(), 32);
+    /// ```
+    pub fn fingerprint_bits(&self) -> u32 {
+        u32::from(F::BITS)
+    }
+
+    /// Serializes the filter in the `format` layout, with the header naming
+    /// the bucket size and fingerprint width and the buckets written by
+    /// `WideCodec`.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let header = Header {
+            flags: format::FLAG_CHECKSUM,
+            pow: self.pow as u8,
+            num_buckets: self.buckets.len() as u64,
+            size: self.size as u64,
+        };
+        let mut out = header.encode_with::<WideCodec<F, B>>().to_vec();
+        for bucket in &self.buckets {
+            WideCodec::<F, B>::encode(bucket, &mut out);
+        }
+        let crc = format::checksum(&out[format::BUCKETS_OFFSET..]);
+        out.extend_from_slice(&format::checksum_section(crc));
+        out
+    }
+
+    /// Loads a filter written by `to_bytes` for the same fingerprint type and
+    /// bucket size.
+    ///
+    /// Filters of another layout fail with `FormatError::Mismatch` of
+    /// `fingerprint_bits` or `bucket_size`.
+    ///
+    /// # Example
+    /// ```
+    /// use dakv_cuckoo::format::FormatError;
+    /// use dakv_cuckoo::WideCuckooFilter;
+    ///
+    /// let bytes = WideCuckooFilter::<u16>::new(100).to_bytes();
+    /// assert!(matches!(
+    ///     WideCuckooFilter::<u32>::from_bytes(&bytes),
+    ///     Err(FormatError::Mismatch { field: "fingerprint_bits", .. })
+    /// ));
+    /// ```
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FormatError> {
+        let header = Header::parse_with::<WideCodec<F, B>>(bytes)?;
+        // The sparse encoding stores one byte per fingerprint.
+        if header.flags & format::FLAG_SPARSE != 0 {
+            return Err(FormatError::UnsupportedCapabilities(format::FLAG_SPARSE));
+        }
+        let raw_len = usize::try_from(header.num_buckets)
+            .ok()
+            .and_then(|n| n.checked_mul(WideCodec::<F, B>::BUCKET_BYTES))
+            .ok_or(FormatError::Truncated { field: "buckets" })?;
+        let (data, sections) = bytes
+            .get(format::BUCKETS_OFFSET..)
+            .and_then(|rest| rest.split_at_checked(raw_len))
+            .ok_or(FormatError::Truncated { field: "buckets" })?;
+        for (flag, section) in format::sections(header.flags, sections)? {
+            if flag == format::FLAG_CHECKSUM {
+                format::verify_checksum(section, format::checksum(data))?;
+            }
+        }
+        let buckets = data
+            .chunks_exact(WideCodec::<F, B>::BUCKET_BYTES)
+            .map(WideCodec::<F, B>::decode)
+            .collect::<Result<Vec<_>, _>>()?;
+        let occupied = buckets
+            .iter()
+            .flatten()
+            .filter(|&&slot| slot != F::default())
+            .count() as u64;
+        if occupied != header.size {
+            return Err(FormatError::Mismatch {
+                field: "size",
+                expected: header.size,
+                found: occupied,
+            });
+        }
+        let mut filter = Self::with_buckets(buckets);
+        filter.pow = usize::from(header.pow);
+        filter.size = occupied as usize;
+        Ok(filter)
+    }
+}
+
+// The operations `AnyCuckooFilter` forwards to a `WideCuckooFilter`.
+trait WideLayout: Send + Sync {
+    fn add(&mut self, item: &[u8]) -> CResult<CuckooError>;
+    fn contains(&self, item: &[u8]) -> bool;
+    #[cfg(not(feature = "no-delete"))]
+    fn delete(&mut self, item: &[u8]) -> bool;
+    fn size(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn bucket_size(&self) -> usize;
+    fn fingerprint_bits(&self) -> u32;
+    fn to_bytes(&self) -> Vec<u8>;
+}
+
+impl<F: Fingerprint + Send + Sync, const B: usize> WideLayout for WideCuckooFilter<F, B> {
+    fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        WideCuckooFilter::add(self, item)
+    }
+
+    fn contains(&self, item: &[u8]) -> bool {
+        WideCuckooFilter::contains(self, item)
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    fn delete(&mut self, item: &[u8]) -> bool {
+        WideCuckooFilter::delete(self, item)
+    }
+
+    fn size(&self) -> usize {
+        WideCuckooFilter::size(self)
+    }
+
+    fn capacity(&self) -> usize {
+        WideCuckooFilter::capacity(self)
+    }
+
+    fn bucket_size(&self) -> usize {
+        B
+    }
+
+    fn fingerprint_bits(&self) -> u32 {
+        WideCuckooFilter::fingerprint_bits(self)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        WideCuckooFilter::to_bytes(self)
+    }
+}
+
+enum AnyLayout {
+    Standard(Box<CuckooFilter>),
+    Wide(Box<dyn WideLayout>),
+}
+
+/// A filter whose bucket size and fingerprint width are chosen at run time,
+/// built by `CuckooFilterBuilder::build_any`.
+///
+/// The built-in layout of 8-bit fingerprints in buckets of four slots is a
+/// `CuckooFilter`; every other layout is a `WideCuckooFilter` of that
+/// layout, which hashes keys differently.
+///
+/// # Example
+/// ```
+/// use dakv_cuckoo::CuckooFilterBuilder;
+///
+/// let mut cf = CuckooFilterBuilder::new()
+///     .capacity(1000)
+///     .bucket_size(8)
+///     .build_any()
+///     .unwrap();
+/// cf.add(b"test").unwrap();
+/// assert!(cf.contains(b"test"));
+/// assert_eq!((cf.bucket_size(), cf.capacity()), (8, 1024));
+/// assert!(cf.as_standard().is_none());
+/// ```
+pub struct AnyCuckooFilter(AnyLayout);
+
+impl AnyCuckooFilter {
+    /// Creates a `WideCuckooFilter` of the given layout from `config`, or
+    /// returns `None` if the layout is not one of 2, 4 or 8 slots of 8, 16
+    /// or 32 bits.
+    pub(crate) fn wide(config: &CuckooConfig, bucket_size: usize, bits: u32) -> Option<Self> {
+        fn layout<F: Fingerprint + Send + Sync + 'static, const B: usize>(
+            config: &CuckooConfig,
+        ) -> Box<dyn WideLayout> {
+            Box::new(WideCuckooFilter::<F, B>::from_config(config))
+        }
+        let filter = match (bucket_size, bits) {
+            (2, 8) => layout::<u8, 2>(config),
+            (4, 8) => layout::<u8, 4>(config),
+            (8, 8) => layout::<u8, 8>(config),
+            (2, 12) => layout::<Fingerprint12, 2>(config),
+            (4, 12) => layout::<Fingerprint12, 4>(config),
+            (8, 12) => layout::<Fingerprint12, 8>(config),
+            (2, 16) => layout::<u16, 2>(config),
+            (4, 16) => layout::<u16, 4>(config),
+            (8, 16) => layout::<u16, 8>(config),
+            (2, 32) => layout::<u32, 2>(config),
+            (4, 32) => layout::<u32, 4>(config),
+            (8, 32) => layout::<u32, 8>(config),
+            _ => return None,
+        };
+        Some(AnyCuckooFilter(AnyLayout::Wide(filter)))
+    }
+
+    pub fn add(&mut self, item: &[u8]) -> CResult<CuckooError> {
+        match &mut self.0 {
+            AnyLayout::Standard(cf) => cf.add(item),
+            AnyLayout::Wide(cf) => cf.add(item),
+        }
+    }
+
+    pub fn contains(&self, item: &[u8]) -> bool {
+        match &self.0 {
+            AnyLayout::Standard(cf) => cf.contains(item),
+            AnyLayout::Wide(cf) => cf.contains(item),
+        }
+    }
+
+    #[cfg(not(feature = "no-delete"))]
+    pub fn delete(&mut self, item: &[u8]) -> bool {
+        match &mut self.0 {
+            AnyLayout::Standard(cf) => cf.delete(item),
+            AnyLayout::Wide(cf) => cf.delete(item),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match &self.0 {
+            AnyLayout::Standard(cf) => cf.size(),
+            AnyLayout::Wide(cf) => cf.size(),
+        }
+    }
+
+    /// Returns the number of fingerprint slots.
+    pub fn capacity(&self) -> usize {
+        match &self.0 {
+            AnyLayout::Standard(cf) => cf.capacity(),
+            AnyLayout::Wide(cf) => cf.capacity(),
+        }
+    }
+
+    pub fn bucket_size(&self) -> usize {
+        match &self.0 {
+            AnyLayout::Standard(cf) => cf.bucket_size(),
+            AnyLayout::Wide(cf) => cf.bucket_size(),
+        }
+    }
+
+    pub fn fingerprint_bits(&self) -> u32 {
+        match &self.0 {
+            AnyLayout::Standard(cf) => cf.fingerprint_bits(),
+            AnyLayout::Wide(cf) => cf.fingerprint_bits(),
+        }
+    }
+
+    /// Serializes the filter with `CuckooFilter::to_bytes` or
+    /// `WideCuckooFilter::to_bytes`; the header names the layout.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match &self.0 {
+            AnyLayout::Standard(cf) => cf.to_bytes(),
+            AnyLayout::Wide(cf) => cf.to_bytes(),
+        }
+    }
+
+    /// Returns the filter if it has the built-in layout.
+    pub fn as_standard(&self) -> Option<&CuckooFilter> {
+        match &self.0 {
+            AnyLayout::Standard(cf) => Some(cf),
+            AnyLayout::Wide(_) => None,
+        }
+    }
+
+    /// Returns the filter if it has the built-in layout.
+    pub fn into_standard(self) -> Option<CuckooFilter> {
+        match self.0 {
+            AnyLayout::Standard(cf) => Some(*cf),
+            AnyLayout::Wide(_) => None,
+        }
+    }
+}
+
+impl From<CuckooFilter> for AnyCuckooFilter {
+    fn from(cf: CuckooFilter) -> Self {
+        AnyCuckooFilter(AnyLayout::Standard(Box::new(cf)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::format::{FormatError, SlotCodec};
+    use crate::WideCuckooFilter;
+    use crate::{CuckooConfig, CuckooFilter12x2, Fingerprint, Fingerprint12, WideCodec};
+
+    #[test]
+    fn test_wide_filter() {
+        let mut cf = WideCuckooFilter::<u16>::new(4000);
+        let mut added = 0u32;
+        while cf.add(&added.to_le_bytes()).is_ok() {
+            added += 1;
+        }
+        assert!(added as usize > cf.capacity() * 9 / 10);
+        assert_eq!(cf.size(), added as usize);
+        assert!((0..added).all(|i| cf.contains(&i.to_le_bytes())));
+        // 16-bit fingerprints leave almost no false positives at full load.
+        let hits = (1_000_000..1_100_000u32)
+            .filter(|i| cf.contains(&i.to_le_bytes()))
+            .count();
+        assert!(hits < 50, "{}", hits);
+
+        let bytes = cf.to_bytes();
+        let loaded = WideCuckooFilter::<u16>::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.size(), cf.size());
+        assert!((0..added).all(|i| loaded.contains(&i.to_le_bytes())));
+        let mut damaged = bytes.clone();
+        damaged[40] ^= 1;
+        assert!(matches!(
+            WideCuckooFilter::<u16>::from_bytes(&damaged),
+            Err(FormatError::Corrupted { .. })
+        ));
+
+        #[cfg(not(feature = "no-delete"))]
+        {
+            assert!(cf.delete(&0u32.to_le_bytes()));
+            assert!(!cf.contains(&0u32.to_le_bytes()));
+            assert_eq!(cf.size(), added as usize - 1);
+        }
+    }
+
+    #[test]
+    fn test_bucket_sizes() {
+        fn fill<const B: usize>() -> f64 {
+            let mut cf = WideCuckooFilter::<u8, B>::new(4000);
+            let mut added = 0u32;
+            while cf.add(&added.to_le_bytes()).is_ok() {
+                added += 1;
+            }
+            assert!((0..added).all(|i| cf.contains(&i.to_le_bytes())));
+            f64::from(added) / cf.capacity() as f64
+        }
+        let (two, four, eight) = (fill::<2>(), fill::<4>(), fill::<8>());
+        assert!(
+            two > 0.75 && two < four && eight > 0.95,
+            "{} {} {}",
+            two,
+            four,
+            eight
+        );
+
+        let bytes = WideCuckooFilter::<u8, 2>::new(100).to_bytes();
+        assert!(matches!(
+            WideCuckooFilter::<u8, 8>::from_bytes(&bytes),
+            Err(FormatError::Mismatch {
+                field: "bucket_size",
+                ..
+            })
+        ));
+        assert_eq!(
+            WideCuckooFilter::<u8, 2>::from_bytes(&bytes)
+                .unwrap()
+                .bucket_size(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_wide_filter_u32() {
+        let mut cf = WideCuckooFilter::<u32>::new(100);
+        cf.add(b"test").unwrap();
+        assert_eq!(cf.fingerprint_bits(), 32);
+        assert_eq!(cf.to_bytes().len(), 32 + cf.capacity() * 4 + 12);
+        assert!(WideCuckooFilter::<u32>::from_bytes(&cf.to_bytes())
+            .unwrap()
+            .contains(b"test"));
+    }
+    #[test]
+    fn test_from_config() {
+        let config = CuckooConfig::new(1000).seed(3).max_kicks(0);
+        let fill = |config: &CuckooConfig| {
+            let mut cf = WideCuckooFilter::<u16, 2>::from_config(config);
+            let mut n = 0u32;
+            while cf.add(&n.to_le_bytes()).is_ok() {
+                n += 1;
+            }
+            (n, cf.to_bytes())
+        };
+        // Without kicks, the first key whose two buckets are full fails.
+        assert!(fill(&config).0 < fill(&config.max_kicks(500)).0);
+        assert_eq!(fill(&config.max_kicks(500)), fill(&config.max_kicks(500)));
+    }
+
+    #[test]
+    fn test_packed_codec() {
+        let slots = [
+            Fingerprint12::from_u64(0xabc),
+            Fingerprint12::from_u64(0x123),
+        ];
+        let mut out = Vec::new();
+        WideCodec::<Fingerprint12, 2>::encode(&slots, &mut out);
+        assert_eq!(out, [0xbc, 0x3a, 0x12]);
+        assert_eq!(WideCodec::<Fingerprint12, 2>::decode(&out).unwrap(), slots);
+
+        // Byte-wide fingerprints keep their little endian layout.
+        out.clear();
+        WideCodec::<u16, 2>::encode(&[0x0102, 0x0304], &mut out);
+        assert_eq!(out, [0x02, 0x01, 0x04, 0x03]);
+
+        let mut cf = CuckooFilter12x2::new(1000);
+        let mut added = 0u32;
+        while cf.add(&added.to_le_bytes()).is_ok() {
+            added += 1;
+        }
+        assert_eq!(cf.to_bytes().len(), 3 * cf.capacity() / 2 + 44);
+        let loaded = CuckooFilter12x2::from_bytes(&cf.to_bytes()).unwrap();
+        assert_eq!(loaded.size(), added as usize);
+        assert!((0..added).all(|i| loaded.contains(&i.to_le_bytes())));
+    }
+}
@@ -0,0 +1,109 @@
+use dakv_cuckoo::{CuckooConfig, CuckooError, CuckooFilter};
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_le_bytes()
+}
+
+fn seeded(max_num_keys: u64) -> CuckooFilter {
+    CuckooFilter::from_config(&CuckooConfig::new(max_num_keys).seed(42))
+}
+
+#[test]
+fn test_lifecycle_at_capacities() {
+    for &capacity in &[1u32, 4, 100, 1000, 100_000] {
+        let mut cf = seeded(u64::from(capacity));
+        assert_eq!(cf.size(), 0);
+        for i in 0..capacity {
+            cf.add(&key(i)).unwrap();
+        }
+        assert_eq!(cf.size(), capacity as usize);
+        assert!((0..capacity).all(|i| cf.contains(&key(i))), "{}", capacity);
+
+        #[cfg(not(feature = "no-delete"))]
+        {
+            for i in 0..capacity {
+                assert!(cf.delete(&key(i)), "{}", capacity);
+            }
+            assert_eq!(cf.size(), 0);
+            assert!((0..capacity).all(|i| !cf.contains(&key(i))));
+            assert!(!cf.delete(&key(0)));
+        }
+    }
+}
+
+#[test]
+fn test_saturation() {
+    let mut cf = CuckooFilter::with_capacity_and_seed(1024, 7);
+    let mut added = 0;
+    let err = loop {
+        match cf.add(&key(added)) {
+            Ok(()) => added += 1,
+            Err(e) => break e,
+        }
+    };
+    assert!(matches!(err, CuckooError::NotEnoughSpace(_)));
+    assert!(cf.load_factor() > 0.9, "{}", cf.load_factor());
+
+    // A failed insert leaves the filter as it was.
+    assert_eq!(cf.size(), added as usize);
+    assert!((0..added).all(|i| cf.contains(&key(i))));
+    assert!(cf.check_invariants().is_ok());
+
+    #[cfg(not(feature = "no-delete"))]
+    {
+        for i in 0..200 {
+            assert!(cf.delete(&key(i)));
+        }
+        for i in 0..50 {
+            cf.add(&key(1_000_000 + i)).unwrap();
+        }
+        assert!((200..added).all(|i| cf.contains(&key(i))));
+        assert!((0..50).all(|i| cf.contains(&key(1_000_000 + i))));
+    }
+}
+
+#[cfg(not(feature = "no-delete"))]
+#[test]
+fn test_interleaved_operations_match_model() {
+    let mut cf = seeded(4096);
+    let mut model: std::collections::HashMap<u32, usize> = Default::default();
+    // A fixed LCG keeps the operation sequence identical on every run.
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    for _ in 0..20_000 {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+        let k = (state >> 33) as u32 % 3000;
+        if (state >> 20) & 3 == 0 {
+            let present = model.get(&k).copied().unwrap_or(0) > 0;
+            // Another key sharing the fingerprint may be deleted instead,
+            // so only deletes of present keys are checked.
+            if present {
+                assert!(cf.delete(&key(k)));
+                *model.get_mut(&k).unwrap() -= 1;
+            }
+        } else if model.values().sum::<usize>() < 3500 {
+            cf.add(&key(k)).unwrap();
+            *model.entry(k).or_insert(0) += 1;
+        }
+        assert_eq!(cf.size(), model.values().sum::<usize>());
+    }
+    for (k, &count) in &model {
+        if count > 0 {
+            assert!(cf.contains(&key(*k)), "{}", k);
+        }
+    }
+    assert!(cf.check_invariants().is_ok());
+}
+
+#[test]
+fn test_false_positive_rate() {
+    let mut cf = seeded(10_000);
+    for i in 0..10_000 {
+        cf.add(&key(i)).unwrap();
+    }
+    let probes = 1_000_000..1_100_000u32;
+    let hits = probes.clone().filter(|&i| cf.contains(&key(i))).count();
+    // Eight 8-bit comparisons per lookup bound the rate by about 3%.
+    assert!(hits < probes.len() * 4 / 100, "{}", hits);
+}
@@ -0,0 +1,76 @@
+use dakv_cuckoo::format::{self, FormatError};
+use dakv_cuckoo::{CuckooConfig, CuckooFilter};
+use std::io::Cursor;
+
+fn key(i: u32) -> [u8; 4] {
+    i.to_le_bytes()
+}
+
+fn filled(max_num_keys: u64, items: u32) -> CuckooFilter {
+    let mut cf = CuckooFilter::from_config(&CuckooConfig::new(max_num_keys).seed(3));
+    for i in 0..items {
+        cf.add(&key(i)).unwrap();
+    }
+    cf
+}
+
+fn assert_same(a: &CuckooFilter, b: &CuckooFilter) {
+    assert_eq!(a.size(), b.size());
+    assert_eq!(a.capacity(), b.capacity());
+    assert_eq!(a.to_bytes_raw(), b.to_bytes_raw());
+    // The format does not record whether anything was displaced, so `b`
+    // probes both buckets and may answer `true` where `a` skips one.
+    assert!((0..20_000).all(|i| !a.contains(&key(i)) || b.contains(&key(i))));
+}
+
+#[test]
+fn test_bytes_round_trip() {
+    // Sparse filters are stored sparse-encoded, dense ones raw.
+    for &items in &[0, 10, 1000, 7000] {
+        let cf = filled(4000, items);
+        assert_same(&cf, &CuckooFilter::from_bytes(&cf.to_bytes()).unwrap());
+        assert_same(&cf, &CuckooFilter::from_bytes(&cf.to_bytes_raw()).unwrap());
+        assert_same(&cf, &CuckooFilter::from_base64(&cf.to_base64()).unwrap());
+
+        let mut out = Vec::new();
+        cf.write_to(&mut out).unwrap();
+        assert_same(
+            &cf,
+            &CuckooFilter::read_from(&mut Cursor::new(out)).unwrap(),
+        );
+    }
+}
+
+#[test]
+fn test_restored_filter_stays_usable() {
+    let cf = filled(1000, 500);
+    let mut restored = CuckooFilter::from_bytes(&cf.to_bytes()).unwrap();
+    for i in 500..900 {
+        restored.add(&key(i)).unwrap();
+    }
+    assert!((0..900).all(|i| restored.contains(&key(i))));
+    #[cfg(not(feature = "no-delete"))]
+    {
+        assert!(restored.delete(&key(0)));
+        assert_eq!(restored.size(), 899);
+    }
+    assert!(restored.check_invariants().is_ok());
+}
+
+#[test]
+fn test_damaged_input_is_rejected() {
+    let bytes = filled(1000, 500).to_bytes_raw();
+    let mut flipped = bytes.clone();
+    flipped[format::BUCKETS_OFFSET + 17] ^= 0x40;
+    assert!(matches!(
+        CuckooFilter::from_bytes(&flipped),
+        Err(FormatError::Corrupted { .. })
+    ));
+
+    for len in [0, format::HEADER_LEN - 1, bytes.len() - 1] {
+        assert!(CuckooFilter::from_bytes(&bytes[..len]).is_err(), "{}", len);
+    }
+    let mut magic = bytes;
+    magic[0] = b'X';
+    assert!(CuckooFilter::from_bytes(&magic).is_err());
+}